@@ -0,0 +1,290 @@
+//! Archives of the whole notes directory, for backups.
+//!
+//! Archives are gzipped tar files, or zip files on Windows, made and unpacked by the `tar`
+//! program, which must be installed; Windows 10 and later include one that can make zip files.
+//! Every archive is read back in full after it is made and before it is unpacked, so that a
+//! truncated or corrupted archive is found out while the notes it holds can still be recovered.
+
+use crate::config::Config;
+use crate::error::*;
+use crate::notes_dir;
+
+use chrono::{DateTime, FixedOffset, TimeZone};
+
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The format of archive file names, with the time the archive was made.
+#[cfg(not(windows))]
+const NAME_FORMAT: &str = "newt-backup-%Y%m%dT%H%M%S.tar.gz";
+
+/// The format of archive file names, with the time the archive was made.
+#[cfg(windows)]
+const NAME_FORMAT: &str = "newt-backup-%Y%m%dT%H%M%S.zip";
+
+/// The key under which the time of the latest backup is kept in the notes directory's state file.
+const LAST_BACKUP_KEY: &str = "last_backup";
+
+/// The options that make `tar` write an archive in this platform's format.
+#[cfg(not(windows))]
+const CREATE_ARGS: &[&str] = &["-czf"];
+
+/// The options that make `tar` write an archive in this platform's format. `-a` picks the format
+/// from the archive's file name.
+#[cfg(windows)]
+const CREATE_ARGS: &[&str] = &["-a", "-cf"];
+
+/// The file name of an archive made at the given time.
+pub fn file_name<Tz>(time: &DateTime<Tz>) -> String
+where
+    Tz: TimeZone,
+    Tz::Offset: Display,
+{
+    time.format(NAME_FORMAT).to_string()
+}
+
+/// Whether a file name is that of an archive made by [`create`], as named by [`file_name`].
+pub fn is_archive_name(name: &str) -> bool {
+    name.starts_with("newt-backup-") && (name.ends_with(".tar.gz") || name.ends_with(".zip"))
+}
+
+/// Record that the notes directory was backed up at the given time.
+pub fn record_backup<Tz>(config: &Config, time: &DateTime<Tz>) -> Result<()>
+where
+    Tz: TimeZone,
+    Tz::Offset: Display,
+{
+    notes_dir::set_state(config, LAST_BACKUP_KEY, &time.to_rfc3339())
+}
+
+/// When the notes directory was last backed up, if a backup has been recorded.
+pub fn last_backup(config: &Config) -> Result<Option<DateTime<FixedOffset>>> {
+    Ok(notes_dir::state(config, LAST_BACKUP_KEY)?
+        .and_then(|time| DateTime::parse_from_rfc3339(&time).ok()))
+}
+
+/// Archive everything in the notes directory to the file at `archive` and [`verify`] it. Returns
+/// the number of files archived.
+///
+/// Fails with [`Error::FileExists`] if there is already a file at `archive`. If the archive is
+/// inside the notes directory, it is left out of itself, along with any other archives next to it.
+pub fn create(config: &Config, archive: &Path) -> Result<usize> {
+    let notes_dir = config.notes_dir()?;
+    match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(archive)
+    {
+        Ok(_) => {}
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+            return Err(Error::FileExists {
+                path: archive.to_owned(),
+            })
+        }
+        Err(err) => return Err(err).context("creating", archive),
+    }
+
+    let mut cmd = Command::new("tar");
+    cmd.args(CREATE_ARGS).arg(archive);
+    if let Some(inside) = path_inside(&notes_dir, archive) {
+        cmd.arg(exclude(&inside));
+        for others in &["newt-backup-*.tar.gz", "newt-backup-*.zip"] {
+            cmd.arg(exclude(&inside.with_file_name(others)));
+        }
+    }
+    cmd.arg("-C").arg(&notes_dir).arg(".");
+    let res = tar(cmd).and_then(|_| verify(archive));
+    if res.is_err() {
+        let _ = std::fs::remove_file(archive);
+    }
+    Ok(res?.len())
+}
+
+/// The path of an existing file relative to a directory, as `tar` names it when archiving the
+/// directory, if the file is inside it.
+fn path_inside(dir: &Path, file: &Path) -> Option<PathBuf> {
+    let dir = dir.canonicalize().ok()?;
+    let file = file.canonicalize().ok()?;
+    file.strip_prefix(dir)
+        .ok()
+        .map(|path| Path::new(".").join(path))
+}
+
+/// The option that keeps files matching a pattern out of an archive.
+fn exclude(pattern: &Path) -> OsString {
+    let mut arg = OsString::from("--exclude=");
+    arg.push(pattern);
+    arg
+}
+
+/// Check that an archive is intact by reading every file in it, without unpacking anything.
+/// Returns the files in the archive, as [`contents`] does.
+///
+/// Fails with [`Error::CorruptArchive`] if the archive cannot be read in full, or if the data of
+/// any of its files does not match the checksum stored with it.
+pub fn verify(archive: &Path) -> Result<Vec<PathBuf>> {
+    let corrupt = |err| match err {
+        Error::ChildFailed { .. } => Error::CorruptArchive {
+            path: archive.to_owned(),
+        },
+        err => err,
+    };
+
+    let files = contents(archive).map_err(corrupt)?;
+    let mut cmd = Command::new("tar");
+    cmd.arg("-xOf").arg(archive).stdout(Stdio::null());
+    run(cmd).map_err(corrupt)?;
+    Ok(files)
+}
+
+/// The files in an archive, relative to the notes directory it was made from.
+pub fn contents(archive: &Path) -> Result<Vec<PathBuf>> {
+    let mut cmd = Command::new("tar");
+    cmd.arg("-tf").arg(archive);
+    Ok(tar(cmd)?
+        .lines()
+        .map(|entry| entry.strip_prefix("./").unwrap_or(entry))
+        .filter(|entry| !entry.is_empty() && !entry.ends_with('/'))
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Unpack an archive into the given directory, creating it if needed. Returns the number of files
+/// unpacked.
+///
+/// Fails without unpacking anything if the archive does not pass [`verify`], or if any of its
+/// files already exists in the directory.
+pub fn extract(archive: &Path, dir: &Path) -> Result<usize> {
+    let files = verify(archive)?;
+    for file in &files {
+        let path = dir.join(file);
+        if path.exists() {
+            return Err(Error::FileExists { path });
+        }
+    }
+
+    std::fs::create_dir_all(dir).context("creating", dir)?;
+    let mut cmd = Command::new("tar");
+    cmd.arg("-xf").arg(archive).arg("-C").arg(dir);
+    tar(cmd)?;
+    Ok(files.len())
+}
+
+/// Run tar, returning its output.
+fn tar(mut cmd: Command) -> Result<String> {
+    trace!("Running {:?}", cmd);
+    let output = cmd
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|err| cannot_invoke("tar", err))?;
+    if !output.status.success() {
+        return Err(Error::ChildFailed {
+            command: String::from("tar"),
+            status: output.status,
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Run tar with its output going wherever `cmd` sends it, and its errors discarded.
+fn run(mut cmd: Command) -> Result<()> {
+    trace!("Running {:?}", cmd);
+    let status = cmd
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|err| cannot_invoke("tar", err))?;
+    if !status.success() {
+        return Err(Error::ChildFailed {
+            command: String::from("tar"),
+            status,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::temp::TempDir;
+
+    #[test]
+    fn archive_names() {
+        let time = chrono::Utc.ymd(2024, 5, 1).and_hms(9, 30, 0);
+        let name = file_name(&time);
+        assert!(name.starts_with("newt-backup-20240501T093000."));
+        assert!(is_archive_name(&name));
+        assert!(!is_archive_name("notes.tar.gz"));
+    }
+
+    #[test]
+    fn last_backup_time() {
+        let temp = TempDir::new("last-backup-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let config = Config::default().with_notes_dir(dir.clone());
+        assert_eq!(last_backup(&config).unwrap(), None);
+
+        let time = chrono::Utc.ymd(2024, 5, 1).and_hms(9, 30, 0);
+        record_backup(&config, &time).unwrap();
+        assert_eq!(last_backup(&config).unwrap(), Some(time.into()));
+    }
+
+    #[test]
+    fn create_and_extract() {
+        let temp = TempDir::new("archive-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let notes = dir.join("notes");
+        std::fs::create_dir_all(notes.join("sub")).unwrap();
+        std::fs::write(notes.join("a.md"), "a").unwrap();
+        std::fs::write(notes.join("sub/b.md"), "b").unwrap();
+        let config = Config::default().with_notes_dir(notes);
+
+        let archive = dir.join("backup.tar.gz");
+        assert_eq!(create(&config, &archive).unwrap(), 2);
+        let mut files = contents(&archive).unwrap();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![PathBuf::from("a.md"), PathBuf::from("sub/b.md")]
+        );
+
+        assert!(matches!(
+            create(&config, &archive),
+            Err(Error::FileExists { .. })
+        ));
+
+        let inside = config.notes_dir().unwrap().join("newt-backup-1.tar.gz");
+        std::fs::write(inside.with_file_name("newt-backup-0.tar.gz"), "old").unwrap();
+        assert_eq!(create(&config, &inside).unwrap(), 2);
+        let mut files = contents(&inside).unwrap();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![PathBuf::from("a.md"), PathBuf::from("sub/b.md")]
+        );
+
+        let restored = dir.join("restored");
+        assert_eq!(extract(&archive, &restored).unwrap(), 2);
+        assert_eq!(
+            std::fs::read_to_string(restored.join("sub/b.md")).unwrap(),
+            "b"
+        );
+        assert!(matches!(
+            extract(&archive, &restored),
+            Err(Error::FileExists { .. })
+        ));
+
+        let bytes = std::fs::read(&archive).unwrap();
+        std::fs::write(&archive, &bytes[..bytes.len() / 2]).unwrap();
+        assert!(matches!(
+            extract(&archive, &dir.join("truncated")),
+            Err(Error::CorruptArchive { .. })
+        ));
+        assert!(!dir.join("truncated").exists());
+    }
+}
@@ -0,0 +1,311 @@
+//! Storage backends for a [`NoteStore`](crate::NoteStore).
+//!
+//! A [`Backend`] stores the contents of notes under their names, which are relative paths such as
+//! `projects/plan.md`. [`FsBackend`] keeps them in the configured notes directory, as the CLI
+//! does; [`MemoryBackend`] keeps them in memory, for tests and for library users who want a
+//! scratch store. With the `sqlite` feature, `SqliteBackend` keeps them in a SQLite database.
+
+use crate::config::Config;
+use crate::error::*;
+use crate::frontmatter::{self, Frontmatter};
+use crate::notes_dir;
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteBackend;
+
+/// Storage for notes.
+///
+/// Only the basic operations must be implemented; the rest have default implementations in
+/// terms of them, which backends may replace with more efficient ones.
+pub trait Backend: std::fmt::Debug + Send + Sync {
+    /// The names of the notes, in listing order.
+    fn list(&self) -> Result<Vec<PathBuf>>;
+
+    /// Whether a note with the given name exists.
+    fn exists(&self, name: &Path) -> Result<bool>;
+
+    /// Whether the given name is a directory containing notes, or which notes may be moved into.
+    fn is_dir(&self, name: &Path) -> Result<bool>;
+
+    /// Read the contents of a note.
+    fn read(&self, name: &Path) -> Result<String>;
+
+    /// Replace the contents of a note, creating it and any missing parent directories if it does
+    /// not exist.
+    fn write(&self, name: &Path, contents: &str) -> Result<()>;
+
+    /// Give a note a new name. The new name must not be taken.
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    /// Delete a note.
+    fn remove(&self, name: &Path) -> Result<()>;
+
+    /// When a note was created, if the backend records it.
+    fn created(&self, name: &Path) -> Result<SystemTime>;
+
+    /// When a note was last modified.
+    fn modified(&self, name: &Path) -> Result<SystemTime>;
+
+    /// Iterate over the names of the notes, in no particular order.
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<PathBuf>>>> {
+        Ok(Box::new(self.list()?.into_iter().map(Ok)))
+    }
+
+    /// The name of the note at the given index in listing order.
+    fn note_at(&self, index: usize) -> Result<PathBuf> {
+        let notes = self.list()?;
+        let count = notes.len();
+        notes
+            .into_iter()
+            .nth(index)
+            .ok_or(Error::FileIndexOutOfRange { index, count })
+    }
+
+    /// The title of a note. See [`notes_dir::title`].
+    fn title(&self, name: &Path, max_len: usize) -> Result<Option<String>> {
+        notes_dir::title_line(self.read(name)?.as_bytes(), max_len)
+    }
+
+    /// The first `count` non-empty lines of a note. See [`notes_dir::preview`].
+    fn preview(&self, name: &Path, count: usize, max_len: usize) -> Result<Vec<String>> {
+        notes_dir::preview_lines(self.read(name)?.as_bytes(), count, max_len)
+    }
+
+    /// The frontmatter block of a note, if it has one.
+    fn frontmatter(&self, name: &Path) -> Result<Option<Frontmatter>> {
+        Ok(frontmatter::read(&mut self.read(name)?.as_bytes())?)
+    }
+}
+
+/// Notes stored as files in the configured notes directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsBackend {
+    config: Config,
+}
+
+impl FsBackend {
+    /// A backend for the notes directory of the given configuration.
+    pub fn new(config: Config) -> FsBackend {
+        FsBackend { config }
+    }
+}
+
+impl Backend for FsBackend {
+    fn list(&self) -> Result<Vec<PathBuf>> {
+        notes_dir::list(&self.config)
+    }
+
+    fn exists(&self, name: &Path) -> Result<bool> {
+        Ok(self.config.notes_dir()?.join(name).exists())
+    }
+
+    fn is_dir(&self, name: &Path) -> Result<bool> {
+        Ok(self.config.notes_dir()?.join(name).is_dir())
+    }
+
+    fn read(&self, name: &Path) -> Result<String> {
+        notes_dir::read_file(&self.config, name)
+    }
+
+    fn write(&self, name: &Path, contents: &str) -> Result<()> {
+        notes_dir::create_parent_dirs(&self.config, name)?;
+        notes_dir::write_file(&self.config, name, contents)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let notes_dir = self.config.notes_dir()?;
+        notes_dir::create_parent_dirs(&self.config, to)?;
+        let path = notes_dir.join(from);
+        std::fs::rename(&path, notes_dir.join(to)).context("renaming", path)?;
+        notes_dir::rename_in_listing(&self.config, from, to)
+    }
+
+    fn remove(&self, name: &Path) -> Result<()> {
+        notes_dir::rm_file(&self.config, name)
+    }
+
+    fn created(&self, name: &Path) -> Result<SystemTime> {
+        let path = self.config.notes_dir()?.join(name);
+        std::fs::metadata(&path)
+            .and_then(|metadata| metadata.created())
+            .context("reading", path)
+    }
+
+    fn modified(&self, name: &Path) -> Result<SystemTime> {
+        notes_dir::modified(&self.config, name)
+    }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<PathBuf>>>> {
+        Ok(Box::new(
+            notes_dir::walk(&self.config)?.map(|entry| entry.map(|(name, _)| name)),
+        ))
+    }
+
+    fn note_at(&self, index: usize) -> Result<PathBuf> {
+        notes_dir::file_at_index(&self.config, index)
+    }
+
+    fn title(&self, name: &Path, max_len: usize) -> Result<Option<String>> {
+        notes_dir::title(&self.config, name, max_len)
+    }
+
+    fn preview(&self, name: &Path, count: usize, max_len: usize) -> Result<Vec<String>> {
+        notes_dir::preview(&self.config, name, count, max_len)
+    }
+
+    fn frontmatter(&self, name: &Path) -> Result<Option<Frontmatter>> {
+        notes_dir::frontmatter(&self.config, name)
+    }
+}
+
+#[derive(Debug)]
+struct MemoryNote {
+    contents: String,
+    created: SystemTime,
+    modified: SystemTime,
+
+    /// The position of the note in the order notes were created, which is the listing order.
+    sequence: u64,
+}
+
+/// Notes stored in memory, and lost when the backend is dropped.
+///
+/// Notes are listed in the order they were created.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    notes: Mutex<BTreeMap<PathBuf, MemoryNote>>,
+    next_sequence: AtomicU64,
+}
+
+impl MemoryBackend {
+    /// An empty backend.
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+
+    fn notes(&self) -> std::sync::MutexGuard<'_, BTreeMap<PathBuf, MemoryNote>> {
+        self.notes.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn with_note<T, F: FnOnce(&MemoryNote) -> T>(&self, name: &Path, f: F) -> Result<T> {
+        self.notes().get(name).map(f).ok_or_else(|| not_found(name))
+    }
+}
+
+fn not_found(name: &Path) -> Error {
+    std::io::Error::new(
+        ErrorKind::NotFound,
+        format!("no note named {}", name.display()),
+    )
+    .into()
+}
+
+impl Backend for MemoryBackend {
+    fn list(&self) -> Result<Vec<PathBuf>> {
+        let notes = self.notes();
+        let mut names: Vec<_> = notes.keys().cloned().collect();
+        names.sort_by_key(|name| notes[name].sequence);
+        Ok(names)
+    }
+
+    fn exists(&self, name: &Path) -> Result<bool> {
+        Ok(self.notes().contains_key(name))
+    }
+
+    fn is_dir(&self, name: &Path) -> Result<bool> {
+        Ok(self
+            .notes()
+            .keys()
+            .any(|note| note != name && note.starts_with(name)))
+    }
+
+    fn read(&self, name: &Path) -> Result<String> {
+        self.with_note(name, |note| note.contents.clone())
+    }
+
+    fn write(&self, name: &Path, contents: &str) -> Result<()> {
+        let now = SystemTime::now();
+        let sequence = &self.next_sequence;
+        self.notes()
+            .entry(name.to_path_buf())
+            .and_modify(|note| {
+                note.contents = String::from(contents);
+                note.modified = now;
+            })
+            .or_insert_with(|| MemoryNote {
+                contents: String::from(contents),
+                created: now,
+                modified: now,
+                sequence: sequence.fetch_add(1, Ordering::Relaxed),
+            });
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut notes = self.notes();
+        if notes.contains_key(to) {
+            return Err(Error::FileExists {
+                path: to.to_path_buf(),
+            });
+        }
+        let note = notes.remove(from).ok_or_else(|| not_found(from))?;
+        notes.insert(to.to_path_buf(), note);
+        Ok(())
+    }
+
+    fn remove(&self, name: &Path) -> Result<()> {
+        self.notes()
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| not_found(name))
+    }
+
+    fn created(&self, name: &Path) -> Result<SystemTime> {
+        self.with_note(name, |note| note.created)
+    }
+
+    fn modified(&self, name: &Path) -> Result<SystemTime> {
+        self.with_note(name, |note| note.modified)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn memory_backend() {
+        let backend = MemoryBackend::new();
+        let (a, b) = (Path::new("b/a.md"), Path::new("a.md"));
+        backend.write(a, "# Title\n\nbody\n").unwrap();
+        backend.write(b, "other\n").unwrap();
+        assert_eq!(backend.list().unwrap(), &[a, b]);
+        assert!(backend.is_dir(Path::new("b")).unwrap());
+        assert!(!backend.is_dir(b).unwrap());
+
+        assert_eq!(backend.title(a, 80).unwrap().as_deref(), Some("Title"));
+        assert_eq!(backend.preview(a, 2, 80).unwrap(), &["# Title", "body"]);
+        assert_eq!(backend.note_at(1).unwrap(), b);
+        assert!(backend.note_at(2).is_err());
+
+        let c = Path::new("c.md");
+        assert!(backend.rename(a, b).is_err());
+        backend.rename(a, c).unwrap();
+        assert!(!backend.exists(a).unwrap());
+        assert_eq!(backend.read(c).unwrap(), "# Title\n\nbody\n");
+
+        backend.remove(c).unwrap();
+        assert!(backend.remove(c).is_err());
+        assert_eq!(backend.list().unwrap(), &[b]);
+    }
+}
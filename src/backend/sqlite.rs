@@ -0,0 +1,233 @@
+//! Notes stored in a SQLite database.
+
+use super::{not_found, Backend};
+use crate::error::*;
+use crate::notes_dir;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS notes (
+    name TEXT PRIMARY KEY NOT NULL,
+    contents TEXT NOT NULL,
+    title TEXT,
+    created INTEGER NOT NULL,
+    modified INTEGER NOT NULL
+);
+";
+
+/// Notes stored in a single SQLite database file.
+///
+/// Every change is a single transaction, so a note is never left half written. Each note's title
+/// is stored alongside it, so that listing notes does not mean reading all of them. Notes are
+/// listed in the order they were created.
+#[derive(Debug)]
+pub struct SqliteBackend {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteBackend {
+    /// Open the database at the given path, creating it and any missing parent directories if it
+    /// does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteBackend> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent).context("creating", parent)?;
+        }
+        SqliteBackend::with_connection(Connection::open(path)?)
+    }
+
+    /// A database kept in memory, and lost when the backend is dropped.
+    pub fn in_memory() -> Result<SqliteBackend> {
+        SqliteBackend::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<SqliteBackend> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteBackend {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn time(&self, column: &str, name: &Path) -> Result<SystemTime> {
+        let query = format!("SELECT {} FROM notes WHERE name = ?1", column);
+        self.conn()
+            .query_row(&query, params![key(name)], |row| row.get(0))
+            .optional()?
+            .map(from_nanos)
+            .ok_or_else(|| not_found(name))
+    }
+}
+
+/// The key a note is stored under: its name, with its components separated by `/` on every
+/// platform.
+fn key(name: &Path) -> String {
+    name.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn to_nanos(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or_default()
+}
+
+fn from_nanos(nanos: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos.max(0) as u64)
+}
+
+impl Backend for SqliteBackend {
+    fn list(&self) -> Result<Vec<PathBuf>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare("SELECT name FROM notes ORDER BY created, rowid")?;
+        let names = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .map(|name| Ok(PathBuf::from(name?)))
+            .collect();
+        names
+    }
+
+    fn exists(&self, name: &Path) -> Result<bool> {
+        Ok(self.conn().query_row(
+            "SELECT EXISTS(SELECT 1 FROM notes WHERE name = ?1)",
+            params![key(name)],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn is_dir(&self, name: &Path) -> Result<bool> {
+        Ok(self.conn().query_row(
+            "SELECT EXISTS(SELECT 1 FROM notes WHERE substr(name, 1, length(?1)) = ?1)",
+            params![format!("{}/", key(name))],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn read(&self, name: &Path) -> Result<String> {
+        self.conn()
+            .query_row(
+                "SELECT contents FROM notes WHERE name = ?1",
+                params![key(name)],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| not_found(name))
+    }
+
+    fn write(&self, name: &Path, contents: &str) -> Result<()> {
+        let title = notes_dir::title_line(contents.as_bytes(), usize::MAX)?;
+        self.conn().execute(
+            "INSERT INTO notes (name, contents, title, created, modified)
+             VALUES (?1, ?2, ?3, ?4, ?4)
+             ON CONFLICT (name) DO UPDATE SET
+                contents = excluded.contents,
+                title = excluded.title,
+                modified = excluded.modified",
+            params![key(name), contents, title, to_nanos(SystemTime::now())],
+        )?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut conn = self.conn();
+        let transaction = conn.transaction()?;
+        let taken: bool = transaction.query_row(
+            "SELECT EXISTS(SELECT 1 FROM notes WHERE name = ?1)",
+            params![key(to)],
+            |row| row.get(0),
+        )?;
+        if taken {
+            return Err(Error::FileExists {
+                path: to.to_path_buf(),
+            });
+        }
+
+        let renamed = transaction.execute(
+            "UPDATE notes SET name = ?2 WHERE name = ?1",
+            params![key(from), key(to)],
+        )?;
+        if renamed == 0 {
+            return Err(not_found(from));
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn remove(&self, name: &Path) -> Result<()> {
+        let removed = self
+            .conn()
+            .execute("DELETE FROM notes WHERE name = ?1", params![key(name)])?;
+        if removed == 0 {
+            return Err(not_found(name));
+        }
+        Ok(())
+    }
+
+    fn created(&self, name: &Path) -> Result<SystemTime> {
+        self.time("created", name)
+    }
+
+    fn modified(&self, name: &Path) -> Result<SystemTime> {
+        self.time("modified", name)
+    }
+
+    fn title(&self, name: &Path, max_len: usize) -> Result<Option<String>> {
+        let title: Option<String> = self
+            .conn()
+            .query_row(
+                "SELECT title FROM notes WHERE name = ?1",
+                params![key(name)],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| not_found(name))?;
+        Ok(title.map(|title| notes_dir::truncate(title, max_len)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sqlite_backend() {
+        let backend = SqliteBackend::in_memory().unwrap();
+        let (a, b) = (Path::new("b/a.md"), Path::new("a.md"));
+        backend.write(a, "# Title\n\nbody\n").unwrap();
+        backend.write(b, "other\n").unwrap();
+        assert_eq!(backend.list().unwrap(), &[a, b]);
+        assert!(backend.is_dir(Path::new("b")).unwrap());
+        assert!(!backend.is_dir(b).unwrap());
+
+        assert_eq!(backend.title(a, 80).unwrap().as_deref(), Some("Title"));
+        assert_eq!(backend.title(a, 4).unwrap().as_deref(), Some("T..."));
+        assert_eq!(backend.preview(a, 2, 80).unwrap(), &["# Title", "body"]);
+        assert_eq!(backend.note_at(1).unwrap(), b);
+
+        let created = backend.created(b).unwrap();
+        backend.write(b, "# Changed\n").unwrap();
+        assert_eq!(backend.created(b).unwrap(), created);
+        assert!(backend.modified(b).unwrap() >= created);
+        assert_eq!(backend.title(b, 80).unwrap().as_deref(), Some("Changed"));
+
+        let c = Path::new("c.md");
+        assert!(backend.rename(a, b).is_err());
+        backend.rename(a, c).unwrap();
+        assert!(!backend.exists(a).unwrap());
+        assert_eq!(backend.read(c).unwrap(), "# Title\n\nbody\n");
+
+        backend.remove(c).unwrap();
+        assert!(backend.remove(c).is_err());
+        assert!(backend.read(c).is_err());
+        assert_eq!(backend.list().unwrap(), &[b]);
+    }
+}
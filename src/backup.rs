@@ -0,0 +1,203 @@
+//! Automatic backups of notes, taken before they are edited or deleted.
+//!
+//! The backups of each note are kept in a directory named after the note's path under
+//! [`BACKUPS_DIR`], one file per backup, named after the time it was taken. Only the most recent
+//! [`Config::backup_count`] backups of each note are kept.
+
+use crate::config::Config;
+use crate::error::*;
+
+use chrono::{Local, NaiveDateTime};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The hidden subdirectory of the notes directory in which note backups are kept.
+pub const BACKUPS_DIR: &str = ".backups";
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d_%H-%M-%S%.3f";
+
+/// A backup of a note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    /// The path to the backup file.
+    pub path: PathBuf,
+
+    /// The local time at which the backup was taken.
+    pub time: NaiveDateTime,
+}
+
+fn backups_dir<P: AsRef<Path>>(config: &Config, path: P) -> Result<PathBuf> {
+    Ok(config.notes_dir()?.join(BACKUPS_DIR).join(path))
+}
+
+/// Back up a note before it is changed or deleted.
+///
+/// Nothing is done if backups are disabled, if the note does not exist or is blank, or if its
+/// contents are the same as its most recent backup. Returns the path to the new backup, if one
+/// was taken.
+pub fn backup_note<P: AsRef<Path>>(config: &Config, path: P) -> Result<Option<PathBuf>> {
+    if config.backup_count() == 0 {
+        return Ok(None);
+    }
+
+    let note = config.notes_dir()?.join(path.as_ref());
+    let contents = match fs::read(&note) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).context("reading", note),
+    };
+    if contents.iter().all(|b| b.is_ascii_whitespace()) {
+        return Ok(None);
+    }
+
+    let backups = backups(config, path.as_ref())?;
+    if let Some(latest) = backups.first() {
+        if fs::read(&latest.path).context("reading", &latest.path)? == contents {
+            return Ok(None);
+        }
+    }
+
+    let dir = backups_dir(config, path.as_ref())?;
+    fs::create_dir_all(&dir).context("creating", &dir)?;
+    let backup = dir.join(Local::now().format(TIMESTAMP_FORMAT).to_string());
+    fs::write(&backup, contents).context("writing", &backup)?;
+    debug!(
+        "Backed up {} to {}",
+        path.as_ref().display(),
+        backup.display()
+    );
+
+    for old in backups.iter().skip(config.backup_count() - 1) {
+        fs::remove_file(&old.path).context("removing", &old.path)?;
+    }
+
+    Ok(Some(backup))
+}
+
+/// List the backups of a note, newest first.
+pub fn backups<P: AsRef<Path>>(config: &Config, path: P) -> Result<Vec<Backup>> {
+    let dir = backups_dir(config, path)?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).context("reading", dir),
+    };
+
+    let mut backups = Vec::new();
+    for entry in entries {
+        let entry = entry.context("reading", &dir)?;
+        if !entry
+            .file_type()
+            .context("reading", entry.path())?
+            .is_file()
+        {
+            continue;
+        }
+
+        let time = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| NaiveDateTime::parse_from_str(name, TIMESTAMP_FORMAT).ok());
+        if let Some(time) = time {
+            backups.push(Backup {
+                path: entry.path(),
+                time,
+            });
+        }
+    }
+
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.time));
+    Ok(backups)
+}
+
+/// List the notes that have backups, including notes that have since been deleted.
+///
+/// The returned paths are relative to the notes directory.
+pub fn backed_up_notes(config: &Config) -> Result<Vec<PathBuf>> {
+    let mut notes = Vec::new();
+    let root = config.notes_dir()?.join(BACKUPS_DIR);
+    if root.is_dir() {
+        walk(&root, Path::new(""), &mut notes)?;
+    }
+    notes.sort();
+    Ok(notes)
+}
+
+fn walk(root: &Path, rel_dir: &Path, notes: &mut Vec<PathBuf>) -> Result<()> {
+    let mut has_backups = false;
+    let dir = root.join(rel_dir);
+    for entry in fs::read_dir(&dir).context("reading", &dir)? {
+        let entry = entry.context("reading", &dir)?;
+        if entry.file_type().context("reading", entry.path())?.is_dir() {
+            walk(root, &rel_dir.join(entry.file_name()), notes)?;
+        } else {
+            has_backups = true;
+        }
+    }
+
+    if has_backups {
+        notes.push(PathBuf::from(rel_dir));
+    }
+    Ok(())
+}
+
+/// Replace a note's contents with those of one of its backups.
+///
+/// The note's current contents are backed up first. The note is recreated if it was deleted.
+pub fn restore_backup<P: AsRef<Path>>(config: &Config, path: P, backup: &Backup) -> Result<()> {
+    let contents = fs::read(&backup.path).context("reading", &backup.path)?;
+    backup_note(config, path.as_ref())?;
+    let note = config.notes_dir()?.join(path.as_ref());
+    if let Some(parent) = note.parent() {
+        fs::create_dir_all(parent).context("creating", parent)?;
+    }
+    fs::write(&note, contents).context("writing", &note)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::temp::TempDir;
+
+    #[test]
+    fn backup_rotation() {
+        let temp = TempDir::new("backup-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let config = Config::default()
+            .with_notes_dir(dir.clone())
+            .with_backup_count(2);
+        let note = Path::new("sub/a.md");
+
+        assert_eq!(backup_note(&config, note).unwrap(), None);
+        fs::write(dir.join(note), "  \n").unwrap();
+        assert_eq!(backup_note(&config, note).unwrap(), None);
+
+        for contents in &["one", "two", "two", "three"] {
+            fs::write(dir.join(note), contents).unwrap();
+            backup_note(&config, note).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+
+        let saved = backups(&config, note).unwrap();
+        let contents: Vec<_> = saved
+            .iter()
+            .map(|backup| fs::read_to_string(&backup.path).unwrap())
+            .collect();
+        assert_eq!(contents, &["three", "two"]);
+        assert_eq!(backed_up_notes(&config).unwrap(), &[note]);
+
+        fs::write(dir.join(note), "four").unwrap();
+        restore_backup(&config, note, &saved[1]).unwrap();
+        assert_eq!(fs::read_to_string(dir.join(note)).unwrap(), "two");
+        assert_eq!(
+            fs::read_to_string(&backups(&config, note).unwrap()[0].path).unwrap(),
+            "four"
+        );
+
+        let disabled = config.with_backup_count(0);
+        assert_eq!(backup_note(&disabled, note).unwrap(), None);
+    }
+}
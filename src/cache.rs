@@ -0,0 +1,314 @@
+//! A persistent cache of note titles and previews, kept in `.newt/cache`.
+//!
+//! Listing a large notes directory otherwise means opening every note on every run. Each cache
+//! entry records the modification time of its note when it was read, and is discarded as soon as
+//! the note's modification time changes. Titles and previews are cached untruncated, so that one
+//! entry serves every width they are shown at.
+
+use crate::backend::Backend;
+use crate::config::Config;
+use crate::error::*;
+use crate::notes_dir::{self, DATA_DIR};
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+const HEADER: &str = "# newt preview cache, version 1";
+
+/// What is known about one note.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Entry {
+    /// The note's modification time, in nanoseconds since the Unix epoch.
+    mtime: u128,
+
+    /// The note's title, if it has been read.
+    title: Option<Option<String>>,
+
+    /// The number of lines asked for when the note's preview was read, and the lines found.
+    preview: Option<(usize, Vec<String>)>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    notes: HashMap<PathBuf, Entry>,
+    changed: bool,
+}
+
+/// The cached titles and previews of a notes directory.
+///
+/// The cache can be shared between threads; lookups take a lock only while reading or updating
+/// the entry, not while reading the note.
+#[derive(Debug, Default)]
+pub struct Previews {
+    entries: Mutex<Entries>,
+}
+
+fn cache_path(config: &Config) -> Result<PathBuf> {
+    Ok(config
+        .notes_dir()?
+        .join(DATA_DIR)
+        .join("cache")
+        .join("previews"))
+}
+
+fn mtime(backend: &dyn Backend, path: &Path) -> Result<u128> {
+    Ok(backend
+        .modified(path)?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default())
+}
+
+impl Previews {
+    /// Load the cache from disk, returning an empty cache if there is none or it cannot be read.
+    pub fn load(config: &Config) -> Result<Previews> {
+        let path = cache_path(config)?;
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err)
+                if err.kind() == std::io::ErrorKind::NotFound
+                    || err.kind() == std::io::ErrorKind::InvalidData =>
+            {
+                return Ok(Previews::default())
+            }
+            Err(err) => return Err(err).context("reading", path),
+        };
+
+        let notes = Previews::parse(&text).unwrap_or_else(|| {
+            debug!("Discarding unreadable preview cache");
+            HashMap::new()
+        });
+        Ok(Previews {
+            entries: Mutex::new(Entries {
+                notes,
+                changed: false,
+            }),
+        })
+    }
+
+    fn parse(text: &str) -> Option<HashMap<PathBuf, Entry>> {
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return None;
+        }
+
+        let mut notes = HashMap::new();
+        let mut current: Option<(PathBuf, Entry)> = None;
+        for line in lines {
+            let (kind, rest) = line.split_at(line.find(' ').unwrap_or(line.len()));
+            let rest = rest.strip_prefix(' ').unwrap_or(rest);
+            if kind == "N" {
+                let (time, path) = rest.split_at(rest.find(' ')?);
+                let entry = Entry {
+                    mtime: time.parse().ok()?,
+                    ..Entry::default()
+                };
+                if let Some((path, entry)) = current.replace((PathBuf::from(&path[1..]), entry)) {
+                    notes.insert(path, entry);
+                }
+                continue;
+            }
+
+            let entry = &mut current.as_mut()?.1;
+            match kind {
+                "T" => entry.title = Some(Some(String::from(rest))),
+                "E" => entry.title = Some(None),
+                "P" => entry.preview = Some((rest.parse().ok()?, Vec::new())),
+                "L" => entry.preview.as_mut()?.1.push(String::from(rest)),
+                _ => return None,
+            }
+        }
+
+        if let Some((path, entry)) = current {
+            notes.insert(path, entry);
+        }
+        Some(notes)
+    }
+
+    /// Write the cache to disk if anything in it has changed since it was loaded.
+    pub fn save(&self, config: &Config) -> Result<()> {
+        let mut entries = self.lock();
+        if !entries.changed {
+            return Ok(());
+        }
+
+        let mut notes: Vec<_> = entries.notes.iter().collect();
+        notes.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut text = format!("{}\n", HEADER);
+        for (path, entry) in notes {
+            text.push_str(&format!("N {} {}\n", entry.mtime, path.display()));
+            match &entry.title {
+                Some(Some(title)) => text.push_str(&format!("T {}\n", title)),
+                Some(None) => text.push_str("E\n"),
+                None => (),
+            }
+            if let Some((count, lines)) = &entry.preview {
+                text.push_str(&format!("P {}\n", count));
+                for line in lines {
+                    text.push_str(&format!("L {}\n", line));
+                }
+            }
+        }
+
+        let path = cache_path(config)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("creating", parent)?;
+        }
+        fs::write(&path, text).context("writing", &path)?;
+        entries.changed = false;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Drop the entries of notes that are not in the given list, such as removed notes.
+    pub fn retain(&self, notes: &[PathBuf]) {
+        let mut entries = self.lock();
+        let before = entries.notes.len();
+        let notes: HashSet<_> = notes.iter().collect();
+        entries.notes.retain(|path, _| notes.contains(path));
+        if entries.notes.len() != before {
+            entries.changed = true;
+        }
+    }
+
+    /// Get the cached entry for a note, if it is still up to date with the note's modification
+    /// time, along with that modification time.
+    fn cached(&self, backend: &dyn Backend, path: &Path) -> Result<(u128, Option<Entry>)> {
+        let time = mtime(backend, path)?;
+        let entry = self
+            .lock()
+            .notes
+            .get(path)
+            .filter(|entry| entry.mtime == time)
+            .cloned();
+        Ok((time, entry))
+    }
+
+    fn update<F: FnOnce(&mut Entry)>(&self, path: &Path, time: u128, f: F) {
+        let mut entries = self.lock();
+        let entry = entries.notes.entry(path.to_path_buf()).or_default();
+        if entry.mtime != time {
+            *entry = Entry {
+                mtime: time,
+                ..Entry::default()
+            };
+        }
+        f(entry);
+        entries.changed = true;
+    }
+
+    /// Get the title of a note, as [`Backend::title`] does, reading the note only if its title is
+    /// not cached.
+    pub fn title(
+        &self,
+        backend: &dyn Backend,
+        path: &Path,
+        max_len: usize,
+    ) -> Result<Option<String>> {
+        let (time, entry) = self.cached(backend, path)?;
+        let title = match entry.and_then(|entry| entry.title) {
+            Some(title) => title,
+            None => {
+                let title = backend.title(path, usize::MAX)?;
+                self.update(path, time, |entry| entry.title = Some(title.clone()));
+                title
+            }
+        };
+        Ok(title.map(|title| notes_dir::truncate(title, max_len)))
+    }
+
+    /// Get the preview of a note, as [`Backend::preview`] does, reading the note only if a preview
+    /// of at least as many lines is not cached.
+    pub fn preview(
+        &self,
+        backend: &dyn Backend,
+        path: &Path,
+        count: usize,
+        max_len: usize,
+    ) -> Result<Vec<String>> {
+        let (time, entry) = self.cached(backend, path)?;
+        let lines = match entry.and_then(|entry| entry.preview) {
+            Some((cached, lines)) if cached >= count => lines,
+            _ => {
+                let lines = backend.preview(path, count, usize::MAX)?;
+                self.update(path, time, |entry| {
+                    entry.preview = Some((count, lines.clone()))
+                });
+                lines
+            }
+        };
+        Ok(lines
+            .into_iter()
+            .take(count)
+            .map(|line| notes_dir::truncate(line, max_len))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::FsBackend;
+    use crate::util::temp::TempDir;
+
+    #[test]
+    fn cached_previews() {
+        let temp = TempDir::new("cache-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let config = Config::default().with_notes_dir(dir.clone());
+        let backend = FsBackend::new(config.clone());
+        let a = Path::new("a.md");
+        let b = Path::new("b.md");
+        fs::write(dir.join(a), "# Groceries\n\nmilk\neggs\n").unwrap();
+        fs::write(dir.join(b), "\n").unwrap();
+
+        let cache = Previews::load(&config).unwrap();
+        assert_eq!(
+            cache.title(&backend, a, 80).unwrap().as_deref(),
+            Some("Groceries")
+        );
+        assert_eq!(cache.title(&backend, b, 80).unwrap(), None);
+        assert_eq!(
+            cache.preview(&backend, a, 2, 80).unwrap(),
+            &["# Groceries", "milk"]
+        );
+        cache.save(&config).unwrap();
+
+        let cache = Previews::load(&config).unwrap();
+        assert!(!cache.lock().notes.is_empty());
+        assert_eq!(
+            cache.title(&backend, a, 6).unwrap().as_deref(),
+            Some("Gro...")
+        );
+        assert_eq!(cache.title(&backend, b, 80).unwrap(), None);
+        assert_eq!(cache.preview(&backend, a, 1, 80).unwrap(), &["# Groceries"]);
+        assert!(!cache.lock().changed);
+        assert_eq!(cache.preview(&backend, a, 3, 80).unwrap().len(), 3);
+        assert!(cache.lock().changed);
+
+        // Changing a note's modification time discards its entry.
+        let entry = cache.lock().notes[a].clone();
+        cache.lock().notes.get_mut(a).unwrap().mtime -= 1;
+        assert_eq!(
+            cache.title(&backend, a, 80).unwrap().as_deref(),
+            Some("Groceries")
+        );
+        assert_eq!(cache.lock().notes[a].mtime, entry.mtime);
+        assert_eq!(cache.lock().notes[a].preview, None);
+
+        cache.retain(&[a.to_path_buf()]);
+        cache.save(&config).unwrap();
+        let loaded = Previews::load(&config).unwrap();
+        assert_eq!(loaded.lock().notes.keys().collect::<Vec<_>>(), &[a]);
+
+        fs::write(cache_path(&config).unwrap(), "garbage").unwrap();
+        assert!(Previews::load(&config).unwrap().lock().notes.is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+//! Month calendars showing the days notes were written on.
+//!
+//! A note's day is the first date in its file name, written `YYYY-MM-DD` or `YYYYMMDD` as the
+//! default file name formats write them, or otherwise the day it was created.
+
+use crate::error::*;
+use crate::store::NoteStore;
+
+use chrono::{DateTime, Datelike, Local, NaiveDate};
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The width of each day's cell in a calendar: the day of the month and its number of notes.
+const CELL_WIDTH: usize = 7;
+
+/// The date in a note's file name, if it has one.
+pub fn file_name_date<P: AsRef<Path>>(name: P) -> Option<NaiveDate> {
+    let name = name.as_ref().file_name()?.to_str()?;
+    let starts = name
+        .char_indices()
+        .filter(|&(i, c)| c.is_ascii_digit() && !name[..i].ends_with(|c: char| c.is_ascii_digit()))
+        .map(|(i, _)| i);
+
+    for start in starts {
+        let rest = &name[start..];
+        let dashed = rest
+            .get(..10)
+            .and_then(|text| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok());
+        let compact = rest
+            .get(..8)
+            .filter(|text| text.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|text| NaiveDate::parse_from_str(text, "%Y%m%d").ok());
+        if let Some(date) = dashed.or(compact) {
+            return Some(date);
+        }
+    }
+    None
+}
+
+/// The number of notes written on each day, by the dates in their file names or the days they
+/// were created.
+pub fn note_counts(store: &NoteStore) -> Result<BTreeMap<NaiveDate, usize>> {
+    let mut counts = BTreeMap::new();
+    for name in store.list()? {
+        let date = match file_name_date(&name) {
+            Some(date) => date,
+            None => {
+                let note = store.note(&name);
+                let time = note.created().or_else(|_| note.modified())?;
+                DateTime::<Local>::from(time).date().naive_local()
+            }
+        };
+        *counts.entry(date).or_insert(0) += 1;
+    }
+    Ok(counts)
+}
+
+/// Parse a month given as `YYYY-MM`, or as a month number in the year of `today`. Returns the
+/// first day of the month.
+pub fn parse_month(text: &str, today: NaiveDate) -> Option<NaiveDate> {
+    let (year, month) = match text.split_once('-') {
+        Some((year, month)) => (year.parse().ok()?, month.parse().ok()?),
+        None => (today.year(), text.parse().ok()?),
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)
+}
+
+/// Render the calendar of the month starting on `first`, with the number of notes written on
+/// each day that has any. With `color`, those days are highlighted.
+pub fn render(first: NaiveDate, counts: &BTreeMap<NaiveDate, usize>, color: bool) -> String {
+    let next = if first.month() == 12 {
+        NaiveDate::from_ymd(first.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd(first.year(), first.month() + 1, 1)
+    };
+
+    let title = first.format("%B %Y").to_string();
+    let width = CELL_WIDTH * 7;
+    let mut res = format!("{:^width$}\n", title, width = width)
+        .trim_end()
+        .to_string();
+    res.push('\n');
+    for day in &["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"] {
+        res.push_str(&format!("{:>3}{:4}", day, ""));
+    }
+    res.truncate(res.trim_end().len());
+    res.push('\n');
+
+    let mut line = " ".repeat(CELL_WIDTH * first.weekday().num_days_from_monday() as usize);
+    let mut total = 0;
+    let mut date = first;
+    while date < next {
+        let count = counts.get(&date).copied().unwrap_or(0);
+        total += count;
+        let day = format!("{:>3}", date.day());
+        let day = if color && count > 0 {
+            format!("\x1b[1;7m{}\x1b[0m", day)
+        } else {
+            day
+        };
+        let count = if count > 0 {
+            format!("({})", count)
+        } else {
+            String::new()
+        };
+        line.push_str(&format!("{}{:4}", day, count));
+
+        if date.weekday().num_days_from_monday() == 6 {
+            res.push_str(line.trim_end());
+            res.push('\n');
+            line.clear();
+        }
+        date = date.succ();
+    }
+    if !line.trim().is_empty() {
+        res.push_str(line.trim_end());
+        res.push('\n');
+    }
+
+    res.push_str(&format!("\n{} note(s) this month\n", total));
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_name_dates() {
+        let date = NaiveDate::from_ymd(2024, 5, 1);
+        assert_eq!(file_name_date("2024-05-01_1.md"), Some(date));
+        assert_eq!(file_name_date("journal/2024-05-01.md"), Some(date));
+        assert_eq!(file_name_date("202405011030-standup.md"), Some(date));
+        assert_eq!(file_name_date("meeting-20240501.txt"), Some(date));
+        assert_eq!(file_name_date("2024-13-01.md"), None);
+        assert_eq!(file_name_date("ideas.md"), None);
+        assert_eq!(file_name_date("x12024050.md"), None);
+    }
+
+    #[test]
+    fn months() {
+        let today = NaiveDate::from_ymd(2024, 5, 17);
+        assert_eq!(
+            parse_month("2023-12", today),
+            Some(NaiveDate::from_ymd(2023, 12, 1))
+        );
+        assert_eq!(
+            parse_month("2", today),
+            Some(NaiveDate::from_ymd(2024, 2, 1))
+        );
+        assert_eq!(parse_month("13", today), None);
+        assert_eq!(parse_month("may", today), None);
+    }
+
+    #[test]
+    fn calendar() {
+        let mut counts = BTreeMap::new();
+        counts.insert(NaiveDate::from_ymd(2024, 5, 1), 2);
+        counts.insert(NaiveDate::from_ymd(2024, 5, 17), 1);
+        counts.insert(NaiveDate::from_ymd(2024, 6, 1), 5);
+        let cal = render(NaiveDate::from_ymd(2024, 5, 1), &counts, false);
+        let lines: Vec<_> = cal.lines().collect();
+        assert_eq!(lines[0].trim(), "May 2024");
+        assert_eq!(lines[1], " Mo     Tu     We     Th     Fr     Sa     Su");
+        assert_eq!(lines[2], "                1(2)   2      3      4      5");
+        assert_eq!(lines[4], " 13     14     15     16     17(1)  18     19");
+        assert_eq!(lines[6], " 27     28     29     30     31");
+        assert_eq!(lines[8], "3 note(s) this month");
+    }
+}
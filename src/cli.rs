@@ -1,12 +1,36 @@
 //! Command-line invocation and options.
 
-use crate::config::{self, Config};
+use crate::archive;
+use crate::backup;
+use crate::cache;
+use crate::calendar;
+use crate::clipboard;
+use crate::config::{self, Compat, Config, CreateNotesDir, NonInteractive, SearchCase, Storage};
+use crate::conflicts::{self, Conflict};
 use crate::edit;
 use crate::error::*;
+use crate::history;
+use crate::import;
+use crate::index;
+use crate::journal::{self, Change};
+use crate::logging::Level;
 use crate::notes_dir;
-use crate::util;
+use crate::rpc;
+use crate::session::Session;
+use crate::share;
+use crate::site;
+use crate::store::NoteStore;
+use crate::sync;
+use crate::util::{self, json::json, regex::Regex, term::Key};
+use crate::watch;
+use crate::wikilink;
 
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
 
 use structopt::StructOpt;
 
@@ -15,12 +39,21 @@ use structopt::StructOpt;
 pub enum Command {
     /// Create a new note. Default if no other command is specified.
     New {
-        /// File name for the created note. Generates a unique name by default.
+        /// File name for the created note, which may include subdirectories. Generates a unique
+        /// name by default.
         name: Option<String>,
+
+        /// Title for the note, used in the generated file name and as an initial heading.
+        #[structopt(short, long)]
+        title: Option<String>,
+
+        /// Fill the note with the text on the clipboard, rather than opening it in the editor.
+        #[structopt(long)]
+        from_clipboard: bool,
     },
 
     /// List current notes.
-    List,
+    List(ListOptions),
 
     /// View a note in the configured pager program.
     View {
@@ -34,10 +67,343 @@ pub enum Command {
         index: usize,
     },
 
+    /// Copy a note's contents to the clipboard.
+    Copy {
+        /// Index of the file, as displayed by the list command.
+        index: usize,
+    },
+
+    /// Publish a note to a paste service and print its URL.
+    ///
+    /// The note is given on standard input to the command set by the `share_command` option,
+    /// which should print the URL the note was published at. Without a share command, the note
+    /// is published as a secret GitHub gist, using the token set by the `gist_token` option or
+    /// `$GITHUB_TOKEN`.
+    Share {
+        /// Index of the file, as displayed by the list command.
+        index: usize,
+    },
+
+    /// Email a note, with its title as the subject.
+    ///
+    /// The note is sent by the command set by the `mail_command` option, by default `msmtp -t` or
+    /// `sendmail -t`, which must be set up to deliver mail.
+    Mail {
+        /// Index of the file, as displayed by the list command.
+        index: usize,
+
+        /// The address to send the note to. May be given more than once.
+        #[structopt(long, value_name = "ADDRESS", required = true, number_of_values = 1)]
+        to: Vec<String>,
+    },
+
+    /// Copy every note to a plain file in the given directory, keeping its name.
+    ///
+    /// This is how notes are taken out of SQLite storage. With `--site`, the notes are instead
+    /// rendered as a static HTML site, with an index page, a page for each tag, and wiki-style
+    /// links between notes resolved. With `--via-pandoc`, a single note is converted to PDF,
+    /// Word, or HTML by pandoc, which must be installed. No existing files are overwritten.
+    Export {
+        /// Render the notes as HTML pages rather than copying them.
+        #[structopt(long, conflicts_with = "via-pandoc")]
+        site: bool,
+
+        /// Convert the note with the given index with pandoc, rather than copying every note.
+        #[structopt(long, requires = "to")]
+        via_pandoc: bool,
+
+        /// The format to convert the note to.
+        #[structopt(
+            long,
+            value_name = "FORMAT",
+            possible_values = &["pdf", "docx", "html"],
+            requires = "via-pandoc"
+        )]
+        to: Option<String>,
+
+        /// The file to write the converted note to. Defaults to the note's file name, with the
+        /// format as its extension, in the current directory.
+        #[structopt(short, long, requires = "via-pandoc")]
+        output: Option<PathBuf>,
+
+        /// The directory to copy the notes into, which is created if it does not exist. With
+        /// `--via-pandoc`, the index of the note to convert, as displayed by the list command.
+        #[structopt(value_name = "DIR|INDEX")]
+        target: PathBuf,
+    },
+
+    /// Create notes from another note app's export.
+    ///
+    /// Each imported note keeps its title, tags, and creation time in its frontmatter, and its
+    /// modification time as the note file's own. Simplenote exports are read from their
+    /// `notes.json`, Evernote exports from a `.enex` file, and nvALT notes from the directory
+    /// they are kept in; nvALT tags are not imported. When a note of the same name already
+    /// exists, you are asked whether to import the note under another name, replace the existing
+    /// note, or skip it. Importing can be undone.
+    Import {
+        /// The app the notes were exported from.
+        #[structopt(
+            long,
+            value_name = "APP",
+            possible_values = &["simplenote", "enex", "nvalt"]
+        )]
+        from: String,
+
+        /// The exported file, or with `--from nvalt`, the notes directory.
+        path: PathBuf,
+    },
+
     /// Edit a note in the configured editor.
     Edit {
         /// Index of the file, as displayed by the list command.
         index: usize,
+
+        /// The line to open the note at.
+        ///
+        /// This is passed to the editor in its {line} placeholder, or as a +N argument to editors
+        /// that accept one, such as vim, nano, and emacs.
+        #[structopt(long, conflicts_with = "at")]
+        line: Option<usize>,
+
+        /// Open the note at the first line containing this text.
+        #[structopt(long)]
+        at: Option<String>,
+
+        /// Edit the note even if another newt process is editing it.
+        #[structopt(long)]
+        force: bool,
+    },
+
+    /// Move or rename a note within the notes directory.
+    ///
+    /// In an Obsidian vault (see the `compat` option), wiki-style links to the note in other
+    /// notes are updated to its new name.
+    Mv {
+        /// Index of the file, as displayed by the list command.
+        index: usize,
+
+        /// New file name for the note, or a subdirectory to move it into. Destinations ending in
+        /// a path separator are always treated as directories.
+        destination: String,
+    },
+
+    /// Concatenate several notes into one, separated by horizontal rules.
+    ///
+    /// The merged notes are appended to the destination note if it already exists.
+    Merge {
+        /// Indices of the files, as displayed by the list command.
+        #[structopt(required = true)]
+        indices: Vec<usize>,
+
+        /// File name for the merged note, which may include subdirectories. Generates a unique
+        /// name by default.
+        #[structopt(long)]
+        into: Option<String>,
+
+        /// Offer to delete the merged notes afterwards.
+        #[structopt(long)]
+        delete: bool,
+    },
+
+    /// Find the conflicted copies of notes left by sync services, and resolve them.
+    ///
+    /// Dropbox's "conflicted copy" and Syncthing's "sync-conflict" copies are found. For each,
+    /// the differences from its note are shown, and the copy can be discarded, kept in place of
+    /// the note, or merged into the note in the editor, with the lines that differ between
+    /// conflict markers. Resolving conflicts can be undone.
+    Conflicts {
+        /// Only list the conflicted copies, each followed by a tab and the note it is a copy of.
+        #[structopt(short, long)]
+        list: bool,
+    },
+
+    /// Rename every note whose path matches a regular expression.
+    ///
+    /// Each match in a note's path, relative to the notes directory, is replaced as in the
+    /// replace command, so `$1` stands for the text matched by the first capture group. The
+    /// renames are shown and confirmed before any are made. In an Obsidian vault, links to the
+    /// renamed notes are updated as with the mv command.
+    RenameAll {
+        /// The regular expression to match note paths against.
+        #[structopt(long)]
+        from: String,
+
+        /// The text to replace each match with.
+        #[structopt(long)]
+        to: String,
+
+        /// Only show the renames that would be made, without making them.
+        #[structopt(short = "n", long)]
+        dry_run: bool,
+    },
+
+    /// Copy a file into a note's attachments directory and link to it from the note.
+    Attach {
+        /// Index of the file, as displayed by the list command.
+        index: usize,
+
+        /// The file to attach.
+        file: PathBuf,
+    },
+
+    /// List the files attached to a note.
+    Attachments {
+        /// Index of the file, as displayed by the list command.
+        index: usize,
+    },
+
+    /// List the backups of a note, newest first.
+    ///
+    /// Notes are backed up automatically before they are edited or deleted. Without an index,
+    /// lists every note that has backups, including deleted notes.
+    Backups {
+        /// Index of the file, as displayed by the list command.
+        index: Option<usize>,
+    },
+
+    /// Restore a note from one of its backups.
+    ///
+    /// The note's current contents are backed up first, so a restore can itself be undone.
+    RestoreBackup {
+        /// Index of the file, as displayed by the list command, or the path of a deleted note
+        /// relative to the notes directory.
+        note: String,
+
+        /// The backup to restore, as numbered by the backups command.
+        #[structopt(default_value = "1")]
+        backup: usize,
+    },
+
+    /// List the saved versions of a note, newest first.
+    ///
+    /// A version is saved whenever Newt changes a note or its editor exits.
+    History {
+        /// Index of the file, as displayed by the list command.
+        index: usize,
+    },
+
+    /// Show the differences between two notes, or the changes made to a note since one of its
+    /// saved versions.
+    ///
+    /// The output is colored when written to a terminal.
+    Diff {
+        /// Index of the file, as displayed by the list command.
+        index: usize,
+
+        /// Index of a second file to compare the first against.
+        other: Option<usize>,
+
+        /// The version to compare against, as numbered by the history command. Defaults to the
+        /// latest version that differs from the note's current contents.
+        #[structopt(short, long, conflicts_with = "other")]
+        version: Option<usize>,
+    },
+
+    /// Search notes for lines containing the given text.
+    ///
+    /// Notes are listed most relevant first: those with more matches for their length, and those
+    /// modified more recently, rank higher. Long lines are shortened to the text around the
+    /// first match. Exits with status 1 if nothing matches.
+    Search {
+        /// The text to search for.
+        #[structopt(required_unless = "interactive")]
+        pattern: Option<String>,
+
+        /// Only search notes within this subdirectory of the notes directory.
+        #[structopt(long = "in", value_name = "DIR")]
+        folder: Option<PathBuf>,
+
+        /// Ignore case when matching.
+        #[structopt(short, long, conflicts_with_all = &["case-sensitive", "smart-case"])]
+        ignore_case: bool,
+
+        /// Match case exactly.
+        #[structopt(short = "s", long, conflicts_with = "smart-case")]
+        case_sensitive: bool,
+
+        /// Ignore case unless the search text contains an uppercase letter.
+        #[structopt(short = "S", long)]
+        smart_case: bool,
+
+        /// List results in directory order, rather than by relevance.
+        #[structopt(long)]
+        no_rank: bool,
+
+        /// Print each match as file:line:column:text, with the note's full path, as expected by
+        /// editors' quickfix lists.
+        #[structopt(long, conflicts_with_all = &["json", "files-with-matches", "count"])]
+        vimgrep: bool,
+
+        /// Print each match as a JSON object on its own line, with the fields "index", "note",
+        /// "path", "line", "column", and "text".
+        #[structopt(long, conflicts_with_all = &["files-with-matches", "count"])]
+        json: bool,
+
+        /// Only print the names of notes with matches.
+        #[structopt(short = "l", long, conflicts_with = "count")]
+        files_with_matches: bool,
+
+        /// Only print the number of matching lines in each note with matches.
+        #[structopt(short, long)]
+        count: bool,
+
+        /// Search interactively, updating the results as the search text is typed. Use the arrow
+        /// keys to select a result and Enter to edit the note at that line. The search text may
+        /// still be given to start from.
+        #[structopt(
+            short = "I",
+            long,
+            conflicts_with_all = &["vimgrep", "json", "files-with-matches", "count"]
+        )]
+        interactive: bool,
+    },
+
+    /// Replace text matching a regular expression across notes.
+    ///
+    /// Shows the changes to each note and asks before saving them. Patterns use the syntax of
+    /// the Rust regex crate. In the replacement, `$1` or `${1}` stands for the text matched by
+    /// the first capture group, and `$$` for a literal `$`; write `${1}` when the next character
+    /// is a letter, digit or underscore, since `$1a` names a group called `1a`.
+    Replace {
+        /// The regular expression to search for.
+        pattern: String,
+
+        /// The text to replace each match with.
+        replacement: String,
+
+        /// Only replace within these notes, by index as displayed by the list command. Defaults
+        /// to all notes.
+        #[structopt(long = "in", value_name = "INDEX")]
+        indices: Vec<usize>,
+
+        /// Match the pattern literally rather than as a regular expression.
+        #[structopt(short = "F", long)]
+        fixed_strings: bool,
+
+        /// Only show the changes that would be made, without saving them.
+        #[structopt(short = "n", long)]
+        dry_run: bool,
+    },
+
+    /// Open a note in its default application rather than the editor.
+    Open {
+        /// Index of the file, as displayed by the list command.
+        index: usize,
+    },
+
+    /// Run a command on one or more notes from within the notes directory.
+    ///
+    /// Any argument of the command that is exactly `{}` is replaced by the paths of the notes,
+    /// relative to the notes directory. If there is no such argument, the paths are appended to the
+    /// end of the command.
+    Exec {
+        /// Indices of the files, as displayed by the list command.
+        #[structopt(required = true)]
+        indices: Vec<usize>,
+
+        /// The command to run, following a `--` separator.
+        #[structopt(last = true, required = true)]
+        command: Vec<String>,
     },
 
     /// Delete a note from the notes directory.
@@ -46,13 +412,363 @@ pub enum Command {
         index: usize,
     },
 
+    /// Manage the search index.
+    Index {
+        /// Search index subcommand.
+        #[structopt(subcommand)]
+        command: IndexCommand,
+    },
+
+    /// Find notes with the same contents, and offer to delete or merge the copies.
+    ///
+    /// In each group of duplicates, the first note listed is kept.
+    Dedupe {
+        /// Also find notes that differ only in case, punctuation, and whitespace.
+        #[structopt(long)]
+        near: bool,
+    },
+
+    /// Delete all empty or whitespace-only notes from the notes directory.
+    Prune,
+
+    /// List notes with due dates, soonest first.
+    ///
+    /// Due dates are read from a note's `due` frontmatter field or its first `@due(YYYY-MM-DD)`
+    /// marker.
+    Due {
+        /// Only show notes whose due date has passed.
+        #[structopt(long, conflicts_with = "within")]
+        overdue: bool,
+
+        /// Only show notes due within the given time from now (e.g. 7d, 2w), including overdue
+        /// notes.
+        #[structopt(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+        within: Option<chrono::Duration>,
+    },
+
+    /// Print a month's calendar, with the number of notes written on each day.
+    ///
+    /// A note's day is the date in its file name, as YYYY-MM-DD or YYYYMMDD, or otherwise the
+    /// day it was created. Days with notes are highlighted when written to a terminal.
+    Cal {
+        /// The month to show, as YYYY-MM, or a month number in this year. Defaults to this month.
+        #[structopt(parse(try_from_str = parse_month))]
+        month: Option<chrono::NaiveDate>,
+    },
+
+    /// Append a timestamped entry to today's daily note, creating it if necessary.
+    Log {
+        /// The text of the entry.
+        #[structopt(required = true)]
+        message: Vec<String>,
+    },
+
+    /// Edit the scratch note, a single reusable note for throwaway thoughts.
+    Scratch {
+        /// Wipe the scratch note's contents instead of editing it.
+        #[structopt(long)]
+        clear: bool,
+    },
+
+    /// Undo the latest change made by new, mv, rename-all, rm, tidy, or replace.
+    ///
+    /// Changes are recorded in a journal in the notes directory. Each run undoes one more, so long
+    /// as the notes involved have not been changed since in ways that prevent it.
+    Undo,
+
+    /// Archive or delete notes that have not been modified for a given time.
+    ///
+    /// Archived notes are moved into the `archive` subdirectory of the notes directory.
+    Tidy {
+        /// Act on notes last modified longer ago than this (e.g. 90d, 12w).
+        #[structopt(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+        older_than: chrono::Duration,
+
+        /// Move old notes into the archive directory. This is the default.
+        #[structopt(long, conflicts_with = "delete")]
+        archive: bool,
+
+        /// Delete old notes instead of archiving them.
+        #[structopt(long)]
+        delete: bool,
+    },
+
+    /// Inspect or manage the Newt configuration.
+    Config {
+        /// Configuration subcommand.
+        #[structopt(subcommand)]
+        command: ConfigCommand,
+    },
+
+    /// Query or update a note's frontmatter metadata.
+    Meta {
+        /// Metadata subcommand.
+        #[structopt(subcommand)]
+        command: MetaCommand,
+    },
+
     /// Print the canonicalized path to the configured notes directory.
     NotesDir,
+
+    /// Open the notes directory in the system file manager.
+    OpenDir,
+
+    /// Start an interactive shell in the notes directory.
+    ///
+    /// The shell is the configured one, by default `$SHELL`, and `NEWT_NOTES_DIR` is set in its
+    /// environment.
+    Shell,
+
+    /// Serve the notes as web pages, for reading in a browser on this machine.
+    ///
+    /// The note list and a search box are shown at the root of the site, and each note is
+    /// rendered from Markdown. Other programs can list, read, create, replace, and delete notes
+    /// as JSON through `/notes` and `/notes/<name>`. Requires Newt to be built with the `serve`
+    /// feature.
+    Serve {
+        /// The port to listen on.
+        #[structopt(short, long, default_value = "8080")]
+        port: u16,
+    },
+
+    /// Mirror the notes directory with a directory on another machine, using rsync over SSH.
+    ///
+    /// Files created or changed on either side are copied to the other, the newer copy winning
+    /// when both have changed, and files deleted from either side since the last sync are deleted
+    /// from the other. Each file replaced or deleted is kept in `.newt/sync-backups` on the side it
+    /// was removed from. rsync must be installed on both machines.
+    Sync {
+        /// The directory to sync with, as host:dir or user@host:dir. Defaults to the
+        /// `sync_remote` option.
+        #[structopt(long, value_name = "REMOTE")]
+        rsync: Option<String>,
+
+        /// Only show the files that would be copied and deleted, without changing anything.
+        #[structopt(short = "n", long)]
+        dry_run: bool,
+    },
+
+    /// Back up the whole notes directory to a timestamped archive.
+    ///
+    /// The archive is a tar.gz file, or a zip file on Windows, written to the directory set by the
+    /// `backup_dir` option, or the current directory, unless --out or --to is given. It is read
+    /// back in full once written, to check that it is intact. An existing file is never
+    /// overwritten, and an archive written inside the notes directory leaves out itself and any
+    /// other backups next to it.
+    ///
+    /// With --to, the archive is uploaded to S3 with the AWS command-line tool, `aws`, which finds
+    /// credentials in its usual environment variables and configuration files; set
+    /// `AWS_ENDPOINT_URL` to use another S3-compatible object store. Requires Newt to be built
+    /// with the `s3` feature.
+    Backup {
+        /// The file to write the archive to.
+        #[structopt(long, value_name = "FILE", conflicts_with = "to")]
+        out: Option<PathBuf>,
+
+        /// Where to upload the archive, as s3://bucket/prefix.
+        #[structopt(long, value_name = "URL")]
+        to: Option<String>,
+
+        /// Backup subcommand.
+        #[structopt(subcommand)]
+        command: Option<BackupCommand>,
+    },
+
+    /// Print notes as they are created, modified, or deleted, until interrupted.
+    ///
+    /// Each change is printed on a line of its own, such as `modified ideas.md`. Changes are
+    /// noticed as they happen, through inotify on Linux, FSEvents on macOS, kqueue on the BSDs,
+    /// and ReadDirectoryChangesW on Windows. Where the notes directory cannot be watched, it is
+    /// checked once per interval instead.
+    Watch {
+        /// Print each change as a JSON object on its own line, with the fields "event", "note",
+        /// and "time".
+        #[structopt(long)]
+        json: bool,
+
+        /// Check the notes directory at least this often (e.g. 5s, 1m).
+        #[structopt(
+            long,
+            value_name = "DURATION",
+            default_value = "5s",
+            parse(try_from_str = parse_duration)
+        )]
+        interval: chrono::Duration,
+    },
+
+    /// Any other command is run as an external `newt-<command>` program found on `$PATH`.
+    #[structopt(external_subcommand)]
+    External(Vec<String>),
+}
+
+/// Options for the list command.
+#[derive(Debug, Clone, StructOpt)]
+pub struct ListOptions {
+    /// Only show notes within this subdirectory of the notes directory.
+    pub folder: Option<PathBuf>,
+
+    /// Show the first N non-empty lines of each note beneath its entry. Overrides the
+    /// preview_lines setting.
+    #[structopt(long, value_name = "N")]
+    pub preview_lines: Option<usize>,
+
+    /// Omit notes that are empty or contain only whitespace.
+    #[structopt(long)]
+    pub non_empty: bool,
+
+    /// Only show notes at most N subdirectories deep, counting from the folder if one is given.
+    #[structopt(long, value_name = "N", conflicts_with = "flat")]
+    pub depth: Option<usize>,
+
+    /// Only show notes directly inside the notes directory. Equivalent to `--depth 0`.
+    #[structopt(long)]
+    pub flat: bool,
+
+    /// Show notes as a tree following the subdirectory structure.
+    #[structopt(long)]
+    pub tree: bool,
+}
+
+impl ListOptions {
+    fn shows(&self, name: &Path) -> bool {
+        let name = match &self.folder {
+            Some(folder) => match name.strip_prefix(folder) {
+                Ok(rest) => rest,
+                Err(_) => return false,
+            },
+            None => name,
+        };
+
+        let depth = notes_dir::depth(name);
+        if self.flat {
+            depth == 0
+        } else {
+            self.depth.map(|max| depth <= max).unwrap_or(true)
+        }
+    }
+}
+
+/// Metadata subcommand.
+#[derive(Debug, Clone, StructOpt)]
+pub enum MetaCommand {
+    /// Print a metadata field, or the whole frontmatter block if no key is given.
+    ///
+    /// List values are printed one item per line.
+    Get {
+        /// Index of the file, as displayed by the list command.
+        index: usize,
+
+        /// The metadata field to print.
+        key: Option<String>,
+    },
+
+    /// Set a metadata field, adding a frontmatter block to the note if necessary.
+    Set {
+        /// Index of the file, as displayed by the list command.
+        index: usize,
+
+        /// The metadata field to set.
+        key: String,
+
+        /// The new value. Lists may be given in YAML flow style, e.g. "[one, two]".
+        value: String,
+    },
+}
+
+/// Backup subcommand.
+#[derive(Debug, Clone, StructOpt)]
+pub enum BackupCommand {
+    /// List the backups stored at an s3://bucket/prefix location, oldest first.
+    List {
+        /// Where the backups are stored.
+        #[structopt(value_name = "URL")]
+        location: String,
+    },
+
+    /// Unpack a backup into the notes directory, or another directory.
+    ///
+    /// The backup is checked to be intact first. No existing files are overwritten; nothing is
+    /// unpacked if the backup is damaged or if any of its files already exists.
+    Restore {
+        /// The backup's archive file, or its location in S3 as s3://bucket/prefix/name, as shown
+        /// by the list subcommand.
+        #[structopt(value_name = "BACKUP")]
+        backup: String,
+
+        /// The directory to unpack the backup into. Defaults to the notes directory.
+        #[structopt(long, value_name = "DIR")]
+        into: Option<PathBuf>,
+    },
+}
+
+/// Configuration subcommand.
+#[derive(Debug, Clone, StructOpt)]
+pub enum ConfigCommand {
+    /// Check a configuration file for errors, exiting with a failure status if any are found.
+    ///
+    /// Values are validated as well as parsed: the notes directory must exist and an editor must
+    /// be available. Configured commands that are not found on $PATH produce warnings.
+    Check {
+        /// The configuration file to check. Defaults to the file Newt would use.
+        path: Option<PathBuf>,
+    },
+
+    /// Write a fully commented default configuration file.
+    ///
+    /// The file is written to $XDG_CONFIG_HOME/newt/config, or $HOME/.config/newt/config if
+    /// XDG_CONFIG_HOME is unset.
+    Init {
+        /// Write the file to this path instead.
+        path: Option<PathBuf>,
+
+        /// Overwrite the file if it already exists.
+        #[structopt(long)]
+        force: bool,
+    },
+}
+
+/// Search index subcommand.
+#[derive(Debug, Clone, StructOpt)]
+pub enum IndexCommand {
+    /// Rebuild the search index from scratch.
+    ///
+    /// The index is kept up to date automatically, so this should only be needed if it has been
+    /// damaged.
+    Rebuild,
+}
+
+impl Command {
+    /// Whether the command works however notes are stored, rather than only on note files.
+    fn supports_any_storage(&self) -> bool {
+        matches!(
+            self,
+            Command::New { .. }
+                | Command::List(_)
+                | Command::View { .. }
+                | Command::Cat { .. }
+                | Command::Copy { .. }
+                | Command::Share { .. }
+                | Command::Cal { .. }
+                | Command::Mail { .. }
+                | Command::Export { .. }
+                | Command::Import { .. }
+                | Command::Edit { .. }
+                | Command::Mv { .. }
+                | Command::Rm { .. }
+                | Command::Config { .. }
+                | Command::Serve { .. }
+                | Command::NotesDir
+        )
+    }
 }
 
 impl Default for Command {
     fn default() -> Self {
-        Command::New { name: None }
+        Command::New {
+            name: None,
+            title: None,
+            from_clipboard: false,
+        }
     }
 }
 
@@ -75,9 +791,39 @@ pub struct Options {
     #[structopt(short, long)]
     pub yes: bool,
 
-    /// Print verbose debugging output.
-    #[structopt(long, short)]
-    pub verbose: bool,
+    /// Never ask for input: give prompts their default answer, and fail if they have none.
+    #[structopt(long)]
+    pub no_input: bool,
+
+    /// Answer requests from editor plugins, as line-delimited JSON-RPC on standard input and
+    /// output, instead of running a command.
+    #[structopt(long)]
+    pub rpc: bool,
+
+    /// Show what rm, tidy, replace, rename-all, sync, and import would do, without changing any files.
+    #[structopt(long)]
+    pub dry_run: bool,
+
+    /// Log more detail: -v for progress, -vv for debugging, -vvv for tracing.
+    #[structopt(long, short, parse(from_occurrences))]
+    pub verbose: u64,
+
+    /// Print no warnings.
+    #[structopt(long, short, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Never pipe long output through the pager.
+    #[structopt(long)]
+    pub no_pager: bool,
+
+    /// Fail, with the program's exit code, if the editor, pager, or another program run by newt
+    /// fails.
+    #[structopt(long)]
+    pub strict: bool,
+
+    /// List and index files of any extension, ignoring the note_extensions setting.
+    #[structopt(long, global = true)]
+    pub all_files: bool,
 
     /// Subcommand.
     #[structopt(subcommand)]
@@ -85,6 +831,26 @@ pub struct Options {
 }
 
 impl Options {
+    /// The session for these options' flags.
+    pub fn session(&self) -> Session {
+        let level = if self.quiet {
+            Level::Off
+        } else {
+            Level::from_verbosity(self.verbose)
+        };
+        let session = Session::new()
+            .with_yes(self.yes)
+            .with_dry_run(self.dry_run)
+            .with_log_level(level);
+        if self.no_input {
+            session
+                .with_interactive(false)
+                .with_non_interactive(NonInteractive::Default)
+        } else {
+            session
+        }
+    }
+
     /// Resolve the Newt configuration for these options.
     pub fn config(&self) -> Result<Config> {
         if let Some(path) = &self.config {
@@ -96,119 +862,2358 @@ impl Options {
             config
                 .with_notes_dir(self.notes_dir.clone())
                 .with_editor(self.editor.clone())
+                .with_no_pager(self.no_pager)
+                .with_strict_editor(if self.strict { Some(true) } else { None })
+                .with_all_files(self.all_files)
         })
     }
 }
 
-fn new(config: &Config, name: Option<String>) -> Result<()> {
-    let name = name
-        .map(|n| Ok(PathBuf::from(n)))
-        .unwrap_or_else(|| notes_dir::new_file_name(&config))?;
-    let status = edit::edit_note(&config, &name)?;
-    if !status.success() {
-        eprintln!("Warning: editor process returned with status {}", status);
-    }
-    Ok(())
+fn parse_duration(text: &str) -> std::result::Result<chrono::Duration, String> {
+    util::parse_duration(text).ok_or_else(|| format!("invalid duration {:?}", text))
 }
 
-fn list(config: &Config) -> Result<()> {
-    let files = notes_dir::list(config)?;
-    let digits_space = util::digits(files.len()) + 1;
+fn parse_month(text: &str) -> std::result::Result<chrono::NaiveDate, String> {
+    let today = chrono::Local::today().naive_local();
+    calendar::parse_month(text, today).ok_or_else(|| format!("invalid month {:?}", text))
+}
 
-    let first_lines = files
-        .iter()
-        .map(|name| {
-            let name_space = name.display().to_string().chars().count() + 3;
-            notes_dir::first_line(config, name, 80 - name_space - digits_space)
-        })
-        .collect::<Result<Vec<_>>>()?;
+fn new(
+    session: &Session,
+    store: &NoteStore,
+    name: Option<String>,
+    title: Option<String>,
+) -> Result<()> {
+    let name = store.new_name(name.as_deref(), title.as_deref())?;
+    let initial = if store.backend().exists(&name)? {
+        None
+    } else {
+        Some(store.create(&name, title.as_deref())?)
+    };
 
-    for (i, (name, line)) in files.iter().zip(first_lines.iter()).enumerate() {
-        println!(
-            "{} {} - {}",
-            i,
-            name.display(),
-            line.as_deref().unwrap_or("<empty>")
-        );
+    let status = edit::edit_stored_note(store, &name, None)?;
+    if let Some(initial) = initial {
+        discard_if_unchanged(session, store, &name, &initial)?;
+        if store.backend().exists(&name)? {
+            let change = Change::Created { path: name.clone() };
+            journal::record(store.config(), "new", vec![change])?;
+        }
     }
-
-    Ok(())
+    edit::check_status(store.config(), "editor", status)
 }
 
-fn view(config: &Config, index: usize) -> Result<()> {
-    let file = notes_dir::file_at_index(config, index)?;
-    let status = edit::view_note(config, &file)?;
-    if !status.success() {
-        eprintln!("Warning: pager process returned with status {}", status);
+fn new_from_clipboard(
+    session: &Session,
+    store: &NoteStore,
+    name: Option<String>,
+    title: Option<String>,
+) -> Result<()> {
+    let text = clipboard::paste()?;
+    if text.trim().is_empty() {
+        return Err(Error::ClipboardEmpty);
     }
-    Ok(())
-}
 
-fn cat(config: &Config, index: usize) -> Result<()> {
-    let file = notes_dir::file_at_index(config, index)?;
-    notes_dir::cat_file(config, file, &mut std::io::stdout())
-}
-
-fn edit(config: &Config, index: usize) -> Result<()> {
-    let file = notes_dir::file_at_index(config, index)?;
-    let status = edit::edit_note(config, &file)?;
-    if !status.success() {
-        eprintln!("Warning: editor process returned with status {}", status);
+    let name = store.new_name(name.as_deref(), title.as_deref())?;
+    let mut contents = store.create(&name, title.as_deref())?;
+    contents.push_str(&text);
+    if !contents.ends_with('\n') {
+        contents.push('\n');
     }
+    store.write(&name, &contents)?;
+    journal::record(
+        store.config(),
+        "new",
+        vec![Change::Created { path: name.clone() }],
+    )?;
+    writeln!(session.output(), "Created {}", name.display())?;
     Ok(())
 }
 
-fn rm(config: &Config, index: usize) -> Result<()> {
-    let file = notes_dir::file_at_index(config, index)?;
-    let file_name = file.display();
-    let first_line = notes_dir::first_line(config, &file, 77)?;
+/// Offer to remove a freshly created note if it was left empty or unchanged from its initial
+/// contents.
+fn discard_if_unchanged(
+    session: &Session,
+    store: &NoteStore,
+    name: &Path,
+    initial: &str,
+) -> Result<()> {
+    if !store.backend().exists(name)? {
+        return Ok(());
+    }
 
-    let prompt = if let Some(line) = first_line {
-        format!("Remove file {} with first line:\n\"{}\"?", file_name, line)
-    } else {
-        format!("Remove empty file {}?", file_name)
+    let config = store.config();
+    let contents = store.read(name)?;
+    if contents != initial && contents.chars().any(|c| !c.is_whitespace()) {
+        return Ok(());
+    }
+
+    let discard = match config.discard_empty() {
+        Some(discard) => discard,
+        None => {
+            let prompt = format!("Note {} is empty. Remove it?", name.display());
+            session.prompt(&prompt, Some(true), None, None)?
+        }
     };
-    let yes_response = Some("Removing file");
-    let no_response = Some("Cancelling");
 
-    if util::prompt(&prompt, Some(false), yes_response, no_response)? {
-        notes_dir::rm_file(config, &file)?;
+    if discard {
+        writeln!(session.output(), "Removing empty note {}", name.display())?;
+        store.delete(name)?;
     }
 
     Ok(())
 }
 
-fn notes_dir(config: &Config) -> Result<()> {
-    let path = config.notes_dir()?;
-    println!("{}", path.canonicalize()?.display());
-    Ok(())
-}
+fn list(session: &Session, store: &NoteStore, options: &ListOptions) -> Result<()> {
+    let config = store.config();
+    if let Some(age) = config.tidy_after() {
+        if config.storage() == Storage::Files {
+            tidy(session, config, age, false)?;
+        }
+    }
 
-/// Execute the given command with the given configuration.
-pub fn execute(command: Command, config: Config) -> Result<()> {
-    match command {
-        Command::New { name } => new(&config, name),
-        Command::List => list(&config),
-        Command::View { index } => view(&config, index),
-        Command::Cat { index } => cat(&config, index),
-        Command::Edit { index } => edit(&config, index),
-        Command::Rm { index } => rm(&config, index),
-        Command::NotesDir => notes_dir(&config),
+    let files = store.list()?;
+    notes_dir::save_listing(config, &files)?;
+    let digits_space = util::digits(files.len()) + 1;
+    let previews = Arc::new(cache::Previews::load(config)?);
+    previews.retain(&files);
+    let shown: Vec<_> = files
+        .into_iter()
+        .enumerate()
+        .filter(|(_, name)| options.shows(name))
+        .collect();
+    let preview_lines = options.preview_lines.or_else(|| config.preview_lines());
+
+    // Reading the notes' previews and titles dominates the running time for large notebooks, so
+    // it is done in parallel, and only for the notes not in the cache.
+    let entries = {
+        let store = store.clone();
+        let options = options.clone();
+        let previews = Arc::clone(&previews);
+        util::par_map(shown, move |(i, name)| {
+            list_entry(
+                &store,
+                &options,
+                &previews,
+                preview_lines,
+                digits_space,
+                i,
+                name,
+            )
+        })
+    };
+    previews.save(config)?;
+
+    if options.tree {
+        let mut root = TreeDir::default();
+        for entry in entries {
+            if let Some((rel, lines)) = entry? {
+                root.insert(&rel, lines);
+            }
+        }
+
+        let mut output = match &options.folder {
+            Some(folder) => format!("{}\n", folder.display()),
+            None => String::from(".\n"),
+        };
+        root.render("", &" ".repeat(digits_space), &mut output);
+        return print_paged(session, config, &output);
+    }
+
+    let mut output = String::new();
+    for entry in entries {
+        if let Some((_, lines)) = entry? {
+            for line in lines {
+                output.push_str(&line);
+                output.push('\n');
+            }
+        }
     }
+
+    print_paged(session, config, &output)
 }
 
-/// Run the Newt CLI.
-pub fn run() -> Result<()> {
-    let options = Options::from_args();
+/// Render the lines of the list command's output for one note, returning them with the path the
+/// note is listed under, or `None` if the note should not be shown.
+fn list_entry(
+    store: &NoteStore,
+    options: &ListOptions,
+    previews: &cache::Previews,
+    preview_lines: Option<usize>,
+    digits_space: usize,
+    i: usize,
+    name: PathBuf,
+) -> Result<Option<(PathBuf, Vec<String>)>> {
+    let backend = store.backend();
+    if options.tree {
+        let rel = options
+            .folder
+            .as_ref()
+            .and_then(|folder| name.strip_prefix(folder).ok())
+            .unwrap_or(&name)
+            .to_path_buf();
+        let file_name = rel.file_name().unwrap_or_default().to_string_lossy();
+        let indent = 4 * (notes_dir::depth(&rel) + 1) + digits_space;
 
-    if options.verbose {
-        crate::debug::verbose(true);
-    }
+        let lines = if let Some(count) = preview_lines {
+            let mut lines = previews.preview(
+                backend,
+                &name,
+                count,
+                80usize.saturating_sub(indent + 2).max(10),
+            )?;
+            if options.non_empty && lines.is_empty() {
+                return Ok(None);
+            }
+            lines.insert(0, format!("{} {}", i, file_name));
+            lines
+        } else {
+            let width = 80usize.saturating_sub(indent + file_name.chars().count() + 3);
+            let title = previews.title(backend, &name, width.max(10))?;
+            if options.non_empty && title.is_none() {
+                return Ok(None);
+            }
+            vec![format!(
+                "{} {} - {}",
+                i,
+                file_name,
+                title.as_deref().unwrap_or("<empty>")
+            )]
+        };
 
-    if options.yes {
-        crate::util::set_yes(true);
+        return Ok(Some((rel, lines)));
     }
 
-    let config = options.config()?;
-    execute(options.command.unwrap_or_default(), config)
+    let lines = if let Some(count) = preview_lines {
+        let indent = " ".repeat(digits_space + 2);
+        let preview = previews.preview(
+            backend,
+            &name,
+            count,
+            80usize.saturating_sub(indent.len()).max(10),
+        )?;
+        if options.non_empty && preview.is_empty() {
+            return Ok(None);
+        }
+
+        let mut lines = vec![format!("{} {}", i, name.display())];
+        lines.extend(
+            preview
+                .into_iter()
+                .map(|line| format!("{}{}", indent, line)),
+        );
+        lines
+    } else {
+        let name_space = name.display().to_string().chars().count() + 3;
+        let title = previews.title(
+            backend,
+            &name,
+            80usize.saturating_sub(name_space + digits_space).max(10),
+        )?;
+        if options.non_empty && title.is_none() {
+            return Ok(None);
+        }
+
+        vec![format!(
+            "{} {} - {}",
+            i,
+            name.display(),
+            title.as_deref().unwrap_or("<empty>")
+        )]
+    };
+
+    Ok(Some((name, lines)))
+}
+
+/// A directory in the tree view of the list command.
+#[derive(Debug, Default)]
+struct TreeDir {
+    /// The rendered lines for each note directly in this directory, in listing order.
+    notes: Vec<Vec<String>>,
+    dirs: BTreeMap<String, TreeDir>,
+}
+
+impl TreeDir {
+    fn insert(&mut self, name: &Path, lines: Vec<String>) {
+        let mut dir = self;
+        for component in name.parent().into_iter().flatten() {
+            dir = dir
+                .dirs
+                .entry(component.to_string_lossy().into_owned())
+                .or_default();
+        }
+        dir.notes.push(lines);
+    }
+
+    fn render(&self, prefix: &str, indent: &str, output: &mut String) {
+        let count = self.notes.len() + self.dirs.len();
+        let mut entries = 0..count;
+
+        for lines in &self.notes {
+            let (branch, cont) = tree_branch(entries.next() == Some(count - 1));
+            let mut lines = lines.iter();
+            if let Some(first) = lines.next() {
+                output.push_str(&format!("{}{}{}\n", prefix, branch, first));
+            }
+            for line in lines {
+                output.push_str(&format!("{}{}{}  {}\n", prefix, cont, indent, line));
+            }
+        }
+
+        for (name, dir) in &self.dirs {
+            let (branch, cont) = tree_branch(entries.next() == Some(count - 1));
+            output.push_str(&format!("{}{}{}/\n", prefix, branch, name));
+            dir.render(&format!("{}{}", prefix, cont), indent, output);
+        }
+    }
+}
+
+fn tree_branch(last: bool) -> (&'static str, &'static str) {
+    if last {
+        ("└── ", "    ")
+    } else {
+        ("├── ", "│   ")
+    }
+}
+
+/// Print command output, piping it through the pager if it won't fit on the terminal.
+fn print_paged(session: &Session, config: &Config, output: &str) -> Result<()> {
+    let page = session.output_is_stdout()
+        && !config.no_pager()
+        && util::term::stdout_is_tty()
+        && util::term::height()
+            .map(|height| output.lines().count() >= height)
+            .unwrap_or(false);
+
+    if page {
+        let status = edit::page_text(config, output)?;
+        edit::check_status(config, "pager", status)?;
+    } else {
+        write!(session.output(), "{}", output)?;
+    }
+
+    Ok(())
+}
+
+fn view(store: &NoteStore, index: usize) -> Result<()> {
+    let file = store.note_at(index)?;
+    let status = match store.config().storage() {
+        Storage::Files => edit::view_note(store.config(), &file)?,
+        _ => edit::page_text(store.config(), &store.read(&file)?)?,
+    };
+    edit::check_status(store.config(), "pager", status)?;
+    Ok(())
+}
+
+fn cat(session: &Session, store: &NoteStore, index: usize) -> Result<()> {
+    let file = store.note_at(index)?;
+    match store.config().storage() {
+        Storage::Files => notes_dir::cat_file(store.config(), file, &mut *session.output()),
+        _ => {
+            write!(session.output(), "{}", store.read(file)?)?;
+            Ok(())
+        }
+    }
+}
+
+fn copy(session: &Session, store: &NoteStore, index: usize) -> Result<()> {
+    let file = store.note_at(index)?;
+    clipboard::copy(&store.read(&file)?)?;
+    writeln!(
+        session.output(),
+        "Copied {} to the clipboard",
+        file.display()
+    )?;
+    Ok(())
+}
+
+fn share(session: &Session, store: &NoteStore, index: usize) -> Result<()> {
+    let url = share::share(store, store.note_at(index)?)?;
+    writeln!(session.output(), "{}", url)?;
+    Ok(())
+}
+
+fn mail(session: &Session, store: &NoteStore, index: usize, to: &[String]) -> Result<()> {
+    let file = store.note_at(index)?;
+    share::mail(store, &file, to)?;
+    writeln!(
+        session.output(),
+        "Sent {} to {}",
+        file.display(),
+        to.join(", ")
+    )?;
+    Ok(())
+}
+
+fn export(session: &Session, store: &NoteStore, site: bool, dir: &Path) -> Result<()> {
+    let (count, what) = if site {
+        (site::export(store, dir)?, "a site in ")
+    } else {
+        (store.export(dir)?, "")
+    };
+    writeln!(
+        session.output(),
+        "Exported {} note(s) to {}{}",
+        count,
+        what,
+        dir.display()
+    )?;
+    Ok(())
+}
+
+fn export_note(
+    session: &Session,
+    store: &NoteStore,
+    index: &Path,
+    format: &str,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let index = index
+        .to_str()
+        .and_then(|text| text.parse().ok())
+        .ok_or_else(|| Error::InvalidIndex {
+            text: index.to_string_lossy().into_owned(),
+        })?;
+    let name = store.note_at(index)?;
+    let output = output.unwrap_or_else(|| {
+        PathBuf::from(name.file_name().unwrap_or_default()).with_extension(format)
+    });
+    if output.exists() {
+        return Err(Error::FileExists { path: output });
+    }
+
+    let status = edit::convert_note(store, &name, format, &output)?;
+    if !status.success() {
+        return Err(Error::ChildFailed {
+            command: String::from("pandoc"),
+            status,
+        });
+    }
+    writeln!(
+        session.output(),
+        "Converted {} to {}",
+        name.display(),
+        output.display()
+    )?;
+    Ok(())
+}
+
+fn import(session: &Session, store: &NoteStore, from: &str, path: &Path) -> Result<()> {
+    let notes = match from {
+        "simplenote" => import::simplenote(path)?,
+        "enex" => import::enex(path)?,
+        _ => import::nvalt(path)?,
+    };
+    let names = import::names(store, &notes)?;
+    if session.dry_run() {
+        for name in names {
+            writeln!(session.output(), "Would create {}", name.name.display())?;
+        }
+        return Ok(());
+    }
+
+    let mut saves = Vec::with_capacity(names.len());
+    for (note, name) in notes.iter().zip(names) {
+        let save = match name.taken {
+            None => import::Save::New(name.name),
+            Some(taken) => {
+                let choices = [
+                    format!("Import it as {}", name.name.display()),
+                    format!("Replace {}", taken.display()),
+                    String::from("Skip it"),
+                ];
+                let prompt = format!(
+                    "{:?} would be imported as {}, which already exists.",
+                    note.title,
+                    taken.display()
+                );
+                match session.select(&prompt, &choices, Some(0))? {
+                    0 => import::Save::New(name.name),
+                    1 => import::Save::Replace(taken),
+                    _ => import::Save::Skip,
+                }
+            }
+        };
+        saves.push(save);
+    }
+
+    let names = import::write(store, &notes, &saves)?;
+    writeln!(
+        session.output(),
+        "Imported {} note(s) from {}",
+        names.len(),
+        path.display()
+    )?;
+    Ok(())
+}
+
+fn edit(
+    store: &NoteStore,
+    index: usize,
+    line: Option<usize>,
+    at: Option<&str>,
+    force: bool,
+) -> Result<()> {
+    let config = store.config();
+    let file = store.note_at(index)?;
+    let _lock = match config.storage() {
+        Storage::Files => Some(notes_dir::lock_note(config, &file, force)?),
+        _ => None,
+    };
+    let line = match at {
+        Some(pattern) => Some(
+            store
+                .read(&file)?
+                .lines()
+                .position(|line| line.contains(pattern))
+                .map(|lineno| lineno + 1)
+                .ok_or_else(|| Error::NoMatch {
+                    pattern: String::from(pattern),
+                })?,
+        ),
+        None => line,
+    };
+
+    let status = edit::edit_stored_note(store, &file, line)?;
+    edit::check_status(store.config(), "editor", status)?;
+    Ok(())
+}
+
+fn open(config: &Config, index: usize) -> Result<()> {
+    let file = notes_dir::file_at_index(config, index)?;
+    let status = edit::open_path(config, config.notes_dir()?.join(file))?;
+    edit::check_status(config, "opener", status)?;
+    Ok(())
+}
+
+fn exec(config: &Config, indices: &[usize], command: &[String]) -> Result<()> {
+    let files = indices
+        .iter()
+        .map(|&index| notes_dir::file_at_index(config, index))
+        .collect::<Result<Vec<_>>>()?;
+
+    let status = edit::exec_command(config, command, &files)?;
+    edit::check_status(config, "command", status)?;
+    Ok(())
+}
+
+fn mv(session: &Session, store: &NoteStore, index: usize, destination: &str) -> Result<()> {
+    let file = store.note_at(index)?;
+    let target = store.rename(&file, destination)?;
+    let mut changes = vec![Change::Renamed {
+        from: file.clone(),
+        to: target.clone(),
+    }];
+    let result = update_links(
+        store.config(),
+        &[(file.clone(), target.clone())],
+        &mut changes,
+    );
+    journal::record(store.config(), "mv", changes)?;
+    writeln!(
+        session.output(),
+        "Moved {} to {}",
+        file.display(),
+        target.display()
+    )?;
+    result
+}
+
+/// In an Obsidian vault, point wiki-style links to renamed notes at their new names, adding the
+/// changes made to `changes`.
+fn update_links(
+    config: &Config,
+    renames: &[(PathBuf, PathBuf)],
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    if config.compat() != Compat::Obsidian || config.storage() != Storage::Files {
+        return Ok(());
+    }
+
+    let notes_path = config.notes_dir()?;
+    for note in notes_dir::list(config)? {
+        let path = notes_path.join(&note);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::InvalidData => continue,
+            Err(err) => return Err(err).context("reading", path),
+        };
+        if let Some(updated) = wikilink::retarget(&text, renames) {
+            let backup = journal::backup(config, &note)?;
+            std::fs::write(&path, updated).context("writing", &path)?;
+            info!("Updated links in {}", note.display());
+            changes.push(Change::Replaced { path: note, backup });
+        }
+    }
+    Ok(())
+}
+
+fn merge(
+    session: &Session,
+    config: &Config,
+    indices: &[usize],
+    into: Option<String>,
+    delete: bool,
+) -> Result<()> {
+    let files = indices
+        .iter()
+        .map(|&index| notes_dir::file_at_index(config, index))
+        .collect::<Result<Vec<_>>>()?;
+    let into = match into {
+        Some(name) => notes_dir::with_note_extension(config, PathBuf::from(name)),
+        None => notes_dir::new_file_name(config, None)?,
+    };
+
+    notes_dir::merge_files(config, &files, &into)?;
+    writeln!(session.output(), "Merged into {}", into.display())?;
+
+    let sources: Vec<_> = files.iter().filter(|file| **file != into).collect();
+    if !delete || sources.is_empty() {
+        return Ok(());
+    }
+
+    let mut prompt = format!("Merged {} note(s):\n", sources.len());
+    for file in &sources {
+        prompt.push_str(&format!("  {}\n", file.display()));
+    }
+    prompt.push_str("Delete them?");
+
+    if session.prompt(
+        &prompt,
+        Some(false),
+        Some("Deleting files"),
+        Some("Cancelling"),
+    )? {
+        for file in sources {
+            notes_dir::rm_file(config, file)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn resolve_conflicts(session: &Session, config: &Config, list: bool) -> Result<()> {
+    let conflicts = conflicts::find(config)?;
+    if conflicts.is_empty() {
+        writeln!(session.output(), "No conflicted copies found")?;
+        return Ok(());
+    }
+
+    if list {
+        for conflict in &conflicts {
+            writeln!(
+                session.output(),
+                "{}\t{}",
+                conflict.copy.display(),
+                conflict.original.display()
+            )?;
+        }
+        return Ok(());
+    }
+
+    let mut changes = Vec::new();
+    let result = conflicts
+        .iter()
+        .try_for_each(|conflict| resolve_conflict(session, config, conflict, &mut changes));
+    journal::record(config, "conflicts", changes)?;
+    result
+}
+
+fn resolve_conflict(
+    session: &Session,
+    config: &Config,
+    conflict: &Conflict,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    let Conflict { copy, original } = conflict;
+    let exists = config.notes_dir()?.join(original).exists();
+    let ours = if exists {
+        notes_dir::read_file(config, original)?
+    } else {
+        String::new()
+    };
+    let theirs = notes_dir::read_file(config, copy)?;
+
+    let discard = |changes: &mut Vec<Change>| -> Result<()> {
+        let backup = journal::backup(config, copy)?;
+        notes_dir::rm_file(config, copy)?;
+        writeln!(session.output(), "Discarded {}", copy.display())?;
+        changes.push(Change::Deleted {
+            path: copy.clone(),
+            backup,
+        });
+        Ok(())
+    };
+    let replace = |changes: &mut Vec<Change>, text: &str| -> Result<()> {
+        if exists {
+            let backup = journal::backup(config, original)?;
+            notes_dir::write_file(config, original, text)?;
+            changes.push(Change::Replaced {
+                path: original.clone(),
+                backup,
+            });
+        } else {
+            notes_dir::write_file(config, original, text)?;
+            changes.push(Change::Created {
+                path: original.clone(),
+            });
+        }
+        Ok(())
+    };
+
+    writeln!(
+        session.output(),
+        "{} is a conflicted copy of {}",
+        copy.display(),
+        original.display()
+    )?;
+    let diff = util::diff::unified(&ours, &theirs, 3);
+    if diff.is_empty() && exists {
+        if session.prompt(
+            "They are the same. Discard the copy?",
+            Some(true),
+            None,
+            None,
+        )? {
+            discard(changes)?;
+        }
+        return Ok(());
+    } else if util::term::stdout_is_tty() {
+        write!(session.output(), "{}", util::diff::colorize(&diff))?;
+    } else {
+        write!(session.output(), "{}", diff)?;
+    }
+
+    let choices = [
+        format!("Keep {} and discard the copy", original.display()),
+        format!("Keep the copy in place of {}", original.display()),
+        format!("Merge the copy into {} in the editor", original.display()),
+        String::from("Skip it for now"),
+    ];
+    match session.select("What should be done with the copy?", &choices, None)? {
+        0 => discard(changes)?,
+        1 => {
+            replace(changes, &theirs)?;
+            discard(changes)?;
+        }
+        2 => {
+            let merged = util::diff::with_conflict_markers(
+                &ours,
+                &theirs,
+                &original.to_string_lossy(),
+                &copy.to_string_lossy(),
+            );
+            replace(changes, &merged)?;
+            let status = edit::edit_note(config, original)?;
+            edit::check_status(config, "editor", status)?;
+
+            let text = notes_dir::read_file(config, original)?;
+            if text.lines().any(|line| line.starts_with("<<<<<<< ")) {
+                warn!(
+                    "{} still has conflict markers; keeping {}",
+                    original.display(),
+                    copy.display()
+                );
+            } else {
+                discard(changes)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn rename_all(
+    session: &Session,
+    config: &Config,
+    regex: &Regex,
+    to: &str,
+    dry_run: bool,
+) -> Result<()> {
+    let notes_path = config.notes_dir()?;
+    let mut renames = Vec::new();
+    for note in notes_dir::list(config)? {
+        let name = note.to_string_lossy();
+        let (target, count) = regex.replace_all(&name, to);
+        if count > 0 && target != name {
+            renames.push((note, PathBuf::from(target)));
+        }
+    }
+
+    if renames.is_empty() {
+        writeln!(session.output(), "No matching notes found")?;
+        return Ok(());
+    }
+
+    let mut prompt = format!("Found {} note(s) to rename:\n", renames.len());
+    for (i, (note, target)) in renames.iter().enumerate() {
+        notes_dir::check_note_name(target)?;
+        let taken = notes_path.join(target).exists()
+            || renames[..i].iter().any(|(_, other)| other == target);
+        if taken {
+            return Err(Error::FileExists {
+                path: target.clone(),
+            });
+        }
+        prompt.push_str(&format!("  {} -> {}\n", note.display(), target.display()));
+    }
+
+    if dry_run || session.dry_run() {
+        write!(session.output(), "{}", prompt)?;
+        return Ok(());
+    }
+    prompt.push_str("Rename them?");
+
+    if session.prompt(
+        &prompt,
+        Some(false),
+        Some("Renaming files"),
+        Some("Cancelling"),
+    )? {
+        let mut changes = Vec::new();
+        let mut renamed = Vec::new();
+        let result: Result<()> = renames.into_iter().try_for_each(|(note, target)| {
+            notes_dir::create_parent_dirs(config, &target)?;
+            let from = notes_path.join(&note);
+            std::fs::rename(&from, notes_path.join(&target)).context("renaming", from)?;
+            changes.push(Change::Renamed {
+                from: note.clone(),
+                to: target.clone(),
+            });
+            renamed.push((note, target));
+            Ok(())
+        });
+        let result = result.and_then(|()| update_links(config, &renamed, &mut changes));
+        journal::record(config, "rename-all", changes)?;
+        result?;
+    }
+
+    Ok(())
+}
+
+fn attach(session: &Session, config: &Config, index: usize, file: &Path) -> Result<()> {
+    let note = notes_dir::file_at_index(config, index)?;
+    let attachment = notes_dir::attach_file(config, &note, file)?;
+    let link = notes_dir::attachment_link(&note, &attachment);
+    notes_dir::append_file(config, &note, &format!("{}\n", link))?;
+    writeln!(
+        session.output(),
+        "Attached {} to {}",
+        attachment.display(),
+        note.display()
+    )?;
+    Ok(())
+}
+
+fn attachments(session: &Session, config: &Config, index: usize) -> Result<()> {
+    let note = notes_dir::file_at_index(config, index)?;
+    for attachment in notes_dir::attachments(config, &note)? {
+        writeln!(session.output(), "{}", attachment.display())?;
+    }
+    Ok(())
+}
+
+fn backups(session: &Session, config: &Config, index: Option<usize>) -> Result<()> {
+    let note = match index {
+        Some(index) => notes_dir::file_at_index(config, index)?,
+        None => {
+            for note in backup::backed_up_notes(config)? {
+                writeln!(session.output(), "{}", note.display())?;
+            }
+            return Ok(());
+        }
+    };
+
+    for (i, backup) in backup::backups(config, &note)?.into_iter().enumerate() {
+        writeln!(
+            session.output(),
+            "{} {}",
+            i + 1,
+            backup.time.format("%Y-%m-%d %H:%M:%S")
+        )?;
+    }
+    Ok(())
+}
+
+fn restore_backup(session: &Session, config: &Config, note: &str, number: usize) -> Result<()> {
+    let mut note = match note.parse() {
+        Ok(index) => notes_dir::file_at_index(config, index)?,
+        Err(_) => PathBuf::from(note),
+    };
+
+    let mut backups = backup::backups(config, &note)?;
+    if backups.is_empty() && !config.notes_dir()?.join(&note).is_file() {
+        // A bare file name may refer to a deleted note in any folder.
+        let notes = backup::backed_up_notes(config)?;
+        let matching: Vec<_> = notes
+            .iter()
+            .filter(|path| path.file_name() == Some(note.as_os_str()))
+            .collect();
+        note = match matching.as_slice() {
+            [] => {
+                let names: Vec<_> = notes.iter().filter_map(|note| note.to_str()).collect();
+                return Err(Error::NoSuchNote {
+                    suggestion: note
+                        .to_str()
+                        .and_then(|note| util::closest_match(note, names))
+                        .map(PathBuf::from),
+                    path: note,
+                });
+            }
+            [path] => PathBuf::from(path),
+            _ => {
+                let shown: Vec<_> = matching.iter().map(|path| path.display()).collect();
+                let choice = session.select(
+                    &format!("Several notes are named {}:", note.display()),
+                    &shown,
+                    None,
+                )?;
+                PathBuf::from(matching[choice])
+            }
+        };
+        backups = backup::backups(config, &note)?;
+    }
+
+    let backup = number
+        .checked_sub(1)
+        .and_then(|i| backups.get(i))
+        .ok_or_else(|| Error::NoBackup {
+            path: note.clone(),
+            number,
+        })?;
+
+    backup::restore_backup(config, &note, backup)?;
+    writeln!(
+        session.output(),
+        "Restored {} from the backup taken {}",
+        note.display(),
+        backup.time.format("%Y-%m-%d %H:%M:%S")
+    )?;
+    Ok(())
+}
+
+fn history(session: &Session, config: &Config, index: usize) -> Result<()> {
+    let note = notes_dir::file_at_index(config, index)?;
+    for (i, version) in history::versions(config, &note)?.into_iter().enumerate() {
+        writeln!(
+            session.output(),
+            "{} {} {}",
+            i + 1,
+            version.time.format("%Y-%m-%d %H:%M:%S"),
+            version.hash
+        )?;
+    }
+    Ok(())
+}
+
+fn diff(
+    session: &Session,
+    config: &Config,
+    index: usize,
+    other: Option<usize>,
+    version: Option<usize>,
+) -> Result<()> {
+    let note = notes_dir::file_at_index(config, index)?;
+    let current = notes_dir::read_file(config, &note)?;
+
+    if let Some(other) = other {
+        let other = notes_dir::file_at_index(config, other)?;
+        let other = notes_dir::read_file(config, other)?;
+        return print_diff(session, config, &current, &other);
+    }
+
+    let versions = history::versions(config, &note)?;
+    let old = match version {
+        Some(number) => {
+            let version = number
+                .checked_sub(1)
+                .and_then(|i| versions.get(i))
+                .ok_or_else(|| Error::NoVersion {
+                    path: note.clone(),
+                    number,
+                })?;
+            history::read_version(config, version)?
+        }
+
+        None => {
+            let mut old = None;
+            for version in &versions {
+                let contents = history::read_version(config, version)?;
+                if contents != current {
+                    old = Some(contents);
+                    break;
+                }
+            }
+            match old {
+                Some(old) => old,
+                None => return Ok(()),
+            }
+        }
+    };
+
+    print_diff(session, config, &old, &current)
+}
+
+fn print_diff(session: &Session, config: &Config, old: &str, new: &str) -> Result<()> {
+    let diff = util::diff::unified(old, new, 3);
+    if util::term::stdout_is_tty() {
+        print_paged(session, config, &util::diff::colorize(&diff))
+    } else {
+        print_paged(session, config, &diff)
+    }
+}
+
+/// The maximum width, in characters, of the lines shown in search results.
+const SNIPPET_WIDTH: usize = 100;
+
+/// Output formats of the search command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SearchFormat {
+    Text,
+    Vimgrep,
+    Json,
+    FilesWithMatches,
+    Count,
+}
+
+/// A match found by the search command.
+pub(crate) struct SearchMatch {
+    /// The line number, starting from 1.
+    pub(crate) line: usize,
+
+    /// The byte offset of the match within the line, starting from 1.
+    pub(crate) column: usize,
+
+    /// The text of the line containing the match.
+    pub(crate) text: String,
+}
+
+/// A note with matches found by the search command.
+pub(crate) struct SearchResult {
+    /// The note's index, as displayed by the list command.
+    pub(crate) index: usize,
+
+    /// The note's path, relative to the notes directory.
+    pub(crate) name: PathBuf,
+
+    /// The matches in the note, in order.
+    pub(crate) matches: Vec<SearchMatch>,
+}
+
+/// Find the notes containing the given text, most relevant first unless `no_rank` is set.
+///
+/// The notes are numbered in the current order, which is saved as the listing later indices
+/// refer to.
+pub(crate) fn find_matches(
+    config: &Config,
+    index: &index::Index,
+    pattern: &str,
+    folder: Option<&Path>,
+    ignore_case: bool,
+    no_rank: bool,
+) -> Result<Vec<SearchResult>> {
+    let pattern = if ignore_case {
+        pattern.to_lowercase()
+    } else {
+        String::from(pattern)
+    };
+
+    let candidates = index.candidates(&pattern);
+    let files = notes_dir::list(config)?;
+    notes_dir::save_listing(config, &files)?;
+    let notes: Vec<_> = files
+        .into_iter()
+        .enumerate()
+        .filter(|(_, name)| {
+            folder.map(|f| name.starts_with(f)).unwrap_or(true) && candidates.contains(name)
+        })
+        .collect();
+
+    // Reading the notes dominates the running time for large notebooks, so it is done in
+    // parallel.
+    let scanned = {
+        let config = config.clone();
+        util::par_map(notes, move |(i, name)| {
+            scan_note(&config, &pattern, ignore_case, i, name)
+        })
+    };
+
+    let mut results = Vec::new();
+    for result in scanned {
+        if let Some(result) = result? {
+            results.push(result);
+        }
+    }
+
+    if !no_rank {
+        results.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    }
+    Ok(results.into_iter().map(|(_, result)| result).collect())
+}
+
+/// Find the matches for a search in one note, returning them with the note's relevance, or
+/// `None` if there are none. The pattern must already be lowercase if `ignore_case` is set.
+fn scan_note(
+    config: &Config,
+    pattern: &str,
+    ignore_case: bool,
+    i: usize,
+    name: PathBuf,
+) -> Result<Option<(f64, SearchResult)>> {
+    let contents = notes_dir::read_file_lossy(config, &name)?;
+
+    let mut matches = Vec::new();
+    for (lineno, line) in contents.lines().enumerate() {
+        let haystack = if ignore_case {
+            line.to_lowercase()
+        } else {
+            String::from(line)
+        };
+
+        for (at, _) in haystack.match_indices(pattern) {
+            // Lowercasing can change the length of some characters, so find the match in the
+            // original line by its character offset.
+            let chars = haystack[..at].chars().count();
+            let column = line
+                .char_indices()
+                .nth(chars)
+                .map_or(line.len(), |(b, _)| b);
+            matches.push(SearchMatch {
+                line: lineno + 1,
+                column: column + 1,
+                text: String::from(line),
+            });
+        }
+    }
+
+    if matches.is_empty() {
+        return Ok(None);
+    }
+
+    let age = SystemTime::now()
+        .duration_since(notes_dir::modified(config, &name)?)
+        .unwrap_or_default();
+    let words = contents.split_whitespace().count();
+    let relevance = index::relevance(matches.len(), words, age);
+    Ok(Some((
+        relevance,
+        SearchResult {
+            index: i,
+            name,
+            matches,
+        },
+    )))
+}
+
+/// Format a matching line for display, shortening it to the text around the match.
+fn match_snippet(m: &SearchMatch, width: usize) -> String {
+    let trimmed = m.text.trim_start();
+    let indent = m.text.len() - trimmed.len();
+    let at = m.text[indent..(m.column - 1).max(indent)].chars().count();
+    util::snippet(trimmed.trim_end(), at, width)
+}
+
+/// A line shown in the interactive search's results: the note, line number, and display text.
+type SearchEntry = (PathBuf, usize, String);
+
+fn interactive_search(
+    config: &Config,
+    mut pattern: String,
+    folder: Option<&Path>,
+    case: SearchCase,
+    no_rank: bool,
+) -> Result<()> {
+    let index = index::updated(config)?;
+    let raw = util::term::RawMode::enable()?;
+    // Draw on the terminal's alternate screen, so that its contents are restored afterwards.
+    print!("\x1b[?1049h");
+
+    let mut selected = 0;
+    let mut search = || -> Result<Option<(PathBuf, usize)>> {
+        loop {
+            let entries = if pattern.is_empty() {
+                Vec::new()
+            } else {
+                let ignore_case = case.ignore_case(&pattern);
+                let results = find_matches(config, &index, &pattern, folder, ignore_case, no_rank)?;
+                search_entries(results)
+            };
+            selected = selected.min(entries.len().saturating_sub(1));
+            draw_search(&pattern, &entries, selected)?;
+
+            match raw.read_key()? {
+                Key::Char(c) => {
+                    pattern.push(c);
+                    selected = 0;
+                }
+                Key::Backspace => {
+                    pattern.pop();
+                    selected = 0;
+                }
+                Key::Ctrl('u') => {
+                    pattern.clear();
+                    selected = 0;
+                }
+                Key::Up | Key::Ctrl('p') => selected = selected.saturating_sub(1),
+                Key::Down | Key::Ctrl('n') => selected += 1,
+                Key::Enter => {
+                    if let Some((name, line, _)) = entries.into_iter().nth(selected) {
+                        return Ok(Some((name, line)));
+                    }
+                }
+                Key::Ctrl('c') | Key::Ctrl('d') | Key::Ctrl('g') => return Ok(None),
+                _ => {}
+            }
+        }
+    };
+    let chosen = search();
+
+    print!("\x1b[?1049l");
+    std::io::stdout().flush()?;
+    drop(raw);
+
+    if let Some((name, line)) = chosen? {
+        let _lock = notes_dir::lock_note(config, &name, false)?;
+        let status = edit::edit_note_at(config, &name, Some(line))?;
+        edit::check_status(config, "editor", status)?;
+    }
+    Ok(())
+}
+
+/// List each matching line once, with the text to display for it.
+fn search_entries(results: Vec<SearchResult>) -> Vec<SearchEntry> {
+    let width = util::term::width().unwrap_or(80);
+    let mut entries = Vec::new();
+    for result in results {
+        let mut last_line = None;
+        for m in &result.matches {
+            if last_line == Some(m.line) {
+                continue;
+            }
+            last_line = Some(m.line);
+
+            let prefix = format!("{}:{}: ", result.name.display(), m.line);
+            let snippet = match_snippet(m, width.saturating_sub(prefix.chars().count()).max(10));
+            let text: String = format!("{}{}", prefix, snippet)
+                .chars()
+                .take(width)
+                .collect();
+            entries.push((result.name.clone(), m.line, text));
+        }
+    }
+    entries
+}
+
+fn draw_search(pattern: &str, entries: &[SearchEntry], selected: usize) -> Result<()> {
+    let rows = util::term::height().unwrap_or(24).saturating_sub(2).max(1);
+    let offset = (selected + 1).saturating_sub(rows);
+
+    let mut screen = format!("\x1b[H\x1b[2J> {}\r\n", pattern);
+    screen.push_str(&format!(
+        "\x1b[2m{} matching line(s); arrow keys to select, Enter to edit, Ctrl-C to cancel\x1b[0m\r\n",
+        entries.len()
+    ));
+    for (i, (_, _, text)) in entries.iter().enumerate().skip(offset).take(rows) {
+        if i == selected {
+            screen.push_str(&format!("\x1b[7m{}\x1b[0m\r\n", text));
+        } else {
+            screen.push_str(&format!("{}\r\n", text));
+        }
+    }
+    screen.push_str(&format!("\x1b[1;{}H", pattern.chars().count() + 3));
+
+    let mut stdout = std::io::stdout();
+    stdout.write_all(screen.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn search(
+    session: &Session,
+    config: &Config,
+    pattern: &str,
+    folder: Option<&Path>,
+    ignore_case: bool,
+    no_rank: bool,
+    format: SearchFormat,
+) -> Result<()> {
+    let index = index::updated(config)?;
+    let results = find_matches(config, &index, pattern, folder, ignore_case, no_rank)?;
+
+    if results.is_empty() {
+        return Err(Error::NoMatches);
+    }
+
+    let notes_path = config.notes_dir()?;
+    let mut output = String::new();
+    for SearchResult {
+        index: i,
+        name,
+        matches,
+    } in results
+    {
+        match format {
+            SearchFormat::FilesWithMatches => {
+                output.push_str(&format!("{}\n", name.display()));
+                continue;
+            }
+
+            SearchFormat::Count => {
+                let mut lines: Vec<_> = matches.iter().map(|m| m.line).collect();
+                lines.dedup();
+                output.push_str(&format!("{}:{}\n", name.display(), lines.len()));
+                continue;
+            }
+
+            _ => {}
+        }
+
+        let path = notes_path.join(&name);
+        let mut last_line = None;
+        for m in matches {
+            match format {
+                SearchFormat::Text => {
+                    // Show each line once, however many matches it has.
+                    if last_line == Some(m.line) {
+                        continue;
+                    }
+                    last_line = Some(m.line);
+
+                    output.push_str(&format!(
+                        "{} {}:{}: {}\n",
+                        i,
+                        name.display(),
+                        m.line,
+                        match_snippet(&m, SNIPPET_WIDTH)
+                    ));
+                }
+
+                SearchFormat::Vimgrep => output.push_str(&format!(
+                    "{}:{}:{}:{}\n",
+                    path.display(),
+                    m.line,
+                    m.column,
+                    m.text
+                )),
+
+                SearchFormat::FilesWithMatches | SearchFormat::Count => unreachable!(),
+
+                SearchFormat::Json => {
+                    let value = json!({
+                        "index": i,
+                        "note": name.to_string_lossy(),
+                        "path": path.to_string_lossy(),
+                        "line": m.line,
+                        "column": m.column,
+                        "text": m.text,
+                    });
+                    output.push_str(&format!("{}\n", value));
+                }
+            }
+        }
+    }
+
+    match format {
+        SearchFormat::Text => print_paged(session, config, &output),
+        _ => {
+            write!(session.output(), "{}", output)?;
+            Ok(())
+        }
+    }
+}
+
+fn replace(
+    session: &Session,
+    config: &Config,
+    regex: &Regex,
+    replacement: &str,
+    indices: &[usize],
+    dry_run: bool,
+) -> Result<()> {
+    let dry_run = dry_run || session.dry_run();
+    let notes = if indices.is_empty() {
+        notes_dir::list(config)?
+    } else {
+        indices
+            .iter()
+            .map(|&index| notes_dir::file_at_index(config, index))
+            .collect::<Result<_>>()?
+    };
+
+    let (mut replaced, mut changes) = (0, Vec::new());
+    for note in notes {
+        let contents = match notes_dir::read_file(config, &note) {
+            Ok(contents) => contents,
+            Err(err) if err.io_kind() == Some(std::io::ErrorKind::InvalidData) => continue,
+            Err(err) => return Err(err),
+        };
+
+        let (new, count) = regex.replace_all(&contents, replacement);
+        if new == contents {
+            continue;
+        }
+
+        let diff = util::diff::unified(&contents, &new, 1);
+        writeln!(session.output(), "{}", note.display())?;
+        if util::term::stdout_is_tty() {
+            write!(session.output(), "{}", util::diff::colorize(&diff))?;
+        } else {
+            write!(session.output(), "{}", diff)?;
+        }
+
+        if dry_run {
+            continue;
+        }
+
+        let prompt = format!("Replace {} match(es) in {}?", count, note.display());
+        if session.prompt(&prompt, Some(false), None, None)? {
+            let backup = journal::backup(config, &note)?;
+            notes_dir::write_file(config, &note, &new)?;
+            replaced += count;
+            changes.push(Change::Replaced { path: note, backup });
+        }
+    }
+
+    if !dry_run {
+        writeln!(
+            session.output(),
+            "Replaced {} match(es) in {} note(s)",
+            replaced,
+            changes.len()
+        )?;
+        journal::record(config, "replace", changes)?;
+    }
+    Ok(())
+}
+
+fn rm(session: &Session, store: &NoteStore, index: usize) -> Result<()> {
+    let note = store.note(store.note_at(index)?);
+    let file = note.path();
+    let file_name = file.display();
+    let first_line = note.preview(1, 77)?.into_iter().next();
+
+    let prompt = if let Some(line) = first_line {
+        format!("Remove file {} with first line:\n\"{}\"?", file_name, line)
+    } else {
+        format!("Remove empty file {}?", file_name)
+    };
+    if session.dry_run() {
+        writeln!(session.output(), "Would remove file {}", file_name)?;
+        return Ok(());
+    }
+    let yes_response = Some("Removing file");
+    let no_response = Some("Cancelling");
+
+    if session.prompt(&prompt, Some(false), yes_response, no_response)? {
+        let backup = journal::backup(store.config(), file)?;
+        store.delete(file)?;
+        let change = Change::Deleted {
+            path: file.to_path_buf(),
+            backup,
+        };
+        journal::record(store.config(), "rm", vec![change])?;
+    }
+
+    Ok(())
+}
+
+fn dedupe(session: &Session, config: &Config, near: bool) -> Result<()> {
+    let mut found = false;
+    for group in notes_dir::duplicates(config, false)? {
+        found = true;
+        let (keep, copies) = group.split_first().unwrap();
+        let mut prompt = format!("Found {} copies of {}:\n", copies.len(), keep.display());
+        for copy in copies {
+            prompt.push_str(&format!("  {}\n", copy.display()));
+        }
+        prompt.push_str("Delete them?");
+
+        if session.prompt(
+            &prompt,
+            Some(false),
+            Some("Deleting files"),
+            Some("Skipping"),
+        )? {
+            for copy in copies {
+                notes_dir::rm_file(config, copy)?;
+            }
+        }
+    }
+
+    if near {
+        for group in notes_dir::duplicates(config, true)? {
+            let first = notes_dir::read_file(config, &group[0])?;
+            let mut same = true;
+            for note in &group[1..] {
+                same = same && notes_dir::read_file(config, note)? == first;
+            }
+            if same {
+                // Exact copies that the user chose to keep.
+                continue;
+            }
+
+            found = true;
+            let (keep, copies) = group.split_first().unwrap();
+            let mut prompt = format!(
+                "Found {} near-copies of {}:\n",
+                copies.len(),
+                keep.display()
+            );
+            for copy in copies {
+                prompt.push_str(&format!("  {}\n", copy.display()));
+            }
+
+            let merge = format!("{}Merge them into {}?", prompt, keep.display());
+            let delete = "Delete them without merging?";
+            if session.prompt(&merge, Some(false), None, None)? {
+                notes_dir::merge_files(config, copies, keep)?;
+            } else if !session.prompt(delete, Some(false), None, Some("Skipping"))? {
+                continue;
+            }
+            for copy in copies {
+                notes_dir::rm_file(config, copy)?;
+            }
+        }
+    }
+
+    if !found {
+        writeln!(session.output(), "No duplicate notes found")?;
+    }
+    Ok(())
+}
+
+fn prune(session: &Session, config: &Config) -> Result<()> {
+    let mut empty = Vec::new();
+    for file in notes_dir::list(config)? {
+        if notes_dir::is_blank(config, &file)? {
+            empty.push(file);
+        }
+    }
+
+    if empty.is_empty() {
+        writeln!(session.output(), "No empty notes found")?;
+        return Ok(());
+    }
+
+    let mut prompt = format!("Found {} empty note(s):\n", empty.len());
+    for file in &empty {
+        prompt.push_str(&format!("  {}\n", file.display()));
+    }
+    prompt.push_str("Remove them?");
+
+    if session.prompt(
+        &prompt,
+        Some(false),
+        Some("Removing files"),
+        Some("Cancelling"),
+    )? {
+        for file in &empty {
+            notes_dir::rm_file(config, file)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn cal(session: &Session, store: &NoteStore, month: Option<chrono::NaiveDate>) -> Result<()> {
+    use chrono::Datelike;
+
+    let first = month.unwrap_or_else(|| {
+        let today = chrono::Local::today().naive_local();
+        today.with_day(1).unwrap_or(today)
+    });
+    let counts = calendar::note_counts(store)?;
+    let calendar = calendar::render(first, &counts, util::term::stdout_is_tty());
+    write!(session.output(), "{}", calendar)?;
+    Ok(())
+}
+
+fn due(
+    session: &Session,
+    config: &Config,
+    overdue: bool,
+    within: Option<chrono::Duration>,
+) -> Result<()> {
+    let today = chrono::Local::today().naive_local();
+    let files = notes_dir::list(config)?;
+    notes_dir::save_listing(config, &files)?;
+    let mut notes = Vec::new();
+    for (i, name) in files.into_iter().enumerate() {
+        if let Some(date) = notes_dir::due_date(config, &name)? {
+            let show = if overdue {
+                date < today
+            } else if let Some(within) = within {
+                today
+                    .checked_add_signed(within)
+                    .is_none_or(|end| date <= end)
+            } else {
+                true
+            };
+
+            if show {
+                notes.push((date, i, name));
+            }
+        }
+    }
+    notes.sort();
+
+    let mut output = String::new();
+    for (date, i, name) in notes {
+        let title = notes_dir::title(config, &name, 40)?;
+        output.push_str(&format!(
+            "{} {} - {}{} - {}\n",
+            i,
+            name.display(),
+            date,
+            if date < today { " (overdue)" } else { "" },
+            title.as_deref().unwrap_or("<empty>")
+        ));
+    }
+
+    print_paged(session, config, &output)
+}
+
+fn log(config: &Config, message: Vec<String>) -> Result<()> {
+    let now = chrono::Local::now();
+    let name = notes_dir::daily_note_name(config, &now)?;
+
+    if !config.notes_dir()?.join(&name).exists() {
+        let title = now.format("%Y-%m-%d").to_string();
+        notes_dir::write_file(
+            config,
+            &name,
+            &notes_dir::initial_contents(config, Some(&title)),
+        )?;
+    }
+
+    let entry = format!("- {} {}\n", now.format("%H:%M"), message.join(" "));
+    notes_dir::append_file(config, &name, &entry)
+}
+
+fn scratch(config: &Config, clear: bool) -> Result<()> {
+    let file = config.scratch_file();
+    if clear {
+        backup::backup_note(config, &file)?;
+        return notes_dir::write_file(config, &file, "");
+    }
+
+    let _lock = notes_dir::lock_note(config, &file, false)?;
+    let status = edit::edit_note(config, &file)?;
+    edit::check_status(config, "editor", status)?;
+    Ok(())
+}
+
+fn undo(session: &Session, config: &Config) -> Result<()> {
+    let operation = journal::latest(config)?;
+    let changed = journal::changed_since(config, &operation)?;
+    if !changed.is_empty() && !session.dry_run() {
+        let mut prompt = format!(
+            "Undoing {} deletes note(s) changed since they were created:\n",
+            operation
+        );
+        for path in &changed {
+            prompt.push_str(&format!("  {}\n", path.display()));
+        }
+        prompt.push_str("Undo anyway?");
+        if !session.prompt(&prompt, Some(false), None, Some("Cancelling"))? {
+            return Ok(());
+        }
+    }
+
+    let (operation, verb) = if session.dry_run() {
+        (operation, "Would undo")
+    } else {
+        (journal::undo(config)?, "Undid")
+    };
+
+    writeln!(session.output(), "{} {}:", verb, operation)?;
+    for change in &operation.changes {
+        writeln!(session.output(), "  {}", change)?;
+    }
+    Ok(())
+}
+
+fn tidy(
+    session: &Session,
+    config: &Config,
+    older_than: chrono::Duration,
+    delete: bool,
+) -> Result<()> {
+    // A cutoff before the earliest representable time leaves no note old enough.
+    let cutoff = match SystemTime::now().checked_sub(older_than.to_std().unwrap_or_default()) {
+        Some(cutoff) => cutoff,
+        None => return Ok(()),
+    };
+    let mut old = Vec::new();
+    for file in notes_dir::list(config)? {
+        if notes_dir::modified(config, &file)? < cutoff {
+            old.push(file);
+        }
+    }
+
+    if old.is_empty() {
+        return Ok(());
+    }
+
+    let action = if delete { "Delete" } else { "Archive" };
+    let mut prompt = format!(
+        "Found {} note(s) not modified in {}:\n",
+        old.len(),
+        util::describe_duration(older_than)
+    );
+    for file in &old {
+        prompt.push_str(&format!("  {}\n", file.display()));
+    }
+    if session.dry_run() {
+        write!(session.output(), "{}", prompt)?;
+        return Ok(());
+    }
+    prompt.push_str(&format!("{} them?", action));
+
+    if session.prompt(&prompt, Some(false), None, Some("Cancelling"))? {
+        let mut changes = Vec::new();
+        let result: Result<()> = old.into_iter().try_for_each(|file| {
+            if delete {
+                let backup = journal::backup(config, &file)?;
+                notes_dir::rm_file(config, &file)?;
+                writeln!(session.output(), "Deleted {}", file.display())?;
+                changes.push(Change::Deleted { path: file, backup });
+            } else {
+                let target = notes_dir::archive_file(config, &file)?;
+                writeln!(
+                    session.output(),
+                    "Archived {} to {}",
+                    file.display(),
+                    target.display()
+                )?;
+                changes.push(Change::Renamed {
+                    from: file,
+                    to: target,
+                });
+            }
+            Ok(())
+        });
+        journal::record(config, "tidy", changes)?;
+        result?;
+    }
+
+    Ok(())
+}
+
+fn meta(session: &Session, config: &Config, command: MetaCommand) -> Result<()> {
+    match command {
+        MetaCommand::Get { index, key } => {
+            let file = notes_dir::file_at_index(config, index)?;
+            let block = notes_dir::frontmatter(config, &file)?.unwrap_or_default();
+            if let Some(key) = key {
+                let raw = block
+                    .get_raw(&key)
+                    .ok_or_else(|| Error::MissingField { key: key.clone() })?;
+                if raw.starts_with('[') || raw.starts_with('\n') {
+                    for item in block.get_list(&key).unwrap_or_default() {
+                        writeln!(session.output(), "{}", item)?;
+                    }
+                } else {
+                    writeln!(session.output(), "{}", block.get(&key).unwrap_or_default())?;
+                }
+            } else if !block.is_empty() {
+                write!(session.output(), "{}", block)?;
+            }
+            Ok(())
+        }
+
+        MetaCommand::Set { index, key, value } => {
+            let file = notes_dir::file_at_index(config, index)?;
+            let mut block = notes_dir::frontmatter(config, &file)?.unwrap_or_default();
+            block.set(&key, &value);
+            notes_dir::write_frontmatter(config, &file, &block)
+        }
+    }
+}
+
+fn notes_dir(session: &Session, config: &Config) -> Result<()> {
+    let path = config.notes_dir()?;
+    let path = path.canonicalize().context("resolving", &path)?;
+    writeln!(session.output(), "{}", path.display())?;
+    Ok(())
+}
+
+fn open_dir(config: &Config) -> Result<()> {
+    let status = edit::open_path(config, config.notes_dir()?)?;
+    edit::check_status(config, "opener", status)?;
+    Ok(())
+}
+
+fn shell(config: &Config) -> Result<()> {
+    edit::spawn_shell(config)?;
+    Ok(())
+}
+
+#[cfg(feature = "serve")]
+fn serve(session: &Session, store: &NoteStore, port: u16) -> Result<()> {
+    crate::serve::serve(session, store, port)
+}
+
+#[cfg(not(feature = "serve"))]
+fn serve(_session: &Session, _store: &NoteStore, _port: u16) -> Result<()> {
+    Err(Error::NoServeSupport)
+}
+
+fn sync(session: &Session, config: &Config, remote: Option<&str>, dry_run: bool) -> Result<()> {
+    let remote = remote
+        .or_else(|| config.sync_remote())
+        .ok_or(Error::NoSyncRemote)?;
+    let transfers = sync::sync(config, remote, dry_run)?;
+
+    let mut output = session.output();
+    for transfer in &transfers {
+        writeln!(output, "{}", transfer)?;
+    }
+    if dry_run {
+        writeln!(
+            output,
+            "Would make {} change(s) to sync with {}",
+            transfers.len(),
+            remote
+        )?;
+    } else {
+        writeln!(
+            output,
+            "Synced with {}: {} change(s)",
+            remote,
+            transfers.len()
+        )?;
+    }
+    Ok(())
+}
+
+fn backup(
+    session: &Session,
+    config: &Config,
+    out: Option<PathBuf>,
+    to: Option<&str>,
+    command: Option<BackupCommand>,
+) -> Result<()> {
+    let now = chrono::Local::now();
+    let creating = command.is_none();
+    match command {
+        None if to.is_none() => {
+            let file = match (out, config.backup_dir()?) {
+                (Some(file), _) => file,
+                (None, Some(dir)) => {
+                    std::fs::create_dir_all(&dir).context("creating", &dir)?;
+                    dir.join(archive::file_name(&now))
+                }
+                (None, None) => PathBuf::from(archive::file_name(&now)),
+            };
+            let count = archive::create(config, &file)?;
+            writeln!(
+                session.output(),
+                "Backed up {} file(s) to {}",
+                count,
+                file.display()
+            )?;
+        }
+
+        Some(BackupCommand::Restore { backup, into }) if !backup.starts_with("s3://") => {
+            let dir = match into {
+                Some(dir) => dir,
+                None => config.notes_dir()?,
+            };
+            let count = archive::extract(Path::new(&backup), &dir)?;
+            writeln!(
+                session.output(),
+                "Restored {} file(s) into {}",
+                count,
+                dir.display()
+            )?;
+        }
+
+        command => s3_backup(session, config, to, command)?,
+    }
+
+    if creating {
+        archive::record_backup(config, &now)?;
+    }
+    Ok(())
+}
+
+/// Back up the notes directory to the `backup_dir` directory if the last backup is older than the
+/// `backup_every` setting, or remind the user to back it up if no directory is configured.
+fn scheduled_backup(session: &Session, config: &Config) -> Result<()> {
+    let every = match config.backup_every() {
+        Some(every) => every,
+        None => return Ok(()),
+    };
+
+    let now = chrono::Local::now();
+    let last = archive::last_backup(config)?;
+    if let Some(last) = last {
+        if now.signed_duration_since(last) < every {
+            return Ok(());
+        }
+    }
+
+    match config.backup_dir()? {
+        Some(dir) if !session.dry_run() => {
+            std::fs::create_dir_all(&dir).context("creating", &dir)?;
+            let file = dir.join(archive::file_name(&now));
+            let count = archive::create(config, &file)?;
+            archive::record_backup(config, &now)?;
+            writeln!(
+                session.errors(),
+                "Backed up {} file(s) to {}",
+                count,
+                file.display()
+            )?;
+        }
+
+        _ => {
+            let since = match last {
+                Some(last) => format!(
+                    "was last backed up {} day(s) ago",
+                    now.signed_duration_since(last).num_days()
+                ),
+                None => String::from("has not been backed up"),
+            };
+            writeln!(
+                session.errors(),
+                "The notes directory {}; run \"newt backup\" to back it up",
+                since
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "s3")]
+fn s3_backup(
+    session: &Session,
+    config: &Config,
+    to: Option<&str>,
+    command: Option<BackupCommand>,
+) -> Result<()> {
+    use crate::s3::{self, Location};
+
+    match (command, to) {
+        (Some(BackupCommand::List { location }), _) => {
+            let mut output = session.output();
+            for backup in s3::list(&Location::parse(&location)?)? {
+                writeln!(
+                    output,
+                    "{}  {:>10}  {}",
+                    backup.time, backup.size, backup.location
+                )?;
+            }
+        }
+
+        (Some(BackupCommand::Restore { backup, into }), _) => {
+            let dir = match into {
+                Some(dir) => dir,
+                None => config.notes_dir()?,
+            };
+            let count = s3::restore(&Location::parse(&backup)?, &dir)?;
+            writeln!(
+                session.output(),
+                "Restored {} file(s) into {}",
+                count,
+                dir.display()
+            )?;
+        }
+
+        (None, to) => {
+            let prefix = Location::parse(to.unwrap_or_default())?;
+            let (location, count) = s3::upload(config, &prefix)?;
+            writeln!(
+                session.output(),
+                "Backed up {} file(s) to {}",
+                count,
+                location
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "s3"))]
+fn s3_backup(
+    _session: &Session,
+    _config: &Config,
+    _to: Option<&str>,
+    _command: Option<BackupCommand>,
+) -> Result<()> {
+    Err(Error::NoS3Support)
+}
+
+fn external(config: &Config, mut args: Vec<String>) -> Result<()> {
+    let name = args.remove(0);
+    let status = edit::run_external(config, &name, &args)?;
+    edit::check_status(config, &format!("newt-{}", name), status)
+}
+
+fn config_command(
+    session: &Session,
+    command: &ConfigCommand,
+    config_file: Option<&Path>,
+) -> Result<()> {
+    match command {
+        ConfigCommand::Check { path } => {
+            let path = path
+                .clone()
+                .or_else(|| config_file.map(PathBuf::from))
+                .or_else(config::find_conf_file);
+            config_check(session, path.as_deref())
+        }
+
+        ConfigCommand::Init { path, force } => {
+            let path = path.clone().unwrap_or_else(config::default_conf_path);
+            config_init(session, &path, *force)
+        }
+    }
+}
+
+fn config_init(session: &Session, path: &Path, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        return Err(Error::FileExists {
+            path: PathBuf::from(path),
+        });
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context("creating", parent)?;
+    }
+    std::fs::write(path, config::DEFAULT_CONFIG).context("writing", path)?;
+    writeln!(
+        session.output(),
+        "Wrote default configuration to {}",
+        path.display()
+    )?;
+    Ok(())
+}
+
+fn config_check(session: &Session, path: Option<&Path>) -> Result<()> {
+    let (config, file_errors) = match path {
+        Some(path) => {
+            writeln!(session.output(), "Checking {}", path.display())?;
+            config::read_config_file_partial(path)?
+        }
+        None => {
+            writeln!(
+                session.output(),
+                "No configuration file found, checking default configuration"
+            )?;
+            (Config::default(), Vec::new())
+        }
+    };
+
+    // An unrecognized key is most likely a typo or a setting from another version of Newt, so it
+    // is reported as a warning and the rest of the file is still checked.
+    let mut problems: Vec<_> = file_errors
+        .into_iter()
+        .map(|err| match err {
+            Error::Config {
+                kind: ConfigErrorKind::UnrecognizedKey { .. },
+                ..
+            } => config::Problem::Warning(err.to_string()),
+            err => config::Problem::Error(err.to_string()),
+        })
+        .collect();
+    problems.extend(config.check());
+
+    let mut errors = 0;
+    for problem in problems {
+        match problem {
+            config::Problem::Warning(msg) => writeln!(session.output(), "warning: {}", msg)?,
+            config::Problem::Error(msg) => {
+                writeln!(session.output(), "error: {}", msg)?;
+                errors += 1;
+            }
+        }
+    }
+
+    if errors > 0 {
+        Err(Error::ConfigCheckFailed { count: errors })
+    } else {
+        writeln!(session.output(), "Configuration OK")?;
+        Ok(())
+    }
+}
+
+/// Execute the given command with the given configuration, in the given session.
+pub fn execute(session: &Session, command: Command, config: Config) -> Result<()> {
+    let _entered = session.enter();
+    let store = NoteStore::open(config)?;
+    let config = store.config();
+    if config.storage() != Storage::Files && !command.supports_any_storage() {
+        return Err(Error::RequiresFileStorage);
+    }
+
+    match command {
+        Command::New {
+            name,
+            title,
+            from_clipboard: false,
+        } => new(session, &store, name, title),
+        Command::New { name, title, .. } => new_from_clipboard(session, &store, name, title),
+        Command::List(options) => list(session, &store, &options),
+        Command::View { index } => view(&store, index),
+        Command::Cat { index } => cat(session, &store, index),
+        Command::Copy { index } => copy(session, &store, index),
+        Command::Share { index } => share(session, &store, index),
+        Command::Mail { index, to } => mail(session, &store, index, &to),
+        Command::Export {
+            via_pandoc: true,
+            to: Some(format),
+            output,
+            target,
+            ..
+        } => export_note(session, &store, &target, &format, output),
+        Command::Export { site, target, .. } => export(session, &store, site, &target),
+        Command::Import { from, path } => import(session, &store, &from, &path),
+        Command::Edit {
+            index,
+            line,
+            at,
+            force,
+        } => edit(&store, index, line, at.as_deref(), force),
+        Command::Open { index } => open(config, index),
+        Command::Exec { indices, command } => exec(config, &indices, &command),
+        Command::Mv { index, destination } => mv(session, &store, index, &destination),
+        Command::Conflicts { list } => resolve_conflicts(session, config, list),
+        Command::Merge {
+            indices,
+            into,
+            delete,
+        } => merge(session, config, &indices, into, delete),
+        Command::RenameAll { from, to, dry_run } => {
+            rename_all(session, config, &Regex::new(&from)?, &to, dry_run)
+        }
+        Command::Attach { index, file } => attach(session, config, index, &file),
+        Command::Attachments { index } => attachments(session, config, index),
+        Command::Backups { index } => backups(session, config, index),
+        Command::History { index } => history(session, config, index),
+        Command::Diff {
+            index,
+            other,
+            version,
+        } => diff(session, config, index, other, version),
+        Command::RestoreBackup { note, backup } => restore_backup(session, config, &note, backup),
+        Command::Search {
+            pattern,
+            folder,
+            ignore_case,
+            case_sensitive,
+            smart_case,
+            no_rank,
+            vimgrep,
+            json,
+            files_with_matches,
+            count,
+            interactive,
+        } => {
+            let pattern = pattern.unwrap_or_default();
+            let case = if ignore_case {
+                SearchCase::Insensitive
+            } else if case_sensitive {
+                SearchCase::Sensitive
+            } else if smart_case {
+                SearchCase::Smart
+            } else {
+                config.search_case()
+            };
+            if interactive {
+                return interactive_search(config, pattern, folder.as_deref(), case, no_rank);
+            }
+
+            let ignore_case = case.ignore_case(&pattern);
+            let format = if vimgrep {
+                SearchFormat::Vimgrep
+            } else if json {
+                SearchFormat::Json
+            } else if files_with_matches {
+                SearchFormat::FilesWithMatches
+            } else if count {
+                SearchFormat::Count
+            } else {
+                SearchFormat::Text
+            };
+            let folder = folder.as_deref();
+            search(
+                session,
+                config,
+                &pattern,
+                folder,
+                ignore_case,
+                no_rank,
+                format,
+            )
+        }
+        Command::Replace {
+            pattern,
+            replacement,
+            indices,
+            fixed_strings,
+            dry_run,
+        } => {
+            let regex = if fixed_strings {
+                Regex::literal(&pattern)
+            } else {
+                Regex::new(&pattern)?
+            };
+            replace(session, config, &regex, &replacement, &indices, dry_run)
+        }
+        Command::Rm { index } => rm(session, &store, index),
+        Command::Index { command } => match command {
+            IndexCommand::Rebuild => {
+                index::rebuild(config)?;
+                Ok(())
+            }
+        },
+        Command::Dedupe { near } => dedupe(session, config, near),
+        Command::Prune => prune(session, config),
+        Command::Due { overdue, within } => due(session, config, overdue, within),
+        Command::Cal { month } => cal(session, &store, month),
+        Command::Log { message } => log(config, message),
+        Command::Scratch { clear } => scratch(config, clear),
+        Command::Undo => undo(session, config),
+        Command::Tidy {
+            older_than, delete, ..
+        } => tidy(session, config, older_than, delete),
+        Command::Config { command } => config_command(session, &command, None),
+        Command::Meta { command } => meta(session, config, command),
+        Command::NotesDir => notes_dir(session, config),
+        Command::OpenDir => open_dir(config),
+        Command::Shell => shell(config),
+        Command::Serve { port } => serve(session, &store, port),
+        Command::Sync { rsync, dry_run } => sync(
+            session,
+            config,
+            rsync.as_deref(),
+            dry_run || session.dry_run(),
+        ),
+        Command::Backup { out, to, command } => {
+            backup(session, config, out, to.as_deref(), command)
+        }
+        Command::Watch { json, interval } => {
+            let interval = interval.to_std().unwrap_or_default();
+            watch::watch(session, config, json, interval)
+        }
+        Command::External(args) => external(config, args),
+    }
+}
+
+/// Run the Newt CLI.
+///
+/// An error is reported on standard error before it is returned, followed in verbose mode by the
+/// chain of errors that caused it.
+pub fn run() -> Result<()> {
+    let mut session = Session::new();
+    let result = run_session(Options::from_args(), &mut session);
+    if let Err(err) = &result {
+        report(&session, err);
+    }
+    result
+}
+
+/// Print an error to the session's error stream, followed in verbose mode by the chain of errors
+/// that caused it.
+///
+/// A search that found no matches is not reported, as with grep.
+pub fn report(session: &Session, err: &Error) {
+    if matches!(err, Error::NoMatches) {
+        return;
+    }
+
+    let mut errors = session.errors();
+    let _ = writeln!(errors, "{}", err);
+    if session.log_level() >= Level::Info {
+        let mut source = std::error::Error::source(err);
+        while let Some(err) = source {
+            let _ = writeln!(errors, "  caused by: {}", err);
+            source = err.source();
+        }
+    }
+}
+
+fn run_session(mut options: Options, session: &mut Session) -> Result<()> {
+    *session = options.session();
+    let mut entered = session.enter();
+
+    // Configuration commands must not require the configuration to load successfully first.
+    if let Some(Command::Config { command }) = &options.command {
+        return config_command(session, command, options.config.as_deref());
+    }
+
+    let mut config = options.config()?;
+
+    if let Some(Command::External(args)) = &options.command {
+        if let Some(expansion) = config.alias(&args[0]) {
+            let mut argv: Vec<_> = std::env::args_os().collect();
+            let start = argv.len() - args.len();
+            argv.splice(
+                start..start + 1,
+                util::sh::split(expansion).into_iter().map(OsString::from),
+            );
+
+            options = Options::from_iter(argv);
+            drop(entered);
+            *session = options.session();
+            entered = session.enter();
+            config = options.config()?;
+        }
+    }
+
+    if let Some(path) = config.log_file()? {
+        drop(entered);
+        *session = std::mem::take(session).with_log_file(path)?;
+        entered = session.enter();
+    }
+    if !options.no_input {
+        *session = std::mem::take(session).with_non_interactive(config.non_interactive());
+    }
+
+    ensure_notes_dir(session, &config)?;
+    if !options.rpc && !matches!(options.command, Some(Command::Backup { .. })) {
+        if let Err(err) = scheduled_backup(session, &config) {
+            warn!("Scheduled backup failed: {}", err);
+        }
+    }
+    let result = if options.rpc {
+        NoteStore::open(config).and_then(|store| rpc::run(session, &store))
+    } else {
+        execute(session, options.command.unwrap_or_default(), config)
+    };
+    drop(entered);
+    result
+}
+
+/// Create the notes directory if it does not exist, as allowed by the create_notes_dir setting.
+fn ensure_notes_dir(session: &Session, config: &Config) -> Result<()> {
+    let path = match config.missing_notes_dir()? {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let create = match config.create_notes_dir() {
+        CreateNotesDir::Always => true,
+        CreateNotesDir::Ask => {
+            let prompt = format!(
+                "Notes directory {} does not exist. Create it?",
+                path.display()
+            );
+            session.prompt(&prompt, Some(true), None, None)?
+        }
+        CreateNotesDir::Never => false,
+    };
+
+    if !create {
+        return Err(Error::NoNotesDir);
+    }
+
+    std::fs::create_dir_all(&path).context("creating", &path)?;
+    writeln!(
+        session.errors(),
+        "Created notes directory {}",
+        path.display()
+    )?;
+    Ok(())
 }
@@ -0,0 +1,149 @@
+//! The system clipboard, used through the platform's clipboard programs.
+//!
+//! On macOS these are `pbcopy` and `pbpaste`, and on Windows `clip` and PowerShell. Elsewhere,
+//! `wl-copy` and `wl-paste` are used under Wayland, and `xclip` or `xsel` under X11; whichever is
+//! installed and has a display to talk to is used.
+
+use crate::error::*;
+use crate::util::env;
+
+use std::io::{ErrorKind, Write};
+use std::process::{Command, Stdio};
+
+/// A pair of programs for copying text to the clipboard and pasting it from there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Programs {
+    /// The command that copies its standard input to the clipboard.
+    copy: &'static [&'static str],
+
+    /// The command that prints the clipboard's contents.
+    paste: &'static [&'static str],
+
+    /// An environment variable that must be set for the programs to work, such as the display
+    /// they talk to.
+    needs_var: Option<&'static str>,
+}
+
+#[cfg(target_os = "macos")]
+const PROGRAMS: &[Programs] = &[Programs {
+    copy: &["pbcopy"],
+    paste: &["pbpaste"],
+    needs_var: None,
+}];
+
+#[cfg(windows)]
+const PROGRAMS: &[Programs] = &[Programs {
+    copy: &["clip"],
+    paste: &["powershell", "-NoProfile", "-Command", "Get-Clipboard -Raw"],
+    needs_var: None,
+}];
+
+#[cfg(not(any(target_os = "macos", windows)))]
+const PROGRAMS: &[Programs] = &[
+    Programs {
+        copy: &["wl-copy"],
+        paste: &["wl-paste", "--no-newline"],
+        needs_var: Some("WAYLAND_DISPLAY"),
+    },
+    Programs {
+        copy: &["xclip", "-selection", "clipboard"],
+        paste: &["xclip", "-selection", "clipboard", "-out"],
+        needs_var: Some("DISPLAY"),
+    },
+    Programs {
+        copy: &["xsel", "--clipboard", "--input"],
+        paste: &["xsel", "--clipboard", "--output"],
+        needs_var: Some("DISPLAY"),
+    },
+];
+
+/// Copy text to the clipboard.
+pub fn copy(text: &str) -> Result<()> {
+    let programs = find(PROGRAMS).ok_or(Error::NoClipboard)?;
+    let mut cmd = command(programs.copy);
+
+    // Some copy programs stay in the background to serve the clipboard, so their output is not
+    // waited for.
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|err| cannot_invoke(programs.copy[0], err))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        match stdin.write_all(text.as_bytes()) {
+            Err(err) if err.kind() != ErrorKind::BrokenPipe => return Err(err.into()),
+            _ => {}
+        }
+    }
+    check(programs.copy[0], child.wait()?)
+}
+
+/// The text on the clipboard.
+pub fn paste() -> Result<String> {
+    let programs = find(PROGRAMS).ok_or(Error::NoClipboard)?;
+    let output = command(programs.paste)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| cannot_invoke(programs.paste[0], err))?;
+    check(programs.paste[0], output.status)?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The first of the given clipboard programs that is installed and usable.
+fn find(candidates: &[Programs]) -> Option<Programs> {
+    candidates.iter().copied().find(|programs| {
+        let has_var = match programs.needs_var {
+            Some(var) => env::env_var(var).filter(|val| !val.is_empty()).is_some(),
+            None => true,
+        };
+        has_var && env::search_path(programs.copy[0]).is_some()
+    })
+}
+
+fn command(words: &[&str]) -> Command {
+    let mut cmd = Command::new(words[0]);
+    cmd.args(&words[1..]);
+    trace!("Running {:?}", cmd);
+    cmd
+}
+
+fn check(program: &str, status: std::process::ExitStatus) -> Result<()> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::ChildFailed {
+            command: String::from(program),
+            status,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn finding_programs() {
+        let missing_display = Programs {
+            copy: &["sh"],
+            paste: &["sh"],
+            needs_var: Some("NEWT_TEST_NO_SUCH_DISPLAY"),
+        };
+        let not_installed = Programs {
+            copy: &["newt-test-no-such-program"],
+            paste: &["newt-test-no-such-program"],
+            needs_var: None,
+        };
+        let usable = Programs {
+            copy: &["sh"],
+            paste: &["cat"],
+            needs_var: None,
+        };
+        assert_eq!(find(&[missing_display, not_installed]), None);
+        assert_eq!(
+            find(&[missing_display, not_installed, usable]),
+            Some(usable)
+        );
+    }
+}
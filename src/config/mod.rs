@@ -1,241 +1,1441 @@
 //! Configuration file definitions.
 
 use crate::error::*;
-use crate::util::env;
+use crate::util::{self, env};
 
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 mod parse;
+mod platform;
+mod toml;
 
-#[cfg(not(debug_assertions))]
-const CONFIG_PATHS: &[&str] = &[
-    "$NEWT_CONFIG",
-    "$XDG_CONFIG_HOME/newt/config",
-    "$HOME/.config/newt/config",
-    "$HOME/.newtrc",
-    "/etc/newtrc",
+pub use platform::default_conf_path;
+use platform::{CONFIG_PATHS, EDITORS, NOTES_PATHS, OPENERS, PAGERS};
+
+const DEFAULT_FILENAME_FORMAT: &str = "%Y-%m-%d_{n}";
+
+const DEFAULT_TITLED_FILENAME_FORMAT: &str = "%Y-%m-%d-{title}";
+
+/// The file name format for new notes in an Obsidian vault, which names notes by their titles.
+const OBSIDIAN_FILENAME_FORMAT: &str = "{title}";
+
+const DEFAULT_NOTE_EXTENSION: &str = "md";
+
+const DEFAULT_DAILY_FORMAT: &str = "%Y-%m-%d";
+
+const DEFAULT_SCRATCH_FILE: &str = "scratch.md";
+
+const DEFAULT_BACKUP_COUNT: usize = 10;
+
+const DEFAULT_PREVIEW_BYTES: usize = 64 * 1024;
+
+/// A fully commented configuration file documenting every supported key.
+pub const DEFAULT_CONFIG: &str = include_str!("default_config");
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// The keys recognized in configuration files.
+pub(crate) const KEYS: &[&str] = &[
+    "include",
+    "notes_dir",
+    "editor",
+    "editor_fallback",
+    "pager",
+    "opener",
+    "pandoc",
+    "sync_remote",
+    "share_command",
+    "gist_token",
+    "mail_command",
+    "viewer",
+    "alias",
+    "filename_format",
+    "daily_format",
+    "scratch_file",
+    "log_file",
+    "tidy_after",
+    "backup_every",
+    "backup_dir",
+    "note_extension",
+    "note_extensions",
+    "preview_lines",
+    "preview_bytes",
+    "backup_count",
+    "create_notes_dir",
+    "non_interactive",
+    "storage",
+    "compat",
+    "search_case",
+    "shell",
+    "shell_exec",
+    "safe_edit",
+    "strict_editor",
+    "follow_symlinks",
+    "frontmatter",
+    "discard_empty",
 ];
 
-#[cfg(debug_assertions)]
-const CONFIG_PATHS: &[&str] = &[
-    "$NEWT_CONFIG",
-    concat!(env!("CARGO_MANIFEST_DIR"), "/newtrc"),
-    concat!(env!("CARGO_MANIFEST_DIR"), "/config"),
-    "./newtrc",
-    "./config",
-];
+/// The tables recognized in TOML configuration files.
+pub(crate) const TABLES: &[&str] = &["alias", "viewer"];
+
+/// Resolve a command from a configured list of candidates, or else from a list of defaults.
+///
+/// The first candidate whose program is found on `$PATH` is chosen. If none of the configured
+/// candidates are found, the first is used anyway, so that any error refers to the command the
+/// user asked for.
+fn resolve_command<S: AsRef<str>>(
+    configured: Option<&[PathBuf]>,
+    defaults: &[S],
+) -> Result<Option<PathBuf>> {
+    if let Some(candidates) = configured.filter(|candidates| !candidates.is_empty()) {
+        let candidates = candidates
+            .iter()
+            .map(|path| expand_path(path))
+            .collect::<Result<Vec<_>>>()?;
+        return Ok(candidates
+            .iter()
+            .find(|command| on_path(command))
+            .or_else(|| candidates.first())
+            .cloned());
+    }
+
+    let defaults = defaults
+        .iter()
+        .map(|command| env::expand_path(command).map(PathBuf::from))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(defaults.into_iter().find(|command| on_path(command)))
+}
+
+/// Expand a leading tilde and any environment variables in a configured path.
+fn expand_path(path: &Path) -> Result<PathBuf> {
+    match path.to_str() {
+        Some(s) => Ok(PathBuf::from(env::expand_path(s)?)),
+        None => Ok(PathBuf::from(path)),
+    }
+}
+
+/// Whether the program of the given command line can be found on `$PATH`.
+fn on_path(command: &Path) -> bool {
+    command
+        .to_str()
+        .and_then(|command| util::sh::split(command).into_iter().next())
+        .map(|program| env::search_path(program).is_some())
+        .unwrap_or(false)
+}
+
+/// Find the configuration file that would be used, if any.
+pub fn find_conf_file() -> Option<PathBuf> {
+    for path in CONFIG_PATHS.iter().map(env::interpolate).map(PathBuf::from) {
+        if let Ok(metadata) = std::fs::metadata(&path) {
+            if metadata.is_file() {
+                info!("Using configuration file {}", path.display());
+                return Some(path);
+            }
+        }
+    }
+
+    info!("No configuration file found, using default config");
+    None
+}
+
+/// Resolve the Newt configuration from the runtime environment.
+pub fn resolve() -> Result<Config> {
+    if let Some(path) = find_conf_file() {
+        read_config_file(path)
+    } else {
+        Ok(Config::default())
+    }
+}
+
+/// Read the Newt configuration from the given file.
+///
+/// Files with a `.toml` extension are parsed as TOML; any other file is parsed in the plain
+/// key/value format.
+pub fn read_config_file<P: AsRef<Path>>(path: P) -> Result<Config> {
+    let (config, errors) = read_config_file_partial(path)?;
+    config_errors(errors)?;
+    Ok(config)
+}
+
+/// Read as much of the Newt configuration from the given file as is valid, returning the errors
+/// in the rest of it, such as unrecognized keys or invalid values, alongside.
+///
+/// Fails only if the file, or a file it includes, cannot be read or parsed at all.
+pub fn read_config_file_partial<P: AsRef<Path>>(path: P) -> Result<(Config, Vec<Error>)> {
+    let mut errors = Vec::new();
+    let config = read_config_file_nested(path.as_ref(), 0, &mut errors)?;
+    Ok((config, errors))
+}
+
+fn read_config_file_nested(path: &Path, depth: usize, errors: &mut Vec<Error>) -> Result<Config> {
+    let path = PathBuf::from(path);
+    let mut file = File::open(&path).context("opening", &path)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)
+        .context("reading", &path)?;
+
+    let dir = path.parent();
+    let mut file_errors = Vec::new();
+    let config = if path.extension().map(|ext| ext == "toml").unwrap_or(false) {
+        toml::parse(&contents, dir, depth, &mut file_errors)
+    } else {
+        parse_config_partial(&contents, dir, depth, &mut file_errors)
+    };
+    errors.extend(file_errors.into_iter().map(|err| err.in_config_file(&path)));
+    config.map_err(|err| err.in_config_file(&path))
+}
+
+/// Layer the configuration file at the given path on top of the given configuration.
+///
+/// Relative paths are resolved against `dir`, the directory of the including file. Files that
+/// do not exist are ignored, so that optional machine-specific overrides can be included
+/// unconditionally. Errors in the included file that do not stop it being read are added to
+/// `errors`.
+fn include(
+    config: Config,
+    path: &str,
+    dir: Option<&Path>,
+    depth: usize,
+    line: usize,
+    errors: &mut Vec<Error>,
+) -> Result<Config> {
+    if depth >= MAX_INCLUDE_DEPTH {
+        return Err(ConfigErrorKind::IncludeDepth.at_line(line, None::<&Path>));
+    }
+
+    let path = expand_path(Path::new(path))?;
+    let path = match dir {
+        Some(dir) => dir.join(path),
+        None => path,
+    };
+
+    match read_config_file_nested(&path, depth + 1, errors) {
+        Ok(included) => Ok(config.overlay(included)),
+        Err(err) if err.io_kind() == Some(std::io::ErrorKind::NotFound) => {
+            debug!(
+                "Skipping missing included configuration file {}",
+                path.display()
+            );
+            Ok(config)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// A problem with a configuration, found by [`Config::check`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// Something that may not work as intended, but will not stop Newt from running.
+    Warning(String),
+
+    /// Something that will stop Newt from working.
+    Error(String),
+}
+
+/// How the search command treats case when matching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchCase {
+    /// Case must match exactly.
+    Sensitive,
+
+    /// Case is ignored.
+    Insensitive,
+
+    /// Case is ignored unless the search text contains an uppercase letter.
+    Smart,
+}
+
+impl SearchCase {
+    /// Whether case should be ignored when searching for the given text.
+    pub fn ignore_case(self, pattern: &str) -> bool {
+        match self {
+            SearchCase::Sensitive => false,
+            SearchCase::Insensitive => true,
+            SearchCase::Smart => !pattern.chars().any(char::is_uppercase),
+        }
+    }
+}
+
+/// Whether Newt creates the notes directory when it does not exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreateNotesDir {
+    /// The directory is created without asking.
+    Always,
+
+    /// The user is asked before the directory is created.
+    Ask,
+
+    /// The directory is never created.
+    Never,
+}
+
+/// How prompts are answered when there is no terminal to ask them on, as when Newt is run by
+/// cron or a CI job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonInteractive {
+    /// Prompts are given their default answer, and fail if they have none.
+    Default,
+
+    /// Prompts are answered 'yes'.
+    Yes,
+
+    /// Prompts fail.
+    Fail,
+}
+
+/// How notes are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Storage {
+    /// Each note is a plain file in the notes directory.
+    Files,
+
+    /// Notes and their metadata are kept in a single SQLite database in the notes directory.
+    /// Requires Newt to be built with the `sqlite` feature.
+    Sqlite,
+}
+
+/// The conventions of another note app that notes are kept to, so that both can be used on the
+/// same notes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compat {
+    /// Only Newt's own conventions.
+    None,
+
+    /// The notes directory is an Obsidian vault. New notes are named by their titles, and
+    /// wiki-style links to notes are updated when the notes are moved or renamed.
+    Obsidian,
+}
+
+/// Newt configuration options.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct Config {
+    notes_dir: Option<PathBuf>,
+    create_notes_dir: Option<CreateNotesDir>,
+    non_interactive: Option<NonInteractive>,
+    storage: Option<Storage>,
+    compat: Option<Compat>,
+    editor: Option<Vec<PathBuf>>,
+    editor_fallback: Option<Vec<String>>,
+    pager: Option<Vec<PathBuf>>,
+    opener: Option<Vec<PathBuf>>,
+    pandoc: Option<Vec<PathBuf>>,
+    sync_remote: Option<String>,
+    share_command: Option<Vec<PathBuf>>,
+    gist_token: Option<String>,
+    mail_command: Option<Vec<PathBuf>>,
+    viewers: Vec<(String, PathBuf)>,
+    no_pager: bool,
+    discard_empty: Option<bool>,
+    filename_format: Option<String>,
+    note_extension: Option<String>,
+    note_extensions: Option<Vec<String>>,
+    all_files: bool,
+    follow_symlinks: Option<bool>,
+    frontmatter: Option<bool>,
+    shell: Option<PathBuf>,
+    shell_exec: Option<bool>,
+    safe_edit: Option<bool>,
+    strict_editor: Option<bool>,
+    daily_format: Option<String>,
+    scratch_file: Option<PathBuf>,
+    tidy_after: Option<chrono::Duration>,
+    backup_every: Option<chrono::Duration>,
+    backup_dir: Option<PathBuf>,
+    preview_lines: Option<usize>,
+    preview_bytes: Option<usize>,
+    backup_count: Option<usize>,
+    search_case: Option<SearchCase>,
+    log_file: Option<PathBuf>,
+    aliases: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// The configured notes directory, if available.
+    pub fn notes_dir(&self) -> Result<PathBuf> {
+        if let Some(path) = &self.notes_dir {
+            return expand_path(path);
+        }
+
+        NOTES_PATHS
+            .iter()
+            .map(env::interpolate)
+            .map(PathBuf::from)
+            .find(|path| {
+                if let Ok(md) = std::fs::metadata(path) {
+                    if md.is_dir() {
+                        info!("Using notes directory {}", path.display());
+                        true
+                    } else {
+                        false
+                    }
+                } else {
+                    false
+                }
+            })
+            .ok_or(Error::NoNotesDir)
+    }
+
+    /// The notes directory that would be used, if it does not exist yet.
+    ///
+    /// If no notes directory is configured, this is the first of the default locations that can be
+    /// resolved.
+    pub fn missing_notes_dir(&self) -> Result<Option<PathBuf>> {
+        let path = match &self.notes_dir {
+            Some(path) => expand_path(path)?,
+            None if self.notes_dir().is_ok() => return Ok(None),
+            None => match NOTES_PATHS
+                .iter()
+                .map(env::interpolate)
+                .find(|path| !path.is_empty())
+            {
+                Some(path) => PathBuf::from(path),
+                None => return Err(Error::NoNotesDir),
+            },
+        };
+
+        if path.is_dir() {
+            Ok(None)
+        } else {
+            Ok(Some(path))
+        }
+    }
+
+    /// Whether a missing notes directory is created.
+    pub fn create_notes_dir(&self) -> CreateNotesDir {
+        self.create_notes_dir.unwrap_or(CreateNotesDir::Ask)
+    }
+
+    /// How notes are stored.
+    pub fn storage(&self) -> Storage {
+        self.storage.unwrap_or(Storage::Files)
+    }
+
+    /// The conventions of another note app that notes are kept to.
+    pub fn compat(&self) -> Compat {
+        self.compat.unwrap_or(Compat::None)
+    }
+
+    /// The configured editor command, if available.
+    pub fn editor(&self) -> Result<PathBuf> {
+        match &self.editor_fallback {
+            Some(fallback) => resolve_command(self.editor.as_deref(), fallback),
+            None => resolve_command(self.editor.as_deref(), EDITORS),
+        }?
+        .ok_or(Error::NoEditor)
+    }
+
+    /// The configured pager command, if available.
+    pub fn pager(&self) -> Result<PathBuf> {
+        resolve_command(self.pager.as_deref(), PAGERS)?.ok_or(Error::NoPager)
+    }
+
+    /// The configured command for opening files and directories in their default application, if
+    /// available.
+    pub fn opener(&self) -> Result<PathBuf> {
+        resolve_command(self.opener.as_deref(), OPENERS)?.ok_or(Error::NoOpener)
+    }
+
+    /// The configured pandoc command, for converting notes to other formats, if available.
+    pub fn pandoc(&self) -> Result<PathBuf> {
+        resolve_command(self.pandoc.as_deref(), &["pandoc"])?.ok_or(Error::NoPandoc)
+    }
+
+    /// The viewer command for the given file, if a viewer rule matches its name.
+    ///
+    /// Rules are tried in order, and the first whose pattern matches the file name is used.
+    pub fn viewer<P: AsRef<Path>>(&self, path: P) -> Result<Option<PathBuf>> {
+        let name = match path.as_ref().file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+
+        self.viewers
+            .iter()
+            .find(|(pattern, _)| util::glob_match(pattern, name))
+            .map(|(_, command)| expand_path(command))
+            .transpose()
+    }
+
+    /// Whether automatic paging of long command output is disabled.
+    pub fn no_pager(&self) -> bool {
+        self.no_pager
+    }
+
+    /// The format used to generate the file names of daily notes.
+    pub fn daily_format(&self) -> &str {
+        self.daily_format.as_deref().unwrap_or(DEFAULT_DAILY_FORMAT)
+    }
+
+    /// The path to the scratch note, relative to the notes directory.
+    pub fn scratch_file(&self) -> PathBuf {
+        let path = self
+            .scratch_file
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SCRATCH_FILE));
+        if let Some(s) = path.to_str() {
+            PathBuf::from(env::interpolate(s))
+        } else {
+            path
+        }
+    }
+
+    /// The age after which notes are automatically archived when listing, if enabled.
+    pub fn tidy_after(&self) -> Option<chrono::Duration> {
+        self.tidy_after
+    }
+
+    /// How often the whole notes directory should be backed up, if backups are scheduled.
+    pub fn backup_every(&self) -> Option<chrono::Duration> {
+        self.backup_every
+    }
+
+    /// The directory the backup command writes archives to when not told where, if one is
+    /// configured.
+    pub fn backup_dir(&self) -> Result<Option<PathBuf>> {
+        self.backup_dir.as_deref().map(expand_path).transpose()
+    }
+
+    /// The number of preview lines the list command shows for each note by default, if set.
+    pub fn preview_lines(&self) -> Option<usize> {
+        self.preview_lines
+    }
+
+    /// The number of bytes read from the start of a note when finding its title or preview.
+    pub fn preview_bytes(&self) -> usize {
+        self.preview_bytes.unwrap_or(DEFAULT_PREVIEW_BYTES)
+    }
+
+    /// The expansion of the given command alias, if one is defined.
+    pub fn alias(&self, name: &str) -> Option<&str> {
+        self.aliases.get(name).map(String::as_str)
+    }
+
+    /// The file extension given to new notes, without a leading `.`.
+    pub fn note_extension(&self) -> &str {
+        self.note_extension
+            .as_deref()
+            .unwrap_or(DEFAULT_NOTE_EXTENSION)
+    }
+
+    /// The file extensions of files recognized as notes, if restricted.
+    ///
+    /// Returns `None` if all files are recognized, either because no extensions are configured or
+    /// because the restriction has been overridden.
+    pub fn note_extensions(&self) -> Option<&[String]> {
+        if self.all_files {
+            None
+        } else {
+            self.note_extensions.as_deref()
+        }
+    }
+
+    /// The number of backups kept for each note. Zero disables backups.
+    pub fn backup_count(&self) -> usize {
+        self.backup_count.unwrap_or(DEFAULT_BACKUP_COUNT)
+    }
+
+    /// How prompts are answered when there is no terminal to ask them on.
+    pub fn non_interactive(&self) -> NonInteractive {
+        self.non_interactive.unwrap_or(NonInteractive::Default)
+    }
+
+    /// How the search command treats case when matching, unless overridden on the command line.
+    pub fn search_case(&self) -> SearchCase {
+        self.search_case.unwrap_or(SearchCase::Sensitive)
+    }
+
+    /// The file log messages are appended to, if one is configured.
+    pub fn log_file(&self) -> Result<Option<PathBuf>> {
+        self.log_file.as_deref().map(expand_path).transpose()
+    }
+
+    /// The shell used to run commands and interactive sessions.
+    ///
+    /// Defaults to `$SHELL`, falling back to `sh`.
+    pub fn shell(&self) -> Result<PathBuf> {
+        match &self.shell {
+            Some(shell) => expand_path(shell),
+            None => Ok(env::env_var("SHELL")
+                .filter(|shell| !shell.is_empty())
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("sh"))),
+        }
+    }
+
+    /// Whether the editor, pager, and opener commands are run through the shell.
+    pub fn shell_exec(&self) -> bool {
+        self.shell_exec.unwrap_or(false)
+    }
+
+    /// Whether notes are edited through a temporary copy that replaces the note only if the editor
+    /// exits successfully.
+    pub fn safe_edit(&self) -> bool {
+        self.safe_edit.unwrap_or(false)
+    }
+
+    /// Whether an editor, pager, or other program exiting unsuccessfully is an error, rather than
+    /// a warning.
+    pub fn strict_editor(&self) -> bool {
+        self.strict_editor.unwrap_or(false)
+    }
+
+    /// Whether symlinks to notes and directories in the notes directory are followed.
+    pub fn follow_symlinks(&self) -> bool {
+        self.follow_symlinks.unwrap_or(true)
+    }
+
+    /// Whether new notes are given a YAML frontmatter block.
+    pub fn frontmatter(&self) -> bool {
+        self.frontmatter.unwrap_or(false)
+    }
+
+    /// Whether to delete new notes that are left empty after editing.
+    ///
+    /// `None` means the user should be asked.
+    pub fn discard_empty(&self) -> Option<bool> {
+        self.discard_empty
+    }
+
+    /// The rsync destination the notes directory is synced with by default, such as
+    /// `user@host:notes/`, if one is configured.
+    pub fn sync_remote(&self) -> Option<&str> {
+        self.sync_remote.as_deref()
+    }
+
+    /// The configured command for sharing notes, if any.
+    pub fn share_command(&self) -> Result<Option<PathBuf>> {
+        resolve_command::<&str>(self.share_command.as_deref(), &[])
+    }
+
+    /// The GitHub token used to share notes as gists: the configured one, or `$GITHUB_TOKEN`.
+    pub fn gist_token(&self) -> Option<String> {
+        self.gist_token
+            .clone()
+            .or_else(|| env::env_var("GITHUB_TOKEN")?.into_string().ok())
+            .filter(|token| !token.is_empty())
+    }
+
+    /// The configured command for sending notes by email, if available.
+    pub fn mail_command(&self) -> Result<PathBuf> {
+        resolve_command(self.mail_command.as_deref(), &["msmtp -t", "sendmail -t"])?
+            .ok_or(Error::NoMailCommand)
+    }
+
+    /// The format used to generate file names for new notes.
+    ///
+    /// If no format is configured, the default depends on whether the new note has a title, except
+    /// in an Obsidian vault, where notes are named by their titles.
+    pub fn filename_format(&self, titled: bool) -> &str {
+        self.filename_format
+            .as_deref()
+            .unwrap_or(match (self.compat(), titled) {
+                (Compat::Obsidian, _) => OBSIDIAN_FILENAME_FORMAT,
+                (Compat::None, true) => DEFAULT_TITLED_FILENAME_FORMAT,
+                (Compat::None, false) => DEFAULT_FILENAME_FORMAT,
+            })
+    }
+}
+
+impl Config {
+    /// Check this configuration for values that will not work at runtime.
+    ///
+    /// The notes directory must exist and an editor must be available. Configured commands that
+    /// cannot be found on `$PATH` are reported as warnings.
+    pub fn check(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+
+        match self.notes_dir() {
+            Ok(dir) if !dir.is_dir() => problems.push(Problem::Error(format!(
+                "notes directory {} does not exist",
+                dir.display()
+            ))),
+            Ok(_) => {}
+            Err(err) => problems.push(Problem::Error(err.to_string())),
+        }
+
+        let commands = [
+            ("editor", &self.editor, self.editor()),
+            ("pager", &self.pager, self.pager()),
+            ("opener", &self.opener, self.opener()),
+        ];
+        for (key, configured, resolved) in commands.iter() {
+            for command in configured.iter().flatten() {
+                if !on_path(command) {
+                    problems.push(Problem::Warning(format!(
+                        "{} command `{}` not found on $PATH",
+                        key,
+                        command.display()
+                    )));
+                }
+            }
+
+            let usable = resolved.as_ref().map(|c| on_path(c)).unwrap_or(false);
+            if !usable {
+                let msg = format!("no usable {} found", key);
+                problems.push(if *key == "editor" {
+                    Problem::Error(msg)
+                } else {
+                    Problem::Warning(msg)
+                });
+            }
+        }
+
+        problems
+    }
+
+    /// Layer another `Config` on top of this one.
+    ///
+    /// Options set in `other` take precedence over those set in `self`.
+    pub fn overlay(self, other: Config) -> Self {
+        let mut aliases = self.aliases;
+        aliases.extend(other.aliases);
+        let mut viewers = other.viewers;
+        viewers.extend(self.viewers);
+        Config {
+            notes_dir: other.notes_dir.or(self.notes_dir),
+            editor: other.editor.or(self.editor),
+            editor_fallback: other.editor_fallback.or(self.editor_fallback),
+            pager: other.pager.or(self.pager),
+            opener: other.opener.or(self.opener),
+            pandoc: other.pandoc.or(self.pandoc),
+            sync_remote: other.sync_remote.or(self.sync_remote),
+            share_command: other.share_command.or(self.share_command),
+            gist_token: other.gist_token.or(self.gist_token),
+            mail_command: other.mail_command.or(self.mail_command),
+            viewers,
+            no_pager: other.no_pager || self.no_pager,
+            discard_empty: other.discard_empty.or(self.discard_empty),
+            filename_format: other.filename_format.or(self.filename_format),
+            note_extension: other.note_extension.or(self.note_extension),
+            note_extensions: other.note_extensions.or(self.note_extensions),
+            all_files: other.all_files || self.all_files,
+            follow_symlinks: other.follow_symlinks.or(self.follow_symlinks),
+            frontmatter: other.frontmatter.or(self.frontmatter),
+            shell: other.shell.or(self.shell),
+            shell_exec: other.shell_exec.or(self.shell_exec),
+            safe_edit: other.safe_edit.or(self.safe_edit),
+            strict_editor: other.strict_editor.or(self.strict_editor),
+            daily_format: other.daily_format.or(self.daily_format),
+            scratch_file: other.scratch_file.or(self.scratch_file),
+            tidy_after: other.tidy_after.or(self.tidy_after),
+            backup_every: other.backup_every.or(self.backup_every),
+            backup_dir: other.backup_dir.or(self.backup_dir),
+            preview_lines: other.preview_lines.or(self.preview_lines),
+            preview_bytes: other.preview_bytes.or(self.preview_bytes),
+            backup_count: other.backup_count.or(self.backup_count),
+            log_file: other.log_file.or(self.log_file),
+            search_case: other.search_case.or(self.search_case),
+            non_interactive: other.non_interactive.or(self.non_interactive),
+            create_notes_dir: other.create_notes_dir.or(self.create_notes_dir),
+            storage: other.storage.or(self.storage),
+            compat: other.compat.or(self.compat),
+            aliases,
+        }
+    }
+
+    /// Set the notes dir on this `Config`.
+    pub fn with_notes_dir<O: Into<Option<PathBuf>>>(self, notes_dir: O) -> Self {
+        Config {
+            notes_dir: notes_dir.into().or(self.notes_dir),
+            ..self
+        }
+    }
+
+    /// Set an ordered list of fallback editors on this `Config`.
+    pub fn with_editors<O: Into<Option<Vec<PathBuf>>>>(self, editors: O) -> Self {
+        Config {
+            editor: editors.into().or(self.editor),
+            ..self
+        }
+    }
+
+    /// Set the editors to try, in order, when no editor is configured on this `Config`.
+    pub fn with_editor_fallback<O: Into<Option<Vec<String>>>>(self, editor_fallback: O) -> Self {
+        Config {
+            editor_fallback: editor_fallback.into().or(self.editor_fallback),
+            ..self
+        }
+    }
+
+    /// Set the editor on this `Config`.
+    pub fn with_editor<O: Into<Option<PathBuf>>>(self, editor: O) -> Self {
+        Config {
+            editor: editor.into().map(|command| vec![command]).or(self.editor),
+            ..self
+        }
+    }
+
+    /// Set the pager on this `Config`.
+    pub fn with_pager<O: Into<Option<PathBuf>>>(self, pager: O) -> Self {
+        Config {
+            pager: pager.into().map(|command| vec![command]).or(self.pager),
+            ..self
+        }
+    }
+
+    /// Set the opener on this `Config`.
+    pub fn with_opener<O: Into<Option<PathBuf>>>(self, opener: O) -> Self {
+        Config {
+            opener: opener.into().map(|command| vec![command]).or(self.opener),
+            ..self
+        }
+    }
+
+    /// Set the pandoc command on this `Config`.
+    pub fn with_pandoc<O: Into<Option<PathBuf>>>(self, pandoc: O) -> Self {
+        Config {
+            pandoc: pandoc.into().map(|command| vec![command]).or(self.pandoc),
+            ..self
+        }
+    }
+
+    /// Disable automatic paging of long command output on this `Config`.
+    pub fn with_no_pager(self, no_pager: bool) -> Self {
+        Config {
+            no_pager: no_pager || self.no_pager,
+            ..self
+        }
+    }
+
+    /// Set the rsync destination to sync with on this `Config`.
+    pub fn with_sync_remote<O: Into<Option<String>>>(self, sync_remote: O) -> Self {
+        Config {
+            sync_remote: sync_remote.into().or(self.sync_remote),
+            ..self
+        }
+    }
+
+    /// Set the share command on this `Config`.
+    pub fn with_share_command<O: Into<Option<PathBuf>>>(self, share_command: O) -> Self {
+        Config {
+            share_command: share_command
+                .into()
+                .map(|command| vec![command])
+                .or(self.share_command),
+            ..self
+        }
+    }
+
+    /// Set the GitHub token for sharing notes as gists on this `Config`.
+    pub fn with_gist_token<O: Into<Option<String>>>(self, gist_token: O) -> Self {
+        Config {
+            gist_token: gist_token.into().or(self.gist_token),
+            ..self
+        }
+    }
+
+    /// Set the mail command on this `Config`.
+    pub fn with_mail_command<O: Into<Option<PathBuf>>>(self, mail_command: O) -> Self {
+        Config {
+            mail_command: mail_command
+                .into()
+                .map(|command| vec![command])
+                .or(self.mail_command),
+            ..self
+        }
+    }
+
+    /// Set the new note file name format on this `Config`.
+    pub fn with_filename_format<O: Into<Option<String>>>(self, filename_format: O) -> Self {
+        Config {
+            filename_format: filename_format.into().or(self.filename_format),
+            ..self
+        }
+    }
+
+    /// Set the daily note file name format on this `Config`.
+    pub fn with_daily_format<O: Into<Option<String>>>(self, daily_format: O) -> Self {
+        Config {
+            daily_format: daily_format.into().or(self.daily_format),
+            ..self
+        }
+    }
+
+    /// Set the scratch note path on this `Config`.
+    pub fn with_scratch_file<O: Into<Option<PathBuf>>>(self, scratch_file: O) -> Self {
+        Config {
+            scratch_file: scratch_file.into().or(self.scratch_file),
+            ..self
+        }
+    }
+
+    /// Set the log file on this `Config`.
+    pub fn with_log_file<O: Into<Option<PathBuf>>>(self, log_file: O) -> Self {
+        Config {
+            log_file: log_file.into().or(self.log_file),
+            ..self
+        }
+    }
+
+    /// Set the automatic archival age on this `Config`.
+    pub fn with_tidy_after<O: Into<Option<chrono::Duration>>>(self, tidy_after: O) -> Self {
+        Config {
+            tidy_after: tidy_after.into().or(self.tidy_after),
+            ..self
+        }
+    }
+
+    /// Set the scheduled backup interval on this `Config`.
+    pub fn with_backup_every<O: Into<Option<chrono::Duration>>>(self, backup_every: O) -> Self {
+        Config {
+            backup_every: backup_every.into().or(self.backup_every),
+            ..self
+        }
+    }
+
+    /// Set the backup directory on this `Config`.
+    pub fn with_backup_dir<O: Into<Option<PathBuf>>>(self, backup_dir: O) -> Self {
+        Config {
+            backup_dir: backup_dir.into().or(self.backup_dir),
+            ..self
+        }
+    }
+
+    /// Add a viewer rule to this `Config`, after any existing ones.
+    pub fn with_viewer<S: Into<String>, P: Into<PathBuf>>(
+        mut self,
+        pattern: S,
+        command: P,
+    ) -> Self {
+        self.viewers.push((pattern.into(), command.into()));
+        self
+    }
+
+    /// Define a command alias on this `Config`, replacing any existing alias of the same name.
+    pub fn with_alias<N: Into<String>, E: Into<String>>(mut self, name: N, expansion: E) -> Self {
+        self.aliases.insert(name.into(), expansion.into());
+        self
+    }
+
+    /// Set the default number of list preview lines on this `Config`.
+    pub fn with_preview_lines<O: Into<Option<usize>>>(self, preview_lines: O) -> Self {
+        Config {
+            preview_lines: preview_lines.into().or(self.preview_lines),
+            ..self
+        }
+    }
+
+    /// Set the number of bytes read for note titles and previews on this `Config`.
+    pub fn with_preview_bytes<O: Into<Option<usize>>>(self, preview_bytes: O) -> Self {
+        Config {
+            preview_bytes: preview_bytes.into().or(self.preview_bytes),
+            ..self
+        }
+    }
+
+    /// Set the new note file extension on this `Config`.
+    pub fn with_note_extension<O: Into<Option<String>>>(self, note_extension: O) -> Self {
+        Config {
+            note_extension: note_extension.into().or(self.note_extension),
+            ..self
+        }
+    }
+
+    /// Set the recognized note file extensions on this `Config`.
+    pub fn with_note_extensions<O: Into<Option<Vec<String>>>>(self, note_extensions: O) -> Self {
+        Config {
+            note_extensions: note_extensions.into().or(self.note_extensions),
+            ..self
+        }
+    }
+
+    /// Recognize files of any extension as notes on this `Config`.
+    pub fn with_all_files(self, all_files: bool) -> Self {
+        Config {
+            all_files: all_files || self.all_files,
+            ..self
+        }
+    }
+
+    /// Set the number of backups kept for each note on this `Config`.
+    pub fn with_backup_count<O: Into<Option<usize>>>(self, backup_count: O) -> Self {
+        Config {
+            backup_count: backup_count.into().or(self.backup_count),
+            ..self
+        }
+    }
+
+    /// Set whether a missing notes directory is created on this `Config`.
+    pub fn with_create_notes_dir<O: Into<Option<CreateNotesDir>>>(
+        self,
+        create_notes_dir: O,
+    ) -> Self {
+        Config {
+            create_notes_dir: create_notes_dir.into().or(self.create_notes_dir),
+            ..self
+        }
+    }
+
+    /// Set how notes are stored on this `Config`.
+    pub fn with_storage<O: Into<Option<Storage>>>(self, storage: O) -> Self {
+        Config {
+            storage: storage.into().or(self.storage),
+            ..self
+        }
+    }
+
+    /// Set the conventions of another note app that notes are kept to on this `Config`.
+    pub fn with_compat<O: Into<Option<Compat>>>(self, compat: O) -> Self {
+        Config {
+            compat: compat.into().or(self.compat),
+            ..self
+        }
+    }
+
+    /// Set how prompts are answered without a terminal on this `Config`.
+    pub fn with_non_interactive<O: Into<Option<NonInteractive>>>(self, non_interactive: O) -> Self {
+        Config {
+            non_interactive: non_interactive.into().or(self.non_interactive),
+            ..self
+        }
+    }
+
+    /// Set how the search command treats case on this `Config`.
+    pub fn with_search_case<O: Into<Option<SearchCase>>>(self, search_case: O) -> Self {
+        Config {
+            search_case: search_case.into().or(self.search_case),
+            ..self
+        }
+    }
+
+    /// Set the shell on this `Config`.
+    pub fn with_shell<O: Into<Option<PathBuf>>>(self, shell: O) -> Self {
+        Config {
+            shell: shell.into().or(self.shell),
+            ..self
+        }
+    }
+
+    /// Set whether commands are run through the shell on this `Config`.
+    pub fn with_shell_exec<O: Into<Option<bool>>>(self, shell_exec: O) -> Self {
+        Config {
+            shell_exec: shell_exec.into().or(self.shell_exec),
+            ..self
+        }
+    }
+
+    /// Set whether notes are edited through a temporary copy on this `Config`.
+    pub fn with_safe_edit<O: Into<Option<bool>>>(self, safe_edit: O) -> Self {
+        Config {
+            safe_edit: safe_edit.into().or(self.safe_edit),
+            ..self
+        }
+    }
+
+    /// Set whether unsuccessful programs are errors on this `Config`.
+    pub fn with_strict_editor<O: Into<Option<bool>>>(self, strict_editor: O) -> Self {
+        Config {
+            strict_editor: strict_editor.into().or(self.strict_editor),
+            ..self
+        }
+    }
+
+    /// Set whether symlinks in the notes directory are followed on this `Config`.
+    pub fn with_follow_symlinks<O: Into<Option<bool>>>(self, follow_symlinks: O) -> Self {
+        Config {
+            follow_symlinks: follow_symlinks.into().or(self.follow_symlinks),
+            ..self
+        }
+    }
+
+    /// Set whether new notes get frontmatter on this `Config`.
+    pub fn with_frontmatter<O: Into<Option<bool>>>(self, frontmatter: O) -> Self {
+        Config {
+            frontmatter: frontmatter.into().or(self.frontmatter),
+            ..self
+        }
+    }
+
+    /// Set the empty note discard policy on this `Config`.
+    pub fn with_discard_empty<O: Into<Option<bool>>>(self, discard_empty: O) -> Self {
+        Config {
+            discard_empty: discard_empty.into().or(self.discard_empty),
+            ..self
+        }
+    }
+}
+
+impl FromStr for Config {
+    type Err = Error;
+
+    fn from_str(contents: &str) -> Result<Config> {
+        parse_config(contents, None, 0)
+    }
+}
+
+/// Parse a configuration file in the plain key/value format.
+///
+/// Parsing continues past errors, skipping the rest of the offending line, so that every error in
+/// the file can be reported at once.
+fn parse_config(contents: &str, dir: Option<&Path>, depth: usize) -> Result<Config> {
+    let mut errors = Vec::new();
+    let config = parse_config_partial(contents, dir, depth, &mut errors)?;
+    config_errors(errors)?;
+    Ok(config)
+}
+
+/// Parse a configuration file in the plain key/value format, adding the errors in it to `errors`
+/// and returning the configuration set by the rest of it.
+fn parse_config_partial(
+    contents: &str,
+    dir: Option<&Path>,
+    depth: usize,
+    errors: &mut Vec<Error>,
+) -> Result<Config> {
+    let mut lexer = parse::Lexer::new(contents.chars());
+    let mut config = Config::default();
+
+    loop {
+        let res = match lexer.scan() {
+            Ok(Some(tok)) => parse_entry(&mut lexer, &mut config, &tok, dir, depth, errors),
+            Ok(None) => break,
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = res {
+            errors.push(err);
+            lexer.skip_line();
+        }
+    }
+
+    Ok(config)
+}
+
+fn parse_entry(
+    lexer: &mut parse::Lexer<std::str::Chars<'_>>,
+    config: &mut Config,
+    tok: &str,
+    dir: Option<&Path>,
+    depth: usize,
+    errors: &mut Vec<Error>,
+) -> Result<()> {
+    match tok {
+        "include" => {
+            if let Some(path) = lexer.scan()? {
+                let line = lexer.line();
+                *config = include(std::mem::take(config), &path, dir, depth, line, errors)?;
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
+
+        "notes_dir" => {
+            if let Some(path) = lexer.scan()? {
+                config.notes_dir = Some(PathBuf::from(path));
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
+
+        "editor" => {
+            let commands = lexer.scan_line()?;
+            if commands.is_empty() {
+                return unexpected_eof(lexer.line());
+            }
+            config.editor = Some(commands.into_iter().map(PathBuf::from).collect());
+        }
+
+        "editor_fallback" => {
+            let commands = lexer.scan_line()?;
+            if commands.is_empty() {
+                return unexpected_eof(lexer.line());
+            }
+            config.editor_fallback = Some(commands);
+        }
+
+        "pager" => {
+            let commands = lexer.scan_line()?;
+            if commands.is_empty() {
+                return unexpected_eof(lexer.line());
+            }
+            config.pager = Some(commands.into_iter().map(PathBuf::from).collect());
+        }
+
+        "opener" => {
+            let commands = lexer.scan_line()?;
+            if commands.is_empty() {
+                return unexpected_eof(lexer.line());
+            }
+            config.opener = Some(commands.into_iter().map(PathBuf::from).collect());
+        }
+
+        "pandoc" => {
+            let commands = lexer.scan_line()?;
+            if commands.is_empty() {
+                return unexpected_eof(lexer.line());
+            }
+            config.pandoc = Some(commands.into_iter().map(PathBuf::from).collect());
+        }
+
+        "sync_remote" => {
+            if let Some(remote) = lexer.scan()? {
+                config.sync_remote = Some(remote);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
+
+        "share_command" => {
+            let commands = lexer.scan_line()?;
+            if commands.is_empty() {
+                return unexpected_eof(lexer.line());
+            }
+            config.share_command = Some(commands.into_iter().map(PathBuf::from).collect());
+        }
+
+        "gist_token" => {
+            if let Some(token) = lexer.scan()? {
+                config.gist_token = Some(token);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
+
+        "mail_command" => {
+            let commands = lexer.scan_line()?;
+            if commands.is_empty() {
+                return unexpected_eof(lexer.line());
+            }
+            config.mail_command = Some(commands.into_iter().map(PathBuf::from).collect());
+        }
+
+        "filename_format" => {
+            if let Some(format) = lexer.scan()? {
+                config.filename_format = Some(format);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
+
+        "daily_format" => {
+            if let Some(format) = lexer.scan()? {
+                config.daily_format = Some(format);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
+
+        "scratch_file" => {
+            if let Some(path) = lexer.scan()? {
+                config.scratch_file = Some(PathBuf::from(path));
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
+
+        "log_file" => {
+            if let Some(path) = lexer.scan()? {
+                config.log_file = Some(PathBuf::from(path));
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
+
+        "tidy_after" => {
+            if let Some(value) = lexer.scan()? {
+                config.tidy_after = Some(parse::parse_duration(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
 
-#[cfg(not(debug_assertions))]
-const NOTES_PATHS: &[&str] = &["$NEWT_NOTES_DIR", "$HOME/.newt"];
+        "backup_every" => {
+            if let Some(value) = lexer.scan()? {
+                config.backup_every = Some(parse::parse_duration(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
 
-#[cfg(debug_assertions)]
-const NOTES_PATHS: &[&str] = &[
-    "$NEWT_NOTES_DIR",
-    concat!(env!("CARGO_MANIFEST_DIR"), "/notes"),
-    "./notes",
-];
+        "backup_dir" => {
+            if let Some(path) = lexer.scan()? {
+                config.backup_dir = Some(PathBuf::from(path));
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
 
-const EDITORS: &[&str] = &["$EDITOR", "vim", "vi", "nano"];
+        "note_extension" => {
+            if let Some(ext) = lexer.scan()? {
+                config.note_extension = Some(String::from(ext.trim_start_matches('.')));
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
 
-const PAGERS: &[&str] = &["$PAGER", "less", "more", "cat"];
+        "viewer" => {
+            let pattern = match lexer.scan()? {
+                Some(pattern) => pattern,
+                None => return unexpected_eof(lexer.line()),
+            };
+            if let Some(command) = lexer.scan()? {
+                config.viewers.push((pattern, PathBuf::from(command)));
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
 
-fn find_conf_file() -> Option<PathBuf> {
-    for path in CONFIG_PATHS.iter().map(env::interpolate).map(PathBuf::from) {
-        if let Ok(metadata) = std::fs::metadata(&path) {
-            if metadata.is_file() {
-                dbg!("Using configuration file {}", path.display());
-                return Some(path);
+        "alias" => {
+            let name = match lexer.scan()? {
+                Some(name) => name,
+                None => return unexpected_eof(lexer.line()),
+            };
+            if let Some(expansion) = lexer.scan()? {
+                config.aliases.insert(name, expansion);
+            } else {
+                return unexpected_eof(lexer.line());
             }
         }
-    }
 
-    dbg!("No configuration file found, using default config");
-    None
-}
+        "note_extensions" => {
+            let exts = lexer.scan_line()?;
+            if exts.is_empty() {
+                return unexpected_eof(lexer.line());
+            }
+            config.note_extensions = Some(
+                exts.iter()
+                    .map(|ext| String::from(ext.trim_start_matches('.')))
+                    .collect(),
+            );
+        }
 
-/// Resolve the Newt configuration from the runtime environment.
-pub fn resolve() -> Result<Config> {
-    if let Some(path) = find_conf_file() {
-        read_config_file(path)
-    } else {
-        Ok(Config::default())
-    }
-}
+        "preview_lines" => {
+            if let Some(value) = lexer.scan()? {
+                config.preview_lines = Some(parse::parse_integer(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
 
-/// Read the Newt configuration from the given file.
-pub fn read_config_file<P: AsRef<Path>>(path: P) -> Result<Config> {
-    let path = PathBuf::from(path.as_ref());
-    let mut file = File::open(&path)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    Config::from_str(&contents).map_err(|err| match err {
-        Error::Config { line, kind, .. } => Error::Config {
-            line,
-            kind,
-            path: Some(path),
-        },
-        e => e,
-    })
-}
+        "preview_bytes" => {
+            if let Some(value) = lexer.scan()? {
+                config.preview_bytes = Some(parse::parse_integer(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
 
-/// Newt configuration options.
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
-#[non_exhaustive]
-pub struct Config {
-    notes_dir: Option<PathBuf>,
-    editor: Option<PathBuf>,
-    pager: Option<PathBuf>,
-}
+        "backup_count" => {
+            if let Some(value) = lexer.scan()? {
+                config.backup_count = Some(parse::parse_integer(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
 
-impl Config {
-    /// The configured notes directory, if available.
-    pub fn notes_dir(&self) -> Result<PathBuf> {
-        self.notes_dir
-            .clone()
-            .map(|path| {
-                if let Some(s) = path.to_str() {
-                    PathBuf::from(env::interpolate(s))
-                } else {
-                    path
-                }
-            })
-            .or_else(|| {
-                NOTES_PATHS
-                    .iter()
-                    .map(env::interpolate)
-                    .map(PathBuf::from)
-                    .find(|path| {
-                        if let Ok(md) = std::fs::metadata(path) {
-                            if md.is_dir() {
-                                dbg!("Using notes directory {}", path.display());
-                                true
-                            } else {
-                                false
-                            }
-                        } else {
-                            false
-                        }
-                    })
-            })
-            .ok_or(Error::NoNotesDir)
-    }
+        "create_notes_dir" => {
+            if let Some(value) = lexer.scan()? {
+                config.create_notes_dir =
+                    Some(parse::parse_create_notes_dir(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
 
-    /// The configured editor command, if available.
-    pub fn editor(&self) -> Result<PathBuf> {
-        self.editor
-            .clone()
-            .map(|path| {
-                if let Some(s) = path.to_str() {
-                    PathBuf::from(env::interpolate(s))
-                } else {
-                    path
-                }
-            })
-            .or_else(|| {
-                EDITORS
-                    .iter()
-                    .map(env::interpolate)
-                    .map(PathBuf::from)
-                    .find(|command| env::search_path(&command).is_some())
-            })
-            .ok_or(Error::NoEditor)
-    }
+        "storage" => {
+            if let Some(value) = lexer.scan()? {
+                config.storage = Some(parse::parse_storage(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
 
-    /// The configured pager command, if available.
-    pub fn pager(&self) -> Result<PathBuf> {
-        self.pager
-            .clone()
-            .map(|path| {
-                if let Some(s) = path.to_str() {
-                    PathBuf::from(env::interpolate(s))
-                } else {
-                    path
-                }
-            })
-            .or_else(|| {
-                PAGERS
-                    .iter()
-                    .map(env::interpolate)
-                    .map(PathBuf::from)
-                    .find(|command| env::search_path(&command).is_some())
-            })
-            .ok_or(Error::NoPager)
-    }
-}
+        "compat" => {
+            if let Some(value) = lexer.scan()? {
+                config.compat = Some(parse::parse_compat(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
 
-impl Config {
-    /// Set the notes dir on this `Config`.
-    pub fn with_notes_dir<O: Into<Option<PathBuf>>>(self, notes_dir: O) -> Self {
-        Config {
-            notes_dir: notes_dir.into().or(self.notes_dir),
-            ..self
+        "non_interactive" => {
+            if let Some(value) = lexer.scan()? {
+                config.non_interactive =
+                    Some(parse::parse_non_interactive(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
         }
-    }
 
-    /// Set the editor on this `Config`.
-    pub fn with_editor<O: Into<Option<PathBuf>>>(self, editor: O) -> Self {
-        Config {
-            editor: editor.into().or(self.editor),
-            ..self
+        "search_case" => {
+            if let Some(value) = lexer.scan()? {
+                config.search_case = Some(parse::parse_search_case(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
         }
-    }
 
-    /// Set the pager on this `Config`.
-    pub fn with_pager<O: Into<Option<PathBuf>>>(self, pager: O) -> Self {
-        Config {
-            pager: pager.into().or(self.pager),
-            ..self
+        "shell" => {
+            if let Some(path) = lexer.scan()? {
+                config.shell = Some(PathBuf::from(path));
+            } else {
+                return unexpected_eof(lexer.line());
+            }
         }
-    }
-}
 
-impl FromStr for Config {
-    type Err = Error;
+        "shell_exec" => {
+            if let Some(value) = lexer.scan()? {
+                config.shell_exec = Some(parse::parse_bool(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
 
-    fn from_str(contents: &str) -> Result<Config> {
-        let mut lexer = parse::Lexer::new(contents.chars());
-        let mut config = Config::default();
-
-        while let Some(tok) = lexer.scan()? {
-            match tok.as_str() {
-                "notes_dir" => {
-                    if let Some(path) = lexer.scan()? {
-                        config.notes_dir = Some(PathBuf::from(path));
-                    } else {
-                        return unexpected_eof(lexer.line());
-                    }
-                }
+        "safe_edit" => {
+            if let Some(value) = lexer.scan()? {
+                config.safe_edit = Some(parse::parse_bool(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
 
-                "editor" => {
-                    if let Some(command) = lexer.scan()? {
-                        config.editor = Some(PathBuf::from(command));
-                    } else {
-                        return unexpected_eof(lexer.line());
-                    }
-                }
+        "strict_editor" => {
+            if let Some(value) = lexer.scan()? {
+                config.strict_editor = Some(parse::parse_bool(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
 
-                "pager" => {
-                    if let Some(command) = lexer.scan()? {
-                        config.pager = Some(PathBuf::from(command));
-                    } else {
-                        return unexpected_eof(lexer.line());
-                    }
-                }
+        "follow_symlinks" => {
+            if let Some(value) = lexer.scan()? {
+                config.follow_symlinks = Some(parse::parse_bool(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
 
-                s => return unrecognized_key(s, lexer.line()),
+        "frontmatter" => {
+            if let Some(value) = lexer.scan()? {
+                config.frontmatter = Some(parse::parse_bool(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
             }
         }
 
-        Ok(config)
+        "discard_empty" => {
+            if let Some(value) = lexer.scan()? {
+                config.discard_empty = Some(parse::parse_bool(tok, &value, lexer.line())?);
+            } else {
+                return unexpected_eof(lexer.line());
+            }
+        }
+
+        s => return unrecognized_key(s, lexer.line()),
     }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::util::temp::TempDir;
     use std::str::FromStr;
 
     #[test]
@@ -274,6 +1474,448 @@ mod test {
         assert_eq!(Config::from_str(conf).unwrap(), expected);
     }
 
+    #[test]
+    fn editor_fallback() {
+        let conf = "editor_fallback $NEWT_NO_SUCH_VAR \"sh -e\" vi\n";
+        let config = Config::from_str(conf).unwrap();
+        let expected = Config::default().with_editor_fallback(vec![
+            String::from("$NEWT_NO_SUCH_VAR"),
+            String::from("sh -e"),
+            String::from("vi"),
+        ]);
+        assert_eq!(config, expected);
+        assert_eq!(config.editor().unwrap(), PathBuf::from("sh -e"));
+
+        let config = config.with_editor(PathBuf::from("sh -u"));
+        assert_eq!(config.editor().unwrap(), PathBuf::from("sh -u"));
+    }
+
+    #[test]
+    fn shell() {
+        let conf = "shell /bin/zsh\nshell_exec yes\n";
+        let expected = Config::default()
+            .with_shell(PathBuf::from("/bin/zsh"))
+            .with_shell_exec(true);
+        let config = Config::from_str(conf).unwrap();
+        assert_eq!(config, expected);
+        assert_eq!(config.shell().unwrap(), PathBuf::from("/bin/zsh"));
+        assert!(config.shell_exec());
+        assert!(!Config::default().shell_exec());
+    }
+
+    #[test]
+    fn viewers() {
+        let conf = "viewer *.pdf zathura\nviewer \"*.png\" \"feh -F\"\nviewer *.pdf evince\n";
+        let config = Config::from_str(conf).unwrap();
+        let expected = Config::default()
+            .with_viewer("*.pdf", "zathura")
+            .with_viewer("*.png", "feh -F")
+            .with_viewer("*.pdf", "evince");
+        assert_eq!(config, expected);
+        assert_eq!(
+            config.viewer("papers/a.pdf").unwrap(),
+            Some(PathBuf::from("zathura"))
+        );
+        assert_eq!(config.viewer("a.md").unwrap(), None);
+
+        let overlaid = config.overlay(Config::default().with_viewer("*.pdf", "okular"));
+        assert_eq!(
+            overlaid.viewer("a.pdf").unwrap(),
+            Some(PathBuf::from("okular"))
+        );
+        assert_eq!(Config::from_str("viewer *.pdf\n"), unexpected_eof(2));
+    }
+
+    #[test]
+    fn editor_fallbacks() {
+        let conf = "editor newt-no-such-editor \"sh -e\" vi\n";
+        let config = Config::from_str(conf).unwrap();
+        let expected = Config::default().with_editors(vec![
+            PathBuf::from("newt-no-such-editor"),
+            PathBuf::from("sh -e"),
+            PathBuf::from("vi"),
+        ]);
+        assert_eq!(config, expected);
+        assert_eq!(config.editor().unwrap(), PathBuf::from("sh -e"));
+
+        let conf = "editor newt-no-such-editor newt-nor-this-one\n";
+        let config = Config::from_str(conf).unwrap();
+        assert_eq!(
+            config.editor().unwrap(),
+            PathBuf::from("newt-no-such-editor")
+        );
+    }
+
+    #[test]
+    fn opener() {
+        let conf = "opener \"gio open\"\n";
+        let expected = Config::default().with_opener(PathBuf::from("gio open"));
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+    }
+
+    #[test]
+    fn pandoc() {
+        let conf = "pandoc \"pandoc --pdf-engine=xelatex\"\n";
+        let expected = Config::default().with_pandoc(PathBuf::from("pandoc --pdf-engine=xelatex"));
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+    }
+
+    #[test]
+    fn sync_remote() {
+        let conf = "sync_remote me@example.com:notes/\n";
+        let expected = Config::default().with_sync_remote(String::from("me@example.com:notes/"));
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert_eq!(expected.sync_remote(), Some("me@example.com:notes/"));
+        assert_eq!(Config::default().sync_remote(), None);
+    }
+
+    #[test]
+    fn share_options() {
+        let conf = "share_command \"curl -F file=@- https://0x0.st\"\ngist_token abc123\n";
+        let expected = Config::default()
+            .with_share_command(PathBuf::from("curl -F file=@- https://0x0.st"))
+            .with_gist_token(String::from("abc123"));
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert_eq!(
+            expected.share_command().unwrap(),
+            Some(PathBuf::from("curl -F file=@- https://0x0.st"))
+        );
+        assert_eq!(expected.gist_token().as_deref(), Some("abc123"));
+        assert_eq!(Config::default().share_command().unwrap(), None);
+    }
+
+    #[test]
+    fn mail_command() {
+        let conf = "mail_command \"mail -s {subject} {to}\"\n";
+        let expected = Config::default().with_mail_command(PathBuf::from("mail -s {subject} {to}"));
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert_eq!(
+            expected.mail_command().unwrap(),
+            PathBuf::from("mail -s {subject} {to}")
+        );
+    }
+
+    #[test]
+    fn filename_format() {
+        let conf = r#"filename_format "{title}-%Y%m%d.txt""#;
+        let expected = Config::default().with_filename_format(String::from("{title}-%Y%m%d.txt"));
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+    }
+
+    #[test]
+    fn note_extension() {
+        let conf = "note_extension .txt\n";
+        let expected = Config::default().with_note_extension(String::from("txt"));
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+    }
+
+    #[test]
+    fn note_extensions() {
+        let conf = "note_extensions md .txt # comment\nnote_extension txt\n";
+        let expected = Config::default()
+            .with_note_extensions(vec![String::from("md"), String::from("txt")])
+            .with_note_extension(String::from("txt"));
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert_eq!(Config::from_str("note_extensions\n"), unexpected_eof(1));
+    }
+
+    #[test]
+    fn aliases() {
+        let conf = r#"alias standup "new --title Standup"
+alias t "list --tree"
+alias t "list --tree --flat"
+"#;
+        let expected = Config::default()
+            .with_alias("standup", "new --title Standup")
+            .with_alias("t", "list --tree --flat");
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert_eq!(expected.alias("t"), Some("list --tree --flat"));
+        assert_eq!(Config::from_str("alias t"), unexpected_eof(1));
+    }
+
+    #[test]
+    fn tidy_after() {
+        let conf = "tidy_after 90d\n";
+        let expected = Config::default().with_tidy_after(chrono::Duration::days(90));
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert_eq!(
+            Config::from_str("tidy_after soon"),
+            invalid_value("tidy_after", "soon", "a duration (e.g. 90d, 12w)", 1)
+        );
+    }
+
+    #[test]
+    fn non_interactive() {
+        let conf = "non_interactive fail\n";
+        let expected = Config::default().with_non_interactive(NonInteractive::Fail);
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert_eq!(Config::default().non_interactive(), NonInteractive::Default);
+        assert_eq!(
+            Config::from_str("non_interactive maybe"),
+            invalid_value(
+                "non_interactive",
+                "maybe",
+                "one of default, yes, or fail",
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn search_case() {
+        let conf = "search_case smart\n";
+        let expected = Config::default().with_search_case(SearchCase::Smart);
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert_eq!(
+            Config::from_str("search_case loud"),
+            invalid_value(
+                "search_case",
+                "loud",
+                "one of sensitive, insensitive, or smart",
+                1
+            )
+        );
+
+        assert!(SearchCase::Smart.ignore_case("router config"));
+        assert!(!SearchCase::Smart.ignore_case("Router config"));
+        assert!(SearchCase::Insensitive.ignore_case("Router"));
+        assert!(!SearchCase::Sensitive.ignore_case("router"));
+    }
+
+    #[test]
+    fn discard_empty() {
+        let conf = "discard_empty true\n";
+        let expected = Config::default().with_discard_empty(true);
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+    }
+
+    #[test]
+    fn preview_lines() {
+        let conf = "preview_lines 3\n";
+        let expected = Config::default().with_preview_lines(3);
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert_eq!(
+            Config::from_str("preview_lines -1"),
+            invalid_value("preview_lines", "-1", "a non-negative integer", 1)
+        );
+    }
+
+    #[test]
+    fn preview_bytes() {
+        let conf = "preview_bytes 4096\n";
+        let expected = Config::default().with_preview_bytes(4096);
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert_eq!(Config::default().preview_bytes(), DEFAULT_PREVIEW_BYTES);
+    }
+
+    #[test]
+    fn strict_editor() {
+        let conf = "strict_editor true\n";
+        let expected = Config::default().with_strict_editor(true);
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert!(expected.strict_editor());
+        assert!(!Config::default().strict_editor());
+    }
+
+    #[test]
+    fn follow_symlinks() {
+        let conf = "follow_symlinks false\n";
+        let expected = Config::default().with_follow_symlinks(false);
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert!(!expected.follow_symlinks());
+        assert!(Config::default().follow_symlinks());
+    }
+
+    #[test]
+    fn log_file() {
+        let conf = "log_file /tmp/newt.log\n";
+        let expected = Config::default().with_log_file(PathBuf::from("/tmp/newt.log"));
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert_eq!(
+            expected.log_file().unwrap(),
+            Some(PathBuf::from("/tmp/newt.log"))
+        );
+        assert_eq!(Config::default().log_file().unwrap(), None);
+    }
+
+    #[test]
+    fn backup_schedule() {
+        let conf = "backup_every 7d\nbackup_dir /tmp/newt-backups\n";
+        let expected = Config::default()
+            .with_backup_every(chrono::Duration::days(7))
+            .with_backup_dir(PathBuf::from("/tmp/newt-backups"));
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert_eq!(
+            expected.backup_dir().unwrap(),
+            Some(PathBuf::from("/tmp/newt-backups"))
+        );
+        assert_eq!(Config::default().backup_every(), None);
+        assert_eq!(
+            Config::from_str("backup_every weekly"),
+            invalid_value("backup_every", "weekly", "a duration (e.g. 90d, 12w)", 1)
+        );
+    }
+
+    #[test]
+    fn storage() {
+        let conf = "storage sqlite\n";
+        let expected = Config::default().with_storage(Storage::Sqlite);
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert_eq!(Config::default().storage(), Storage::Files);
+        assert_eq!(
+            Config::from_str("storage cloud"),
+            invalid_value("storage", "cloud", "one of files or sqlite", 1)
+        );
+    }
+
+    #[test]
+    fn compat() {
+        let conf = "compat obsidian\n";
+        let expected = Config::default().with_compat(Compat::Obsidian);
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert_eq!(expected.filename_format(false), "{title}");
+        assert_eq!(Config::default().compat(), Compat::None);
+        assert_eq!(
+            Config::from_str("compat logseq"),
+            invalid_value("compat", "logseq", "one of none or obsidian", 1)
+        );
+    }
+
+    #[test]
+    fn create_notes_dir() {
+        let conf = "create_notes_dir always\n";
+        let expected = Config::default().with_create_notes_dir(CreateNotesDir::Always);
+        assert_eq!(Config::from_str(conf).unwrap(), expected);
+        assert_eq!(Config::default().create_notes_dir(), CreateNotesDir::Ask);
+        assert_eq!(
+            Config::from_str("create_notes_dir sometimes"),
+            invalid_value(
+                "create_notes_dir",
+                "sometimes",
+                "one of always, ask, or never",
+                1
+            )
+        );
+
+        let temp = TempDir::new("missing-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let config = Config::default().with_notes_dir(dir.join("notes"));
+        assert_eq!(config.missing_notes_dir().unwrap(), Some(dir.join("notes")));
+        std::fs::create_dir_all(dir.join("notes")).unwrap();
+        assert_eq!(config.missing_notes_dir().unwrap(), None);
+    }
+
+    #[test]
+    fn bad_bool() {
+        let conf = "discard_empty sure";
+        assert_eq!(
+            Config::from_str(conf),
+            invalid_value("discard_empty", "sure", "a boolean (true or false)", 1)
+        );
+    }
+
+    #[test]
+    fn include_files() {
+        let temp = TempDir::new("include-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        std::fs::write(dir.join("local"), "editor nano\npager more\n").unwrap();
+        std::fs::write(dir.join("loop"), "include loop\n").unwrap();
+        let main = dir.join("config");
+        std::fs::write(
+            &main,
+            "editor vim\nnotes_dir ~/notes\ninclude local\ninclude missing\npager less\n",
+        )
+        .unwrap();
+
+        let expected = Config::default()
+            .with_editor(PathBuf::from("nano"))
+            .with_notes_dir(PathBuf::from("~/notes"))
+            .with_pager(PathBuf::from("less"));
+        assert_eq!(read_config_file(&main).unwrap(), expected);
+
+        let err = read_config_file(dir.join("loop")).unwrap_err();
+        assert_eq!(
+            err,
+            ConfigErrorKind::IncludeDepth.at_line(1, Some(dir.join("loop")))
+        );
+
+        // Errors in included files are collected along with the rest, and do not stop the
+        // valid settings in them being read.
+        std::fs::write(dir.join("typo"), "editr nano\npager more\n").unwrap();
+        std::fs::write(&main, "include typo\nnot_a_key 1\n").unwrap();
+        let (config, errors) = read_config_file_partial(&main).unwrap();
+        assert_eq!(config, Config::default().with_pager(PathBuf::from("more")));
+        assert_eq!(
+            errors,
+            vec![
+                unrecognized_key::<(), _>("editr", 1)
+                    .unwrap_err()
+                    .in_config_file(dir.join("typo")),
+                unrecognized_key::<(), _>("not_a_key", 2)
+                    .unwrap_err()
+                    .in_config_file(&main),
+            ]
+        );
+    }
+
+    #[test]
+    fn multiple_errors() {
+        let conf = "editor vim\nnot_a_key some value\ndiscard_empty sure\npager less\n";
+        assert_eq!(
+            Config::from_str(conf),
+            Err(Error::ConfigMultiple {
+                errors: vec![
+                    unrecognized_key::<(), _>("not_a_key", 2).unwrap_err(),
+                    invalid_value::<(), _, _>(
+                        "discard_empty",
+                        "sure",
+                        "a boolean (true or false)",
+                        3
+                    )
+                    .unwrap_err(),
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn check_problems() {
+        let config = Config::default()
+            .with_notes_dir(PathBuf::from("/newt/no/such/dir"))
+            .with_editors(vec![
+                PathBuf::from("newt-no-such-editor"),
+                PathBuf::from("sh"),
+            ])
+            .with_pager(PathBuf::from("sh"))
+            .with_opener(PathBuf::from("sh"));
+        assert_eq!(
+            config.check(),
+            vec![
+                Problem::Error(String::from(
+                    "notes directory /newt/no/such/dir does not exist"
+                )),
+                Problem::Warning(String::from(
+                    "editor command `newt-no-such-editor` not found on $PATH"
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn default_config() {
+        assert_eq!(Config::from_str(DEFAULT_CONFIG).unwrap(), Config::default());
+
+        let uncommented = DEFAULT_CONFIG
+            .lines()
+            .map(|line| match line.strip_prefix('#') {
+                Some(setting) if !setting.starts_with(' ') && !setting.is_empty() => setting,
+                _ => line,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_ne!(Config::from_str(&uncommented).unwrap(), Config::default());
+    }
+
     #[test]
     fn duplicate_keys() {
         let conf = r"notes_dir ~/.notes
@@ -309,4 +1951,24 @@ notes_dir ~/wait/no/this/one # Change it up
         let conf = r#"not_a_key "heya bish""#;
         assert_eq!(Config::from_str(conf), unrecognized_key("not_a_key", 1));
     }
+
+    #[test]
+    fn key_suggestions() {
+        let err = Config::from_str("editr vim\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Error in configuration at line 1: unrecognized key \"editr\", did you mean \"editor\"?"
+        );
+
+        for key in KEYS {
+            let unrecognized = matches!(
+                Config::from_str(&format!("{} x\n", key)),
+                Err(Error::Config {
+                    kind: ConfigErrorKind::UnrecognizedKey { .. },
+                    ..
+                })
+            );
+            assert!(!unrecognized, "{} is not recognized", key);
+        }
+    }
 }
@@ -1,4 +1,71 @@
+use super::{Compat, CreateNotesDir, NonInteractive, SearchCase, Storage};
 use crate::error::*;
+use crate::util;
+
+pub fn parse_bool(key: &str, value: &str, line: usize) -> Result<bool> {
+    match value {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => invalid_value(key, value, "a boolean (true or false)", line),
+    }
+}
+
+pub fn parse_integer(key: &str, value: &str, line: usize) -> Result<usize> {
+    match value.parse() {
+        Ok(n) => Ok(n),
+        Err(_) => invalid_value(key, value, "a non-negative integer", line),
+    }
+}
+
+pub fn parse_duration(key: &str, value: &str, line: usize) -> Result<chrono::Duration> {
+    match util::parse_duration(value) {
+        Some(duration) => Ok(duration),
+        None => invalid_value(key, value, "a duration (e.g. 90d, 12w)", line),
+    }
+}
+
+pub fn parse_search_case(key: &str, value: &str, line: usize) -> Result<SearchCase> {
+    match value {
+        "sensitive" => Ok(SearchCase::Sensitive),
+        "insensitive" => Ok(SearchCase::Insensitive),
+        "smart" => Ok(SearchCase::Smart),
+        _ => invalid_value(key, value, "one of sensitive, insensitive, or smart", line),
+    }
+}
+
+pub fn parse_create_notes_dir(key: &str, value: &str, line: usize) -> Result<CreateNotesDir> {
+    match value {
+        "always" => Ok(CreateNotesDir::Always),
+        "ask" => Ok(CreateNotesDir::Ask),
+        "never" => Ok(CreateNotesDir::Never),
+        _ => invalid_value(key, value, "one of always, ask, or never", line),
+    }
+}
+
+pub fn parse_non_interactive(key: &str, value: &str, line: usize) -> Result<NonInteractive> {
+    match value {
+        "default" => Ok(NonInteractive::Default),
+        "yes" => Ok(NonInteractive::Yes),
+        "fail" => Ok(NonInteractive::Fail),
+        _ => invalid_value(key, value, "one of default, yes, or fail", line),
+    }
+}
+
+pub fn parse_storage(key: &str, value: &str, line: usize) -> Result<Storage> {
+    match value {
+        "files" => Ok(Storage::Files),
+        "sqlite" => Ok(Storage::Sqlite),
+        _ => invalid_value(key, value, "one of files or sqlite", line),
+    }
+}
+
+pub fn parse_compat(key: &str, value: &str, line: usize) -> Result<Compat> {
+    match value {
+        "none" => Ok(Compat::None),
+        "obsidian" => Ok(Compat::Obsidian),
+        _ => invalid_value(key, value, "one of none or obsidian", line),
+    }
+}
 
 pub struct Lexer<I> {
     chars: I,
@@ -43,6 +110,10 @@ impl<I: Iterator<Item = char>> Lexer<I> {
         }
     }
 
+    pub fn skip_line(&mut self) {
+        self.skip_to_newline();
+    }
+
     fn skip_ws(&mut self) {
         while let Some(c) = self.lookahead {
             if c == '#' {
@@ -126,6 +197,26 @@ impl<I: Iterator<Item = char>> Lexer<I> {
         }
     }
 
+    pub fn scan_line(&mut self) -> Result<Vec<String>> {
+        let mut tokens = Vec::new();
+        loop {
+            while let Some(c) = self.lookahead {
+                if c == '#' {
+                    self.skip_to_newline();
+                } else if c == '\n' || !c.is_whitespace() {
+                    break;
+                } else {
+                    self.advance();
+                }
+            }
+
+            match self.lookahead {
+                None | Some('\n') => return Ok(tokens),
+                _ => tokens.extend(self.scan()?),
+            }
+        }
+    }
+
     pub fn scan(&mut self) -> Result<Option<String>> {
         self.buffer.clear();
         self.skip_ws();
@@ -0,0 +1,104 @@
+//! Platform-specific default paths and programs.
+
+use crate::util::env;
+
+use std::path::PathBuf;
+
+#[cfg(all(not(debug_assertions), windows))]
+pub const CONFIG_PATHS: &[&str] = &[
+    "$NEWT_CONFIG",
+    "$APPDATA\\newt\\config.toml",
+    "$APPDATA\\newt\\config",
+];
+
+#[cfg(all(not(debug_assertions), target_os = "macos"))]
+pub const CONFIG_PATHS: &[&str] = &[
+    "$NEWT_CONFIG",
+    "$XDG_CONFIG_HOME/newt/config.toml",
+    "$XDG_CONFIG_HOME/newt/config",
+    "$HOME/.config/newt/config.toml",
+    "$HOME/.config/newt/config",
+    "$HOME/Library/Application Support/newt/config.toml",
+    "$HOME/Library/Application Support/newt/config",
+    "$HOME/.newtrc",
+    "/etc/newtrc",
+];
+
+#[cfg(all(not(debug_assertions), not(any(target_os = "macos", windows))))]
+pub const CONFIG_PATHS: &[&str] = &[
+    "$NEWT_CONFIG",
+    "$XDG_CONFIG_HOME/newt/config.toml",
+    "$XDG_CONFIG_HOME/newt/config",
+    "$HOME/.config/newt/config.toml",
+    "$HOME/.config/newt/config",
+    "$HOME/.newtrc",
+    "/etc/newtrc",
+];
+
+#[cfg(debug_assertions)]
+pub const CONFIG_PATHS: &[&str] = &[
+    "$NEWT_CONFIG",
+    concat!(env!("CARGO_MANIFEST_DIR"), "/newtrc"),
+    concat!(env!("CARGO_MANIFEST_DIR"), "/config.toml"),
+    concat!(env!("CARGO_MANIFEST_DIR"), "/config"),
+    "./newtrc",
+    "./config.toml",
+    "./config",
+];
+
+#[cfg(all(not(debug_assertions), windows))]
+pub const NOTES_PATHS: &[&str] = &["$NEWT_NOTES_DIR", "$USERPROFILE\\newt"];
+
+#[cfg(all(not(debug_assertions), not(windows)))]
+pub const NOTES_PATHS: &[&str] = &["$NEWT_NOTES_DIR", "$HOME/.newt"];
+
+#[cfg(debug_assertions)]
+pub const NOTES_PATHS: &[&str] = &[
+    "$NEWT_NOTES_DIR",
+    concat!(env!("CARGO_MANIFEST_DIR"), "/notes"),
+    "./notes",
+];
+
+#[cfg(windows)]
+pub const EDITORS: &[&str] = &["$VISUAL", "$EDITOR", "notepad"];
+
+#[cfg(not(windows))]
+pub const EDITORS: &[&str] = &["$VISUAL", "$EDITOR", "vim", "vi", "nano"];
+
+#[cfg(windows)]
+pub const PAGERS: &[&str] = &["$PAGER", "more"];
+
+#[cfg(not(windows))]
+pub const PAGERS: &[&str] = &["$PAGER", "less", "more", "cat"];
+
+#[cfg(target_os = "macos")]
+pub const OPENERS: &[&str] = &["open"];
+
+#[cfg(windows)]
+pub const OPENERS: &[&str] = &["explorer"];
+
+#[cfg(not(any(target_os = "macos", windows)))]
+pub const OPENERS: &[&str] = &["xdg-open"];
+
+/// The preferred location for a newly created configuration file.
+///
+/// This is `%APPDATA%\newt\config`.
+#[cfg(windows)]
+pub fn default_conf_path() -> PathBuf {
+    PathBuf::from(env::interpolate("$APPDATA"))
+        .join("newt")
+        .join("config")
+}
+
+/// The preferred location for a newly created configuration file.
+///
+/// This is `$XDG_CONFIG_HOME/newt/config`, or `$HOME/.config/newt/config` if `XDG_CONFIG_HOME` is
+/// unset.
+#[cfg(not(windows))]
+pub fn default_conf_path() -> PathBuf {
+    let config_home = env::env_var("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env::interpolate("$HOME/.config")));
+    config_home.join("newt").join("config")
+}
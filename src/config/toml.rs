@@ -0,0 +1,376 @@
+//! Parsing of TOML configuration files.
+//!
+//! Files are parsed with the `toml` crate; this module maps the parsed keys onto a [`Config`],
+//! reporting errors against the lines they occur on.
+
+use super::parse::{
+    parse_compat, parse_create_notes_dir, parse_duration, parse_non_interactive, parse_search_case,
+    parse_storage,
+};
+use super::{include, Config, TABLES};
+use crate::error::*;
+
+use toml::de::{DeTable, DeValue};
+use toml::Spanned;
+
+use std::path::{Path, PathBuf};
+
+/// A parsed TOML value, along with the text it was parsed from for use in error messages.
+struct Value<'i> {
+    inner: DeValue<'i>,
+    text: &'i str,
+    contents: &'i str,
+}
+
+impl<'i> Value<'i> {
+    fn new(value: Spanned<DeValue<'i>>, contents: &'i str) -> Value<'i> {
+        let text = &contents[value.span()];
+        Value {
+            inner: value.into_inner(),
+            text,
+            contents,
+        }
+    }
+
+    fn into_string(self, key: &str, line: usize) -> Result<String> {
+        match self.inner {
+            DeValue::String(s) => Ok(s.into_owned()),
+            _ => invalid_value(key, self.text, "a string", line),
+        }
+    }
+
+    fn into_bool(self, key: &str, line: usize) -> Result<bool> {
+        match self.inner {
+            DeValue::Boolean(b) => Ok(b),
+            _ => invalid_value(key, self.text, "a boolean (true or false)", line),
+        }
+    }
+
+    fn into_integer(self, key: &str, line: usize) -> Result<usize> {
+        let int = match &self.inner {
+            DeValue::Integer(i) => usize::from_str_radix(i.as_str(), i.radix()).ok(),
+            _ => None,
+        };
+        match int {
+            Some(i) => Ok(i),
+            None => invalid_value(key, self.text, "a non-negative integer", line),
+        }
+    }
+
+    fn into_strings(self, key: &str, line: usize) -> Result<Vec<String>> {
+        let contents = self.contents;
+        match self.inner {
+            DeValue::Array(values) => values
+                .into_iter()
+                .map(|v| Value::new(v, contents).into_string(key, line))
+                .collect(),
+            _ => Ok(vec![self.into_string(key, line)?]),
+        }
+    }
+}
+
+/// The line of `contents` that the byte offset `pos` falls on.
+fn line_of(contents: &str, pos: usize) -> usize {
+    contents[..pos].matches('\n').count() + 1
+}
+
+/// Parse a TOML configuration file.
+///
+/// Included files are resolved relative to `dir`, and `depth` is the current include depth.
+/// Errors in keys and values are added to `errors`, and the configuration set by the rest of the
+/// file is returned; parsing fails only on malformed TOML.
+pub fn parse(
+    contents: &str,
+    dir: Option<&Path>,
+    depth: usize,
+    errors: &mut Vec<Error>,
+) -> Result<Config> {
+    let document = match DeTable::parse(contents) {
+        Ok(document) => document.into_inner(),
+        Err(err) => {
+            let line = err.span().map_or(1, |span| line_of(contents, span.start));
+            return invalid_toml(err.message().trim_end(), line);
+        }
+    };
+    let mut config = Config::default();
+
+    for (key, value) in document {
+        let line = line_of(contents, key.span().start);
+        let key = key.into_inner().into_owned();
+        let span = value.span();
+        let res = match value.into_inner() {
+            DeValue::Table(table) if TABLES.contains(&key.as_str()) => {
+                for (name, value) in table {
+                    let line = line_of(contents, name.span().start);
+                    let name = name.into_inner().into_owned();
+                    if let Err(err) =
+                        apply(&mut config, &key, name, Value::new(value, contents), line)
+                    {
+                        errors.push(err);
+                    }
+                }
+                Ok(())
+            }
+            DeValue::Table(_) => unrecognized_key(format!("[{}]", key), line),
+            value => {
+                let value = Value::new(Spanned::new(span, value), contents);
+                if key == "include" {
+                    include_all(&mut config, value, line, dir, depth, errors)
+                } else {
+                    apply(&mut config, "", key, value, line)
+                }
+            }
+        };
+        if let Err(err) = res {
+            errors.push(err);
+        }
+    }
+
+    Ok(config)
+}
+
+/// Layer the files named by an `include` key on top of the configuration, in order.
+fn include_all(
+    config: &mut Config,
+    value: Value,
+    line: usize,
+    dir: Option<&Path>,
+    depth: usize,
+    errors: &mut Vec<Error>,
+) -> Result<()> {
+    for path in value.into_strings("include", line)? {
+        *config = include(std::mem::take(config), &path, dir, depth, line, errors)?;
+    }
+    Ok(())
+}
+
+/// Apply a single key/value pair in the given table to the configuration.
+fn apply(config: &mut Config, table: &str, key: String, value: Value, line: usize) -> Result<()> {
+    if table == "alias" {
+        let expansion = value.into_string(&key, line)?;
+        config.aliases.insert(key, expansion);
+        return Ok(());
+    }
+
+    if table == "viewer" {
+        let command = value.into_string(&key, line)?;
+        config.viewers.push((key, PathBuf::from(command)));
+        return Ok(());
+    }
+
+    match key.as_str() {
+        "notes_dir" => config.notes_dir = Some(PathBuf::from(value.into_string(&key, line)?)),
+        "editor" => {
+            let commands = value.into_strings(&key, line)?;
+            config.editor = Some(commands.into_iter().map(PathBuf::from).collect());
+        }
+        "editor_fallback" => config.editor_fallback = Some(value.into_strings(&key, line)?),
+        "pager" => {
+            let commands = value.into_strings(&key, line)?;
+            config.pager = Some(commands.into_iter().map(PathBuf::from).collect());
+        }
+        "opener" => {
+            let commands = value.into_strings(&key, line)?;
+            config.opener = Some(commands.into_iter().map(PathBuf::from).collect());
+        }
+        "pandoc" => {
+            let commands = value.into_strings(&key, line)?;
+            config.pandoc = Some(commands.into_iter().map(PathBuf::from).collect());
+        }
+        "sync_remote" => config.sync_remote = Some(value.into_string(&key, line)?),
+        "share_command" => {
+            let commands = value.into_strings(&key, line)?;
+            config.share_command = Some(commands.into_iter().map(PathBuf::from).collect());
+        }
+        "gist_token" => config.gist_token = Some(value.into_string(&key, line)?),
+        "mail_command" => {
+            let commands = value.into_strings(&key, line)?;
+            config.mail_command = Some(commands.into_iter().map(PathBuf::from).collect());
+        }
+        "filename_format" => config.filename_format = Some(value.into_string(&key, line)?),
+        "daily_format" => config.daily_format = Some(value.into_string(&key, line)?),
+        "scratch_file" => config.scratch_file = Some(PathBuf::from(value.into_string(&key, line)?)),
+        "log_file" => config.log_file = Some(PathBuf::from(value.into_string(&key, line)?)),
+        "tidy_after" => {
+            let value = value.into_string(&key, line)?;
+            config.tidy_after = Some(parse_duration(&key, &value, line)?);
+        }
+        "backup_every" => {
+            let value = value.into_string(&key, line)?;
+            config.backup_every = Some(parse_duration(&key, &value, line)?);
+        }
+        "backup_dir" => config.backup_dir = Some(PathBuf::from(value.into_string(&key, line)?)),
+        "note_extension" => {
+            let ext = value.into_string(&key, line)?;
+            config.note_extension = Some(String::from(ext.trim_start_matches('.')));
+        }
+        "note_extensions" => {
+            let exts = value.into_strings(&key, line)?;
+            config.note_extensions = Some(
+                exts.iter()
+                    .map(|ext| String::from(ext.trim_start_matches('.')))
+                    .collect(),
+            );
+        }
+        "preview_lines" => config.preview_lines = Some(value.into_integer(&key, line)?),
+        "preview_bytes" => config.preview_bytes = Some(value.into_integer(&key, line)?),
+        "backup_count" => config.backup_count = Some(value.into_integer(&key, line)?),
+        "create_notes_dir" => {
+            let value = value.into_string(&key, line)?;
+            config.create_notes_dir = Some(parse_create_notes_dir(&key, &value, line)?);
+        }
+        "storage" => {
+            let value = value.into_string(&key, line)?;
+            config.storage = Some(parse_storage(&key, &value, line)?);
+        }
+        "compat" => {
+            let value = value.into_string(&key, line)?;
+            config.compat = Some(parse_compat(&key, &value, line)?);
+        }
+        "non_interactive" => {
+            let value = value.into_string(&key, line)?;
+            config.non_interactive = Some(parse_non_interactive(&key, &value, line)?);
+        }
+        "search_case" => {
+            let value = value.into_string(&key, line)?;
+            config.search_case = Some(parse_search_case(&key, &value, line)?);
+        }
+        "shell" => config.shell = Some(PathBuf::from(value.into_string(&key, line)?)),
+        "shell_exec" => config.shell_exec = Some(value.into_bool(&key, line)?),
+        "safe_edit" => config.safe_edit = Some(value.into_bool(&key, line)?),
+        "strict_editor" => config.strict_editor = Some(value.into_bool(&key, line)?),
+        "follow_symlinks" => config.follow_symlinks = Some(value.into_bool(&key, line)?),
+        "frontmatter" => config.frontmatter = Some(value.into_bool(&key, line)?),
+        "discard_empty" => config.discard_empty = Some(value.into_bool(&key, line)?),
+        _ => return unrecognized_key(key, line),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(contents: &str) -> Result<Config> {
+        let mut errors = Vec::new();
+        let config = super::parse(contents, None, 0, &mut errors)?;
+        config_errors(errors)?;
+        Ok(config)
+    }
+
+    #[test]
+    fn keys_and_types() {
+        let conf = r#"
+# Comment
+notes_dir = "~/notes"  # trailing comment
+editor = 'nvim -p'
+frontmatter = true
+note_extensions = [
+    "md",
+    ".txt",
+]
+tidy_after = "90d"
+
+[alias]
+standup = "new --title Standup"
+
+[viewer]
+"*.pdf" = "zathura"
+"#;
+        let expected = Config::default()
+            .with_notes_dir(PathBuf::from("~/notes"))
+            .with_editor(PathBuf::from("nvim -p"))
+            .with_frontmatter(true)
+            .with_note_extensions(vec![String::from("md"), String::from("txt")])
+            .with_tidy_after(chrono::Duration::days(90))
+            .with_alias("standup", "new --title Standup")
+            .with_viewer("*.pdf", "zathura");
+        assert_eq!(parse(conf).unwrap(), expected);
+    }
+
+    #[test]
+    fn inline_tables_and_dotted_keys() {
+        let conf = r#"
+alias = { standup = "new --title Standup" }
+viewer."*.pdf" = "zathura"
+preview_lines = 0x10
+"#;
+        let expected = Config::default()
+            .with_alias("standup", "new --title Standup")
+            .with_viewer("*.pdf", "zathura")
+            .with_preview_lines(16);
+        assert_eq!(parse(conf).unwrap(), expected);
+    }
+
+    #[test]
+    fn escapes() {
+        let conf = r#"filename_format = "a\"b\\c""#;
+        let expected = Config::default().with_filename_format(String::from(r#"a"b\c"#));
+        assert_eq!(parse(conf).unwrap(), expected);
+    }
+
+    #[test]
+    fn wrong_type() {
+        assert_eq!(
+            parse("frontmatter = \"yes\""),
+            invalid_value("frontmatter", "\"yes\"", "a boolean (true or false)", 1)
+        );
+        assert_eq!(
+            parse("\n\neditor = 3"),
+            invalid_value("editor", "3", "a string", 3)
+        );
+        assert_eq!(
+            parse("preview_lines = -2"),
+            invalid_value("preview_lines", "-2", "a non-negative integer", 1)
+        );
+        assert_eq!(
+            parse("tidy_after = \"later\""),
+            invalid_value("tidy_after", "later", "a duration (e.g. 90d, 12w)", 1)
+        );
+    }
+
+    #[test]
+    fn multiple_errors() {
+        let conf = "editor = 1\n[colors]\nfg = 'red'\n[alias]\nx = true\n";
+        assert_eq!(
+            parse(conf),
+            Err(Error::ConfigMultiple {
+                errors: vec![
+                    invalid_value::<(), _, _>("editor", "1", "a string", 1).unwrap_err(),
+                    unrecognized_key::<(), _>("[colors]", 2).unwrap_err(),
+                    invalid_value::<(), _, _>("x", "true", "a string", 5).unwrap_err(),
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn bad_syntax() {
+        assert_eq!(
+            parse("editor vim"),
+            invalid_toml("key with no value, expected `=`", 1)
+        );
+        for (conf, line) in &[
+            ("editor = \"vim", 1),
+            ("\neditor = \"vim\" extra", 2),
+            ("editor = 'vim'\neditor = 'vi'", 2),
+        ] {
+            assert!(
+                matches!(
+                    parse(conf),
+                    Err(Error::Config { line: l, kind: ConfigErrorKind::InvalidToml { .. }, .. })
+                        if l == *line
+                ),
+                "{}",
+                conf
+            );
+        }
+        assert_eq!(parse("[colors]"), unrecognized_key("[colors]", 1));
+        assert_eq!(parse("colour = 1"), unrecognized_key("colour", 1));
+        assert_eq!(
+            parse("[viewers]").unwrap_err().to_string(),
+            "Error in configuration at line 1: unrecognized key \"[viewers]\", did you mean \"[viewer]\"?"
+        );
+    }
+}
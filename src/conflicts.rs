@@ -0,0 +1,92 @@
+//! Finding the conflicted copies of notes left by file sync services.
+//!
+//! When a note is changed in two places before they are synced, services such as Dropbox and
+//! Syncthing keep both versions, saving one as a copy next to the note. Dropbox names the copy
+//! like `ideas (Alice's conflicted copy 2024-05-01).md`, and Syncthing like
+//! `ideas.sync-conflict-20240501-093000-ABCDEFG.md`.
+
+use crate::config::Config;
+use crate::error::*;
+use crate::notes_dir;
+
+use std::path::{Path, PathBuf};
+
+/// A conflicted copy of a note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// The copy, relative to the notes directory.
+    pub copy: PathBuf,
+
+    /// The note it is a copy of, relative to the notes directory. The note may no longer exist.
+    pub original: PathBuf,
+}
+
+/// Find the conflicted copies of notes in the notes directory, in the order they are listed.
+pub fn find(config: &Config) -> Result<Vec<Conflict>> {
+    Ok(notes_dir::list(config)?
+        .into_iter()
+        .filter_map(|copy| original_of(&copy).map(|original| Conflict { copy, original }))
+        .collect())
+}
+
+/// The path of the note that the file at `path` is a conflicted copy of, if it is one.
+pub fn original_of(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let (stem, ext) = match name.rfind('.') {
+        Some(idx) if idx > 0 => name.split_at(idx),
+        _ => (name, ""),
+    };
+
+    let (original, ext) = if let Some(idx) = name.find(".sync-conflict-") {
+        // Syncthing puts the date, time, and device after the marker, then the extension.
+        let rest = &name[idx + ".sync-conflict-".len()..];
+        (&name[..idx], rest.find('.').map_or("", |dot| &rest[dot..]))
+    } else if stem.ends_with(')') {
+        // The note's own name may contain parentheses, so the copy's are the last ones that say
+        // it is a conflicted copy.
+        let idx = stem
+            .rmatch_indices(" (")
+            .map(|(idx, _)| idx)
+            .find(|&idx| stem[idx..].to_lowercase().contains("conflicted copy"))?;
+        (&stem[..idx], ext)
+    } else {
+        return None;
+    };
+    if original.is_empty() {
+        return None;
+    }
+    Some(path.with_file_name(format!("{}{}", original, ext)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn conflicted_copies() {
+        let original = |path: &str| original_of(Path::new(path));
+        assert_eq!(
+            original("ideas (conflicted copy).md"),
+            Some(PathBuf::from("ideas.md"))
+        );
+        assert_eq!(
+            original("sub/Plan (draft) (Alice's Conflicted Copy 2024-05-01).md"),
+            Some(PathBuf::from("sub/Plan (draft).md"))
+        );
+        assert_eq!(
+            original("ideas (Bob's conflicted copy 2024-05-01 (1)).txt"),
+            Some(PathBuf::from("ideas.txt"))
+        );
+        assert_eq!(
+            original("ideas.sync-conflict-20240501-093000-ABCDEFG.md"),
+            Some(PathBuf::from("ideas.md"))
+        );
+        assert_eq!(
+            original("notes/todo.sync-conflict-20240501-093000-ABCDEFG"),
+            Some(PathBuf::from("notes/todo"))
+        );
+        assert_eq!(original("ideas (draft).md"), None);
+        assert_eq!(original("ideas.md"), None);
+        assert_eq!(original(" (conflicted copy).md"), None);
+    }
+}
@@ -1,51 +1,518 @@
 //! External command invocations.
 
-use crate::config::Config;
+use crate::backup;
+use crate::config::{Config, Storage};
 use crate::error::*;
+use crate::history;
+use crate::store::NoteStore;
 use crate::util::{env, sh};
 
-use std::path::{Path, PathBuf};
-use std::process::ExitStatus;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+const FILE_PLACEHOLDER: &str = "{file}";
+
+const LINE_PLACEHOLDER: &str = "{line}";
+
+/// Prefix of the temporary copies of notes made when `safe_edit` is set.
+const SAFE_EDIT_PREFIX: &str = ".newt-edit-";
+
+/// Editors that accept a `+N` argument before the file to open it at line N.
+const LINE_ARG_EDITORS: &[&str] = &[
+    "vi",
+    "vim",
+    "nvim",
+    "gvim",
+    "nano",
+    "pico",
+    "emacs",
+    "emacsclient",
+    "micro",
+    "kak",
+];
 
 /// Invoke the configured editor on the given path.
 ///
 /// If a configured editor is found and the child process invocation is successful, returns the
 /// exit status of the editor process. Otherwise returns an error.
 pub fn edit_file<P: AsRef<Path>>(config: &Config, path: P) -> Result<ExitStatus> {
-    let editor = config.editor()?;
-    let interpolated = if let Some(e) = editor.to_str() {
-        PathBuf::from(env::interpolate(e))
-    } else {
-        editor.clone()
-    };
+    edit_file_at(config, path, None)
+}
 
-    let mut cmd = sh::command(&interpolated).ok_or_else(|| cannot_invoke(&editor, None))?;
-    Ok(cmd
-        .arg(path.as_ref())
-        .status()
-        .map_err(|err| cannot_invoke(&editor, err))?)
+/// Invoke the configured editor on the given path, at the given line if the editor command has a
+/// `{line}` placeholder.
+pub fn edit_file_at<P: AsRef<Path>>(
+    config: &Config,
+    path: P,
+    line: Option<usize>,
+) -> Result<ExitStatus> {
+    let editor = config.editor()?;
+    let mut cmd = configured_command(config, &editor, Some(path.as_ref()), line)?;
+    cmd.status().map_err(|err| cannot_invoke(&editor, err))
 }
 
 /// Invoke the configured editor on the given path, relative to the notes directory.
 pub fn edit_note<P: AsRef<Path>>(config: &Config, path: P) -> Result<ExitStatus> {
+    edit_note_at(config, path, None)
+}
+
+/// Invoke the configured editor on the given path, relative to the notes directory, at the given
+/// line if the editor command has a `{line}` placeholder.
+///
+/// With `safe_edit` set, the editor is run on a temporary copy of the note, which replaces the
+/// note only if the editor exits successfully.
+pub fn edit_note_at<P: AsRef<Path>>(
+    config: &Config,
+    path: P,
+    line: Option<usize>,
+) -> Result<ExitStatus> {
+    backup::backup_note(config, path.as_ref())?;
+    history::snapshot(config, path.as_ref())?;
     let mut full_path = config.notes_dir()?;
     full_path.push(path.as_ref());
-    edit_file(config, full_path)
+    let status = if config.safe_edit() {
+        edit_file_safely(config, &full_path, line)?
+    } else {
+        edit_file_at(config, full_path, line)?
+    };
+
+    if status.success() {
+        history::snapshot(config, path.as_ref())?;
+    }
+    Ok(status)
+}
+
+/// Edit a copy of the file in the same directory, then atomically rename it over the file if the
+/// editor succeeds.
+///
+/// If the editor fails, the copy is removed, unless it was changed, in which case it is kept so
+/// that no edits are lost.
+fn edit_file_safely(config: &Config, path: &Path, line: Option<usize>) -> Result<ExitStatus> {
+    let mut temp_name = OsString::from(SAFE_EDIT_PREFIX);
+    temp_name.push(path.file_name().unwrap_or_default());
+    let temp = path.with_file_name(temp_name);
+
+    match fs::copy(path, &temp) {
+        Ok(_) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            fs::File::create(&temp).context("creating", &temp)?;
+        }
+        Err(err) => return Err(err).context("copying", path),
+    }
+
+    let result = edit_file_at(config, &temp, line);
+    if let Ok(status) = &result {
+        if status.success() {
+            fs::rename(&temp, path).context("replacing", path)?;
+            return result;
+        }
+    }
+
+    let original = fs::read(path).unwrap_or_default();
+    if fs::read(&temp)
+        .map(|edited| edited == original)
+        .unwrap_or(true)
+    {
+        fs::remove_file(&temp).context("removing", &temp)?;
+    } else {
+        warn!("unsaved edits kept in {}", temp.display());
+    }
+    result
+}
+
+/// Invoke the configured editor on a note in the given store, at the given line if the editor
+/// command has a `{line}` placeholder.
+///
+/// Notes stored as files are edited in place, as by [`edit_note_at`]. Otherwise the editor is run
+/// on a temporary copy of the note, which is written back to the store if the editor exits
+/// successfully.
+pub fn edit_stored_note<P: AsRef<Path>>(
+    store: &NoteStore,
+    path: P,
+    line: Option<usize>,
+) -> Result<ExitStatus> {
+    let config = store.config();
+    if config.storage() == Storage::Files {
+        return edit_note_at(config, path, line);
+    }
+
+    let mut temp_name = OsString::from(format!("{}{}-", SAFE_EDIT_PREFIX, std::process::id()));
+    temp_name.push(path.as_ref().file_name().unwrap_or_default());
+    let temp = std::env::temp_dir().join(temp_name);
+    let original = store.read(path.as_ref())?;
+    fs::write(&temp, &original).context("writing", &temp)?;
+
+    let result = edit_file_at(config, &temp, line);
+    let edited = fs::read_to_string(&temp).context("reading", &temp)?;
+    if let Ok(status) = &result {
+        if status.success() && edited != original {
+            store.write(path.as_ref(), &edited)?;
+        }
+    }
+
+    if edited == original || result.as_ref().map(ExitStatus::success).unwrap_or(false) {
+        fs::remove_file(&temp).context("removing", &temp)?;
+    } else {
+        warn!("unsaved edits kept in {}", temp.display());
+    }
+    result
 }
 
 /// Invoke the configured pager on the given path, relative to the notes directory.
+///
+/// If a viewer rule matches the file name, its viewer is used instead.
 pub fn view_note<P: AsRef<Path>>(config: &Config, path: P) -> Result<ExitStatus> {
     let path = config.notes_dir()?.join(path.as_ref());
+    let pager = match config.viewer(&path)? {
+        Some(viewer) => viewer,
+        None => config.pager()?,
+    };
+    let mut cmd = configured_command(config, &pager, Some(&path), None)?;
+    cmd.status().map_err(|err| cannot_invoke(&pager, err))
+}
+
+/// Invoke the configured pager with the given text piped to its standard input.
+///
+/// If the pager exits before consuming all of the text, the remainder is silently discarded.
+pub fn page_text(config: &Config, text: &str) -> Result<ExitStatus> {
     let pager = config.pager()?;
-    let interpolated = if let Some(p) = pager.to_str() {
-        PathBuf::from(env::interpolate(p))
-    } else {
-        pager.clone()
+    let mut cmd = configured_command(config, &pager, None, None)?;
+    // Let less show colored output, unless the user has configured it otherwise.
+    if env::env_var("LESS").is_none() {
+        cmd.env("LESS", "R");
+    }
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|err| cannot_invoke(&pager, err))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        match stdin.write_all(text.as_bytes()) {
+            Err(err) if err.kind() != ErrorKind::BrokenPipe => return Err(err.into()),
+            _ => {}
+        }
+    }
+
+    Ok(child.wait()?)
+}
+
+/// Invoke the configured opener on the given path, opening it in its default application.
+///
+/// If a viewer rule matches the file name, its viewer is used instead.
+pub fn open_path<P: AsRef<Path>>(config: &Config, path: P) -> Result<ExitStatus> {
+    let opener = match config.viewer(path.as_ref())? {
+        Some(viewer) => viewer,
+        None => config.opener()?,
     };
+    let mut cmd = configured_command(config, &opener, Some(path.as_ref()), None)?;
+    cmd.status().map_err(|err| cannot_invoke(&opener, err))
+}
+
+/// Convert a note to another format with the configured pandoc command, writing the result to
+/// `output`.
+///
+/// The note is given to pandoc on its standard input, as Markdown, along with its title and,
+/// for note files, the note's directory to find images and other resources in. The output
+/// `format` is passed as pandoc's `--to` option, except for `pdf`, which pandoc chooses a way to
+/// produce from the output file's extension.
+pub fn convert_note<P: AsRef<Path>>(
+    store: &NoteStore,
+    name: P,
+    format: &str,
+    output: &Path,
+) -> Result<ExitStatus> {
+    let config = store.config();
+    let name = name.as_ref();
+    let pandoc = config.pandoc()?;
+    let note = store.note(name);
+    let title = note
+        .title()?
+        .unwrap_or_else(|| name.to_string_lossy().into_owned());
 
-    let mut cmd = sh::command(&interpolated).ok_or_else(|| cannot_invoke(&pager, None))?;
-    Ok(cmd
-        .arg(&path)
+    let mut cmd = configured_command(config, &pandoc, None, None)?;
+    cmd.args(["--from", "markdown", "--standalone"])
+        .arg("--metadata")
+        .arg(format!("pagetitle={}", title))
+        .arg("--output")
+        .arg(output);
+    if format != "pdf" {
+        cmd.args(["--to", format]);
+    }
+    if config.storage() == Storage::Files {
+        if let Some(dir) = config.notes_dir()?.join(name).parent() {
+            cmd.arg("--resource-path").arg(dir);
+        }
+    }
+
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|err| cannot_invoke(&pandoc, err))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        match stdin.write_all(note.content()?.as_bytes()) {
+            Err(err) if err.kind() != ErrorKind::BrokenPipe => return Err(err.into()),
+            _ => {}
+        }
+    }
+    Ok(child.wait()?)
+}
+
+/// Report a program run by Newt that exited unsuccessfully, as an error if `strict_editor` is set
+/// and as a warning otherwise. The command names the kind of program, such as "editor".
+pub fn check_status(config: &Config, command: &str, status: ExitStatus) -> Result<()> {
+    if status.success() {
+        Ok(())
+    } else if config.strict_editor() {
+        Err(Error::ChildFailed {
+            command: String::from(command),
+            status,
+        })
+    } else {
+        warn!("{} process returned with status {}", command, status);
+        Ok(())
+    }
+}
+
+/// Run an arbitrary command on the given paths, relative to the notes directory.
+///
+/// Each argument that is exactly `{}` is replaced by all of the paths; if there is no such
+/// argument, the paths are appended to the end of the command. The command is run with the notes
+/// directory as its working directory.
+pub fn exec_command<P: AsRef<Path>>(
+    config: &Config,
+    command: &[String],
+    paths: &[P],
+) -> Result<ExitStatus> {
+    let args = substitute_paths(command, paths);
+    let (program, args) = args.split_first().ok_or_else(|| cannot_invoke("", None))?;
+
+    let mut cmd = Command::new(program);
+    cmd.args(args).current_dir(config.notes_dir()?);
+    trace!("Running {:?}", cmd);
+    cmd.status().map_err(|err| cannot_invoke(program, err))
+}
+
+/// Launch an interactive shell in the notes directory.
+///
+/// The shell is the configured one, by default `$SHELL`. `NEWT_NOTES_DIR` is set to the notes
+/// directory in the shell's environment.
+pub fn spawn_shell(config: &Config) -> Result<ExitStatus> {
+    let notes_dir = config.notes_dir()?;
+    let notes_dir = notes_dir.canonicalize().context("resolving", &notes_dir)?;
+    let shell = config.shell()?;
+
+    Command::new(&shell)
+        .current_dir(&notes_dir)
+        .env("NEWT_NOTES_DIR", &notes_dir)
         .status()
-        .map_err(|err| cannot_invoke(&pager, err))?)
+        .map_err(|err| cannot_invoke(shell, err))
+}
+
+/// Run the external subcommand program `newt-<name>` found on `$PATH` with the given arguments.
+///
+/// The resolved notes directory, editor, and pager are passed to the program in the
+/// `NEWT_NOTES_DIR`, `NEWT_EDITOR`, and `NEWT_PAGER` environment variables, where available.
+pub fn run_external(config: &Config, name: &str, args: &[String]) -> Result<ExitStatus> {
+    let program = format!("newt-{}", name);
+    let path = env::search_path(&program).ok_or_else(|| Error::UnknownCommand {
+        name: String::from(name),
+    })?;
+
+    let mut cmd = Command::new(&path);
+    cmd.args(args);
+    if let Ok(notes_dir) = config.notes_dir() {
+        cmd.env("NEWT_NOTES_DIR", notes_dir);
+    }
+    if let Ok(editor) = config.editor() {
+        cmd.env("NEWT_EDITOR", editor);
+    }
+    if let Ok(pager) = config.pager() {
+        cmd.env("NEWT_PAGER", pager);
+    }
+
+    trace!("Running {:?}", cmd);
+    cmd.status().map_err(|err| cannot_invoke(path, err))
+}
+
+/// Build a `Command` that runs a configured editor, pager, or opener command line on the given path.
+///
+/// The placeholders `{file}` and `{line}` in the command line are replaced by the path and the
+/// line number, which defaults to 1. The path is appended to the command if it does not contain a
+/// `{file}` placeholder, preceded by a `+N` argument if a line is given without a `{line}`
+/// placeholder and the program is known to accept one.
+///
+/// The command line is expected to have been expanded already, as it is by [`Config::editor`] and
+/// friends. With `shell_exec` set, the command line is run by the configured shell, so that it may
+/// use shell syntax, functions, and aliases. Otherwise it is split into words by [`sh::split`].
+pub(crate) fn configured_command(
+    config: &Config,
+    command: &Path,
+    path: Option<&Path>,
+    line: Option<usize>,
+) -> Result<Command> {
+    let command_line = command
+        .to_str()
+        .ok_or_else(|| cannot_invoke(command, None))?;
+    let words = sh::split(command_line);
+
+    if config.shell_exec() {
+        let mut script = command_line.replace(LINE_PLACEHOLDER, &line.unwrap_or(1).to_string());
+        if script.contains(FILE_PLACEHOLDER) {
+            script = script.replace(FILE_PLACEHOLDER, "\"$1\"");
+        } else {
+            if let Some(arg) = line_arg(&words, line) {
+                script.push(' ');
+                script.push_str(&arg);
+            }
+            script.push_str(" \"$@\"");
+        }
+
+        let mut cmd = Command::new(config.shell()?);
+        cmd.arg("-c").arg(script).arg("newt").args(path);
+        trace!("Running {:?}", cmd);
+        Ok(cmd)
+    } else {
+        let args = substitute_placeholders(&words, path, line);
+        let (program, args) = args
+            .split_first()
+            .ok_or_else(|| cannot_invoke(command, None))?;
+
+        let mut cmd = Command::new(program);
+        cmd.args(args);
+        trace!("Running {:?}", cmd);
+        Ok(cmd)
+    }
+}
+
+/// The `+N` argument for opening a file at the given line, if the command's program accepts one and
+/// the command has no `{line}` placeholder.
+fn line_arg(words: &[String], line: Option<usize>) -> Option<String> {
+    let line = line?;
+    let program = Path::new(words.first()?).file_stem()?.to_str()?;
+    let has_placeholder = words.iter().any(|word| word.contains(LINE_PLACEHOLDER));
+    if LINE_ARG_EDITORS.contains(&program) && !has_placeholder {
+        Some(format!("+{}", line))
+    } else {
+        None
+    }
+}
+
+fn substitute_placeholders(
+    words: &[String],
+    path: Option<&Path>,
+    line: Option<usize>,
+) -> Vec<OsString> {
+    let line_number = line.unwrap_or(1).to_string();
+    let mut args = Vec::new();
+    let mut substituted = false;
+    for word in words {
+        let word = word.replace(LINE_PLACEHOLDER, &line_number);
+        let mut arg = OsString::new();
+        for (i, part) in word.split(FILE_PLACEHOLDER).enumerate() {
+            if i > 0 {
+                arg.push(path.unwrap_or_else(|| Path::new("")));
+                substituted = true;
+            }
+            arg.push(part);
+        }
+        args.push(arg);
+    }
+
+    if !substituted {
+        args.extend(line_arg(words, line).map(OsString::from));
+        args.extend(path.map(|p| p.as_os_str().to_owned()));
+    }
+    args
+}
+
+fn substitute_paths<P: AsRef<Path>>(command: &[String], paths: &[P]) -> Vec<OsString> {
+    let mut args = Vec::new();
+    let mut substituted = false;
+    for arg in command {
+        if arg == "{}" {
+            args.extend(paths.iter().map(|p| p.as_ref().as_os_str().to_owned()));
+            substituted = true;
+        } else {
+            args.push(OsString::from(arg));
+        }
+    }
+
+    if !substituted {
+        args.extend(paths.iter().map(|p| p.as_ref().as_os_str().to_owned()));
+    }
+    args
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn words(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&a| String::from(a)).collect()
+    }
+
+    #[test]
+    fn editor_placeholders() {
+        let args = substitute_placeholders(
+            &words(&["code", "--goto", "{file}:{line}"]),
+            Some(Path::new("a b.md")),
+            Some(12),
+        );
+        assert_eq!(args, &["code", "--goto", "a b.md:12"]);
+
+        let args =
+            substitute_placeholders(&words(&["vim", "+{line}"]), Some(Path::new("a.md")), None);
+        assert_eq!(args, &["vim", "+1", "a.md"]);
+
+        let args = substitute_placeholders(&words(&["less", "-R"]), None, None);
+        assert_eq!(args, &["less", "-R"]);
+    }
+
+    #[test]
+    fn editor_line_args() {
+        let path = Some(Path::new("a.md"));
+        let args = substitute_placeholders(&words(&["/usr/bin/nvim", "-p"]), path, Some(3));
+        assert_eq!(args, &["/usr/bin/nvim", "-p", "+3", "a.md"]);
+
+        let args = substitute_placeholders(&words(&["emacs", "-nw"]), path, None);
+        assert_eq!(args, &["emacs", "-nw", "a.md"]);
+
+        let args = substitute_placeholders(&words(&["code", "--wait"]), path, Some(3));
+        assert_eq!(args, &["code", "--wait", "a.md"]);
+    }
+
+    #[test]
+    fn substitute_placeholder() {
+        let args = substitute_paths(
+            &words(&["pandoc", "{}", "-o", "out.pdf"]),
+            &["a.md", "b.md"],
+        );
+        assert_eq!(args, &["pandoc", "a.md", "b.md", "-o", "out.pdf"]);
+    }
+
+    #[test]
+    fn append_without_placeholder() {
+        let args = substitute_paths(&words(&["wc", "-w"]), &["a.md"]);
+        assert_eq!(args, &["wc", "-w", "a.md"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn strict_status() {
+        let failed = Command::new("sh").args(["-c", "exit 3"]).status().unwrap();
+        let config = Config::default();
+        assert!(check_status(&config, "editor", failed).is_ok());
+
+        let config = config.with_strict_editor(true);
+        let err = check_status(&config, "editor", failed).unwrap_err();
+        assert!(matches!(err, Error::ChildFailed { .. }));
+        assert_eq!(err.exit_code(), 3);
+        assert_eq!(
+            err.to_string(),
+            "editor process returned with status exit status: 3"
+        );
+    }
 }
@@ -1,5 +1,8 @@
 //! The Newt `Error` type.
 
+use crate::config;
+use crate::util;
+
 use std::path::{Path, PathBuf};
 
 /// Newt errors.
@@ -25,6 +28,20 @@ pub enum Error {
         kind: ConfigErrorKind,
     },
 
+    /// Several errors parsing a configuration file.
+    #[error("{}", display_errors(.errors))]
+    ConfigMultiple {
+        /// The individual errors, in the order they occur in the file.
+        errors: Vec<Error>,
+    },
+
+    /// A configuration check found problems that will stop Newt from working.
+    #[error("Configuration check failed with {count} error(s)")]
+    ConfigCheckFailed {
+        /// The number of errors found.
+        count: usize,
+    },
+
     /// No notes directory was configured or could be found.
     #[error("No notes directory configured or found")]
     NoNotesDir,
@@ -37,11 +54,276 @@ pub enum Error {
     #[error("No pager configured or found")]
     NoPager,
 
+    /// No program for opening files in their default application was configured or could be found.
+    #[error("No opener configured or found")]
+    NoOpener,
+
+    /// No pandoc command was configured or could be found.
+    #[error("No pandoc configured or found; install pandoc or set the \"pandoc\" option")]
+    NoPandoc,
+
+    /// The sync command was given no remote to sync with, and none is configured.
+    #[error("No remote to sync with; pass --rsync or set the \"sync_remote\" option")]
+    NoSyncRemote,
+
+    /// No clipboard program is installed, or none has a display to use.
+    #[error("No clipboard available; install a clipboard program such as wl-clipboard or xclip")]
+    NoClipboard,
+
+    /// A note was to be made from the clipboard, but the clipboard holds no text.
+    #[error("The clipboard is empty")]
+    ClipboardEmpty,
+
+    /// No mail command was configured or could be found.
+    #[error(
+        "No mail command configured or found; install msmtp or sendmail, or set the \"mail_command\" option"
+    )]
+    NoMailCommand,
+
+    /// The share command was run with no share command or GitHub token configured.
+    #[error(
+        "No way to share notes configured; set the \"share_command\" option, or \"gist_token\" or $GITHUB_TOKEN to share them as gists"
+    )]
+    NoShareTarget,
+
+    /// A note could not be shared.
+    #[error("Could not share note: {message}")]
+    ShareFailed {
+        /// What went wrong.
+        message: String,
+    },
+
+    /// The user gave something other than a number where a note's index was expected.
+    #[error("Invalid index {text:?}")]
+    InvalidIndex {
+        /// The text given as the index.
+        text: String,
+    },
+
+    /// The configured file name format could not be expanded.
+    #[error("Invalid file name format {format:?}")]
+    InvalidFilenameFormat {
+        /// The offending format string.
+        format: String,
+    },
+
+    /// A regular expression could not be compiled.
+    #[error("Invalid pattern {pattern:?}: {message}")]
+    InvalidRegex {
+        /// The offending pattern.
+        pattern: String,
+
+        /// A description of the problem.
+        message: String,
+    },
+
+    /// A JSON document could not be parsed.
+    #[error("Invalid JSON: {message}")]
+    InvalidJson {
+        /// A description of the problem and where it is.
+        message: String,
+    },
+
+    /// A file exported from another note app could not be read.
+    #[error("Could not import {}: {message}", .path.display())]
+    InvalidImport {
+        /// The exported file.
+        path: PathBuf,
+
+        /// A description of the problem.
+        message: String,
+    },
+
+    /// A requested metadata field is not present in a note's frontmatter.
+    #[error("No field {key:?} in note metadata")]
+    MissingField {
+        /// The missing field.
+        key: String,
+    },
+
+    /// The user specified a backup that does not exist.
+    #[error("No backup {number} of {}", .path.display())]
+    NoBackup {
+        /// The note's path, relative to the notes directory.
+        path: PathBuf,
+
+        /// The requested backup number.
+        number: usize,
+    },
+
+    /// A prompt could not be answered, because there is no terminal to ask it on.
+    #[error(
+        "No terminal to answer a prompt on (use --yes, or set non_interactive in the configuration): {prompt}"
+    )]
+    NonInteractive {
+        /// The prompt that could not be answered.
+        prompt: String,
+    },
+
+    /// A selection prompt was given nothing to choose from.
+    #[error("Nothing to choose from: {prompt}")]
+    NothingToChoose {
+        /// The prompt that had no choices.
+        prompt: String,
+    },
+
+    /// A search found no matches.
+    ///
+    /// Like grep, the command-line interface exits with a failure status without printing this.
+    #[error("No matches found")]
+    NoMatches,
+
+    /// The user specified a version of a note that does not exist.
+    #[error("No version {number} of {}", .path.display())]
+    NoVersion {
+        /// The note's path, relative to the notes directory.
+        path: PathBuf,
+
+        /// The requested version number.
+        number: usize,
+    },
+
+    /// A note is locked for editing by another Newt process.
+    #[error(
+        "Note {} is being edited by another newt process (pid {pid}); use --force to edit it anyway",
+        .path.display()
+    )]
+    NoteLocked {
+        /// The note's path, relative to the notes directory.
+        path: PathBuf,
+
+        /// The ID of the process holding the lock.
+        pid: u32,
+    },
+
+    /// No line of a note contains the text being looked for.
+    #[error("No line matching {pattern:?}")]
+    NoMatch {
+        /// The text being looked for.
+        pattern: String,
+    },
+
+    /// The user named a note that does not exist.
+    #[error("No note {}{}", .path.display(), did_you_mean(&.suggestion.as_ref().map(|p| p.display())))]
+    NoSuchNote {
+        /// The note's path, relative to the notes directory.
+        path: PathBuf,
+
+        /// The existing note closest to it, if any is close enough to be a likely correction.
+        suggestion: Option<PathBuf>,
+    },
+
+    /// The user specified a subcommand that is neither built in nor provided by an external
+    /// `newt-<name>` program.
+    #[error("No such command {name:?}")]
+    UnknownCommand {
+        /// The name of the subcommand.
+        name: String,
+    },
+
+    /// An environment variable required by a `${VAR:?message}` expression is unset or empty.
+    #[error("{name}: {message}")]
+    UnsetVariable {
+        /// The name of the variable.
+        name: String,
+
+        /// The message given in the expression.
+        message: String,
+    },
+
+    /// There was no recorded operation left to undo.
+    #[error("Nothing to undo")]
+    NothingToUndo,
+
+    /// The latest recorded operation could not be undone.
+    #[error("Cannot undo {operation}: {reason}")]
+    CannotUndo {
+        /// The operation that could not be undone.
+        operation: String,
+
+        /// Why it could not be undone.
+        reason: String,
+    },
+
     /// The user specified a file index that does not exist.
-    #[error("No file with index {index}")]
+    #[error("No file with index {index}; {}", valid_indices(*.count))]
     FileIndexOutOfRange {
         /// The provided, out-of-range index.
         index: usize,
+
+        /// The number of notes there are.
+        count: usize,
+    },
+
+    /// A file could not be created or moved because one already exists at the destination.
+    #[error("File {} already exists", .path.display())]
+    FileExists {
+        /// The existing file's path.
+        path: PathBuf,
+    },
+
+    /// A note name given for a new or moved note does not name a file inside the notes directory,
+    /// or names one in the directories Newt keeps its own data and backups in.
+    #[error(
+        "Note name {} must be a relative path within the notes directory, outside .newt and .backups",
+        .name.display()
+    )]
+    InvalidNoteName {
+        /// The name given.
+        name: PathBuf,
+    },
+
+    /// A backup archive could not be read in full, or its contents do not match their checksums.
+    #[error("Backup archive {} is damaged or incomplete", .path.display())]
+    CorruptArchive {
+        /// The archive's path.
+        path: PathBuf,
+    },
+
+    /// SQLite storage was configured, but Newt was built without the `sqlite` feature.
+    #[error("Newt was built without SQLite support; rebuild it with the \"sqlite\" feature")]
+    NoSqliteSupport,
+
+    /// The serve command was run in a build without the web server.
+    #[error("Newt was built without the web server; rebuild it with the \"serve\" feature")]
+    NoServeSupport,
+
+    /// A backup to or from S3 was asked for in a build without S3 support.
+    #[error("Newt was built without S3 support; rebuild it with the \"s3\" feature")]
+    NoS3Support,
+
+    /// An S3 location was not given as an `s3://bucket/prefix` URL.
+    #[cfg(feature = "s3")]
+    #[error("Invalid S3 URL {url:?}; expected s3://bucket/prefix")]
+    InvalidS3Url {
+        /// The URL given.
+        url: String,
+    },
+
+    /// The web server could not listen for connections.
+    #[cfg(feature = "serve")]
+    #[error("Could not listen on {address}: {source}")]
+    Listen {
+        /// The address listened on.
+        address: String,
+
+        /// The source IO error.
+        source: std::io::Error,
+    },
+
+    /// A command that works on note files directly was run with notes stored some other way.
+    #[error(
+        "This command only works with notes stored as files; use `newt export` to copy them out"
+    )]
+    RequiresFileStorage,
+
+    /// An error from the SQLite database notes are stored in.
+    #[cfg(feature = "sqlite")]
+    #[error("Database error: {source}")]
+    Database {
+        /// The source SQLite error.
+        #[from]
+        source: rusqlite::Error,
     },
 
     /// The editor command could not be parsed or invoked.
@@ -62,6 +344,30 @@ pub enum Error {
         source: Option<std::io::Error>,
     },
 
+    /// An editor, pager, or other program run by Newt exited unsuccessfully, with
+    /// `strict_editor` set.
+    #[error("{command} process returned with status {status}")]
+    ChildFailed {
+        /// The kind of program that failed, such as "editor".
+        command: String,
+
+        /// The program's exit status.
+        status: std::process::ExitStatus,
+    },
+
+    /// A system IO error while working on a file or directory.
+    #[error("Error {action} {}: {source}", .path.display())]
+    Io {
+        /// What was being done, such as "reading".
+        action: &'static str,
+
+        /// The file or directory.
+        path: PathBuf,
+
+        /// The source IO error.
+        source: std::io::Error,
+    },
+
     /// A system IO error.
     #[error("File IO error: {source}")]
     FileIo {
@@ -87,20 +393,108 @@ impl PartialEq for Error {
                 },
             ) => selfline == otherline && selfkind == otherkind && selfpath == otherpath,
 
+            (
+                Error::ConfigMultiple { errors: selferrors },
+                Error::ConfigMultiple {
+                    errors: othererrors,
+                },
+            ) => selferrors == othererrors,
+
             _ => false,
         }
     }
 }
 
+impl Error {
+    /// The kind of the underlying system IO error, if this is one.
+    pub fn io_kind(&self) -> Option<std::io::ErrorKind> {
+        match self {
+            Error::Io { source, .. } | Error::FileIo { source } => Some(source.kind()),
+            _ => None,
+        }
+    }
+
+    /// The status the command-line interface exits with for this error.
+    ///
+    /// A failed child program's exit code is passed on, or 128 plus the signal that killed it;
+    /// every other error gives 1.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::ChildFailed { status, .. } => status.code().unwrap_or_else(|| {
+                #[cfg(unix)]
+                {
+                    use std::os::unix::process::ExitStatusExt;
+                    status.signal().map(|signal| 128 + signal).unwrap_or(1)
+                }
+                #[cfg(not(unix))]
+                {
+                    1
+                }
+            }),
+            _ => 1,
+        }
+    }
+
+    /// Attribute a configuration error to the given file, if it is not already attributed to one.
+    pub fn in_config_file<P: AsRef<Path>>(self, file: P) -> Error {
+        match self {
+            Error::Config {
+                line,
+                kind,
+                path: None,
+            } => Error::Config {
+                line,
+                kind,
+                path: Some(PathBuf::from(file.as_ref())),
+            },
+
+            Error::ConfigMultiple { errors } => Error::ConfigMultiple {
+                errors: errors
+                    .into_iter()
+                    .map(|err| err.in_config_file(file.as_ref()))
+                    .collect(),
+            },
+
+            e => e,
+        }
+    }
+}
+
+fn valid_indices(count: usize) -> String {
+    match count {
+        0 => String::from("the notes directory is empty — run `newt new`"),
+        1 => String::from("the only valid index is 0"),
+        _ => format!("valid indices are 0..{}", count - 1),
+    }
+}
+
+fn did_you_mean<T: std::fmt::Display>(suggestion: &Option<T>) -> String {
+    match suggestion {
+        Some(suggestion) => format!(", did you mean \"{}\"?", suggestion),
+        None => String::new(),
+    }
+}
+
+fn display_errors(errors: &[Error]) -> String {
+    let mut res = format!("{} errors in configuration:", errors.len());
+    for err in errors {
+        res.push_str(&format!("\n  {}", err));
+    }
+    res
+}
+
 /// Newt configuration error kinds.
 #[derive(Debug, Clone, PartialEq, Eq, Error)]
 #[non_exhaustive]
 pub enum ConfigErrorKind {
     /// An unrecognized configuration key was specified.
-    #[error("unrecognized key {key:?}")]
+    #[error("unrecognized key {key:?}{}", did_you_mean(.suggestion))]
     UnrecognizedKey {
         /// The unrecognized key.
         key: String,
+
+        /// The recognized key closest to it, if any is close enough to be a likely correction.
+        suggestion: Option<String>,
     },
 
     /// An illegal or unexpected token was found.
@@ -110,13 +504,36 @@ pub enum ConfigErrorKind {
         token: String,
     },
 
+    /// A value was given for a key that does not accept it.
+    #[error("invalid value {value:?} for {key}, expected {expected}")]
+    InvalidValue {
+        /// The key being set.
+        key: String,
+
+        /// The invalid value.
+        value: String,
+
+        /// A description of the values the key accepts.
+        expected: String,
+    },
+
     /// The file ended unexpectedly.
     #[error("file ended unexpectedly")]
     UnexpectedEof,
 
+    /// Configuration files included each other too deeply, most likely in a cycle.
+    #[error("configuration files included too deeply")]
+    IncludeDepth,
+
     /// A string value was unterminated.
     #[error("missing '\"' character at end of string")]
     UnterminatedString,
+    /// A TOML configuration file is not valid TOML.
+    #[error("invalid TOML: {message}")]
+    InvalidToml {
+        /// What the TOML parser found wrong.
+        message: String,
+    },
 }
 
 impl ConfigErrorKind {
@@ -133,6 +550,41 @@ impl ConfigErrorKind {
 /// `Result` type specialized to Newt errors.
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Attaches what was being done, and to which file, to system IO errors.
+pub(crate) trait IoContext<T> {
+    /// Convert an IO error into an [`Error::Io`] for the given action and path.
+    fn context<P: AsRef<Path>>(self, action: &'static str, path: P) -> Result<T>;
+}
+
+impl<T> IoContext<T> for std::io::Result<T> {
+    fn context<P: AsRef<Path>>(self, action: &'static str, path: P) -> Result<T> {
+        self.map_err(|source| Error::Io {
+            action,
+            path: PathBuf::from(path.as_ref()),
+            source,
+        })
+    }
+}
+
+/// Combine the errors collected while parsing a configuration file into a single result.
+///
+/// Nested [`Error::ConfigMultiple`] errors, such as those from included files, are flattened.
+pub(crate) fn config_errors(errors: Vec<Error>) -> Result<()> {
+    let mut flat = Vec::new();
+    for err in errors {
+        match err {
+            Error::ConfigMultiple { errors } => flat.extend(errors),
+            e => flat.push(e),
+        }
+    }
+
+    match flat.len() {
+        0 => Ok(()),
+        1 => Err(flat.remove(0)),
+        _ => Err(Error::ConfigMultiple { errors: flat }),
+    }
+}
+
 pub(crate) fn cannot_invoke<S, O>(command: S, source: O) -> Error
 where
     PathBuf: From<S>,
@@ -144,16 +596,22 @@ where
     }
 }
 
+/// An unrecognized configuration key, or `[table]` header, with the closest recognized one as a
+/// suggestion.
 pub(crate) fn unrecognized_key<T, S>(key: S, line: usize) -> Result<T>
 where
     String: From<S>,
 {
+    let key = String::from(key);
+    let suggestion = match key.strip_prefix('[').and_then(|key| key.strip_suffix(']')) {
+        Some(table) => util::closest_match(table, config::TABLES.iter().copied())
+            .map(|table| format!("[{}]", table)),
+        None => util::closest_match(&key, config::KEYS.iter().copied()).map(str::to_owned),
+    };
     Err(Error::Config {
         line,
         path: None,
-        kind: ConfigErrorKind::UnrecognizedKey {
-            key: String::from(key),
-        },
+        kind: ConfigErrorKind::UnrecognizedKey { key, suggestion },
     })
 }
 
@@ -170,6 +628,21 @@ where
     })
 }
 
+pub(crate) fn invalid_value<T, K, V>(key: K, value: V, expected: &str, line: usize) -> Result<T>
+where
+    String: From<K> + From<V>,
+{
+    Err(Error::Config {
+        line,
+        path: None,
+        kind: ConfigErrorKind::InvalidValue {
+            key: String::from(key),
+            value: String::from(value),
+            expected: String::from(expected),
+        },
+    })
+}
+
 pub(crate) fn unexpected_eof<T>(line: usize) -> Result<T> {
     Err(Error::Config {
         line,
@@ -185,3 +658,16 @@ pub(crate) fn unterminated_string<T>(line: usize) -> Result<T> {
         kind: ConfigErrorKind::UnterminatedString,
     })
 }
+
+pub(crate) fn invalid_toml<T, S>(message: S, line: usize) -> Result<T>
+where
+    String: From<S>,
+{
+    Err(Error::Config {
+        line,
+        path: None,
+        kind: ConfigErrorKind::InvalidToml {
+            message: String::from(message),
+        },
+    })
+}
@@ -0,0 +1,356 @@
+//! YAML frontmatter blocks at the start of notes.
+//!
+//! Only a small subset of YAML is understood: each top-level `key: value` line starts an entry,
+//! and any indented or `- ` prefixed lines that follow it are kept as part of that entry's value.
+//! Values are otherwise preserved verbatim, so a block can be read, modified, and written back
+//! without disturbing entries Newt doesn't know about.
+
+use std::fmt;
+use std::io::{self, BufRead};
+
+/// A parsed frontmatter block.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Frontmatter {
+    entries: Vec<(String, String)>,
+}
+
+impl Frontmatter {
+    /// Parse the contents of a frontmatter block, excluding the `---` delimiter lines.
+    pub fn parse(text: &str) -> Frontmatter {
+        let mut entries: Vec<(String, String)> = Vec::new();
+        for line in text.lines() {
+            let continuation = line.starts_with(char::is_whitespace) || line.starts_with("- ");
+            match (continuation, entries.last_mut(), line.find(':')) {
+                (true, Some((_, value)), _) => {
+                    value.push('\n');
+                    value.push_str(line);
+                }
+
+                (false, _, Some(idx)) => {
+                    let key = line[..idx].trim();
+                    let value = line[idx + 1..].trim();
+                    entries.push((String::from(key), String::from(value)));
+                }
+
+                _ => {}
+            }
+        }
+
+        Frontmatter { entries }
+    }
+
+    /// Get the raw value of the given key, if present.
+    pub fn get_raw(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Get the value of the given key as a string scalar, with any quoting removed.
+    ///
+    /// Returns `None` if the key is missing or its value is empty.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.get_raw(key)
+            .map(unquote)
+            .filter(|value| !value.is_empty())
+    }
+
+    /// Get the value of the given key as a list of strings.
+    ///
+    /// Both flow-style (`[a, b]`) and block-style (`- a` on subsequent lines) lists are
+    /// understood. A scalar value is returned as a single-element list. Returns `None` if the key
+    /// is missing.
+    pub fn get_list(&self, key: &str) -> Option<Vec<String>> {
+        let raw = self.get_raw(key)?.trim();
+        let items = if raw.starts_with('[') && raw.ends_with(']') {
+            raw[1..raw.len() - 1]
+                .split(',')
+                .map(unquote)
+                .filter(|item| !item.is_empty())
+                .collect()
+        } else if raw.starts_with('-') {
+            raw.lines()
+                .filter_map(|line| line.trim().strip_prefix('-'))
+                .map(unquote)
+                .filter(|item| !item.is_empty())
+                .collect()
+        } else if raw.is_empty() {
+            Vec::new()
+        } else {
+            vec![unquote(raw)]
+        };
+
+        Some(items)
+    }
+
+    /// Set the given key to the given value, replacing any existing value.
+    ///
+    /// Flow-style lists (`[a, b]`) are stored as-is; any other value is stored as a string
+    /// scalar, quoted if necessary.
+    pub fn set(&mut self, key: &str, value: &str) {
+        let value = if value.starts_with('[') && value.ends_with(']') || !needs_quotes(value) {
+            String::from(value)
+        } else {
+            quote(value)
+        };
+
+        if let Some((_, v)) = self.entries.iter_mut().find(|(k, _)| k == key) {
+            *v = value;
+        } else {
+            self.entries.push((String::from(key), value));
+        }
+    }
+
+    /// Set the given key to a flow-style list of the given items, quoting any that need it.
+    pub fn set_list<S: AsRef<str>>(&mut self, key: &str, items: &[S]) {
+        let items: Vec<_> = items
+            .iter()
+            .map(|item| {
+                let item = item.as_ref();
+                if needs_quotes(item) {
+                    quote(item)
+                } else {
+                    String::from(item)
+                }
+            })
+            .collect();
+        self.set(key, &format!("[{}]", items.join(", ")));
+    }
+
+    /// Remove the given key, returning whether it was present.
+    pub fn remove(&mut self, key: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|(k, _)| k != key);
+        self.entries.len() != len
+    }
+
+    /// Iterate over the keys in this block, in order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|(k, _)| k.as_str())
+    }
+
+    /// Whether the block contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl fmt::Display for Frontmatter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "---")?;
+        for (key, value) in &self.entries {
+            if value.is_empty() || value.starts_with('\n') {
+                writeln!(f, "{}:{}", key, value)?;
+            } else {
+                writeln!(f, "{}: {}", key, value)?;
+            }
+        }
+        writeln!(f, "---")
+    }
+}
+
+/// Build the frontmatter block for a newly created note.
+pub fn for_new_note(title: Option<&str>, created: &str) -> Frontmatter {
+    Frontmatter {
+        entries: vec![
+            (String::from("title"), quote(title.unwrap_or(""))),
+            (String::from("created"), String::from(created)),
+            (String::from("tags"), String::from("[]")),
+        ],
+    }
+}
+
+/// Split a note's text into its frontmatter, if any, and the remaining body.
+pub fn split(text: &str) -> (Option<Frontmatter>, &str) {
+    let rest = if let Some(rest) = text.strip_prefix("---\n") {
+        rest
+    } else if let Some(rest) = text.strip_prefix("---\r\n") {
+        rest
+    } else {
+        return (None, text);
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        if trimmed == "---" || trimmed == "..." {
+            let block = Frontmatter::parse(&rest[..offset]);
+            return (Some(block), &rest[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+
+    (Some(Frontmatter::parse(rest)), "")
+}
+
+/// Read the frontmatter block from the start of the given reader, if there is one.
+///
+/// If the reader begins with a frontmatter block, the block is consumed and the reader is left
+/// positioned at the start of the note body. Otherwise nothing is consumed.
+pub fn read<R: BufRead>(reader: &mut R) -> io::Result<Option<Frontmatter>> {
+    let buf = reader.fill_buf()?;
+    if !(buf.starts_with(b"---\n") || buf.starts_with(b"---\r\n")) {
+        return Ok(None);
+    }
+
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line)?;
+
+    // Invalid UTF-8 is replaced rather than failing, so that a stray binary file that happens to
+    // start like frontmatter cannot break listings.
+    let mut block = String::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+
+        let line = String::from_utf8_lossy(&line);
+        let trimmed = line.trim_end();
+        if trimmed == "---" || trimmed == "..." {
+            break;
+        }
+        block.push_str(&line);
+    }
+
+    Ok(Some(Frontmatter::parse(&block)))
+}
+
+fn needs_quotes(value: &str) -> bool {
+    value.is_empty()
+        || value.trim() != value
+        || value.starts_with(|c| "-?:,[]{}#&*!|>'\"%@`".contains(c))
+        || value.contains(": ")
+        || value.contains(" #")
+        || value.contains('\n')
+}
+
+fn quote(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        let mut res = String::new();
+        let mut chars = value[1..value.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next() {
+                    Some('n') => res.push('\n'),
+                    Some('t') => res.push('\t'),
+                    Some(c) => res.push(c),
+                    None => {}
+                }
+            } else {
+                res.push(c);
+            }
+        }
+        res
+    } else if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        value[1..value.len() - 1].replace("''", "'")
+    } else {
+        String::from(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_note() {
+        let text = "---\ntitle: \"Hi there\"\ntags: []\n---\n# Body\n";
+        let (block, body) = split(text);
+        let block = block.unwrap();
+        assert_eq!(block.get("title").as_deref(), Some("Hi there"));
+        assert_eq!(block.get_raw("tags"), Some("[]"));
+        assert_eq!(body, "# Body\n");
+    }
+
+    #[test]
+    fn split_no_frontmatter() {
+        let text = "# Body\n---\n";
+        assert_eq!(split(text), (None, text));
+    }
+
+    #[test]
+    fn read_leaves_body() {
+        let mut text = "---\ntitle: x\n---\nbody\n".as_bytes();
+        let block = read(&mut text).unwrap().unwrap();
+        assert_eq!(block.get("title").as_deref(), Some("x"));
+        assert_eq!(text, b"body\n");
+    }
+
+    #[test]
+    fn read_invalid_utf8() {
+        let mut text: &[u8] = b"---\ntitle: \xffx\n---\nbody\n";
+        let block = read(&mut text).unwrap().unwrap();
+        assert_eq!(block.get("title").as_deref(), Some("\u{fffd}x"));
+        assert_eq!(text, b"body\n");
+    }
+
+    #[test]
+    fn read_no_frontmatter() {
+        let mut text = "body\n".as_bytes();
+        assert_eq!(read(&mut text).unwrap(), None);
+        assert_eq!(text, b"body\n");
+    }
+
+    #[test]
+    fn block_values_round_trip() {
+        let text = "title: 'It''s'\ntags:\n  - one\n  - two\nempty:\n";
+        let block = Frontmatter::parse(text);
+        assert_eq!(block.get("title").as_deref(), Some("It's"));
+        assert_eq!(block.get("empty"), None);
+        assert_eq!(block.to_string(), format!("---\n{}---\n", text));
+    }
+
+    #[test]
+    fn lists() {
+        let block = Frontmatter::parse("a: [x, \"y z\"]\nb:\n  - one\n  - 'two'\nc: solo\nd: []\n");
+        assert_eq!(block.get_list("a").unwrap(), &["x", "y z"]);
+        assert_eq!(block.get_list("b").unwrap(), &["one", "two"]);
+        assert_eq!(block.get_list("c").unwrap(), &["solo"]);
+        assert!(block.get_list("d").unwrap().is_empty());
+        assert_eq!(block.get_list("e"), None);
+    }
+
+    #[test]
+    fn set_values() {
+        let mut block = Frontmatter::parse("title: old\n");
+        block.set("title", "New: improved");
+        block.set("tags", "[a, b]");
+        block.set("due", "2024-06-01");
+        assert_eq!(
+            block.to_string(),
+            "---\ntitle: \"New: improved\"\ntags: [a, b]\ndue: 2024-06-01\n---\n"
+        );
+        assert!(block.remove("due"));
+        assert!(!block.remove("due"));
+
+        block.set_list("tags", &["x", "two words", "#hash"]);
+        assert_eq!(block.get_raw("tags"), Some("[x, two words, \"#hash\"]"));
+        assert_eq!(
+            block.get_list("tags").unwrap(),
+            &["x", "two words", "#hash"]
+        );
+    }
+
+    #[test]
+    fn new_note_block() {
+        let block = for_new_note(Some("A \"quoted\" title"), "2024-05-01T09:30:00+00:00");
+        assert_eq!(
+            block.to_string(),
+            "---\ntitle: \"A \\\"quoted\\\" title\"\ncreated: 2024-05-01T09:30:00+00:00\ntags: []\n---\n"
+        );
+        assert_eq!(block.get("title").as_deref(), Some("A \"quoted\" title"));
+    }
+}
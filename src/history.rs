@@ -0,0 +1,181 @@
+//! Note history, kept as content-addressed snapshots taken whenever a note is saved.
+//!
+//! The contents of every version of every note are stored once each under
+//! `.newt/history/objects`, named by a hash of their contents. The versions of each note are
+//! recorded, oldest first, in a log file named after the note's path under `.newt/history/log`.
+
+use crate::config::Config;
+use crate::error::*;
+use crate::notes_dir::DATA_DIR;
+use crate::util;
+
+use chrono::{Local, NaiveDateTime};
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f";
+
+/// A saved version of a note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    /// The name of the snapshot holding the note's contents at this version.
+    pub hash: String,
+
+    /// The local time at which the version was saved.
+    pub time: NaiveDateTime,
+}
+
+fn history_dir(config: &Config) -> Result<PathBuf> {
+    Ok(config.notes_dir()?.join(DATA_DIR).join("history"))
+}
+
+fn log_path<P: AsRef<Path>>(config: &Config, path: P) -> Result<PathBuf> {
+    Ok(history_dir(config)?.join("log").join(path))
+}
+
+fn object_path(config: &Config, hash: &str) -> Result<PathBuf> {
+    Ok(history_dir(config)?.join("objects").join(hash))
+}
+
+/// Store the given contents as a snapshot, if they are not stored already, and return its name.
+///
+/// In the unlikely event of a hash collision, a numeric suffix is added to the name.
+fn store(config: &Config, contents: &[u8]) -> Result<String> {
+    let hash = format!("{:016x}", util::fnv1a(contents));
+    let dir = history_dir(config)?.join("objects");
+    fs::create_dir_all(&dir).context("creating", &dir)?;
+
+    for n in 0.. {
+        let name = if n == 0 {
+            hash.clone()
+        } else {
+            format!("{}-{}", hash, n)
+        };
+
+        let path = dir.join(&name);
+        match fs::read(&path) {
+            Ok(existing) if existing == contents => return Ok(name),
+            Ok(_) => continue,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                fs::write(&path, contents).context("writing", &path)?;
+                return Ok(name);
+            }
+            Err(err) => return Err(err).context("reading", path),
+        }
+    }
+
+    unreachable!()
+}
+
+/// Record the current contents of a note as a new version, if they differ from the latest one.
+///
+/// Returns the new version, if one was recorded. Nothing is done if the note does not exist.
+pub fn snapshot<P: AsRef<Path>>(config: &Config, path: P) -> Result<Option<Version>> {
+    let note = config.notes_dir()?.join(path.as_ref());
+    let contents = match fs::read(&note) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).context("reading", note),
+    };
+
+    if let Some(latest) = versions(config, path.as_ref())?.first() {
+        if fs::read(object_path(config, &latest.hash)?).ok().as_ref() == Some(&contents) {
+            return Ok(None);
+        }
+    }
+
+    let version = Version {
+        hash: store(config, &contents)?,
+        time: Local::now().naive_local(),
+    };
+
+    let log = log_path(config, path.as_ref())?;
+    if let Some(parent) = log.parent() {
+        fs::create_dir_all(parent).context("creating", parent)?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log)
+        .context("opening", &log)?;
+    writeln!(
+        file,
+        "{} {}",
+        version.time.format(TIME_FORMAT),
+        version.hash
+    )
+    .context("writing", &log)?;
+
+    debug!(
+        "Saved version {} of {}",
+        version.hash,
+        path.as_ref().display()
+    );
+    Ok(Some(version))
+}
+
+/// List the recorded versions of a note, newest first.
+pub fn versions<P: AsRef<Path>>(config: &Config, path: P) -> Result<Vec<Version>> {
+    let path = log_path(config, path)?;
+    let log = match fs::read_to_string(&path) {
+        Ok(log) => log,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).context("reading", path),
+    };
+
+    let mut versions: Vec<_> = log
+        .lines()
+        .filter_map(|line| {
+            let (time, hash) = line.split_once(' ')?;
+            Some(Version {
+                hash: String::from(hash),
+                time: NaiveDateTime::parse_from_str(time, TIME_FORMAT).ok()?,
+            })
+        })
+        .collect();
+    versions.reverse();
+    Ok(versions)
+}
+
+/// Read the contents of a note at the given version.
+pub fn read_version(config: &Config, version: &Version) -> Result<String> {
+    let path = object_path(config, &version.hash)?;
+    fs::read_to_string(&path).context("reading", &path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::temp::TempDir;
+
+    #[test]
+    fn snapshots() {
+        let temp = TempDir::new("history-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let config = Config::default().with_notes_dir(dir.clone());
+        let note = Path::new("sub/a.md");
+
+        assert_eq!(snapshot(&config, note).unwrap(), None);
+        for contents in &["one", "two", "two", "one"] {
+            fs::write(dir.join(note), contents).unwrap();
+            snapshot(&config, note).unwrap();
+        }
+
+        let saved = versions(&config, note).unwrap();
+        let contents: Vec<_> = saved
+            .iter()
+            .map(|version| read_version(&config, version).unwrap())
+            .collect();
+        assert_eq!(contents, &["one", "two", "one"]);
+        assert_eq!(saved[0].hash, saved[2].hash);
+        assert_eq!(
+            fs::read_dir(dir.join(".newt/history/objects"))
+                .unwrap()
+                .count(),
+            2
+        );
+    }
+}
@@ -0,0 +1,709 @@
+//! Importing notes exported from other note apps.
+//!
+//! Each importer reads an app's export into [`Imported`] notes, which [`write`] saves as
+//! Markdown notes, new or in place of existing ones. A note's title, tags, and creation time are kept in its frontmatter, and its
+//! modification time as the note file's own when notes are stored as files.
+//!
+//! The supported exports are:
+//!
+//! - Simplenote's `notes.json`, read by [`simplenote`]. Trashed notes are left out.
+//! - Evernote's `.enex` files, read by [`enex`]. The notes' ENML is converted to Markdown;
+//!   attachments are left out.
+//! - nvALT's notes directory of plain text and Markdown files, read by [`nvalt`]. nvALT keeps
+//!   tags in extended file attributes, which are not read, so these notes have no tags.
+
+use crate::config::Storage;
+use crate::error::*;
+use crate::frontmatter;
+use crate::journal::{self, Change};
+use crate::notes_dir;
+use crate::store::NoteStore;
+use crate::util::json::{self, Value};
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The format frontmatter timestamps are written in, as for new notes.
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%:z";
+
+/// The extensions of the files nvALT keeps notes in.
+const NVALT_EXTENSIONS: &[&str] = &["txt", "text", "md", "markdown", "mdown"];
+
+/// A note read from another app's export.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Imported {
+    /// The note's title.
+    pub title: String,
+
+    /// The note's contents, as Markdown.
+    pub body: String,
+
+    /// The note's tags.
+    pub tags: Vec<String>,
+
+    /// When the note was created, if known.
+    pub created: Option<DateTime<Local>>,
+
+    /// When the note was last modified, if known.
+    pub modified: Option<DateTime<Local>>,
+}
+
+impl Imported {
+    /// When the note was created, or else when it was last modified, if either is known.
+    fn written(&self) -> Option<DateTime<Local>> {
+        self.created.or(self.modified)
+    }
+
+    /// The note's contents, with a frontmatter block holding its title, creation time, and tags.
+    pub fn contents(&self) -> String {
+        let created = self
+            .written()
+            .unwrap_or_else(Local::now)
+            .format(TIME_FORMAT)
+            .to_string();
+        let mut block = frontmatter::for_new_note(Some(&self.title), &created);
+        block.set_list("tags", &self.tags);
+        format!("{}\n{}", block, self.body)
+    }
+}
+
+/// The name an imported note is saved under, unless it is saved in place of an existing note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Name {
+    /// A name no other note has.
+    pub name: PathBuf,
+
+    /// The name the note would have been given, had another note not already had it.
+    pub taken: Option<PathBuf>,
+}
+
+/// How an imported note is saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Save {
+    /// As a new note with the given name.
+    New(PathBuf),
+
+    /// In place of the existing note with the given name.
+    Replace(PathBuf),
+
+    /// Not at all.
+    Skip,
+}
+
+/// The names imported notes are saved under: the configured file name format, rendered with
+/// their titles and the times they were created.
+pub fn names(store: &NoteStore, notes: &[Imported]) -> Result<Vec<Name>> {
+    let config = store.config();
+    let mut existing = store.list()?;
+    let mut names = Vec::with_capacity(notes.len());
+    for note in notes {
+        let time = note.written().unwrap_or_else(Local::now);
+        let wanted = notes_dir::new_file_name_at(config, &[], Some(&note.title), &time)?;
+        let name = notes_dir::new_file_name_at(config, &existing, Some(&note.title), &time)?;
+        existing.push(name.clone());
+        names.push(Name {
+            taken: Some(wanted).filter(|wanted| *wanted != name),
+            name,
+        });
+    }
+    Ok(names)
+}
+
+/// Save imported notes as each of `saves` says. Returns the names of the notes saved. Saving them
+/// can be undone, restoring any notes they replaced.
+pub fn write(store: &NoteStore, notes: &[Imported], saves: &[Save]) -> Result<Vec<PathBuf>> {
+    let config = store.config();
+    let mut saved = Vec::with_capacity(notes.len());
+    let mut changes = Vec::with_capacity(notes.len());
+    let result = (|| {
+        for (note, save) in notes.iter().zip(saves) {
+            let name = match save {
+                Save::Replace(name) if store.backend().exists(name)? => {
+                    let backup = journal::backup(config, name)?;
+                    store.write(name, &note.contents())?;
+                    changes.push(Change::Replaced {
+                        path: name.clone(),
+                        backup,
+                    });
+                    name
+                }
+                Save::New(name) | Save::Replace(name) => {
+                    store.write(name, &note.contents())?;
+                    changes.push(Change::Created { path: name.clone() });
+                    name
+                }
+                Save::Skip => continue,
+            };
+            saved.push(name.clone());
+
+            if let (Storage::Files, Some(modified)) = (config.storage(), note.modified) {
+                let path = config.notes_dir()?.join(name);
+                fs::File::options()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|file| file.set_modified(modified.into()))
+                    .context("setting the modification time of", &path)?;
+            }
+        }
+        Ok(())
+    })();
+
+    journal::record(config, "import", changes)?;
+    result.map(|()| saved)
+}
+
+/// Read the notes in a Simplenote `notes.json` export.
+pub fn simplenote(path: &Path) -> Result<Vec<Imported>> {
+    let text = fs::read_to_string(path).context("reading", path)?;
+    let invalid = |message: &str| Error::InvalidImport {
+        path: path.to_path_buf(),
+        message: String::from(message),
+    };
+    let export = json::parse(&text).map_err(|err| invalid(&err.to_string()))?;
+    let notes = match export.get("activeNotes") {
+        Some(Value::Array(notes)) => notes,
+        _ => return Err(invalid("no \"activeNotes\" list")),
+    };
+
+    let mut imported = Vec::with_capacity(notes.len());
+    for note in notes {
+        let body = note
+            .get("content")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("a note has no \"content\""))?;
+        let time = |key| {
+            note.get(key)
+                .and_then(Value::as_str)
+                .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                .map(|time| time.with_timezone(&Local))
+        };
+        let tags = match note.get("tags") {
+            Some(Value::Array(tags)) => tags
+                .iter()
+                .filter_map(Value::as_str)
+                .map(String::from)
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        imported.push(Imported {
+            title: first_line_title(body),
+            body: String::from(body),
+            tags,
+            created: time("creationDate"),
+            modified: time("lastModified"),
+        });
+    }
+    Ok(imported)
+}
+
+/// Read the notes in an Evernote `.enex` export.
+pub fn enex(path: &Path) -> Result<Vec<Imported>> {
+    let text = fs::read_to_string(path).context("reading", path)?;
+    if !text.contains("<en-export") {
+        return Err(Error::InvalidImport {
+            path: path.to_path_buf(),
+            message: String::from("not an Evernote export"),
+        });
+    }
+
+    let mut imported = Vec::new();
+    let mut media = 0;
+    for note in elements(&text, "note") {
+        let field = |name| elements(note, name).next().map(decode_text);
+        let title = field("title")
+            .map(|title| title.trim().to_string())
+            .filter(|title| !title.is_empty())
+            .unwrap_or_else(|| String::from("Untitled"));
+        let content = field("content").unwrap_or_default();
+        media += content.matches("<en-media").count();
+        let time = |name| {
+            field(name)
+                .and_then(|time| NaiveDateTime::parse_from_str(time.trim(), "%Y%m%dT%H%M%SZ").ok())
+                .map(|time| Utc.from_utc_datetime(&time).with_timezone(&Local))
+        };
+
+        let body = enml_to_markdown(&content);
+        imported.push(Imported {
+            body: if body.is_empty() {
+                format!("# {}\n", title)
+            } else {
+                format!("# {}\n\n{}\n", title, body)
+            },
+            tags: elements(note, "tag").map(decode_text).collect(),
+            created: time("created"),
+            modified: time("updated"),
+            title,
+        });
+    }
+
+    if media > 0 {
+        warn!(
+            "Left out {} attachment(s) from {}; Newt imports only the notes' text",
+            media,
+            path.display()
+        );
+    }
+    Ok(imported)
+}
+
+/// Read the notes in an nvALT notes directory: its plain text and Markdown files, titled by their
+/// file names.
+pub fn nvalt(dir: &Path) -> Result<Vec<Imported>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir).context("reading", dir)? {
+        let path = entry.context("reading", dir)?.path();
+        let is_note = match path.extension() {
+            Some(ext) => NVALT_EXTENSIONS
+                .iter()
+                .any(|known| ext.to_string_lossy().eq_ignore_ascii_case(known)),
+            None => false,
+        };
+        if is_note && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut imported = Vec::with_capacity(paths.len());
+    for path in paths {
+        let body = fs::read_to_string(&path).context("reading", &path)?;
+        let md = fs::metadata(&path).context("reading", &path)?;
+        let time = |time: std::io::Result<SystemTime>| time.ok().map(DateTime::<Local>::from);
+        imported.push(Imported {
+            title: path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            body,
+            tags: Vec::new(),
+            created: time(md.created()),
+            modified: time(md.modified()),
+        });
+    }
+    Ok(imported)
+}
+
+/// The first non-blank line of a note's text, without any heading marker, as its title.
+fn first_line_title(text: &str) -> String {
+    text.lines()
+        .map(|line| line.trim().trim_start_matches('#').trim())
+        .find(|line| !line.is_empty())
+        .map(String::from)
+        .unwrap_or_else(|| String::from("Untitled"))
+}
+
+/// The contents of each `<name>` element in `text`, which are assumed not to nest.
+fn elements<'a>(text: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    let close = format!("</{}>", name);
+    let mut rest = text;
+    std::iter::from_fn(move || loop {
+        let start = rest.find(&format!("<{}", name))?;
+        let after = &rest[start + name.len() + 1..];
+        let end = after.find('>')?;
+        // Elements whose names only start with this one, such as <note-attributes> for <note>.
+        if !after[..end].is_empty() && !after.starts_with(char::is_whitespace) {
+            rest = after;
+            continue;
+        }
+        if after[..end].ends_with('/') {
+            rest = &after[end + 1..];
+            return Some("");
+        }
+
+        let inner = &after[end + 1..];
+        let len = inner.find(&close).unwrap_or(inner.len());
+        rest = &inner[len..];
+        return Some(&inner[..len]);
+    })
+}
+
+/// The text of an element's contents: those of a CDATA section, or else with entities decoded.
+fn decode_text(text: &str) -> String {
+    let text = text.trim();
+    match text
+        .strip_prefix("<![CDATA[")
+        .and_then(|text| text.strip_suffix("]]>"))
+    {
+        Some(data) => String::from(data),
+        None => decode_entities(text),
+    }
+}
+
+/// Replace XML and HTML character references with the characters they stand for.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) if end <= 10 => end,
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+        match c {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// The value of an attribute in the text of a start tag, if it has one.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+    while let Some(start) = rest.find(name) {
+        let before = rest[..start].chars().last();
+        let after = rest[start + name.len()..].trim_start();
+        rest = &rest[start + name.len()..];
+        if !matches!(before, Some(c) if c.is_whitespace()) {
+            continue;
+        }
+        if let Some(value) = after.strip_prefix('=') {
+            let value = value.trim_start();
+            let quote = value.chars().next()?;
+            if quote == '"' || quote == '\'' {
+                let value = &value[1..];
+                return value.find(quote).map(|end| decode_entities(&value[..end]));
+            }
+        }
+    }
+    None
+}
+
+/// Convert a note's ENML, Evernote's subset of XHTML, to Markdown.
+///
+/// Paragraphs, line breaks, headings, emphasis, links, lists, checkboxes, code blocks, and rules
+/// are converted; other markup is dropped, keeping its text.
+fn enml_to_markdown(enml: &str) -> String {
+    let mut out = String::new();
+    let mut lists: Vec<Option<usize>> = Vec::new();
+    let mut links = Vec::new();
+    let mut pre = false;
+    let mut rest = enml;
+
+    while !rest.is_empty() {
+        let start = rest.find('<').unwrap_or(rest.len());
+        let text = decode_entities(&rest[..start]);
+        if pre {
+            out.push_str(&text);
+        } else {
+            let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+            let space = rest[..start].starts_with(char::is_whitespace);
+            if space && !text.is_empty() && !out.ends_with(char::is_whitespace) && !out.is_empty() {
+                out.push(' ');
+            }
+            out.push_str(&text);
+            if !text.is_empty() && rest[..start].ends_with(char::is_whitespace) {
+                out.push(' ');
+            }
+        }
+        rest = &rest[start..];
+        if rest.is_empty() {
+            break;
+        }
+
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        match (name.as_str(), closing) {
+            ("div" | "p" | "en-note" | "table" | "tr" | "blockquote", _) => block_break(&mut out),
+            ("br", _) => {
+                trim_spaces(&mut out);
+                out.push('\n');
+            }
+            ("hr", _) => {
+                block_break(&mut out);
+                out.push_str("---\n\n");
+            }
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", false) => {
+                block_break(&mut out);
+                let level = name[1..].parse().unwrap_or(1);
+                out.push_str(&"#".repeat(level));
+                out.push(' ');
+            }
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", true) => block_break(&mut out),
+            ("b" | "strong", _) => out.push_str("**"),
+            ("i" | "em", _) => out.push('*'),
+            ("s" | "strike" | "del", _) => out.push_str("~~"),
+            ("code", _) if !pre => out.push('`'),
+            ("pre", false) => {
+                block_break(&mut out);
+                out.push_str("```\n");
+                pre = true;
+            }
+            ("pre", true) => {
+                if !out.ends_with('\n') {
+                    out.push('\n');
+                }
+                out.push_str("```");
+                pre = false;
+                block_break(&mut out);
+            }
+            ("a", false) => {
+                links.push(attribute(tag, "href"));
+                out.push('[');
+            }
+            ("a", true) => match links.pop().flatten() {
+                Some(href) => out.push_str(&format!("]({})", href)),
+                None => out.push(']'),
+            },
+            ("ul", false) => {
+                line_break(&mut out);
+                lists.push(None);
+            }
+            ("ol", false) => {
+                line_break(&mut out);
+                lists.push(Some(0));
+            }
+            ("ul" | "ol", true) => {
+                lists.pop();
+                if lists.is_empty() {
+                    block_break(&mut out);
+                }
+            }
+            ("li", false) => {
+                line_break(&mut out);
+                out.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        out.push_str(&format!("{}. ", n));
+                    }
+                    _ => out.push_str("- "),
+                }
+            }
+            ("li", true) => line_break(&mut out),
+            ("en-todo", false) => {
+                if attribute(tag, "checked").as_deref() == Some("true") {
+                    out.push_str("[x] ");
+                } else {
+                    out.push_str("[ ] ");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut markdown = String::new();
+    for line in out.lines() {
+        let line = line.trim_end();
+        if line.is_empty() && (markdown.is_empty() || markdown.ends_with("\n\n")) {
+            continue;
+        }
+        markdown.push_str(line);
+        markdown.push('\n');
+    }
+    markdown.trim_end().to_string()
+}
+
+fn trim_spaces(out: &mut String) {
+    let len = out.trim_end_matches(' ').len();
+    out.truncate(len);
+}
+
+/// End the current line, if it has anything on it.
+fn line_break(out: &mut String) {
+    trim_spaces(out);
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+/// End the current paragraph, leaving a blank line after it.
+fn block_break(out: &mut String) {
+    line_break(out);
+    if !out.is_empty() && !out.ends_with("\n\n") {
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::temp::TempDir;
+
+    #[test]
+    fn read_simplenote() {
+        let temp = TempDir::new("import-test-simplenote").unwrap();
+        let dir = temp.path();
+        let path = dir.join("notes.json");
+        fs::write(
+            &path,
+            r#"{"activeNotes": [{"id": "1", "content": "\n# Shopping\n- eggs",
+                "creationDate": "2020-01-02T03:04:05.000Z",
+                "lastModified": "2021-01-02T03:04:05.000Z", "tags": ["home", "todo list"]},
+               {"id": "2", "content": ""}],
+               "trashedNotes": [{"id": "3", "content": "gone"}]}"#,
+        )
+        .unwrap();
+
+        let notes = simplenote(&path).unwrap();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].title, "Shopping");
+        assert_eq!(notes[0].body, "\n# Shopping\n- eggs");
+        assert_eq!(notes[0].tags, &["home", "todo list"]);
+        assert_eq!(
+            notes[0].created.unwrap(),
+            Utc.ymd(2020, 1, 2).and_hms(3, 4, 5)
+        );
+        assert_eq!(
+            notes[0].modified.unwrap(),
+            Utc.ymd(2021, 1, 2).and_hms(3, 4, 5)
+        );
+        assert_eq!(notes[1].title, "Untitled");
+        assert_eq!(notes[1].created, None);
+
+        fs::write(&path, r#"{"notes": []}"#).unwrap();
+        assert!(matches!(
+            simplenote(&path),
+            Err(Error::InvalidImport { .. })
+        ));
+    }
+
+    #[test]
+    fn read_enex() {
+        let temp = TempDir::new("import-test-enex").unwrap();
+        let dir = temp.path();
+        let path = dir.join("notes.enex");
+        fs::write(
+            &path,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE en-export SYSTEM "http://xml.evernote.com/pub/evernote-export3.dtd">
+<en-export export-date="20210101T000000Z" application="Evernote">
+<note><title>Fish &amp; Chips</title><content><![CDATA[<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE en-note SYSTEM "http://xml.evernote.com/pub/enml2.dtd">
+<en-note><h2>Shopping</h2><div>Buy <b>fish</b> &amp; <a href="https://example.com/?a=1&amp;b=2">chips</a>.</div>
+<ul><li><en-todo checked="true"/>Potatoes</li><li><en-todo/>Oil</li></ul>
+<div>Line one<br/>Line two</div><en-media type="image/png" hash="abc"/></en-note>]]></content>
+<created>20200102T030405Z</created><updated>20210102T030405Z</updated>
+<tag>food</tag><tag>to do</tag>
+<note-attributes><author>me</author></note-attributes></note>
+<note><title></title><content><![CDATA[<en-note/>]]></content></note>
+</en-export>
+"#,
+        )
+        .unwrap();
+
+        let notes = enex(&path).unwrap();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].title, "Fish & Chips");
+        assert_eq!(
+            notes[0].body,
+            "# Fish & Chips\n\n## Shopping\n\n\
+             Buy **fish** & [chips](https://example.com/?a=1&b=2).\n\n\
+             - [x] Potatoes\n- [ ] Oil\n\n\
+             Line one\nLine two\n"
+        );
+        assert_eq!(notes[0].tags, &["food", "to do"]);
+        assert_eq!(
+            notes[0].created.unwrap(),
+            Utc.ymd(2020, 1, 2).and_hms(3, 4, 5)
+        );
+        assert_eq!(
+            notes[0].modified.unwrap(),
+            Utc.ymd(2021, 1, 2).and_hms(3, 4, 5)
+        );
+        assert_eq!(notes[1].title, "Untitled");
+        assert_eq!(notes[1].body, "# Untitled\n");
+
+        fs::write(&path, "{}").unwrap();
+        assert!(matches!(enex(&path), Err(Error::InvalidImport { .. })));
+    }
+
+    #[test]
+    fn read_nvalt_and_write() {
+        let temp = TempDir::new("import-test-nvalt").unwrap();
+        let dir = temp.path();
+        let source = dir.join("nvalt");
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("Project Ideas.txt"), "Build a boat").unwrap();
+        fs::write(source.join("recipes.md"), "# Soup").unwrap();
+        fs::write(source.join("picture.png"), "").unwrap();
+
+        let notes = nvalt(&source).unwrap();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].title, "Project Ideas");
+        assert_eq!(notes[0].body, "Build a boat");
+        assert!(notes[0].modified.is_some());
+        assert_eq!(notes[1].title, "recipes");
+
+        let store = NoteStore::at(dir.join("notes"));
+        fs::create_dir_all(store.dir().unwrap()).unwrap();
+        let modified = Local.ymd(2020, 5, 6).and_hms(7, 8, 9);
+        let note = Imported {
+            title: String::from("Project Ideas"),
+            body: String::from("Build a boat"),
+            tags: vec![String::from("boats")],
+            created: None,
+            modified: Some(modified),
+        };
+        let notes = [note.clone(), note];
+        let found = names(&store, &notes).unwrap();
+        assert_eq!(found[0].name, Path::new("2020-05-06-project-ideas.md"));
+        assert_eq!(found[0].taken, None);
+        assert_eq!(found[1].name, Path::new("2020-05-06-project-ideas_1.md"));
+        assert_eq!(found[1].taken.as_ref(), Some(&found[0].name));
+        let saves: Vec<_> = found.into_iter().map(|name| Save::New(name.name)).collect();
+        let names = write(&store, &notes, &saves).unwrap();
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[0], Path::new("2020-05-06-project-ideas.md"));
+        assert_eq!(names[1], Path::new("2020-05-06-project-ideas_1.md"));
+
+        let contents = store.read(&names[0]).unwrap();
+        assert_eq!(
+            contents,
+            format!(
+                "---\ntitle: \"Project Ideas\"\ncreated: {}\ntags: [boats]\n---\n\nBuild a boat",
+                modified.format(TIME_FORMAT)
+            )
+        );
+        assert_eq!(
+            store.note(&names[0]).modified().unwrap(),
+            SystemTime::from(modified)
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//! A persistent full-text search index, kept in `.newt/index`.
+//!
+//! The index maps each word that appears in the notes, lowercased, to the notes it appears in,
+//! and records the modification time of each note when it was indexed. It is brought up to date
+//! before each search by re-reading only the notes that have changed since, and is rebuilt from
+//! scratch if it is missing, unreadable, or was written by a different version of Newt.
+
+use crate::config::Config;
+use crate::error::*;
+use crate::notes_dir::{self, DATA_DIR};
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+const HEADER: &str = "# newt search index, version 1";
+
+/// The search index of a notes directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Index {
+    /// Each indexed note's path and modification time, in nanoseconds since the Unix epoch.
+    /// Removed notes leave a `None` in their place, so that the ids of other notes are stable.
+    notes: Vec<Option<(PathBuf, u128)>>,
+
+    /// The ids of the notes each word appears in.
+    words: BTreeMap<String, BTreeSet<usize>>,
+}
+
+fn index_path(config: &Config) -> Result<PathBuf> {
+    Ok(config
+        .notes_dir()?
+        .join(DATA_DIR)
+        .join("index")
+        .join("words"))
+}
+
+/// Split text into its lowercased words: maximal runs of alphanumeric characters.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+fn mtime(config: &Config, path: &Path) -> Result<u128> {
+    Ok(notes_dir::modified(config, path)?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default())
+}
+
+impl Index {
+    /// Load the index from disk, returning an empty index if there is none or it cannot be read.
+    pub fn load(config: &Config) -> Result<Index> {
+        let path = index_path(config)?;
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err)
+                if err.kind() == std::io::ErrorKind::NotFound
+                    || err.kind() == std::io::ErrorKind::InvalidData =>
+            {
+                return Ok(Index::default())
+            }
+            Err(err) => return Err(err).context("reading", path),
+        };
+
+        Ok(Index::parse(&text).unwrap_or_else(|| {
+            debug!("Discarding unreadable search index");
+            Index::default()
+        }))
+    }
+
+    fn parse(text: &str) -> Option<Index> {
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return None;
+        }
+
+        let mut index = Index::default();
+        for line in lines {
+            let mut fields = line.splitn(3, ' ');
+            match fields.next()? {
+                "N" => {
+                    let time = fields.next()?.parse().ok()?;
+                    index
+                        .notes
+                        .push(Some((PathBuf::from(fields.next()?), time)));
+                }
+                "T" => {
+                    let word = String::from(fields.next()?);
+                    let ids = fields
+                        .next()?
+                        .split(' ')
+                        .map(|id| id.parse().ok().filter(|&id| id < index.notes.len()))
+                        .collect::<Option<_>>()?;
+                    index.words.insert(word, ids);
+                }
+                _ => return None,
+            }
+        }
+        Some(index)
+    }
+
+    /// Write the index to disk, compacting away the ids of removed notes.
+    pub fn save(&self, config: &Config) -> Result<()> {
+        let mut ids = BTreeMap::new();
+        let mut text = format!("{}\n", HEADER);
+        for (id, (path, time)) in self.live_notes() {
+            ids.insert(id, ids.len());
+            text.push_str(&format!("N {} {}\n", time, path.display()));
+        }
+        for (word, notes) in &self.words {
+            let notes: Vec<_> = notes.iter().map(|id| ids[id].to_string()).collect();
+            text.push_str(&format!("T {} {}\n", word, notes.join(" ")));
+        }
+
+        let path = index_path(config)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("creating", parent)?;
+        }
+        fs::write(&path, text).context("writing", &path)?;
+        Ok(())
+    }
+
+    fn live_notes(&self) -> impl Iterator<Item = (usize, &(PathBuf, u128))> {
+        self.notes
+            .iter()
+            .enumerate()
+            .filter_map(|(id, note)| note.as_ref().map(|note| (id, note)))
+    }
+
+    fn remove(&mut self, id: usize) {
+        self.notes[id] = None;
+        for notes in self.words.values_mut() {
+            notes.remove(&id);
+        }
+        self.words.retain(|_, notes| !notes.is_empty());
+    }
+
+    /// Bring the index up to date with the notes directory, re-reading only the notes that were
+    /// added or modified since they were last indexed. Returns whether anything changed.
+    pub fn update(&mut self, config: &Config) -> Result<bool> {
+        let notes: HashSet<PathBuf> = notes_dir::list(config)?.into_iter().collect();
+        let mut indexed = HashSet::new();
+        let mut stale = Vec::new();
+        for (id, (path, time)) in self.live_notes() {
+            if notes.contains(path) && mtime(config, path)? == *time {
+                indexed.insert(path.clone());
+            } else {
+                stale.push(id);
+            }
+        }
+
+        let changed = !stale.is_empty() || indexed.len() < notes.len();
+        for id in stale {
+            self.remove(id);
+        }
+
+        for note in notes.difference(&indexed) {
+            let time = mtime(config, note)?;
+            let id = self.notes.len();
+            self.notes.push(Some((note.clone(), time)));
+
+            let contents = notes_dir::read_file_lossy(config, note)?;
+            for word in words(&contents) {
+                self.words.entry(word).or_default().insert(id);
+            }
+        }
+
+        Ok(changed)
+    }
+
+    /// Find the notes that might contain the given text, ignoring case.
+    ///
+    /// Every note that does contain the text is returned, but some of the notes returned may not.
+    pub fn candidates(&self, text: &str) -> HashSet<PathBuf> {
+        let mut ids: Option<BTreeSet<usize>> = None;
+        for word in words(text) {
+            // The words at either end of the text may be only part of a word in the note.
+            let mut matching = BTreeSet::new();
+            for (indexed, notes) in &self.words {
+                if indexed.contains(&word) {
+                    matching.extend(notes);
+                }
+            }
+            ids = Some(match ids {
+                Some(ids) => ids.intersection(&matching).copied().collect(),
+                None => matching,
+            });
+        }
+
+        match ids {
+            Some(ids) => ids
+                .into_iter()
+                .filter_map(|id| self.notes[id].as_ref().map(|(path, _)| path.clone()))
+                .collect(),
+            None => self
+                .live_notes()
+                .map(|(_, (path, _))| path.clone())
+                .collect(),
+        }
+    }
+}
+
+/// Score how relevant a note is to a search, given the number of matches in it, the number of
+/// words it contains, and how long ago it was last modified.
+///
+/// Matches count for less in longer notes, and the score halves over the first 30 days since
+/// the note was modified, decaying more slowly after that.
+pub fn relevance(matches: usize, words: usize, age: Duration) -> f64 {
+    let frequency = matches as f64 / (words.max(1) as f64).sqrt();
+    let days = age.as_secs_f64() / (24.0 * 60.0 * 60.0);
+    frequency / (1.0 + days / 30.0)
+}
+
+/// Load the search index and bring it up to date, saving it if anything changed.
+pub fn updated(config: &Config) -> Result<Index> {
+    let mut index = Index::load(config)?;
+    if index.update(config)? {
+        index.save(config)?;
+    }
+    Ok(index)
+}
+
+/// Rebuild the search index from scratch.
+pub fn rebuild(config: &Config) -> Result<Index> {
+    let mut index = Index::default();
+    index.update(config)?;
+    index.save(config)?;
+    Ok(index)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::temp::TempDir;
+
+    #[test]
+    fn ranking() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert!(relevance(4, 100, day) > relevance(2, 100, day));
+        assert!(relevance(2, 100, day) > relevance(2, 400, day));
+        assert!(relevance(2, 100, day) > relevance(2, 100, day * 60));
+        assert_eq!(relevance(1, 0, Duration::default()), 1.0);
+    }
+
+    #[test]
+    fn incremental_index() {
+        let temp = TempDir::new("index-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let config = Config::default().with_notes_dir(dir.clone());
+        fs::write(dir.join("a.md"), "Buy milk and eggs\n").unwrap();
+        fs::write(dir.join("b.md"), "Call Bob about the milkman\n").unwrap();
+
+        let candidates = |index: &Index, text| {
+            let mut notes: Vec<_> = index
+                .candidates(text)
+                .into_iter()
+                .map(|note| note.display().to_string())
+                .collect();
+            notes.sort();
+            notes
+        };
+
+        let index = updated(&config).unwrap();
+        assert_eq!(candidates(&index, "MILK"), &["a.md", "b.md"]);
+        assert_eq!(candidates(&index, "milk and"), &["a.md"]);
+        assert_eq!(candidates(&index, "bob"), &["b.md"]);
+        assert_eq!(candidates(&index, "!!"), &["a.md", "b.md"]);
+        assert!(candidates(&index, "cheese").is_empty());
+        assert_eq!(Index::load(&config).unwrap(), index);
+
+        fs::remove_file(dir.join("b.md")).unwrap();
+        fs::write(dir.join("c.md"), "cheese\n").unwrap();
+        let mut index = Index::load(&config).unwrap();
+        assert!(index.update(&config).unwrap());
+        assert!(!index.update(&config).unwrap());
+        assert_eq!(candidates(&index, "milk"), &["a.md"]);
+        assert_eq!(candidates(&index, "cheese"), &["c.md"]);
+
+        index.save(&config).unwrap();
+        let loaded = Index::load(&config).unwrap();
+        assert_eq!(candidates(&loaded, "cheese"), &["c.md"]);
+        assert_eq!(candidates(&rebuild(&config).unwrap(), "milk"), &["a.md"]);
+
+        fs::write(index_path(&config).unwrap(), "garbage").unwrap();
+        assert_eq!(Index::load(&config).unwrap(), Index::default());
+    }
+}
@@ -0,0 +1,569 @@
+//! A journal of the changes Newt commands make to notes, so that the latest can be undone.
+//!
+//! The journal is an append-only text file at `.newt/journal`. Each operation is a line holding
+//! the time and the command that made it, followed by one tab-indented line per change. Undoing
+//! an operation appends an `undo` line, which cancels the latest operation not already undone.
+//!
+//! Backslashes, tabs, and line breaks in note names are written as `\\`, `\t`, `\n`, and `\r`,
+//! so that every name fits in its field. On Unix, bytes of a name that are not valid UTF-8 are
+//! written as `\xHH`.
+
+use crate::backup;
+use crate::config::{Config, Storage};
+use crate::error::*;
+use crate::notes_dir::{self, DATA_DIR};
+
+use chrono::{Local, NaiveDateTime, TimeZone};
+
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f";
+
+const UNDO: &str = "undo";
+
+/// A single change made to a note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// A note was created.
+    Created {
+        /// The new note.
+        path: PathBuf,
+    },
+
+    /// A note was given a new name.
+    Renamed {
+        /// The note's old name.
+        from: PathBuf,
+
+        /// The note's new name.
+        to: PathBuf,
+    },
+
+    /// A note was deleted.
+    Deleted {
+        /// The deleted note.
+        path: PathBuf,
+
+        /// The backup holding the note's contents when it was deleted, if one was kept, relative
+        /// to the notes directory.
+        backup: Option<PathBuf>,
+    },
+
+    /// A note's contents were replaced.
+    Replaced {
+        /// The changed note.
+        path: PathBuf,
+
+        /// The backup holding the note's contents before they were replaced, if one was kept,
+        /// relative to the notes directory.
+        backup: Option<PathBuf>,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Created { path } => write!(f, "created {}", path.display()),
+            Change::Renamed { from, to } => {
+                write!(f, "renamed {} to {}", from.display(), to.display())
+            }
+            Change::Deleted { path, .. } => write!(f, "deleted {}", path.display()),
+            Change::Replaced { path, .. } => write!(f, "changed {}", path.display()),
+        }
+    }
+}
+
+/// The changes made by one run of a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operation {
+    /// The local time at which the operation was made.
+    pub time: NaiveDateTime,
+
+    /// The command that made the operation.
+    pub command: String,
+
+    /// The changes made, in the order they were made.
+    pub changes: Vec<Change>,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {}",
+            self.command,
+            self.time.format("%Y-%m-%d %H:%M:%S")
+        )
+    }
+}
+
+fn journal_path(config: &Config) -> Result<PathBuf> {
+    Ok(config.notes_dir()?.join(DATA_DIR).join("journal"))
+}
+
+fn append(config: &Config, text: &str) -> Result<()> {
+    let path = journal_path(config)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("creating", dir)?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .context("writing", path)
+}
+
+fn now() -> String {
+    Local::now().format(TIME_FORMAT).to_string()
+}
+
+/// Back up a note before it is deleted or changed, returning the path relative to the notes
+/// directory of the backup that holds its current contents, if there is one.
+pub fn backup<P: AsRef<Path>>(config: &Config, path: P) -> Result<Option<PathBuf>> {
+    let notes_dir = config.notes_dir()?;
+    let relative = |backup: PathBuf| {
+        backup
+            .strip_prefix(&notes_dir)
+            .map(Path::to_path_buf)
+            .unwrap_or(backup)
+    };
+    if let Some(backup) = backup::backup_note(config, path.as_ref())? {
+        return Ok(Some(relative(backup)));
+    }
+
+    let note = notes_dir.join(path.as_ref());
+    let latest = backup::backups(config, path.as_ref())?.into_iter().next();
+    match (fs::read(&note), latest) {
+        (Ok(contents), Some(latest))
+            if fs::read(&latest.path).context("reading", &latest.path)? == contents =>
+        {
+            Ok(Some(relative(latest.path)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Record the changes made by a command as a new operation.
+///
+/// Nothing is recorded if there are no changes, or if notes are not stored as files, since only
+/// changes to files can be undone.
+pub fn record(config: &Config, command: &str, changes: Vec<Change>) -> Result<()> {
+    if changes.is_empty() || config.storage() != Storage::Files {
+        return Ok(());
+    }
+
+    let mut text = format!("{}\t{}\n", now(), command);
+    for change in &changes {
+        let optional = |path: &Option<PathBuf>| path.as_deref().map_or(String::new(), escape);
+        let fields = match change {
+            Change::Created { path } => vec![String::from("created"), escape(path)],
+            Change::Renamed { from, to } => {
+                vec![String::from("renamed"), escape(from), escape(to)]
+            }
+            Change::Deleted { path, backup } => {
+                vec![String::from("deleted"), escape(path), optional(backup)]
+            }
+            Change::Replaced { path, backup } => {
+                vec![String::from("replaced"), escape(path), optional(backup)]
+            }
+        };
+        text.push('\t');
+        text.push_str(&fields.join("\t"));
+        text.push('\n');
+    }
+
+    append(config, &text)
+}
+
+/// Write a path as a journal field, escaping anything that would end the field or is not text.
+fn escape(path: &Path) -> String {
+    let mut res = String::new();
+
+    #[cfg(unix)]
+    let mut bytes = {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes()
+    };
+    #[cfg(not(unix))]
+    let lossy = path.to_string_lossy();
+    #[cfg(not(unix))]
+    let mut bytes = lossy.as_bytes();
+
+    while !bytes.is_empty() {
+        let (text, invalid) = match std::str::from_utf8(bytes) {
+            Ok(text) => (text, &[][..]),
+            Err(err) => {
+                let (valid, rest) = bytes.split_at(err.valid_up_to());
+                let len = err.error_len().unwrap_or(rest.len());
+                (std::str::from_utf8(valid).unwrap_or_default(), &rest[..len])
+            }
+        };
+        for c in text.chars() {
+            match c {
+                '\\' => res.push_str("\\\\"),
+                '\t' => res.push_str("\\t"),
+                '\n' => res.push_str("\\n"),
+                '\r' => res.push_str("\\r"),
+                c => res.push(c),
+            }
+        }
+        for byte in invalid {
+            res.push_str(&format!("\\x{:02x}", byte));
+        }
+        bytes = &bytes[text.len() + invalid.len()..];
+    }
+    res
+}
+
+/// Read a path from a journal field written by [`escape`].
+fn unescape(field: &str) -> Option<PathBuf> {
+    let mut bytes = Vec::new();
+    let mut rest = field;
+    while let Some(i) = rest.find('\\') {
+        bytes.extend_from_slice(&rest.as_bytes()[..i]);
+        let escaped = rest.get(i + 1..i + 2)?;
+        rest = &rest[i + 2..];
+        match escaped {
+            "\\" => bytes.push(b'\\'),
+            "t" => bytes.push(b'\t'),
+            "n" => bytes.push(b'\n'),
+            "r" => bytes.push(b'\r'),
+            "x" => {
+                bytes.push(u8::from_str_radix(rest.get(..2)?, 16).ok()?);
+                rest = &rest[2..];
+            }
+            _ => return None,
+        }
+    }
+    bytes.extend_from_slice(rest.as_bytes());
+
+    #[cfg(unix)]
+    let path = {
+        use std::os::unix::ffi::OsStringExt;
+        OsString::from_vec(bytes)
+    };
+    #[cfg(not(unix))]
+    let path = OsString::from(String::from_utf8(bytes).ok()?);
+
+    Some(PathBuf::from(path))
+}
+
+fn parse_change(fields: &[&str]) -> Option<Change> {
+    let backup = |field: &&str| match *field {
+        "" => Some(None),
+        field => unescape(field).map(Some),
+    };
+    match fields {
+        ["created", path] => Some(Change::Created {
+            path: unescape(path)?,
+        }),
+        ["renamed", from, to] => Some(Change::Renamed {
+            from: unescape(from)?,
+            to: unescape(to)?,
+        }),
+        ["deleted", path, rest @ ..] => Some(Change::Deleted {
+            path: unescape(path)?,
+            backup: rest.first().map_or(Some(None), backup)?,
+        }),
+        ["replaced", path, rest @ ..] => Some(Change::Replaced {
+            path: unescape(path)?,
+            backup: rest.first().map_or(Some(None), backup)?,
+        }),
+        _ => None,
+    }
+}
+
+/// List the recorded operations that have not been undone, oldest first.
+pub fn operations(config: &Config) -> Result<Vec<Operation>> {
+    let path = journal_path(config)?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).context("reading", path),
+    };
+
+    let mut operations: Vec<Operation> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let fields: Vec<_> = line.split('\t').collect();
+        let parsed = match fields.as_slice() {
+            ["", change @ ..] => parse_change(change)
+                .and_then(|change| operations.last_mut().map(|op| op.changes.push(change))),
+            [time, UNDO] => NaiveDateTime::parse_from_str(time, TIME_FORMAT)
+                .ok()
+                .map(|_| {
+                    operations.pop();
+                }),
+            [time, command] => NaiveDateTime::parse_from_str(time, TIME_FORMAT)
+                .ok()
+                .map(|time| {
+                    operations.push(Operation {
+                        time,
+                        command: String::from(*command),
+                        changes: Vec::new(),
+                    })
+                }),
+            _ => None,
+        };
+
+        if parsed.is_none() {
+            warn!(
+                "Ignoring malformed line {} of {}",
+                number + 1,
+                path.display()
+            );
+        }
+    }
+
+    Ok(operations)
+}
+
+/// Undo the latest operation that has not been undone, returning it.
+///
+/// Created notes are deleted, renamed notes are given back their old names, and deleted or
+/// changed notes are restored from the backups taken beforehand. Every change is checked before
+/// any is undone, so that an operation is either undone entirely or not at all.
+pub fn undo(config: &Config) -> Result<Operation> {
+    let operation = latest(config)?;
+    for change in operation.changes.iter().rev() {
+        match change {
+            Change::Created { path } => notes_dir::rm_file(config, path)?,
+            Change::Renamed { from, to } => {
+                let notes_dir = config.notes_dir()?;
+                notes_dir::create_parent_dirs(config, from)?;
+                let to = notes_dir.join(to);
+                fs::rename(&to, notes_dir.join(from)).context("renaming", to)?;
+            }
+            Change::Deleted { path, backup } | Change::Replaced { path, backup } => {
+                let backup = find_backup(config, path, backup.as_deref()).map_err(|reason| {
+                    Error::CannotUndo {
+                        operation: operation.to_string(),
+                        reason,
+                    }
+                })?;
+                backup::restore_backup(config, path, &backup)?;
+            }
+        }
+    }
+
+    append(config, &format!("{}\t{}\n", now(), UNDO))?;
+    Ok(operation)
+}
+
+/// The latest operation that has not been undone, checked to be undoable.
+pub fn latest(config: &Config) -> Result<Operation> {
+    let operation = operations(config)?.pop().ok_or(Error::NothingToUndo)?;
+    let notes_dir = config.notes_dir()?;
+    let cannot_undo = |reason: String| Error::CannotUndo {
+        operation: operation.to_string(),
+        reason,
+    };
+
+    for change in &operation.changes {
+        match change {
+            Change::Created { path } if !notes_dir.join(path).is_file() => {
+                return Err(cannot_undo(format!("{} no longer exists", path.display())));
+            }
+            Change::Renamed { from, to } => {
+                if !notes_dir.join(to).is_file() {
+                    return Err(cannot_undo(format!("{} no longer exists", to.display())));
+                }
+                if notes_dir.join(from).exists() {
+                    return Err(cannot_undo(format!("{} already exists", from.display())));
+                }
+            }
+            Change::Deleted { path, backup } => {
+                if notes_dir.join(path).exists() {
+                    return Err(cannot_undo(format!("{} already exists", path.display())));
+                }
+                find_backup(config, path, backup.as_deref()).map_err(cannot_undo)?;
+            }
+            Change::Replaced { path, backup } => {
+                find_backup(config, path, backup.as_deref()).map_err(cannot_undo)?;
+            }
+            _ => (),
+        }
+    }
+
+    Ok(operation)
+}
+
+/// The notes created by an operation that have been modified since, which undoing the operation
+/// would delete along with the changes made to them.
+pub fn changed_since(config: &Config, operation: &Operation) -> Result<Vec<PathBuf>> {
+    // Times are recorded to the millisecond, after the changes were made.
+    let time = match Local.from_local_datetime(&operation.time).latest() {
+        Some(time) => SystemTime::from(time) + Duration::from_millis(1),
+        None => return Ok(Vec::new()),
+    };
+
+    let notes_dir = config.notes_dir()?;
+    let mut changed = Vec::new();
+    for change in &operation.changes {
+        if let Change::Created { path } = change {
+            let modified = fs::metadata(notes_dir.join(path)).and_then(|md| md.modified());
+            if matches!(modified, Ok(modified) if modified > time) {
+                changed.push(path.clone());
+            }
+        }
+    }
+    Ok(changed)
+}
+
+fn find_backup(
+    config: &Config,
+    path: &Path,
+    backup: Option<&Path>,
+) -> std::result::Result<backup::Backup, String> {
+    let backup = backup.ok_or_else(|| format!("no backup of {} was kept", path.display()))?;
+    let backup = config
+        .notes_dir()
+        .map_err(|err| err.to_string())?
+        .join(backup);
+    backup::backups(config, path)
+        .map_err(|err| err.to_string())?
+        .into_iter()
+        .find(|found| found.path == backup)
+        .ok_or_else(|| format!("the backup of {} has been removed", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::temp::TempDir;
+
+    #[test]
+    fn undo_operations() {
+        let temp = TempDir::new("journal-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let config = Config::default().with_notes_dir(dir.clone());
+        let read = |name: &str| fs::read_to_string(dir.join(name)).ok();
+
+        assert!(matches!(undo(&config), Err(Error::NothingToUndo)));
+
+        fs::write(dir.join("a.md"), "one").unwrap();
+        record(
+            &config,
+            "new",
+            vec![Change::Created {
+                path: "a.md".into(),
+            }],
+        )
+        .unwrap();
+
+        let saved = backup(&config, "a.md").unwrap();
+        fs::write(dir.join("a.md"), "two").unwrap();
+        let path = PathBuf::from("a.md");
+        record(
+            &config,
+            "replace",
+            vec![Change::Replaced {
+                path,
+                backup: saved,
+            }],
+        )
+        .unwrap();
+
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::rename(dir.join("a.md"), dir.join("sub/b.md")).unwrap();
+        let rename = Change::Renamed {
+            from: "a.md".into(),
+            to: "sub/b.md".into(),
+        };
+        record(&config, "mv", vec![rename]).unwrap();
+
+        let saved = backup(&config, "sub/b.md").unwrap();
+        notes_dir::rm_file(&config, "sub/b.md").unwrap();
+        let path = PathBuf::from("sub/b.md");
+        record(
+            &config,
+            "rm",
+            vec![Change::Deleted {
+                path,
+                backup: saved,
+            }],
+        )
+        .unwrap();
+        record(&config, "rm", Vec::new()).unwrap();
+
+        let commands = |config| {
+            operations(config)
+                .unwrap()
+                .into_iter()
+                .map(|op| op.command)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(commands(&config), &["new", "replace", "mv", "rm"]);
+
+        assert_eq!(undo(&config).unwrap().command, "rm");
+        assert_eq!(read("sub/b.md").as_deref(), Some("two"));
+        assert_eq!(undo(&config).unwrap().command, "mv");
+        assert_eq!(read("a.md").as_deref(), Some("two"));
+        assert_eq!(read("sub/b.md"), None);
+
+        fs::write(dir.join("sub/b.md"), "taken").unwrap();
+        let rename = Change::Renamed {
+            from: "sub/b.md".into(),
+            to: "c.md".into(),
+        };
+        record(&config, "mv", vec![rename]).unwrap();
+        assert!(matches!(undo(&config), Err(Error::CannotUndo { .. })));
+        append(&config, &format!("{}\t{}\n", now(), UNDO)).unwrap();
+
+        assert_eq!(undo(&config).unwrap().command, "replace");
+        assert_eq!(read("a.md").as_deref(), Some("one"));
+        assert_eq!(undo(&config).unwrap().command, "new");
+        assert_eq!(read("a.md"), None);
+        assert!(commands(&config).is_empty());
+    }
+
+    #[test]
+    fn unusual_note_names() {
+        for name in &[
+            "a\tb.md",
+            "line\nbreak\r.md",
+            "back\\slash\\x41.md",
+            "ideas.md",
+        ] {
+            let path = Path::new(name);
+            let field = escape(path);
+            assert!(!field.contains(['\t', '\n', '\r']));
+            assert_eq!(unescape(&field).as_deref(), Some(path));
+        }
+        assert_eq!(unescape("a\\x4"), None);
+        assert_eq!(unescape("a\\q"), None);
+
+        #[cfg(unix)]
+        {
+            use std::ffi::OsStr;
+            use std::os::unix::ffi::OsStrExt;
+            let path = Path::new(OsStr::from_bytes(b"caf\xe9\xff.md"));
+            assert_eq!(escape(path), "caf\\xe9\\xff.md");
+            assert_eq!(unescape(&escape(path)).as_deref(), Some(path));
+        }
+
+        let temp = TempDir::new("journal-names-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let config = Config::default().with_notes_dir(dir.clone());
+        let path = PathBuf::from("a\tb\nc.md");
+        fs::write(dir.join(&path), "one").unwrap();
+        let change = Change::Created { path: path.clone() };
+        record(&config, "new", vec![change.clone()]).unwrap();
+        let operation = latest(&config).unwrap();
+        assert_eq!(operation.changes, &[change]);
+        assert!(changed_since(&config, &operation).unwrap().is_empty());
+
+        let later = SystemTime::now() + Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(dir.join(&path))
+            .and_then(|file| file.set_modified(later))
+            .unwrap();
+        assert_eq!(changed_since(&config, &operation).unwrap(), &[path]);
+    }
+}
@@ -2,22 +2,65 @@
 
 #![warn(missing_docs)]
 
-#[macro_use]
-extern crate lazy_static;
 #[macro_use]
 extern crate thiserror;
 
-macro_rules! dbg {
+macro_rules! warn {
+    ($($args:tt)*) => {
+        crate::logging::log(crate::logging::Level::Warn, format_args!($($args)*))
+    }
+}
+
+macro_rules! info {
+    ($($args:tt)*) => {
+        crate::logging::log(crate::logging::Level::Info, format_args!($($args)*))
+    }
+}
+
+macro_rules! debug {
     ($($args:tt)*) => {
-        crate::debug::dbg(format_args!($($args)*))
+        crate::logging::log(crate::logging::Level::Debug, format_args!($($args)*))
+    }
+}
+
+macro_rules! trace {
+    ($($args:tt)*) => {
+        crate::logging::log(crate::logging::Level::Trace, format_args!($($args)*))
     }
 }
 
-pub(crate) mod debug;
 pub(crate) mod util;
 
+pub mod archive;
+pub mod backend;
+pub mod backup;
+pub mod cache;
+pub mod calendar;
 pub mod cli;
+pub mod clipboard;
 pub mod config;
+pub mod conflicts;
 pub mod edit;
 pub mod error;
+pub mod frontmatter;
+pub mod history;
+pub mod import;
+pub mod index;
+pub mod journal;
+pub mod logging;
 pub mod notes_dir;
+pub mod rpc;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod session;
+pub mod share;
+pub mod site;
+pub mod store;
+pub mod sync;
+pub mod watch;
+pub mod wikilink;
+
+pub use session::Session;
+pub use store::{Note, NoteStore};
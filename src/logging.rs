@@ -0,0 +1,164 @@
+//! Leveled log messages.
+//!
+//! Messages are logged with the `warn!`, `info!`, `debug!`, and `trace!` macros, and go to
+//! the logger of the current thread, which a [`Session`](crate::session::Session) sets while it is
+//! entered. Warnings are printed as `Warning: ...`; other messages are printed with a timestamp
+//! and their level. If a log file is set, every message shown is also appended to it.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// How important a log message is. Each level includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// No messages are logged, not even warnings.
+    Off,
+
+    /// Problems that do not stop a command.
+    Warn,
+
+    /// What Newt decided, such as which configuration file and notes directory it uses.
+    Info,
+
+    /// The steps Newt takes, such as backing up notes.
+    Debug,
+
+    /// Every detail, such as the commands Newt runs and the files it skips.
+    Trace,
+}
+
+impl Level {
+    /// The level shown by the command-line interface with the given number of `-v` flags.
+    pub fn from_verbosity(count: u64) -> Level {
+        match count {
+            0 => Level::Warn,
+            1 => Level::Info,
+            2 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Level::Off => "OFF",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+/// Where the messages of one thread go.
+#[derive(Clone)]
+pub(crate) struct Logger {
+    pub(crate) level: Level,
+    pub(crate) output: Arc<Mutex<Box<dyn Write + Send>>>,
+    pub(crate) file: Option<Arc<Mutex<File>>>,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Logger {
+            level: Level::Warn,
+            output: Arc::new(Mutex::new(Box::new(io::stderr()))),
+            file: None,
+        }
+    }
+}
+
+thread_local! {
+    static LOGGER: RefCell<Logger> = RefCell::new(Logger::default());
+}
+
+/// The logger of the current thread.
+pub(crate) fn current() -> Logger {
+    LOGGER.with(|logger| logger.borrow().clone())
+}
+
+/// Set the logger of the current thread, returning the previous one.
+pub(crate) fn replace(logger: Logger) -> Logger {
+    LOGGER.with(|current| current.replace(logger))
+}
+
+/// Log a message at the given level, if the current thread's logger shows that level.
+///
+/// Failures to write a message are ignored, so that logging never stops a command.
+pub fn log(level: Level, args: std::fmt::Arguments<'_>) {
+    LOGGER.with(|logger| {
+        let logger = logger.borrow();
+        if level == Level::Off || level > logger.level {
+            return;
+        }
+
+        let now = chrono::Local::now();
+        let mut output = logger.output.lock().unwrap_or_else(|err| err.into_inner());
+        let _ = if level == Level::Warn {
+            writeln!(output, "Warning: {}", args)
+        } else {
+            writeln!(
+                output,
+                "{} {:5} {}",
+                now.format("%H:%M:%S%.3f"),
+                level.name(),
+                args
+            )
+        };
+
+        if let Some(file) = &logger.file {
+            let mut file = file.lock().unwrap_or_else(|err| err.into_inner());
+            let _ = writeln!(
+                file,
+                "{} {:5} {}",
+                now.format("%Y-%m-%dT%H:%M:%S%.3f%:z"),
+                level.name(),
+                args
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn levels() {
+        let buffer = Buffer::default();
+        let previous = replace(Logger {
+            level: Level::Info,
+            output: Arc::new(Mutex::new(Box::new(buffer.clone()))),
+            file: None,
+        });
+        warn!("careful");
+        info!("using {}", "x");
+        debug!("hidden");
+        trace!("hidden");
+        replace(previous);
+
+        let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "Warning: careful");
+        assert!(lines[1].ends_with(" INFO  using x"));
+
+        assert_eq!(Level::from_verbosity(0), Level::Warn);
+        assert_eq!(Level::from_verbosity(2), Level::Debug);
+        assert_eq!(Level::from_verbosity(5), Level::Trace);
+    }
+}
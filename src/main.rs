@@ -1,8 +1,7 @@
 use newt::cli;
 
 fn main() {
-    cli::run().unwrap_or_else(|err| {
-        eprintln!("{}", err);
-        std::process::exit(1);
-    });
+    if let Err(err) = cli::run() {
+        std::process::exit(err.exit_code());
+    }
 }
@@ -1,62 +1,540 @@
 //! Utilities for querying and managing the notes directory.
 
-use crate::config::Config;
+use crate::backup;
+use crate::config::{Compat, Config};
 use crate::error::*;
+use crate::frontmatter::{self, Frontmatter};
+use crate::history;
+use crate::util;
 
+use chrono::NaiveDate;
+
+use std::cmp::Ordering;
 use std::fs::{self, File};
-use std::io::{BufRead, BufReader};
-use std::path::{Path, PathBuf};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// The subdirectory of the notes directory into which old notes are archived.
+pub const ARCHIVE_DIR: &str = "archive";
+
+/// The subdirectory of the notes directory in which note attachments are stored.
+pub const ATTACHMENTS_DIR: &str = "attachments";
+
+/// The hidden subdirectory of the notes directory in which Newt keeps its own data.
+pub const DATA_DIR: &str = ".newt";
+
+/// Lock files older than this are considered stale even if their process may still be running.
+const STALE_LOCK_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "svg", "webp", "bmp"];
+
+/// An advisory lock on a note, held while it is being edited.
+///
+/// The lock is released when this value is dropped.
+#[derive(Debug)]
+pub struct NoteLock {
+    path: PathBuf,
+}
+
+impl Drop for NoteLock {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            debug!(
+                "Failed to remove lock file {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Take an advisory lock on a note, to warn other Newt processes that it is being edited.
+///
+/// Fails if another process holds the lock, unless the lock is stale (its process is no longer
+/// running, or it is more than a day old) or `force` is set.
+pub fn lock_note<P: AsRef<Path>>(config: &Config, path: P, force: bool) -> Result<NoteLock> {
+    let mut lock_name = path.as_ref().as_os_str().to_owned();
+    lock_name.push(".lock");
+    let lock_path = config
+        .notes_dir()?
+        .join(DATA_DIR)
+        .join("locks")
+        .join(lock_name);
+    if let Some(parent) = lock_path.parent() {
+        fs::create_dir_all(parent).context("creating", parent)?;
+    }
+
+    // The PID is written to a file of its own and then linked into place, so that another process
+    // never finds the lock file without it.
+    let mut temp_path = lock_path.clone().into_os_string();
+    temp_path.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = PathBuf::from(temp_path);
+    fs::write(&temp_path, format!("{}\n", std::process::id())).context("writing", &temp_path)?;
+    let res = link_lock(&temp_path, &lock_path, path.as_ref(), force);
+    if let Err(err) = fs::remove_file(&temp_path) {
+        debug!("Failed to remove {}: {}", temp_path.display(), err);
+    }
+    res.map(|()| NoteLock { path: lock_path })
+}
+
+/// Link a file holding this process's PID into place as the lock file for a note, removing the
+/// existing lock file first if it is stale or `force` is set.
+fn link_lock(temp_path: &Path, lock_path: &Path, note: &Path, force: bool) -> Result<()> {
+    loop {
+        match fs::hard_link(temp_path, lock_path) {
+            Ok(()) => return Ok(()),
+
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                let pid = fs::read_to_string(lock_path)
+                    .ok()
+                    .and_then(|contents| contents.trim().parse().ok());
+                let stale = pid.map(|pid| !process_running(pid)).unwrap_or(true)
+                    || fs::metadata(lock_path)
+                        .and_then(|md| md.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok())
+                        .map(|age| age > STALE_LOCK_AGE)
+                        .unwrap_or(false);
+
+                if !stale && !force {
+                    return Err(Error::NoteLocked {
+                        path: PathBuf::from(note),
+                        pid: pid.unwrap_or_default(),
+                    });
+                }
+
+                debug!("Removing lock file {}", lock_path.display());
+                fs::remove_file(lock_path).context("removing", lock_path)?;
+            }
+
+            Err(err) => return Err(err).context("creating", lock_path),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn process_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_running(_pid: u32) -> bool {
+    true
+}
+
+/// An iterator over the files in the notes directory, in the order they are found on disk.
+///
+/// Each item is a file name relative to the notes directory, along with the file's metadata.
+/// Directories are read only as the iteration reaches them. See [`walk`].
+#[derive(Debug)]
+pub struct Walk {
+    notes_dir: PathBuf,
+    follow_symlinks: bool,
+    extensions: Option<Vec<String>>,
+
+    /// The directories being read, innermost last, each with its path relative to the notes
+    /// directory and its canonical path.
+    stack: Vec<(PathBuf, PathBuf, fs::ReadDir)>,
+}
+
+/// Walk the files in the notes directory, without sorting them.
+///
+/// Subdirectories are searched recursively, but the directories themselves are not included.
+/// Hidden directories and the archive and attachments directories are skipped. If note
+/// extensions are configured, only files with one of those extensions are included. Only regular
+/// files are listed, so sockets, pipes, and broken symlinks are skipped.
+///
+/// Symlinks to files and directories are followed only if the `follow_symlinks` setting is on. A
+/// symlinked directory that leads back to one of its own ancestors is skipped, so that symlink
+/// loops cannot make the walk run forever.
+pub fn walk(config: &Config) -> Result<Walk> {
+    let notes_dir = config.notes_dir()?;
+    let root = (
+        PathBuf::new(),
+        notes_dir.canonicalize().context("resolving", &notes_dir)?,
+        fs::read_dir(&notes_dir).context("reading", &notes_dir)?,
+    );
+    Ok(Walk {
+        notes_dir,
+        follow_symlinks: config.follow_symlinks(),
+        extensions: config.note_extensions().map(<[String]>::to_vec),
+        stack: vec![root],
+    })
+}
+
+impl Iterator for Walk {
+    type Item = Result<(PathBuf, fs::Metadata)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (rel_dir, _, entries) = self.stack.last_mut()?;
+            let dirent = match entries.next() {
+                Some(Ok(dirent)) => dirent,
+                Some(Err(err)) => {
+                    return Some(Err(err).context("reading", self.notes_dir.join(rel_dir)))
+                }
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            let name = rel_dir.join(dirent.file_name());
+            let at_root = rel_dir.as_os_str().is_empty();
+
+            match dirent.file_type() {
+                Ok(file_type) if file_type.is_symlink() && !self.follow_symlinks => continue,
+                Ok(_) => {}
+                Err(err) => return Some(Err(err).context("reading", dirent.path())),
+            }
+
+            let path = self.notes_dir.join(&name);
+            let md = match fs::metadata(&path) {
+                Ok(md) => md,
+                Err(err) => {
+                    trace!("Skipping {}: {}", name.display(), err);
+                    continue;
+                }
+            };
+
+            if md.is_dir() {
+                let hidden = dirent.file_name().to_string_lossy().starts_with('.');
+                let reserved = at_root
+                    && (dirent.file_name() == ARCHIVE_DIR || dirent.file_name() == ATTACHMENTS_DIR);
+                if hidden || reserved {
+                    continue;
+                }
+
+                let entered = path.canonicalize().and_then(|real| {
+                    if self.stack.iter().any(|(_, ancestor, _)| *ancestor == real) {
+                        Ok(None)
+                    } else {
+                        Ok(Some((real, fs::read_dir(&path)?)))
+                    }
+                });
+                match entered {
+                    Ok(Some((real, entries))) => self.stack.push((name, real, entries)),
+                    Ok(None) => trace!("Skipping {}, which is a symlink loop", name.display()),
+                    Err(err) => return Some(Err(err).context("reading", path)),
+                }
+            } else if !md.is_file() {
+                trace!("Skipping {}, which is not a regular file", name.display());
+            } else if self
+                .extensions
+                .as_ref()
+                .map(|exts| has_extension(&name, exts))
+                .unwrap_or(true)
+            {
+                return Some(Ok((name, md)));
+            }
+        }
+    }
+}
 
 /// Get a sorted list of file names in the notes directory.
 ///
 /// The elements of the returned vector are file names, rather than paths; that is, they are
-/// paths relative to the notes directory.
+/// paths relative to the notes directory. The files included are those found by [`walk`].
+///
+/// Files are sorted by creation time where the filesystem records it, and otherwise by
+/// modification time. If neither is available for every file, they are sorted by name in natural
+/// order, so that `note2.md` comes before `note10.md`.
 pub fn list(config: &Config) -> Result<Vec<PathBuf>> {
-    let notes_dir = config.notes_dir()?;
-    let mut file_names = fs::read_dir(&notes_dir)?
-        .map(|res| {
-            res.map(|dirent| {
-                let name = PathBuf::from(dirent.file_name());
-                let path = notes_dir.join(&name);
-                if let Ok(md) = fs::metadata(path) {
-                    (name, Some(md))
-                } else {
-                    (name, None)
+    let file_names = walk(config)?.collect::<Result<Vec<_>>>()?;
+
+    // Use the same ordering for every file, so that notes missing a timestamp do not make the
+    // order inconsistent.
+    let times = |time: fn(&fs::Metadata) -> std::io::Result<SystemTime>| {
+        file_names
+            .iter()
+            .map(|(_, md)| time(md).ok())
+            .collect::<Option<Vec<_>>>()
+    };
+    let times = if let Some(times) = times(fs::Metadata::created) {
+        debug!("Ordering notes by creation time");
+        times
+    } else if let Some(times) = times(fs::Metadata::modified) {
+        debug!("Creation times unavailable; ordering notes by modification time");
+        times
+    } else {
+        debug!("Creation and modification times unavailable; ordering notes by name");
+        vec![SystemTime::UNIX_EPOCH; file_names.len()]
+    };
+
+    let mut file_names: Vec<_> = times
+        .into_iter()
+        .zip(file_names.into_iter().map(|(name, _)| name))
+        .collect();
+    file_names
+        .sort_by(|(t1, name1), (t2, name2)| t1.cmp(t2).then_with(|| natural_cmp(name1, name2)));
+
+    Ok(file_names.into_iter().map(|(_, name)| name).collect())
+}
+
+/// Compare two paths component by component, comparing runs of digits in their names by
+/// numeric value rather than character by character.
+fn natural_cmp(a: &Path, b: &Path) -> Ordering {
+    let mut a_parts = a.iter();
+    let mut b_parts = b.iter();
+    loop {
+        match (a_parts.next(), b_parts.next()) {
+            (Some(a_part), Some(b_part)) => {
+                let ordering =
+                    natural_cmp_str(&a_part.to_string_lossy(), &b_part.to_string_lossy());
+                if ordering != Ordering::Equal {
+                    return ordering;
                 }
-            })
+            }
+            (a_part, b_part) => return a_part.is_some().cmp(&b_part.is_some()),
+        }
+    }
+}
+
+fn natural_cmp_str(a: &str, b: &str) -> Ordering {
+    type Chars<'a> = std::iter::Peekable<std::str::CharIndices<'a>>;
+
+    /// Consume the run of digits at the front of `chars`, returning it without leading zeros.
+    fn digits<'a>(s: &'a str, chars: &mut Chars<'a>) -> &'a str {
+        let start = chars.peek().map_or(s.len(), |&(i, _)| i);
+        while chars.next_if(|(_, c)| c.is_ascii_digit()).is_some() {}
+        let end = chars.peek().map_or(s.len(), |&(i, _)| i);
+        s[start..end].trim_start_matches('0')
+    }
+
+    let mut a_chars = a.char_indices().peekable();
+    let mut b_chars = b.char_indices().peekable();
+    loop {
+        let ordering = match (a_chars.peek(), b_chars.peek()) {
+            (Some(&(_, c)), Some(&(_, d))) if c.is_ascii_digit() && d.is_ascii_digit() => {
+                let a_digits = digits(a, &mut a_chars);
+                let b_digits = digits(b, &mut b_chars);
+                a_digits
+                    .len()
+                    .cmp(&b_digits.len())
+                    .then_with(|| a_digits.cmp(b_digits))
+            }
+            (Some(&(_, c)), Some(&(_, d))) => {
+                a_chars.next();
+                b_chars.next();
+                c.cmp(&d)
+            }
+            // Names that are equal apart from leading zeros fall back to plain order, so that
+            // the order is still total.
+            (None, None) => return a.cmp(b),
+            (c, d) => c.is_some().cmp(&d.is_some()),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+fn has_extension(name: &Path, exts: &[String]) -> bool {
+    name.extension()
+        .map(|ext| {
+            let ext = ext.to_string_lossy();
+            exts.iter().any(|e| e.eq_ignore_ascii_case(&ext))
         })
-        .collect::<Result<Vec<_>, _>>()?;
+        .unwrap_or(false)
+}
 
-    file_names.sort_by(|(name1, md1), (name2, md2)| {
-        if let Some((md1, md2)) = md1.as_ref().zip(md2.as_ref()) {
-            if let Some((t1, t2)) = md1.created().ok().zip(md2.created().ok()) {
-                return t1.cmp(&t2);
+/// Get the depth of a note path relative to the notes directory.
+///
+/// Notes directly inside the notes directory have depth 0.
+pub fn depth<P: AsRef<Path>>(path: P) -> usize {
+    path.as_ref().components().count().saturating_sub(1)
+}
+
+const LISTING_HEADER: &str = "# newt last listing";
+
+fn listing_path(config: &Config) -> Result<PathBuf> {
+    Ok(config.notes_dir()?.join(DATA_DIR).join("last-list"))
+}
+
+/// Record the order of notes last shown by a command that prints note indices, which indices are
+/// resolved against until the next such command.
+pub fn save_listing(config: &Config, files: &[PathBuf]) -> Result<()> {
+    let mut text = format!("{}\n", LISTING_HEADER);
+    for file in files {
+        text.push_str(&format!("{}\n", file.display()));
+    }
+
+    let path = listing_path(config)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("creating", parent)?;
+    }
+    fs::write(&path, text).context("writing", &path)?;
+    Ok(())
+}
+
+/// Forget the last recorded order of notes, so that indices refer to the current order. Used when
+/// notes are created or removed, which shifts the indices of the notes in the current order.
+pub fn forget_listing(config: &Config) -> Result<()> {
+    let path = listing_path(config)?;
+    match fs::remove_file(&path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(err).context("removing", path)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Replace a renamed note in the last recorded order of notes, so that its index still refers to
+/// it.
+pub fn rename_in_listing<P: AsRef<Path>, Q: AsRef<Path>>(
+    config: &Config,
+    from: P,
+    to: Q,
+) -> Result<()> {
+    if let Some(mut listing) = last_listing(config)? {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        for name in listing.iter_mut().filter(|name| *name == from) {
+            *name = to.to_path_buf();
+        }
+        save_listing(config, &listing)?;
+    }
+    Ok(())
+}
+
+/// The order of notes last shown by a command that prints note indices, if it has been recorded.
+fn last_listing(config: &Config) -> Result<Option<Vec<PathBuf>>> {
+    let path = listing_path(config)?;
+    match fs::read_to_string(&path) {
+        Ok(text) => {
+            let mut lines = text.lines();
+            if lines.next() != Some(LISTING_HEADER) {
+                return Ok(None);
             }
+            Ok(Some(lines.map(PathBuf::from).collect()))
+        }
+        Err(err)
+            if err.kind() == std::io::ErrorKind::NotFound
+                || err.kind() == std::io::ErrorKind::InvalidData =>
+        {
+            Ok(None)
         }
-        name1.cmp(name2)
-    });
+        Err(err) => Err(err).context("reading", path),
+    }
+}
 
-    Ok(file_names.into_iter().map(|(name, _)| name).collect())
+fn state_path(config: &Config) -> Result<PathBuf> {
+    Ok(config.notes_dir()?.join(DATA_DIR).join("state"))
+}
+
+/// The value recorded for a key in the notes directory's state file, if any.
+///
+/// The state file holds small facts Newt remembers between runs, one `key value` pair per line.
+pub fn state(config: &Config, key: &str) -> Result<Option<String>> {
+    let path = state_path(config)?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).context("reading", path),
+    };
+    Ok(text.lines().find_map(|line| {
+        let (k, value) = line.split_once(' ')?;
+        (k == key).then(|| String::from(value))
+    }))
+}
+
+/// Record a value for a key in the notes directory's state file, replacing any earlier value.
+pub fn set_state(config: &Config, key: &str, value: &str) -> Result<()> {
+    let path = state_path(config)?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).context("reading", path),
+    };
+
+    let mut lines: Vec<_> = text
+        .lines()
+        .filter(|line| line.split(' ').next() != Some(key))
+        .map(String::from)
+        .collect();
+    lines.push(format!("{} {}", key, value));
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("creating", parent)?;
+    }
+    fs::write(&path, lines.join("\n") + "\n").context("writing", &path)?;
+    Ok(())
 }
 
 /// Get the relative path to the note at the given index, if it exists.
+///
+/// Indices refer to the order the notes were last listed in, so that a note added or removed
+/// since does not change which note an index refers to. A warning is printed if the index refers
+/// to a different note in the current order. If the note listed at the index has since been
+/// removed, the current order is used instead.
 pub fn file_at_index(config: &Config, index: usize) -> Result<PathBuf> {
-    list(config)?
-        .get(index)
+    let files = list(config)?;
+    let current = files.get(index);
+
+    if let Some(listed) = last_listing(config)?.and_then(|listing| listing.into_iter().nth(index)) {
+        if Some(&listed) == current {
+            return Ok(listed);
+        } else if config.notes_dir()?.join(&listed).is_file() {
+            warn!(
+                "notes have changed since they were last listed; using {}, listed at index {}",
+                listed.display(),
+                index
+            );
+            return Ok(listed);
+        }
+
+        warn!(
+            "{}, listed at index {}, no longer exists; using the current order",
+            listed.display(),
+            index
+        );
+    }
+
+    current
         .map(PathBuf::from)
-        .ok_or(Error::FileIndexOutOfRange { index })
+        .ok_or(Error::FileIndexOutOfRange {
+            index,
+            count: files.len(),
+        })
 }
 
 /// Find a file name that does not yet exist in the configured note directory.
 ///
+/// The name is generated from the configured file name format, which may contain `strftime`-style
+/// date specifiers along with the placeholders `{n}` (a counter), `{uuid}` (a random UUID), and
+/// `{title}` (the given title as a slug, or `untitled`; in an Obsidian vault, the title as written,
+/// or `Untitled`). If the format does not contain `{n}` and
+/// the generated name is already taken, a counter is appended to the file stem. The configured
+/// note extension is added if the generated name does not have one.
+///
 /// The returned `PathBuf` is a file name, rather than a path; it _is not_ prefixed by the path to
 /// the notes directory.
-pub fn new_file_name(config: &Config) -> Result<PathBuf> {
-    let files = list(config)?;
-    let base = chrono::Local::today().format("%Y-%m-%d").to_string();
+pub fn new_file_name(config: &Config, title: Option<&str>) -> Result<PathBuf> {
+    new_file_name_among(config, &list(config)?, title)
+}
+
+/// Find a file name that is not among the given names, as [`new_file_name`] does for the names
+/// in the notes directory.
+pub fn new_file_name_among(
+    config: &Config,
+    files: &[PathBuf],
+    title: Option<&str>,
+) -> Result<PathBuf> {
+    new_file_name_at(config, files, title, &chrono::Local::now())
+}
+
+/// Find a file name that is not among the given names, as [`new_file_name_among`] does, for a
+/// note written at the given time rather than now.
+pub fn new_file_name_at(
+    config: &Config,
+    files: &[PathBuf],
+    title: Option<&str>,
+    time: &chrono::DateTime<chrono::Local>,
+) -> Result<PathBuf> {
+    let format = config.filename_format(title.is_some());
+    let ext = config.note_extension();
+    let title = title_for_file_name(config, title);
     let mut idx = 0;
     Ok(loop {
-        let name = PathBuf::from(format!("{}_{}.md", base, idx));
+        let name = PathBuf::from(render_file_name(format, time, &title, ext, idx)?);
         if !files.contains(&name) {
             break name;
         } else {
@@ -65,6 +543,145 @@ pub fn new_file_name(config: &Config) -> Result<PathBuf> {
     })
 }
 
+/// The text of a note's title to use in its file name.
+///
+/// In an Obsidian vault, this is the title as written, without the characters Obsidian does not
+/// allow in note names. Otherwise it is a lowercase, hyphenated slug.
+fn title_for_file_name(config: &Config, title: Option<&str>) -> String {
+    let (title, untitled) = match config.compat() {
+        Compat::Obsidian => (
+            title.map(|title| {
+                title
+                    .chars()
+                    .filter(|c| !"\\/:*?\"<>|#^[]".contains(*c))
+                    .collect::<String>()
+                    .trim()
+                    .to_string()
+            }),
+            "Untitled",
+        ),
+        Compat::None => (title.map(util::slugify), "untitled"),
+    };
+    title
+        .filter(|title| !title.is_empty())
+        .unwrap_or_else(|| String::from(untitled))
+}
+
+fn render_file_name<Tz>(
+    format: &str,
+    time: &chrono::DateTime<Tz>,
+    title: &str,
+    ext: &str,
+    idx: usize,
+) -> Result<String>
+where
+    Tz: chrono::TimeZone,
+    Tz::Offset: std::fmt::Display,
+{
+    use std::fmt::Write;
+
+    let mut expanded = String::new();
+    write!(expanded, "{}", time.format(format)).map_err(|_| Error::InvalidFilenameFormat {
+        format: String::from(format),
+    })?;
+
+    let mut name = expanded
+        .replace("{title}", title)
+        .replace("{n}", &idx.to_string());
+    while name.contains("{uuid}") {
+        name = name.replacen("{uuid}", &util::uuid(), 1);
+    }
+
+    if name.is_empty() || name.contains('/') {
+        return Err(Error::InvalidFilenameFormat {
+            format: String::from(format),
+        });
+    }
+
+    if idx > 0 && !format.contains("{n}") {
+        let path = PathBuf::from(&name);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        name = match path.extension() {
+            Some(ext) => format!("{}_{}.{}", stem, idx, ext.to_string_lossy()),
+            None => format!("{}_{}", stem, idx),
+        };
+    }
+
+    Ok(add_extension(name, ext))
+}
+
+/// Get the file name of the daily note for the given time.
+///
+/// The name is generated from the configured daily note format, which may contain
+/// `strftime`-style date specifiers. As with [`new_file_name`], the configured note extension is
+/// added if the format does not include one.
+pub fn daily_note_name<Tz>(config: &Config, time: &chrono::DateTime<Tz>) -> Result<PathBuf>
+where
+    Tz: chrono::TimeZone,
+    Tz::Offset: std::fmt::Display,
+{
+    let format = config.daily_format();
+    let name = render_file_name(format, time, "untitled", config.note_extension(), 0)?;
+    Ok(PathBuf::from(name))
+}
+
+/// Generate the initial contents of a new note with the given title.
+///
+/// This includes a frontmatter block if enabled in the configuration, and a Markdown heading
+/// for the title if one is given.
+pub fn initial_contents(config: &Config, title: Option<&str>) -> String {
+    let mut contents = String::new();
+    if config.frontmatter() {
+        let created = chrono::Local::now()
+            .format("%Y-%m-%dT%H:%M:%S%:z")
+            .to_string();
+        contents.push_str(&frontmatter::for_new_note(title, &created).to_string());
+        contents.push('\n');
+    }
+
+    if let Some(title) = title {
+        contents.push_str(&format!("# {}\n\n", title));
+    }
+
+    contents
+}
+
+/// Check that a note name is a relative path that stays inside the notes directory, with no
+/// root, `.` or `..` components, and outside the directories Newt keeps its own data and backups
+/// in. Fails with [`Error::InvalidNoteName`] otherwise.
+pub fn check_note_name(name: &Path) -> Result<()> {
+    let inside = name
+        .components()
+        .all(|part| matches!(part, Component::Normal(_)));
+    let reserved = match name.components().next() {
+        Some(first) => first.as_os_str() == DATA_DIR || first.as_os_str() == backup::BACKUPS_DIR,
+        None => true,
+    };
+    if inside && !reserved {
+        Ok(())
+    } else {
+        Err(Error::InvalidNoteName {
+            name: name.to_path_buf(),
+        })
+    }
+}
+
+/// Append the configured note extension to the given file name, if it doesn't already have one.
+pub fn with_note_extension(config: &Config, mut name: PathBuf) -> PathBuf {
+    if name.extension().is_none() {
+        name.set_extension(config.note_extension());
+    }
+    name
+}
+
+fn add_extension(name: String, ext: &str) -> String {
+    if Path::new(&name).extension().is_some() || ext.is_empty() {
+        name
+    } else {
+        format!("{}.{}", name, ext)
+    }
+}
+
 /// Pipe the contents of the file at the given path into the given writer.
 ///
 /// The path is taken relative to the configured notes directory.
@@ -74,10 +691,85 @@ where
     W: std::io::Write,
 {
     let path = config.notes_dir()?.join(path);
-    std::io::copy(&mut File::open(path)?, writer)?;
+    std::io::copy(&mut File::open(&path).context("opening", &path)?, writer)?;
+    Ok(())
+}
+
+/// Read the contents of the file at the given path relative to the notes directory.
+pub fn read_file<P: AsRef<Path>>(config: &Config, path: P) -> Result<String> {
+    let path = config.notes_dir()?.join(path);
+    fs::read_to_string(&path).context("reading", &path)
+}
+
+/// Read the contents of the file at the given path relative to the notes directory, replacing any
+/// content that is not valid UTF-8 with U+FFFD REPLACEMENT CHARACTER.
+pub fn read_file_lossy<P: AsRef<Path>>(config: &Config, path: P) -> Result<String> {
+    let path = config.notes_dir()?.join(path);
+    let contents = fs::read(&path).context("reading", &path)?;
+    Ok(String::from_utf8_lossy(&contents).into_owned())
+}
+
+/// Write the given contents to the file at the given path relative to the notes directory.
+///
+/// The file is created if it does not exist, and truncated if it does. Creating a note forgets
+/// the last listing; see [`forget_listing`].
+pub fn write_file<P: AsRef<Path>>(config: &Config, path: P, contents: &str) -> Result<()> {
+    history::snapshot(config, path.as_ref())?;
+    let full_path = config.notes_dir()?.join(path.as_ref());
+    let created = !full_path.exists();
+    fs::write(&full_path, contents).context("writing", &full_path)?;
+    history::snapshot(config, path.as_ref())?;
+    if created {
+        forget_listing(config)?;
+    }
     Ok(())
 }
 
+/// Append the given text to the file at the given path relative to the notes directory.
+///
+/// A newline is inserted first if the file is not empty and does not already end with one.
+pub fn append_file<P: AsRef<Path>>(config: &Config, path: P, text: &str) -> Result<()> {
+    history::snapshot(config, path.as_ref())?;
+    let full_path = config.notes_dir()?.join(path.as_ref());
+    let needs_newline = fs::read(&full_path)
+        .map(|contents| !contents.is_empty() && !contents.ends_with(b"\n"))
+        .unwrap_or(false);
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&full_path)
+        .context("opening", &full_path)?;
+    if needs_newline {
+        file.write_all(b"\n").context("writing", &full_path)?;
+    }
+    file.write_all(text.as_bytes())
+        .context("writing", &full_path)?;
+    history::snapshot(config, path.as_ref())?;
+    Ok(())
+}
+
+/// Read the frontmatter block of the file at the given path relative to the notes directory, if
+/// it has one.
+pub fn frontmatter<P: AsRef<Path>>(config: &Config, path: P) -> Result<Option<Frontmatter>> {
+    let path = config.notes_dir()?.join(path);
+    let file = File::open(&path).context("opening", &path)?;
+    frontmatter::read(&mut BufReader::new(file)).context("reading", &path)
+}
+
+/// Replace the frontmatter block of the file at the given path relative to the notes directory.
+///
+/// If the file has no frontmatter, the block is inserted at the start of the file.
+pub fn write_frontmatter<P: AsRef<Path>>(
+    config: &Config,
+    path: P,
+    block: &Frontmatter,
+) -> Result<()> {
+    let text = read_file(config, &path)?;
+    let (_, body) = frontmatter::split(&text);
+    write_file(config, path, &format!("{}{}", block, body))
+}
+
 /// Get the first non-empty line of the file at the given path relative to the notes directory.
 ///
 /// A line is considered non-empty if it contains at least one non-whitespace character.
@@ -90,32 +782,819 @@ pub fn first_line<P: AsRef<Path>>(
     path: P,
     max_len: usize,
 ) -> Result<Option<String>> {
+    Ok(preview(config, path, 1, max_len)?.into_iter().next())
+}
+
+/// Get the first `count` non-empty lines of the file at the given path relative to the notes
+/// directory.
+///
+/// Each returned line will be truncated if it is longer than `max_len` characters. Fewer than
+/// `count` lines are returned if the file does not contain enough non-empty lines.
+pub fn preview<P: AsRef<Path>>(
+    config: &Config,
+    path: P,
+    count: usize,
+    max_len: usize,
+) -> Result<Vec<String>> {
     let path = config.notes_dir()?.join(path);
-    let mut lines = BufReader::new(File::open(path)?).lines();
+    preview_lines(open_preview(config, path)?, count, max_len)
+}
+
+/// Open a note for reading its title or preview, limited to the configured number of bytes.
+fn open_preview(config: &Config, path: PathBuf) -> Result<impl BufRead> {
+    let limit = config.preview_bytes() as u64;
+    let file = File::open(&path).context("opening", &path)?;
+    Ok(BufReader::new(file.take(limit)))
+}
 
-    let first_line = lines
-        .find(|res| match res {
+pub(crate) fn preview_lines<R: BufRead>(
+    mut reader: R,
+    count: usize,
+    max_len: usize,
+) -> Result<Vec<String>> {
+    frontmatter::read(&mut reader)?;
+    let lines = text_lines(reader)
+        .filter(|res| match res {
             Err(_) => true,
             Ok(line) => line.chars().any(|c| !c.is_whitespace()),
         })
-        .transpose()?;
+        .take(count)
+        .collect::<Result<Vec<_>, _>>()?;
+    if lines.iter().any(|line| line.contains('\0')) {
+        return Ok(Vec::new());
+    }
 
-    Ok(first_line.map(|line| {
-        let len = line.chars().count();
-        if len > max_len {
-            format!("{}...", line.chars().take(max_len - 3).collect::<String>())
-        } else {
-            line
+    Ok(lines
+        .into_iter()
+        .map(|line| truncate(line, max_len))
+        .collect())
+}
+
+/// Check whether the file at the given path relative to the notes directory is blank.
+///
+/// A file is blank if it is empty or contains only whitespace.
+pub fn is_blank<P: AsRef<Path>>(config: &Config, path: P) -> Result<bool> {
+    let path = config.notes_dir()?.join(path);
+    let file = File::open(&path).context("opening", &path)?;
+    for line in BufReader::new(file).lines() {
+        if line
+            .context("reading", &path)?
+            .chars()
+            .any(|c| !c.is_whitespace())
+        {
+            return Ok(false);
         }
-    }))
+    }
+    Ok(true)
 }
 
-/// Remove a file from the configured notes directory.
-pub fn rm_file<P: AsRef<Path>>(
-    config: &Config,
-    path: P,
-) -> Result<()> {
+/// Get the display title of the file at the given path relative to the notes directory.
+///
+/// The title is taken from the `title` field of the file's frontmatter if present. Otherwise it
+/// is the text of the first Markdown heading in the file, with the leading `#` markers stripped.
+/// If the file contains no headings, this is the same as [`first_line`].
+pub fn title<P: AsRef<Path>>(config: &Config, path: P, max_len: usize) -> Result<Option<String>> {
     let path = config.notes_dir()?.join(path);
-    fs::remove_file(path)?;
+    title_line(open_preview(config, path)?, max_len)
+}
+
+pub(crate) fn title_line<R: BufRead>(mut reader: R, max_len: usize) -> Result<Option<String>> {
+    if let Some(title) = frontmatter::read(&mut reader)?.and_then(|block| block.get("title")) {
+        return Ok(Some(truncate(title, max_len)));
+    }
+
+    let mut first_line = None;
+    for line in text_lines(reader) {
+        let line = line?;
+        if line.contains('\0') {
+            return Ok(None);
+        } else if let Some(heading) = heading_text(&line) {
+            return Ok(Some(truncate(String::from(heading), max_len)));
+        } else if first_line.is_none() && line.chars().any(|c| !c.is_whitespace()) {
+            first_line = Some(line);
+        }
+    }
+
+    Ok(first_line.map(|line| truncate(line, max_len)))
+}
+
+/// Iterate over the lines of a reader, replacing any content that is not valid UTF-8 with
+/// U+FFFD REPLACEMENT CHARACTER.
+///
+/// This lets listings show what they can of non-text files rather than failing outright.
+fn text_lines<R: BufRead>(reader: R) -> impl Iterator<Item = std::io::Result<String>> {
+    reader.split(b'\n').map(|res| {
+        res.map(|mut line| {
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            String::from_utf8_lossy(&line).into_owned()
+        })
+    })
+}
+
+/// Get the text of a Markdown ATX heading line, if the line is one.
+fn heading_text(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    let level = line.chars().take_while(|&c| c == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let text = rest.trim().trim_end_matches('#').trim_end();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+/// Shorten a line to at most `max_len` characters, ending it with `...` if anything was cut.
+pub(crate) fn truncate(line: String, max_len: usize) -> String {
+    let len = line.chars().count();
+    if len > max_len {
+        format!(
+            "{}...",
+            line.chars()
+                .take(max_len.saturating_sub(3))
+                .collect::<String>()
+        )
+    } else {
+        line
+    }
+}
+
+/// Get the due date of the file at the given path relative to the notes directory, if it has one.
+///
+/// The due date is taken from the `due` field of the file's frontmatter if present, and otherwise
+/// from the first `@due(YYYY-MM-DD)` marker in the file's body.
+pub fn due_date<P: AsRef<Path>>(config: &Config, path: P) -> Result<Option<NaiveDate>> {
+    let path = config.notes_dir()?.join(path);
+    due_date_in(BufReader::new(File::open(&path).context("opening", &path)?))
+}
+
+fn due_date_in<R: BufRead>(mut reader: R) -> Result<Option<NaiveDate>> {
+    if let Some(due) = frontmatter::read(&mut reader)?.and_then(|block| block.get("due")) {
+        if let Some(date) = parse_date(&due) {
+            return Ok(Some(date));
+        }
+    }
+
+    for line in reader.lines() {
+        if let Some(date) = due_marker(&line?) {
+            return Ok(Some(date));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Find the first valid `@due(...)` marker in the given line.
+fn due_marker(line: &str) -> Option<NaiveDate> {
+    line.match_indices("@due(").find_map(|(idx, marker)| {
+        let rest = &line[idx + marker.len()..];
+        let end = rest.find(')')?;
+        parse_date(&rest[..end])
+    })
+}
+
+/// Parse a `YYYY-MM-DD` date, ignoring any trailing time component.
+fn parse_date(text: &str) -> Option<NaiveDate> {
+    let text = text.trim();
+    let date = text.get(..10).unwrap_or(text);
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+/// Get the last modification time of the file at the given path relative to the notes directory.
+pub fn modified<P: AsRef<Path>>(config: &Config, path: P) -> Result<SystemTime> {
+    let path = config.notes_dir()?.join(path);
+    fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .context("reading", &path)
+}
+
+/// Move a file from the configured notes directory into its archive subdirectory.
+///
+/// The archive directory is created if it does not exist. If a file of the same name has already
+/// been archived, a counter is appended to the archived file's name. Returns the new path of the
+/// file, relative to the notes directory.
+pub fn archive_file<P: AsRef<Path>>(config: &Config, path: P) -> Result<PathBuf> {
+    let notes_dir = config.notes_dir()?;
+    let archive_dir = notes_dir.join(ARCHIVE_DIR);
+    fs::create_dir_all(&archive_dir).context("creating", &archive_dir)?;
+
+    let name = path.as_ref().file_name().unwrap_or_default();
+    let target = unique_path(&notes_dir, Path::new(ARCHIVE_DIR), Path::new(name));
+    let from = notes_dir.join(path);
+    fs::rename(&from, notes_dir.join(&target)).context("archiving", from)?;
+    Ok(target)
+}
+
+/// Get a path for the given file name in the given directory that does not exist yet.
+///
+/// If the name is taken, a counter is appended to its stem until it is unique.
+fn unique_path(notes_dir: &Path, dir: &Path, name: &Path) -> PathBuf {
+    let mut target = dir.join(name);
+    let mut idx = 0;
+    while notes_dir.join(&target).exists() {
+        idx += 1;
+        let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+        let unique = match name.extension() {
+            Some(ext) => format!("{}_{}.{}", stem, idx, ext.to_string_lossy()),
+            None => format!("{}_{}", stem, idx),
+        };
+        target = dir.join(unique);
+    }
+    target
+}
+
+/// Get the directory holding the attachments of the note at the given path, relative to the
+/// notes directory.
+pub fn attachments_dir<P: AsRef<Path>>(path: P) -> PathBuf {
+    Path::new(ATTACHMENTS_DIR).join(path.as_ref().with_extension(""))
+}
+
+/// Copy a file into the attachments directory of the note at the given path.
+///
+/// If the note already has an attachment of the same name, a counter is appended to the new
+/// attachment's name. Returns the path of the attachment, relative to the notes directory.
+pub fn attach_file<P: AsRef<Path>>(config: &Config, path: P, source: &Path) -> Result<PathBuf> {
+    let notes_dir = config.notes_dir()?;
+    let dir = attachments_dir(path);
+    let full_dir = notes_dir.join(&dir);
+    fs::create_dir_all(&full_dir).context("creating", &full_dir)?;
+
+    let name = source.file_name().unwrap_or_default();
+    let target = unique_path(&notes_dir, &dir, Path::new(name));
+    fs::copy(source, notes_dir.join(&target)).context("copying", source)?;
+    Ok(target)
+}
+
+/// Get a sorted list of the attachments of the note at the given path, relative to the notes
+/// directory.
+pub fn attachments<P: AsRef<Path>>(config: &Config, path: P) -> Result<Vec<PathBuf>> {
+    let dir = attachments_dir(path);
+    let full_dir = config.notes_dir()?.join(&dir);
+    let mut names = match fs::read_dir(&full_dir) {
+        Ok(entries) => entries
+            .map(|res| res.map(|dirent| dir.join(dirent.file_name())))
+            .collect::<Result<Vec<_>, _>>()
+            .context("reading", &full_dir)?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => return Err(err).context("reading", full_dir),
+    };
+    names.sort();
+    Ok(names)
+}
+
+/// Build a Markdown link from the note at the given path to one of its attachments.
+///
+/// Images are embedded with `![..](..)`; other files get a plain link. Both paths are relative to
+/// the notes directory.
+pub fn attachment_link<P, A>(path: P, attachment: A) -> String
+where
+    P: AsRef<Path>,
+    A: AsRef<Path>,
+{
+    let attachment = attachment.as_ref();
+    let mut target = "../".repeat(depth(path));
+    target.push_str(&attachment.to_string_lossy());
+
+    let name = attachment.file_name().unwrap_or_default().to_string_lossy();
+    let image = attachment
+        .extension()
+        .map(|ext| {
+            let ext = ext.to_string_lossy();
+            IMAGE_EXTENSIONS
+                .iter()
+                .any(|e| e.eq_ignore_ascii_case(&ext))
+        })
+        .unwrap_or(false);
+
+    format!(
+        "{}[{}]({})",
+        if image { "!" } else { "" },
+        name,
+        target.replace(' ', "%20")
+    )
+}
+
+/// Move a note to a new path relative to the notes directory, returning the new path.
+///
+/// If the destination names an existing directory or ends in a path separator, the note is moved
+/// into that directory under its current file name, creating the directory if necessary.
+/// Otherwise the destination is taken as the note's new file name. Existing files are never
+/// overwritten, and the destination must stay inside the notes directory.
+pub fn move_file<P: AsRef<Path>>(config: &Config, path: P, destination: &str) -> Result<PathBuf> {
+    let notes_dir = config.notes_dir()?;
+    let path = path.as_ref();
+
+    let into_dir =
+        destination.ends_with(std::path::is_separator) || notes_dir.join(destination).is_dir();
+    let target = if into_dir {
+        Path::new(destination).join(path.file_name().unwrap_or_default())
+    } else {
+        with_note_extension(config, PathBuf::from(destination))
+    };
+
+    check_note_name(&target)?;
+    if notes_dir.join(&target).exists() {
+        return Err(Error::FileExists { path: target });
+    }
+
+    create_parent_dirs(config, &target)?;
+    let from = notes_dir.join(path);
+    fs::rename(&from, notes_dir.join(&target)).context("moving", from)?;
+    Ok(target)
+}
+
+/// Find groups of notes with the same contents, in listing order.
+///
+/// Blank notes are ignored. If `normalize` is true, notes are also considered the same if they
+/// differ only in case, punctuation, and whitespace.
+pub fn duplicates(config: &Config, normalize: bool) -> Result<Vec<Vec<PathBuf>>> {
+    // Each group keeps the contents of its first note, so that notes whose hashes collide are
+    // not taken for duplicates.
+    let mut groups: Vec<(u64, String, Vec<PathBuf>)> = Vec::new();
+    for note in list(config)? {
+        let contents = match read_file(config, &note) {
+            Ok(contents) => contents,
+            Err(err) if err.io_kind() == Some(std::io::ErrorKind::InvalidData) => continue,
+            Err(err) => return Err(err),
+        };
+        if contents.trim().is_empty() {
+            continue;
+        }
+
+        let contents = if normalize {
+            normalized(&contents)
+        } else {
+            contents
+        };
+        let hash = util::fnv1a(contents.as_bytes());
+        match groups
+            .iter_mut()
+            .find(|(other, first, _)| *other == hash && *first == contents)
+        {
+            Some((_, _, group)) => group.push(note),
+            None => groups.push((hash, contents, vec![note])),
+        }
+    }
+
+    Ok(groups
+        .into_iter()
+        .map(|(_, _, group)| group)
+        .filter(|group| group.len() > 1)
+        .collect())
+}
+
+/// Reduce text to its lowercase words, separated by single spaces.
+fn normalized(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The separator placed between notes when they are merged.
+pub const MERGE_SEPARATOR: &str = "\n---\n\n";
+
+/// Concatenate the contents of several notes into the given note, creating it if necessary.
+///
+/// The notes are separated by [`MERGE_SEPARATOR`]. If the destination note already exists and is
+/// not blank, the merged notes are appended to it, following a separator.
+pub fn merge_files<P, Q>(config: &Config, paths: &[P], into: Q) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let mut parts = Vec::new();
+    let existing = config.notes_dir()?.join(into.as_ref());
+    if existing.is_file() && !is_blank(config, into.as_ref())? {
+        parts.push(read_file(config, into.as_ref())?);
+    }
+    for path in paths {
+        parts.push(read_file(config, path)?);
+    }
+
+    let parts: Vec<_> = parts.iter().map(|part| part.trim_end()).collect();
+    let mut merged = parts.join(MERGE_SEPARATOR);
+    merged.push('\n');
+
+    create_parent_dirs(config, into.as_ref())?;
+    backup::backup_note(config, into.as_ref())?;
+    write_file(config, into, &merged)
+}
+
+/// Create the parent directories of the given path relative to the notes directory, if necessary.
+pub fn create_parent_dirs<P: AsRef<Path>>(config: &Config, path: P) -> Result<()> {
+    if let Some(parent) = config.notes_dir()?.join(path).parent() {
+        fs::create_dir_all(parent).context("creating", parent)?;
+    }
     Ok(())
 }
+
+/// Remove a file from the configured notes directory, backing it up first. Forgets the last
+/// listing; see [`forget_listing`].
+pub fn rm_file<P: AsRef<Path>>(config: &Config, path: P) -> Result<()> {
+    backup::backup_note(config, path.as_ref())?;
+    let path = config.notes_dir()?.join(path);
+    fs::remove_file(&path).context("removing", &path)?;
+    forget_listing(config)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::temp::TempDir;
+
+    use chrono::TimeZone;
+
+    fn time() -> chrono::DateTime<chrono::Utc> {
+        chrono::Utc.ymd(2024, 5, 1).and_hms(9, 30, 0)
+    }
+
+    #[test]
+    fn natural_order() {
+        let mut names = vec![
+            "note10.md",
+            "note2.md",
+            "b/note1.md",
+            "note02.md",
+            "a10/x.md",
+            "a9/x.md",
+            "note.md",
+            "Note1.md",
+        ];
+        names.sort_by(|a, b| natural_cmp(Path::new(a), Path::new(b)));
+        assert_eq!(
+            names,
+            &[
+                "Note1.md",
+                "a9/x.md",
+                "a10/x.md",
+                "b/note1.md",
+                "note.md",
+                "note02.md",
+                "note2.md",
+                "note10.md",
+            ]
+        );
+    }
+
+    #[test]
+    fn list_mixed_directory() {
+        let temp = TempDir::new("list-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::create_dir_all(dir.join(".hidden")).unwrap();
+        fs::write(dir.join("a.md"), "a\n").unwrap();
+        fs::write(dir.join("sub/b.md"), "b\n").unwrap();
+        fs::write(dir.join(".hidden/c.md"), "c\n").unwrap();
+        let config = Config::default().with_notes_dir(dir.clone());
+        let listed = |config: &Config| {
+            let mut names: Vec<_> = list(config)
+                .unwrap()
+                .into_iter()
+                .map(|name| name.display().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::symlink;
+            symlink(dir.join("a.md"), dir.join("link.md")).unwrap();
+            symlink(dir.join("missing.md"), dir.join("broken.md")).unwrap();
+            symlink(dir.join("sub"), dir.join("linked-dir")).unwrap();
+            let _socket = std::os::unix::net::UnixListener::bind(dir.join("socket")).unwrap();
+
+            symlink(&dir, dir.join("sub/loop")).unwrap();
+            assert_eq!(
+                listed(&config),
+                &["a.md", "link.md", "linked-dir/b.md", "sub/b.md"]
+            );
+        }
+
+        let config = config.with_follow_symlinks(false);
+        assert_eq!(listed(&config), &["a.md", "sub/b.md"]);
+    }
+
+    #[test]
+    fn indices_follow_last_listing() {
+        let temp = TempDir::new("listing-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let config = Config::default().with_notes_dir(dir.clone());
+        fs::write(dir.join("a.md"), "a\n").unwrap();
+        fs::write(dir.join("b.md"), "b\n").unwrap();
+        fs::write(dir.join("c.md"), "c\n").unwrap();
+
+        let listing = vec![PathBuf::from("c.md"), PathBuf::from("a.md")];
+        save_listing(&config, &listing).unwrap();
+        assert_eq!(file_at_index(&config, 0).unwrap(), Path::new("c.md"));
+        assert_eq!(file_at_index(&config, 1).unwrap(), Path::new("a.md"));
+
+        // Past the end of the listing, or for removed notes, the current order is used.
+        let current = list(&config).unwrap();
+        assert_eq!(file_at_index(&config, 2).unwrap(), current[2]);
+        fs::remove_file(dir.join("c.md")).unwrap();
+        let current = list(&config).unwrap();
+        assert_eq!(file_at_index(&config, 0).unwrap(), current[0]);
+        assert!(file_at_index(&config, 2).is_err());
+
+        // Renamed notes keep their index; created or removed notes reset the listing.
+        save_listing(&config, &[PathBuf::from("b.md"), PathBuf::from("a.md")]).unwrap();
+        fs::rename(dir.join("b.md"), dir.join("d.md")).unwrap();
+        rename_in_listing(&config, "b.md", "d.md").unwrap();
+        assert_eq!(
+            last_listing(&config).unwrap().unwrap(),
+            &[PathBuf::from("d.md"), PathBuf::from("a.md")]
+        );
+        write_file(&config, "e.md", "e\n").unwrap();
+        assert_eq!(last_listing(&config).unwrap(), None);
+    }
+
+    #[test]
+    fn state_file() {
+        let temp = TempDir::new("state-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let config = Config::default().with_notes_dir(dir.clone());
+        assert_eq!(state(&config, "last_backup").unwrap(), None);
+
+        set_state(&config, "last_backup", "2024-05-01T09:30:00+00:00").unwrap();
+        set_state(&config, "other", "a b").unwrap();
+        set_state(&config, "last_backup", "2024-05-08T09:30:00+00:00").unwrap();
+        assert_eq!(
+            state(&config, "last_backup").unwrap().as_deref(),
+            Some("2024-05-08T09:30:00+00:00")
+        );
+        assert_eq!(state(&config, "other").unwrap().as_deref(), Some("a b"));
+    }
+
+    #[test]
+    fn preview_byte_limit() {
+        let temp = TempDir::new("preview-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let config = Config::default().with_notes_dir(dir.clone());
+        fs::write(dir.join("a.md"), format!("{}title\n", "\n".repeat(1000))).unwrap();
+
+        assert_eq!(
+            title(&config, "a.md", 80).unwrap().as_deref(),
+            Some("title")
+        );
+        let config = config.with_preview_bytes(500);
+        assert_eq!(title(&config, "a.md", 80).unwrap(), None);
+        assert!(preview(&config, "a.md", 1, 80).unwrap().is_empty());
+    }
+
+    #[test]
+    fn merging() {
+        let temp = TempDir::new("merge-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let config = Config::default().with_notes_dir(dir.clone());
+        fs::write(dir.join("a.md"), "one\n").unwrap();
+        fs::write(dir.join("b.md"), "two\n\n").unwrap();
+
+        merge_files(&config, &["a.md", "b.md"], "sub/c.md").unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("sub/c.md")).unwrap(),
+            "one\n---\n\ntwo\n"
+        );
+
+        merge_files(&config, &["a.md"], "b.md").unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("b.md")).unwrap(),
+            "two\n---\n\none\n"
+        );
+    }
+
+    #[test]
+    fn duplicate_notes() {
+        let temp = TempDir::new("dedupe-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let config = Config::default().with_notes_dir(dir.clone());
+        for (name, contents) in &[
+            ("a.md", "Buy milk.\n"),
+            ("b.md", "buy  MILK\n"),
+            ("c.md", "Buy milk.\n"),
+            ("d.md", "\n"),
+            ("e.md", "\n"),
+        ] {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+
+        let mut exact = duplicates(&config, false).unwrap();
+        exact.iter_mut().for_each(|group| group.sort());
+        assert_eq!(exact, &[[Path::new("a.md"), Path::new("c.md")]]);
+
+        let mut near = duplicates(&config, true).unwrap();
+        near.iter_mut().for_each(|group| group.sort());
+        assert_eq!(
+            near,
+            &[[Path::new("a.md"), Path::new("b.md"), Path::new("c.md")]]
+        );
+    }
+
+    #[test]
+    fn note_locks() {
+        let temp = TempDir::new("lock-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let config = Config::default().with_notes_dir(dir.clone());
+
+        let lock = lock_note(&config, "sub/a.md", false).unwrap();
+        let locks = dir.join(DATA_DIR).join("locks");
+        assert_eq!(
+            fs::read_to_string(locks.join("sub/a.md.lock")).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        assert_eq!(fs::read_dir(locks.join("sub")).unwrap().count(), 1);
+        assert!(matches!(
+            lock_note(&config, "sub/a.md", false),
+            Err(Error::NoteLocked { .. })
+        ));
+        assert!(lock_note(&config, "b.md", false).is_ok());
+        drop(lock);
+        let lock = lock_note(&config, "sub/a.md", false).unwrap();
+        let forced = lock_note(&config, "sub/a.md", true).unwrap();
+
+        let lock_path = locks.join("stale.md.lock");
+        fs::write(&lock_path, "not a pid\n").unwrap();
+        assert!(lock_note(&config, "stale.md", false).is_ok());
+
+        drop((lock, forced));
+    }
+
+    #[test]
+    fn file_name_default_format() {
+        let name = render_file_name("%Y-%m-%d_{n}", &time(), "untitled", "md", 3).unwrap();
+        assert_eq!(name, "2024-05-01_3.md");
+    }
+
+    #[test]
+    fn file_name_title() {
+        let title = title_for_file_name(&Config::default(), Some("Meeting with accounting"));
+        let name = render_file_name("%Y%m%d-{title}.md", &time(), &title, "txt", 0).unwrap();
+        assert_eq!(name, "20240501-meeting-with-accounting.md");
+
+        let config = Config::default().with_compat(Compat::Obsidian);
+        let title = title_for_file_name(&config, Some("Q3 plan: #draft?"));
+        let name = render_file_name(config.filename_format(true), &time(), &title, "md", 0);
+        assert_eq!(name.unwrap(), "Q3 plan draft.md");
+        assert_eq!(title_for_file_name(&config, Some("///")), "Untitled");
+    }
+
+    #[test]
+    fn file_name_counter_appended() {
+        let title = title_for_file_name(&Config::default(), None);
+        let name = render_file_name("{title}", &time(), &title, "md", 2).unwrap();
+        assert_eq!(name, "untitled_2.md");
+    }
+
+    #[test]
+    fn file_name_uuid() {
+        let name = render_file_name("{uuid}", &time(), "untitled", "md", 0).unwrap();
+        assert_eq!(name.len(), 39);
+    }
+
+    #[test]
+    fn file_name_extension() {
+        let name = render_file_name("%Y%m%d", &time(), "untitled", "org", 0).unwrap();
+        assert_eq!(name, "20240501.org");
+        let name = render_file_name("%Y%m%d", &time(), "untitled", "", 0).unwrap();
+        assert_eq!(name, "20240501");
+    }
+
+    #[test]
+    fn file_name_invalid() {
+        assert!(render_file_name("%Q{n}", &time(), "untitled", "md", 0).is_err());
+        assert!(render_file_name("a/{n}", &time(), "untitled", "md", 0).is_err());
+    }
+
+    #[test]
+    fn title_of_binary_file() {
+        let data: &[u8] = b"\xff\xd8\xff\xe0 binary\r\nok";
+        assert_eq!(
+            title_line(data, 80).unwrap().as_deref(),
+            Some("\u{fffd}\u{fffd}\u{fffd}\u{fffd} binary")
+        );
+        assert_eq!(
+            preview_lines(data, 3, 80).unwrap(),
+            &["\u{fffd}\u{fffd}\u{fffd}\u{fffd} binary", "ok"]
+        );
+
+        let data: &[u8] = b"\x7fELF\x02\x01\x01\0\0\0\nmore\n";
+        assert_eq!(title_line(data, 80).unwrap(), None);
+        assert!(preview_lines(data, 3, 80).unwrap().is_empty());
+    }
+
+    #[test]
+    fn attachment_links() {
+        let dir = attachments_dir("projects/plan.md");
+        assert_eq!(dir, Path::new("attachments/projects/plan"));
+        assert_eq!(
+            attachment_link("projects/plan.md", dir.join("chart 1.png")),
+            "![chart 1.png](../attachments/projects/plan/chart%201.png)"
+        );
+        assert_eq!(
+            attachment_link("note.md", "attachments/note/paper.pdf"),
+            "[paper.pdf](attachments/note/paper.pdf)"
+        );
+    }
+
+    #[test]
+    fn extension_filter() {
+        let exts = [String::from("md"), String::from("txt")];
+        assert!(has_extension(Path::new("a/note.md"), &exts));
+        assert!(has_extension(Path::new("NOTE.TXT"), &exts));
+        assert!(!has_extension(Path::new("image.png"), &exts));
+        assert!(!has_extension(Path::new("README"), &exts));
+    }
+
+    #[test]
+    fn path_depth() {
+        assert_eq!(depth("note.md"), 0);
+        assert_eq!(depth("projects/note.md"), 1);
+        assert_eq!(depth("projects/work/note.md"), 2);
+    }
+
+    #[test]
+    fn preview_skips_blank_lines() {
+        let text = "\n   \nfirst\n\n\tsecond\nthird\n";
+        let lines = preview_lines(text.as_bytes(), 2, 80).unwrap();
+        assert_eq!(lines, &["first", "\tsecond"]);
+    }
+
+    #[test]
+    fn preview_short_file() {
+        let text = "only\n\n";
+        let lines = preview_lines(text.as_bytes(), 3, 80).unwrap();
+        assert_eq!(lines, &["only"]);
+    }
+
+    #[test]
+    fn title_prefers_heading() {
+        let text = "some preamble\n\n## The Title ##\nbody\n";
+        let title = title_line(text.as_bytes(), 80).unwrap();
+        assert_eq!(title.as_deref(), Some("The Title"));
+    }
+
+    #[test]
+    fn title_falls_back_to_first_line() {
+        let text = "\n#hashtag, not a heading\nbody\n";
+        let title = title_line(text.as_bytes(), 80).unwrap();
+        assert_eq!(title.as_deref(), Some("#hashtag, not a heading"));
+    }
+
+    #[test]
+    fn title_from_frontmatter() {
+        let text = "---\ntitle: \"From frontmatter\"\n---\n# Heading\n";
+        let title = title_line(text.as_bytes(), 80).unwrap();
+        assert_eq!(title.as_deref(), Some("From frontmatter"));
+
+        let text = "---\ntitle: \"\"\ntags: []\n---\n\nbody\n";
+        let title = title_line(text.as_bytes(), 80).unwrap();
+        assert_eq!(title.as_deref(), Some("body"));
+    }
+
+    #[test]
+    fn preview_skips_frontmatter() {
+        let text = "---\ntitle: x\n---\nfirst\n";
+        let lines = preview_lines(text.as_bytes(), 2, 80).unwrap();
+        assert_eq!(lines, &["first"]);
+    }
+
+    #[test]
+    fn title_empty_file() {
+        let title = title_line("\n  \n".as_bytes(), 80).unwrap();
+        assert_eq!(title, None);
+    }
+
+    #[test]
+    fn due_from_frontmatter() {
+        let text = "---\ndue: 2024-06-01\n---\n@due(2024-07-01)\n";
+        let due = due_date_in(text.as_bytes()).unwrap();
+        assert_eq!(due, Some(NaiveDate::from_ymd(2024, 6, 1)));
+    }
+
+    #[test]
+    fn due_from_marker() {
+        let text = "Pay the bills @due(soon) @due(2024-07-01T12:00)\n";
+        let due = due_date_in(text.as_bytes()).unwrap();
+        assert_eq!(due, Some(NaiveDate::from_ymd(2024, 7, 1)));
+        assert_eq!(due_date_in("no dates\n".as_bytes()).unwrap(), None);
+    }
+
+    #[test]
+    fn preview_truncates() {
+        let text = "a rather long line of text\n";
+        let lines = preview_lines(text.as_bytes(), 1, 10).unwrap();
+        assert_eq!(lines, &["a rathe..."]);
+    }
+}
@@ -0,0 +1,341 @@
+//! A line-delimited JSON protocol on standard input and output, for editor plugins.
+//!
+//! Each request is a JSON-RPC 2.0 request object on a line of its own, with a `method`, an
+//! optional `params` object, and an `id`. Each response is written on a line of its own, with the
+//! request's `id` and either a `result` or an `error` with a `code` and `message`. Requests
+//! without an `id` are notifications, and get no response.
+//!
+//! The methods are:
+//!
+//! - `list`, with an optional `query`: the notes, as with the list command, limited to those
+//!   containing the query text if one is given.
+//! - `read`, with a `name` or an `index`: a note, with its contents.
+//! - `write`, with a `name` and `content`: replace a note's contents, creating it if needed.
+//! - `search`, with a `pattern` and optional `ignore_case`: the matching lines, as with the
+//!   search command.
+
+use crate::cli;
+use crate::config::Storage;
+use crate::error::*;
+use crate::index;
+use crate::journal::{self, Change};
+use crate::session::Session;
+use crate::store::{Note, NoteStore};
+use crate::util::{
+    self,
+    json::{self, json, Value},
+};
+
+use chrono::{DateTime, Local};
+
+use std::convert::TryFrom;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// The code for errors from Newt itself, rather than from the protocol.
+const NEWT_ERROR: i64 = -32000;
+
+/// An error response to a request.
+#[derive(Debug, Clone, PartialEq)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new<S: Into<String>>(code: i64, message: S) -> RpcError {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<Error> for RpcError {
+    fn from(err: Error) -> RpcError {
+        let code = match err {
+            Error::InvalidNoteName { .. } => INVALID_PARAMS,
+            _ => NEWT_ERROR,
+        };
+        RpcError::new(code, err.to_string())
+    }
+}
+
+/// Answer requests from the session's input until it ends.
+pub fn run(session: &Session, store: &NoteStore) -> Result<()> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if session
+            .input()
+            .read_line(&mut line)
+            .context("reading", "standard input")?
+            == 0
+        {
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(response) = handle(store, &line) {
+            let mut output = session.output();
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+    }
+}
+
+/// The response to a request, unless it is a notification.
+fn handle(store: &NoteStore, line: &str) -> Option<Value> {
+    let (id, result) = match json::parse(line) {
+        Ok(request) => {
+            let result = match request.get("method").and_then(Value::as_str) {
+                Some(method) => call(store, method, request.get("params").unwrap_or(&Value::Null)),
+                None => Err(RpcError::new(INVALID_REQUEST, "missing \"method\"")),
+            };
+            (request.get("id")?.clone(), result)
+        }
+        Err(err) => (
+            Value::Null,
+            Err(RpcError::new(PARSE_ERROR, err.to_string())),
+        ),
+    };
+
+    let outcome = match result {
+        Ok(result) => ("result", result),
+        Err(err) => ("error", json!({ "code": err.code, "message": err.message })),
+    };
+    let (key, value) = outcome;
+    Some(json!({ "jsonrpc": "2.0", "id": id, key: value }))
+}
+
+fn call(store: &NoteStore, method: &str, params: &Value) -> std::result::Result<Value, RpcError> {
+    match method {
+        "list" => {
+            let query = string_param(params, "query")?;
+            let mut notes = Vec::new();
+            for (index, note) in store.notes()?.iter().enumerate() {
+                if query.map_or(Ok(true), |text| matches(note, text))? {
+                    notes.push(note_json(note, Some(index), false)?);
+                }
+            }
+            Ok(Value::Array(notes))
+        }
+
+        "read" => {
+            let note = match (string_param(params, "name")?, params.get("index")) {
+                (Some(name), _) => find_note(store, name)?,
+                (None, Some(index)) => {
+                    let index = index
+                        .as_u64()
+                        .and_then(|i| usize::try_from(i).ok())
+                        .ok_or_else(|| {
+                            RpcError::new(INVALID_PARAMS, "\"index\" must be a whole number")
+                        })?;
+                    store.note(store.note_at(index)?)
+                }
+                (None, None) => return Err(missing_param("name")),
+            };
+            Ok(note_json(&note, None, true)?)
+        }
+
+        "write" => {
+            let name = string_param(params, "name")?.ok_or_else(|| missing_param("name"))?;
+            let content =
+                string_param(params, "content")?.ok_or_else(|| missing_param("content"))?;
+            let name = store.new_name(Some(name), None)?;
+            let config = store.config();
+            let change = if store.backend().exists(&name)? {
+                let backup = journal::backup(config, &name)?;
+                store.write(&name, content)?;
+                Change::Replaced {
+                    path: name.clone(),
+                    backup,
+                }
+            } else {
+                store.write(&name, content)?;
+                Change::Created { path: name.clone() }
+            };
+            journal::record(config, "rpc: write", vec![change])?;
+            Ok(note_json(&store.note(name), None, false)?)
+        }
+
+        "search" => {
+            let pattern =
+                string_param(params, "pattern")?.ok_or_else(|| missing_param("pattern"))?;
+            let ignore_case = match params.get("ignore_case") {
+                None | Some(Value::Null) => false,
+                Some(Value::Bool(ignore_case)) => *ignore_case,
+                Some(_) => {
+                    return Err(RpcError::new(
+                        INVALID_PARAMS,
+                        "\"ignore_case\" must be true or false",
+                    ))
+                }
+            };
+
+            let config = store.config();
+            if config.storage() != Storage::Files {
+                return Err(Error::RequiresFileStorage.into());
+            }
+            let index = index::updated(config)?;
+            let mut found = Vec::new();
+            for result in cli::find_matches(config, &index, pattern, None, ignore_case, false)? {
+                for m in result.matches {
+                    found.push(json!({
+                        "index": result.index,
+                        "note": result.name.to_string_lossy(),
+                        "line": m.line,
+                        "column": m.column,
+                        "text": m.text,
+                    }));
+                }
+            }
+            Ok(Value::Array(found))
+        }
+
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("no method {:?}", method),
+        )),
+    }
+}
+
+fn missing_param(key: &str) -> RpcError {
+    RpcError::new(INVALID_PARAMS, format!("{:?} is required", key))
+}
+
+/// The value of a string parameter, if it is given.
+fn string_param<'a>(
+    params: &'a Value,
+    key: &str,
+) -> std::result::Result<Option<&'a str>, RpcError> {
+    match params.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value
+            .as_str()
+            .map(Some)
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("{:?} must be a string", key))),
+    }
+}
+
+/// The note with the given name, which must be one of the notes in the store.
+pub(crate) fn find_note(store: &NoteStore, name: &str) -> Result<Note> {
+    let names = store.list()?;
+    if names.iter().any(|note| note == Path::new(name)) {
+        return Ok(store.note(name));
+    }
+
+    let names: Vec<_> = names.iter().filter_map(|note| note.to_str()).collect();
+    Err(Error::NoSuchNote {
+        path: PathBuf::from(name),
+        suggestion: util::closest_match(name, names).map(PathBuf::from),
+    })
+}
+
+/// Whether a note contains the given text, ignoring case, in its name or contents.
+pub(crate) fn matches(note: &Note, text: &str) -> Result<bool> {
+    let text = text.trim().to_lowercase();
+    if note.path().to_string_lossy().to_lowercase().contains(&text) {
+        return Ok(true);
+    }
+    match note.content() {
+        Ok(content) => Ok(content.to_lowercase().contains(&text)),
+        Err(err) if err.io_kind() == Some(std::io::ErrorKind::InvalidData) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// A note as a JSON object, with its index if given, and its contents if `content` is set.
+pub(crate) fn note_json(note: &Note, index: Option<usize>, content: bool) -> Result<Value> {
+    let mut members = vec![(
+        "name",
+        Value::from(note.path().to_string_lossy().into_owned()),
+    )];
+    if let Some(index) = index {
+        members.push(("index", index.into()));
+    }
+    members.push(("title", note.title()?.into()));
+    members.push(("tags", note.tags()?.into()));
+    let modified = DateTime::<Local>::from(note.modified()?);
+    members.push(("modified", modified.to_rfc3339().into()));
+    if content {
+        members.push(("content", note.content()?.into()));
+    }
+    Ok(members.into_iter().collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::temp::TempDir;
+
+    use std::fs;
+
+    #[test]
+    fn requests() {
+        let temp = TempDir::new("rpc-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let store = NoteStore::at(&dir);
+        let ask = |request: &str| handle(&store, request).map(|response| response.to_string());
+
+        assert_eq!(
+            ask(r#"{"jsonrpc": "2.0", "id": 1, "method": "write", "params": {"name": "a", "content": "Hello there"}}"#)
+                .unwrap(),
+            format!(
+                r#"{{"jsonrpc":"2.0","id":1,"result":{}}}"#,
+                note_json(&store.note("a.md"), None, false).unwrap()
+            )
+        );
+        assert_eq!(
+            ask(r#"{"method": "write", "params": {"name": "b", "content": "x"}}"#),
+            None
+        );
+        assert_eq!(fs::read_to_string(dir.join("b.md")).unwrap(), "x");
+
+        let read = ask(r#"{"id": "r", "method": "read", "params": {"index": 0}}"#).unwrap();
+        assert!(read.contains(r#""name":"a.md""#));
+        assert!(read.contains(r#""content":"Hello there""#));
+
+        assert_eq!(
+            ask(r#"{"id": 2, "method": "search", "params": {"pattern": "THERE", "ignore_case": true}}"#)
+                .unwrap(),
+            r#"{"jsonrpc":"2.0","id":2,"result":[{"index":0,"note":"a.md","line":1,"column":7,"text":"Hello there"}]}"#
+        );
+        let list = ask(r#"{"id": 3, "method": "list", "params": {"query": "x"}}"#).unwrap();
+        assert!(list.contains(r#""name":"b.md","index":1"#));
+        assert!(!list.contains("a.md"));
+
+        for (request, code) in &[
+            ("{", PARSE_ERROR),
+            (r#"{"id": 4}"#, INVALID_REQUEST),
+            (r#"{"id": 4, "method": "frobnicate"}"#, METHOD_NOT_FOUND),
+            (r#"{"id": 4, "method": "read"}"#, INVALID_PARAMS),
+            (
+                r#"{"id": 4, "method": "write", "params": {"name": "../x", "content": ""}}"#,
+                INVALID_PARAMS,
+            ),
+            (
+                r#"{"id": 4, "method": "write", "params": {"name": ".newt/x", "content": ""}}"#,
+                INVALID_PARAMS,
+            ),
+            (
+                r#"{"id": 4, "method": "read", "params": {"name": "c.md"}}"#,
+                NEWT_ERROR,
+            ),
+        ] {
+            let response = ask(request).unwrap();
+            assert!(
+                response.contains(&format!(r#""code":{}"#, code)),
+                "{}",
+                response
+            );
+        }
+    }
+}
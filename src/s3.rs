@@ -0,0 +1,217 @@
+//! Keeping backups of the notes directory in Amazon S3 or another S3-compatible object store.
+//!
+//! Backups are stored as archives made by [`archive::create`], named by the time they were
+//! made, under a location given as `s3://bucket/prefix`. They are transferred by the AWS
+//! command-line tool, `aws`, which must be installed, and which finds credentials in the usual
+//! places: environment variables such as `AWS_ACCESS_KEY_ID` and `AWS_PROFILE`, the shared
+//! `~/.aws/credentials` and `~/.aws/config` files, and the role of the machine it runs on. For an
+//! object store other than S3, set `AWS_ENDPOINT_URL` to its address. Archives are kept in a
+//! private temporary directory while they are transferred.
+
+use crate::archive;
+use crate::config::Config;
+use crate::error::*;
+use crate::util::temp::TempDir;
+
+use chrono::Local;
+
+use std::fmt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// A place in an S3 bucket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// The bucket's name.
+    pub bucket: String,
+
+    /// The key of the object, or the prefix of the keys of the objects, in the bucket. Has no
+    /// leading or trailing `/`.
+    pub key: String,
+}
+
+impl Location {
+    /// Parse an `s3://bucket/key` URL.
+    pub fn parse(url: &str) -> Result<Location> {
+        let invalid = || Error::InvalidS3Url {
+            url: String::from(url),
+        };
+        let rest = url.strip_prefix("s3://").ok_or_else(invalid)?;
+        let (bucket, key) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Location {
+            bucket: String::from(bucket),
+            key: String::from(key.trim_matches('/')),
+        })
+    }
+
+    /// The location of the object with the given name under this prefix.
+    pub fn join(&self, name: &str) -> Location {
+        let key = if self.key.is_empty() {
+            String::from(name)
+        } else {
+            format!("{}/{}", self.key, name)
+        };
+        Location {
+            bucket: self.bucket.clone(),
+            key,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "s3://{}/{}", self.bucket, self.key)
+    }
+}
+
+/// A backup stored in S3.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    /// Where the backup is stored.
+    pub location: Location,
+
+    /// When the backup was last uploaded, in local time, as reported by `aws`.
+    pub time: String,
+
+    /// The size of the backup, in bytes.
+    pub size: u64,
+}
+
+/// Archive the notes directory and upload it under the given prefix. Returns where the archive
+/// was stored and the number of files in it.
+pub fn upload(config: &Config, prefix: &Location) -> Result<(Location, usize)> {
+    let name = archive::file_name(&Local::now());
+    let staging = TempDir::new("s3")?;
+    let file = staging.path().join(&name);
+    let location = prefix.join(&name);
+
+    let count = archive::create(config, &file)?;
+    let mut cmd = Command::new("aws");
+    cmd.args(["s3", "cp", "--only-show-errors"])
+        .arg(&file)
+        .arg(location.to_string());
+    aws(cmd)?;
+    Ok((location, count))
+}
+
+/// The backups stored under the given prefix, oldest first.
+pub fn list(prefix: &Location) -> Result<Vec<Backup>> {
+    let mut cmd = Command::new("aws");
+    cmd.args(["s3", "ls"]).arg(format!("{}/", prefix));
+    let output = match aws(cmd) {
+        Ok(output) => output,
+        // `aws s3 ls` fails when nothing is found.
+        Err(Error::ChildFailed { status, .. }) if status.code() == Some(1) => String::new(),
+        Err(err) => return Err(err),
+    };
+
+    let mut backups = parse_listing(prefix, &output);
+    backups.sort_by(|a, b| a.location.key.cmp(&b.location.key));
+    Ok(backups)
+}
+
+/// Download the backup at the given location and unpack it into a directory, as
+/// [`archive::extract`] does. Returns the number of files unpacked.
+pub fn restore(location: &Location, dir: &Path) -> Result<usize> {
+    let staging = TempDir::new("s3")?;
+    let file = staging
+        .path()
+        .join(location.key.rsplit('/').next().unwrap_or_default());
+
+    let mut cmd = Command::new("aws");
+    cmd.args(["s3", "cp", "--only-show-errors"])
+        .arg(location.to_string())
+        .arg(&file);
+    aws(cmd)?;
+    archive::extract(&file, dir)
+}
+
+/// The backups in the output of `aws s3 ls`, which lists each object's date, time, size, and
+/// name, and each subdirectory as `PRE` and its name.
+fn parse_listing(prefix: &Location, output: &str) -> Vec<Backup> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let date = fields.next()?;
+            let time = fields.next()?;
+            let size = fields.next()?.parse().ok()?;
+            let name = fields.next()?;
+            if !archive::is_archive_name(name) {
+                return None;
+            }
+            Some(Backup {
+                location: prefix.join(name),
+                time: format!("{} {}", date, time),
+                size,
+            })
+        })
+        .collect()
+}
+
+/// Run the AWS command-line tool, returning its output.
+fn aws(mut cmd: Command) -> Result<String> {
+    trace!("Running {:?}", cmd);
+    let output = cmd
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|err| cannot_invoke("aws", err))?;
+    if !output.status.success() {
+        return Err(Error::ChildFailed {
+            command: String::from("aws"),
+            status: output.status,
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn locations() {
+        let prefix = Location::parse("s3://notes-bucket/backups/laptop/").unwrap();
+        assert_eq!(prefix.bucket, "notes-bucket");
+        assert_eq!(prefix.key, "backups/laptop");
+        assert_eq!(
+            prefix.join("a.tar.gz").to_string(),
+            "s3://notes-bucket/backups/laptop/a.tar.gz"
+        );
+        assert_eq!(
+            Location::parse("s3://bucket")
+                .unwrap()
+                .join("a")
+                .to_string(),
+            "s3://bucket/a"
+        );
+        assert!(matches!(
+            Location::parse("https://bucket/x"),
+            Err(Error::InvalidS3Url { .. })
+        ));
+        assert!(Location::parse("s3:///x").is_err());
+    }
+
+    #[test]
+    fn listing() {
+        let prefix = Location::parse("s3://bucket/newt").unwrap();
+        let output = "                           PRE old/\n\
+                      2024-05-02 09:30:00       2048 newt-backup-20240502T093000.tar.gz\n\
+                      2024-05-01 09:30:00       1024 newt-backup-20240501T093000.tar.gz\n\
+                      2024-05-01 09:31:00         10 notes.txt\n";
+        let backups = parse_listing(&prefix, output);
+        assert_eq!(backups.len(), 2);
+        assert_eq!(
+            backups[1],
+            Backup {
+                location: prefix.join("newt-backup-20240501T093000.tar.gz"),
+                time: String::from("2024-05-01 09:30:00"),
+                size: 1024,
+            }
+        );
+    }
+}
@@ -0,0 +1,580 @@
+//! A small web server for reading notes in a browser on the same machine.
+//!
+//! The server shows the note list, with a search box, at `/`, and each note rendered from
+//! Markdown at `/note/<name>`. A JSON API for other programs lists notes at `/notes`, reads,
+//! replaces, and deletes them at `/notes/<name>`, and creates them by posting to `/notes`.
+//!
+//! The server listens only on the loopback interface and answers one request at a time. It
+//! refuses requests addressed to any other host name, and changes to notes must be sent as JSON,
+//! so that web pages in the same browser cannot use it.
+
+use crate::error::*;
+use crate::frontmatter;
+use crate::journal::{self, Change};
+use crate::rpc::{find_note, matches, note_json};
+use crate::session::Session;
+use crate::store::NoteStore;
+use crate::util::{
+    json::{self, json, Value},
+    markdown::{self, encode_path, escape, STYLE},
+};
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+
+/// The largest request body accepted, in bytes.
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+/// An HTTP request.
+#[derive(Debug, Clone, Default)]
+struct Request {
+    method: String,
+    target: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// The value of a header, whose name must be given in lowercase.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A response to an HTTP request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Response {
+    status: u16,
+    reason: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn ok(body: String) -> Response {
+        Response {
+            status: 200,
+            reason: "OK",
+            content_type: "text/html; charset=utf-8",
+            body,
+        }
+    }
+
+    fn error(status: u16, reason: &'static str, message: &str) -> Response {
+        Response {
+            status,
+            reason,
+            content_type: "text/html; charset=utf-8",
+            body: page(reason, None, &format!("<p>{}</p>", escape(message))),
+        }
+    }
+
+    fn json(status: u16, reason: &'static str, value: &Value) -> Response {
+        Response {
+            status,
+            reason,
+            content_type: "application/json",
+            body: value.to_string(),
+        }
+    }
+
+    fn json_error(status: u16, reason: &'static str, message: &str) -> Response {
+        Response::json(status, reason, &json!({ "error": message }))
+    }
+}
+
+impl From<Error> for Response {
+    fn from(err: Error) -> Response {
+        let (status, reason) = match err {
+            Error::NoSuchNote { .. } => (404, "Not Found"),
+            Error::FileExists { .. } => (409, "Conflict"),
+            Error::InvalidJson { .. } | Error::InvalidNoteName { .. } => (400, "Bad Request"),
+            _ => (500, "Internal Server Error"),
+        };
+        Response::json_error(status, reason, &err.to_string())
+    }
+}
+
+/// The outcome of an API request: the response for a success or for a failure.
+type Handled = std::result::Result<Response, Response>;
+
+/// Serve the notes in the given store on the given port of the loopback interface, until the
+/// process is stopped.
+pub fn serve(session: &Session, store: &NoteStore, port: u16) -> Result<()> {
+    let address = format!("127.0.0.1:{}", port);
+    let listener = TcpListener::bind(&address).map_err(|source| Error::Listen {
+        address: address.clone(),
+        source,
+    })?;
+    writeln!(
+        session.output(),
+        "Serving notes at http://{}/ (press Ctrl-C to stop)",
+        address
+    )?;
+    session.output().flush()?;
+
+    for stream in listener.incoming() {
+        if let Err(err) = stream.and_then(|stream| answer(store, stream, port)) {
+            warn!("Failed to answer a request: {}", err);
+        }
+    }
+    Ok(())
+}
+
+/// Read a request from a connection and write the response.
+fn answer(store: &NoteStore, stream: TcpStream, port: u16) -> io::Result<()> {
+    let request = read_request(&mut BufReader::new(&stream))?;
+    let host = request.header("host").unwrap_or_default();
+    let response = if host == format!("127.0.0.1:{}", port) || host == format!("localhost:{}", port)
+    {
+        respond(store, &request)
+    } else {
+        Response::error(403, "Forbidden", "Requests must be addressed to localhost.")
+    };
+    debug!("{} {} {}", request.method, request.target, response.status);
+
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason,
+        response.content_type,
+        response.body.len()
+    )?;
+    if request.method != "HEAD" {
+        stream.write_all(response.body.as_bytes())?;
+    }
+    stream.flush()
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let mut request = Request {
+        method: String::from(parts.next().unwrap_or_default()),
+        target: String::from(parts.next().unwrap_or("/")),
+        ..Request::default()
+    };
+
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let name = name.trim().to_ascii_lowercase();
+            request.headers.push((name, String::from(value.trim())));
+        }
+    }
+
+    let length = request
+        .header("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    if length > MAX_BODY {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "request body too large",
+        ));
+    }
+    request.body = vec![0; length];
+    reader.read_exact(&mut request.body)?;
+    Ok(request)
+}
+
+/// Produce the response to a request.
+fn respond(store: &NoteStore, request: &Request) -> Response {
+    let (path, query) = request
+        .target
+        .split_once('?')
+        .unwrap_or((&request.target, ""));
+    let path = decode(path);
+    if path == "/notes" || path.starts_with("/notes/") {
+        let name = path["/notes".len()..].trim_start_matches('/');
+        return api(store, request, name, query).unwrap_or_else(|response| response);
+    }
+
+    if request.method != "GET" && request.method != "HEAD" {
+        return Response::error(405, "Method Not Allowed", "Pages can only be read.");
+    }
+    let result = if path == "/" {
+        index_page(store, search(query).as_deref()).map(Some)
+    } else if let Some(name) = path.strip_prefix("/note/") {
+        note_page(store, Path::new(name))
+    } else {
+        Ok(None)
+    };
+
+    match result {
+        Ok(Some(body)) => Response::ok(body),
+        Ok(None) => Response::error(404, "Not Found", &format!("No note at {}", path)),
+        Err(err) => Response::error(500, "Internal Server Error", &err.to_string()),
+    }
+}
+
+/// The search text given in a query string, if any.
+fn search(query: &str) -> Option<String> {
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("q="))
+        .map(|value| decode(&value.replace('+', " ")))
+        .filter(|text| !text.trim().is_empty())
+}
+
+/// The note list, showing only the notes containing the search text, if there is any.
+fn index_page(store: &NoteStore, search: Option<&str>) -> Result<String> {
+    let mut items = String::new();
+    for (index, note) in store.notes()?.iter().enumerate() {
+        if let Some(text) = search {
+            if !matches(note, text)? {
+                continue;
+            }
+        }
+
+        let name = note.path().to_string_lossy();
+        let title = note.title()?.unwrap_or_else(|| name.to_string());
+        items.push_str(&format!(
+            "<li><span class=\"index\">{}</span> <a href=\"/note/{}\">{}</a> \
+             <span class=\"path\">{}</span></li>\n",
+            index,
+            encode_path(&name),
+            escape(&title),
+            escape(&name)
+        ));
+    }
+
+    let body = if items.is_empty() {
+        String::from("<p>No notes found.</p>")
+    } else {
+        format!("<ul class=\"notes\">\n{}</ul>", items)
+    };
+    Ok(page("Notes", Some(search.unwrap_or_default()), &body))
+}
+
+/// A rendered note, if there is a note with the given name.
+fn note_page(store: &NoteStore, name: &Path) -> Result<Option<String>> {
+    if !store.list()?.iter().any(|note| note == name) {
+        return Ok(None);
+    }
+
+    let note = store.note(name);
+    let title = note
+        .title()?
+        .unwrap_or_else(|| name.to_string_lossy().into_owned());
+    let (_, body) = frontmatter::split(note.content()?);
+    Ok(Some(page(&title, Some(""), &markdown::to_html(body))))
+}
+
+/// A complete HTML page, with a search box if `search` is given.
+fn page(title: &str, search: Option<&str>, body: &str) -> String {
+    let search = search.map_or_else(String::new, |text| {
+        format!(
+            "<form action=\"/\"><a href=\"/\">All notes</a> \
+             <input name=\"q\" type=\"search\" placeholder=\"Search\" value=\"{}\"></form>\n",
+            escape(text)
+        )
+    });
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <style>{}</style>\n</head>\n<body>\n{}{}\n</body>\n</html>\n",
+        escape(title),
+        STYLE,
+        search,
+        body
+    )
+}
+
+/// Answer a request to the JSON API, about the note with the given name or, if the name is
+/// empty, about all notes.
+fn api(store: &NoteStore, request: &Request, name: &str, query: &str) -> Handled {
+    let body = match request.method.as_str() {
+        "POST" | "PUT" => Some(json_body(request)?),
+        _ => None,
+    };
+
+    match (request.method.as_str(), name, body) {
+        ("GET" | "HEAD", "", _) => list_notes(store, search(query).as_deref()),
+        ("POST", "", Some(body)) => create_note(store, &body),
+        ("GET" | "HEAD", _, _) => {
+            let note = find_note(store, name)?;
+            Ok(Response::json(200, "OK", &note_json(&note, None, true)?))
+        }
+        ("PUT", _, Some(body)) => update_note(store, name, &body),
+        ("DELETE", _, _) => delete_note(store, name),
+        _ => Err(Response::json_error(
+            405,
+            "Method Not Allowed",
+            &format!("{} is not supported here", request.method),
+        )),
+    }
+}
+
+/// Parse the body of a request that changes notes. The body must be labelled as JSON, which
+/// web pages cannot send to another site without its permission.
+fn json_body(request: &Request) -> std::result::Result<Value, Response> {
+    let content_type = request.header("content-type").unwrap_or_default();
+    if !content_type.starts_with("application/json") {
+        return Err(Response::json_error(
+            415,
+            "Unsupported Media Type",
+            "The request body must be JSON, sent as application/json",
+        ));
+    }
+    Ok(json::parse(&String::from_utf8_lossy(&request.body))?)
+}
+
+/// The value of a string member of a request body, if it is present.
+fn string_member<'a>(body: &'a Value, key: &str) -> std::result::Result<Option<&'a str>, Response> {
+    match body.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value.as_str().map(Some).ok_or_else(|| {
+            Response::json_error(400, "Bad Request", &format!("{:?} must be a string", key))
+        }),
+    }
+}
+
+fn list_notes(store: &NoteStore, search: Option<&str>) -> Handled {
+    let mut notes = Vec::new();
+    for (index, note) in store.notes()?.iter().enumerate() {
+        if search.map_or(Ok(true), |text| matches(note, text))? {
+            notes.push(note_json(note, Some(index), false)?);
+        }
+    }
+    Ok(Response::json(200, "OK", &Value::Array(notes)))
+}
+
+/// Create a note named and given initial contents as the new command would. Given contents
+/// replace the initial contents, but keep the initial frontmatter if they have none of their own.
+fn create_note(store: &NoteStore, body: &Value) -> Handled {
+    let title = string_member(body, "title")?;
+    let name = store.new_name(string_member(body, "name")?, title)?;
+    let initial = store.create(&name, title)?;
+    if let Some(content) = string_member(body, "content")? {
+        let contents = match (frontmatter::split(&initial), frontmatter::split(content)) {
+            ((Some(block), _), (None, _)) => format!("{}\n{}", block, content),
+            _ => String::from(content),
+        };
+        store.write(&name, &contents)?;
+    }
+
+    let change = Change::Created { path: name.clone() };
+    journal::record(store.config(), "serve: create", vec![change])?;
+    let note = store.note(name);
+    Ok(Response::json(
+        201,
+        "Created",
+        &note_json(&note, None, true)?,
+    ))
+}
+
+fn update_note(store: &NoteStore, name: &str, body: &Value) -> Handled {
+    let note = find_note(store, name)?;
+    let content = string_member(body, "content")?
+        .ok_or_else(|| Response::json_error(400, "Bad Request", "\"content\" is required"))?;
+
+    let backup = journal::backup(store.config(), note.path())?;
+    store.write(note.path(), content)?;
+    let change = Change::Replaced {
+        path: note.path().to_path_buf(),
+        backup,
+    };
+    journal::record(store.config(), "serve: update", vec![change])?;
+    let note = store.note(name);
+    Ok(Response::json(200, "OK", &note_json(&note, None, true)?))
+}
+
+fn delete_note(store: &NoteStore, name: &str) -> Handled {
+    let note = find_note(store, name)?;
+    let backup = journal::backup(store.config(), note.path())?;
+    store.delete(note.path())?;
+    let change = Change::Deleted {
+        path: note.path().to_path_buf(),
+        backup,
+    };
+    journal::record(store.config(), "serve: delete", vec![change])?;
+    Ok(Response {
+        status: 204,
+        reason: "No Content",
+        content_type: "application/json",
+        body: String::new(),
+    })
+}
+
+/// Decode percent-encoded text, replacing any invalid UTF-8.
+fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::temp::TempDir;
+
+    fn request(method: &str, target: &str, body: Option<&str>) -> Request {
+        let mut headers = Vec::new();
+        if body.is_some() {
+            headers.push((
+                String::from("content-type"),
+                String::from("application/json"),
+            ));
+        }
+        Request {
+            method: String::from(method),
+            target: String::from(target),
+            headers,
+            body: body.unwrap_or_default().as_bytes().to_vec(),
+        }
+    }
+
+    fn get(store: &NoteStore, target: &str) -> Response {
+        respond(store, &request("GET", target, None))
+    }
+
+    fn temp_store(name: &str) -> (TempDir, NoteStore) {
+        let dir = TempDir::new(&format!("serve-{}", name)).unwrap();
+        let store = NoteStore::at(dir.path());
+        (dir, store)
+    }
+
+    #[test]
+    fn requests() {
+        let text = "PUT /notes/a.md HTTP/1.1\r\nHost: localhost:8080\r\n\
+                    Content-Type: application/json\r\nContent-Length: 4\r\n\r\n{}\n\nextra";
+        let request = read_request(&mut text.as_bytes()).unwrap();
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.target, "/notes/a.md");
+        assert_eq!(request.header("host"), Some("localhost:8080"));
+        assert_eq!(request.body, b"{}\n\n");
+    }
+
+    #[test]
+    fn pages() {
+        let (_dir, store) = temp_store("pages");
+        store.write("a b.md", "# Alpha\n\nSome *text*.\n").unwrap();
+        store
+            .write("sub/beta.md", "---\ntitle: Beta\n---\nbeta & more\n")
+            .unwrap();
+
+        let index = get(&store, "/");
+        assert_eq!(index.status, 200);
+        assert!(index.body.contains("<a href=\"/note/a%20b.md\">Alpha</a>"));
+        assert!(index
+            .body
+            .contains("<a href=\"/note/sub/beta.md\">Beta</a>"));
+
+        let search = get(&store, "/?q=BETA+%26");
+        assert!(search.body.contains("value=\"BETA &amp;\""));
+        assert!(search.body.contains("sub/beta.md"));
+        assert!(!search.body.contains("a%20b.md"));
+        assert!(get(&store, "/?q=nothing").body.contains("No notes found."));
+
+        let note = get(&store, "/note/a%20b.md");
+        assert_eq!(note.status, 200);
+        assert!(note.body.contains("<title>Alpha</title>"));
+        assert!(note.body.contains("<p>Some <em>text</em>.</p>"));
+
+        let note = get(&store, "/note/sub/beta.md");
+        assert!(note.body.contains("<p>beta &amp; more</p>"));
+        assert!(!note.body.contains("title:"));
+
+        assert_eq!(get(&store, "/note/../secret.md").status, 404);
+        assert_eq!(get(&store, "/elsewhere").status, 404);
+        assert_eq!(
+            respond(&store, &request("POST", "/", Some("{}"))).status,
+            405
+        );
+    }
+
+    #[test]
+    fn json_api() {
+        let (dir, store) = temp_store("api");
+        let call = |method, target, body| {
+            let response = respond(&store, &request(method, target, body));
+            let value = json::parse(&response.body).unwrap_or(Value::Null);
+            (response.status, value)
+        };
+        let field = |value: &Value, key| value.get(key).and_then(Value::as_str).map(String::from);
+
+        let (status, created) = call(
+            "POST",
+            "/notes",
+            Some(r#"{"name": "idea", "content": "first"}"#),
+        );
+        assert_eq!(status, 201);
+        assert_eq!(field(&created, "name").as_deref(), Some("idea.md"));
+        assert_eq!(field(&created, "content").as_deref(), Some("first"));
+        assert_eq!(call("POST", "/notes", Some(r#"{"name": "idea"}"#)).0, 409);
+        assert_eq!(call("POST", "/notes", Some(r#"{"name": "../x"}"#)).0, 400);
+        assert_eq!(call("POST", "/notes", Some(r#"{"name": "/tmp/x"}"#)).0, 400);
+        assert_eq!(
+            call("POST", "/notes", Some(r#"{"name": ".newt/x"}"#)).0,
+            400
+        );
+        assert_eq!(
+            call("POST", "/notes", Some(r#"{"name": ".backups/x"}"#)).0,
+            400
+        );
+        assert!(!dir.path().parent().unwrap().join("x.md").exists());
+
+        let (status, list) = call("GET", "/notes?q=FIRST", None);
+        assert_eq!(status, 200);
+        assert_eq!(
+            list.to_string(),
+            Value::Array(vec![
+                note_json(&store.note("idea.md"), Some(0), false).unwrap()
+            ])
+            .to_string()
+        );
+
+        let (status, updated) = call("PUT", "/notes/idea.md", Some(r#"{"content": "second"}"#));
+        assert_eq!(status, 200);
+        assert_eq!(field(&updated, "content").as_deref(), Some("second"));
+        assert_eq!(
+            call("PUT", "/notes/idea.md", Some(r#"{"content": 1}"#)).0,
+            400
+        );
+        assert_eq!(call("PUT", "/notes/idea.md", Some("{")).0, 400);
+        let (status, missing) = call("GET", "/notes/idee.md", None);
+        assert_eq!(status, 404);
+        assert!(field(&missing, "error")
+            .unwrap()
+            .contains("did you mean \"idea.md\""));
+
+        let unlabelled = Request {
+            headers: Vec::new(),
+            ..request("PUT", "/notes/idea.md", Some(r#"{"content": "third"}"#))
+        };
+        assert_eq!(respond(&store, &unlabelled).status, 415);
+
+        assert_eq!(call("DELETE", "/notes/idea.md", None).0, 204);
+        assert_eq!(call("GET", "/notes", None).1, Value::Array(Vec::new()));
+        let undone = journal::undo(store.config()).unwrap();
+        assert_eq!(undone.command, "serve: delete");
+        assert_eq!(store.read("idea.md").unwrap(), "second");
+    }
+}
@@ -0,0 +1,495 @@
+//! The state of one run of Newt: how prompts are answered, which messages are logged, and where
+//! input and output go.
+//!
+//! The CLI builds a [`Session`] from its command-line flags and passes it to every command.
+//! Library users can build their own, for instance to answer prompts automatically or to capture
+//! a command's output, and several sessions can be in use in one process at once.
+
+use crate::config::NonInteractive;
+use crate::error::*;
+use crate::logging::{self, Level, Logger};
+use crate::util::term;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A writer shared by a session's users.
+pub type Output = Box<dyn Write + Send>;
+
+/// A reader shared by a session's users.
+pub type Input = Box<dyn BufRead + Send>;
+
+/// Settings and input and output handles for one run of Newt.
+pub struct Session {
+    yes: bool,
+    dry_run: bool,
+    log_level: Level,
+    log_file: Option<Arc<Mutex<File>>>,
+    output: Mutex<Output>,
+    errors: Arc<Mutex<Output>>,
+    input: Mutex<Input>,
+    interactive: bool,
+    non_interactive: NonInteractive,
+    output_is_stdout: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            yes: false,
+            dry_run: false,
+            log_level: Level::Warn,
+            log_file: None,
+            output: Mutex::new(Box::new(io::stdout())),
+            errors: Arc::new(Mutex::new(Box::new(io::stderr()))),
+            input: Mutex::new(Box::new(io::BufReader::new(io::stdin()))),
+            interactive: term::stdin_is_tty(),
+            non_interactive: NonInteractive::Default,
+            output_is_stdout: true,
+        }
+    }
+}
+
+impl std::fmt::Debug for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Session")
+            .field("yes", &self.yes)
+            .field("dry_run", &self.dry_run)
+            .field("log_level", &self.log_level)
+            .field("log_file", &self.log_file.is_some())
+            .field("interactive", &self.interactive)
+            .field("non_interactive", &self.non_interactive)
+            .field("output_is_stdout", &self.output_is_stdout)
+            .finish()
+    }
+}
+
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl Session {
+    /// A session on the standard input and output streams, with prompts asked and only warnings
+    /// logged.
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// Set whether every prompt is answered 'yes' without asking.
+    pub fn with_yes(self, yes: bool) -> Self {
+        Session { yes, ..self }
+    }
+
+    /// Set whether commands only show the changes they would make, without making them.
+    pub fn with_dry_run(self, dry_run: bool) -> Self {
+        Session { dry_run, ..self }
+    }
+
+    /// Set the most detailed level of messages logged.
+    pub fn with_log_level(self, log_level: Level) -> Self {
+        Session { log_level, ..self }
+    }
+
+    /// Append logged messages, with timestamps, to the given file as well, creating it and its
+    /// directory if they do not exist.
+    pub fn with_log_file<P: AsRef<Path>>(self, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context("creating", dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context("opening", path)?;
+        Ok(Session {
+            log_file: Some(Arc::new(Mutex::new(file))),
+            ..self
+        })
+    }
+
+    /// Send normal output to the given writer rather than standard output. Output sent elsewhere
+    /// is never shown in a pager.
+    pub fn with_output<W: Write + Send + 'static>(self, output: W) -> Self {
+        Session {
+            output: Mutex::new(Box::new(output)),
+            output_is_stdout: false,
+            ..self
+        }
+    }
+
+    /// Send warnings and other log messages to the given writer rather than standard error.
+    pub fn with_errors<W: Write + Send + 'static>(self, errors: W) -> Self {
+        Session {
+            errors: Arc::new(Mutex::new(Box::new(errors))),
+            ..self
+        }
+    }
+
+    /// Read answers to prompts from the given reader rather than standard input. Prompts are
+    /// asked on it as if it were a terminal.
+    pub fn with_input<R: BufRead + Send + 'static>(self, input: R) -> Self {
+        Session {
+            input: Mutex::new(Box::new(input)),
+            interactive: true,
+            ..self
+        }
+    }
+
+    /// Set whether prompts can be asked. By default they can be if standard input is a terminal.
+    pub fn with_interactive(self, interactive: bool) -> Self {
+        Session {
+            interactive,
+            ..self
+        }
+    }
+
+    /// Set how prompts are answered when they cannot be asked.
+    pub fn with_non_interactive(self, non_interactive: NonInteractive) -> Self {
+        Session {
+            non_interactive,
+            ..self
+        }
+    }
+
+    /// Whether every prompt is answered 'yes' without asking.
+    pub fn yes(&self) -> bool {
+        self.yes
+    }
+
+    /// Whether commands only show the changes they would make, without making them.
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// The most detailed level of messages logged.
+    pub fn log_level(&self) -> Level {
+        self.log_level
+    }
+
+    /// Whether normal output goes to standard output, where long output may be shown in a pager.
+    pub fn output_is_stdout(&self) -> bool {
+        self.output_is_stdout
+    }
+
+    /// The reader for input. It is locked until the returned guard is dropped.
+    pub fn input(&self) -> MutexGuard<'_, Input> {
+        lock(&self.input)
+    }
+
+    /// The writer for normal output. It is locked until the returned guard is dropped.
+    pub fn output(&self) -> MutexGuard<'_, Output> {
+        lock(&self.output)
+    }
+
+    /// The writer for warnings and other log messages. It is locked until the returned guard is
+    /// dropped.
+    pub fn errors(&self) -> MutexGuard<'_, Output> {
+        lock(&self.errors)
+    }
+
+    /// Log the messages of the current thread as this session does, until the returned guard is
+    /// dropped.
+    pub fn enter(&self) -> Entered {
+        Entered {
+            previous: logging::replace(Logger {
+                level: self.log_level,
+                output: Arc::clone(&self.errors),
+                file: self.log_file.clone(),
+            }),
+        }
+    }
+
+    /// Present an interactive yes/no prompt, asking again until the answer is understood.
+    ///
+    /// An empty answer gives the default, if there is one. If the session answers 'yes' to all
+    /// prompts, nothing is asked. If the session is not interactive, the prompt is answered as its
+    /// `non_interactive` setting says, failing with [`Error::NonInteractive`] if it cannot be.
+    /// Otherwise the given response, if any, is printed after the answer.
+    pub fn prompt(
+        &self,
+        prompt: &str,
+        default: Option<bool>,
+        yes_response: Option<&str>,
+        no_response: Option<&str>,
+    ) -> Result<bool> {
+        if self.yes {
+            return Ok(true);
+        }
+
+        let yn = match default {
+            None => "[y/n]",
+            Some(true) => "[Y/n]",
+            Some(false) => "[y/N]",
+        };
+
+        if !self.interactive {
+            let res = match (self.non_interactive, default) {
+                (NonInteractive::Yes, _) => true,
+                (NonInteractive::Default, Some(def)) => def,
+                _ => {
+                    return Err(Error::NonInteractive {
+                        prompt: String::from(prompt),
+                    })
+                }
+            };
+            writeln!(
+                self.output(),
+                "{} {} {}",
+                prompt,
+                yn,
+                if res { "y" } else { "n" }
+            )?;
+            return self.respond(res, yes_response, no_response);
+        }
+
+        let mut input = String::new();
+        let res = loop {
+            write!(self.output(), "{} {} ", prompt, yn)?;
+            self.output().flush()?;
+            if lock(&self.input).read_line(&mut input)? == 0 {
+                // Treat the end of the input as an empty answer, or a refusal if there is no
+                // default, rather than asking forever.
+                break default.unwrap_or(false);
+            }
+
+            if input.trim().is_empty() {
+                if let Some(def) = default {
+                    break def;
+                }
+            } else {
+                match input.to_lowercase().trim() {
+                    "y" | "yes" => break true,
+                    "n" | "no" => break false,
+                    _ => {}
+                }
+            }
+
+            input.clear();
+        };
+
+        self.respond(res, yes_response, no_response)
+    }
+
+    /// Present a numbered menu of items and ask for one of them, asking again until the answer
+    /// is understood. Returns the index of the chosen item.
+    ///
+    /// An empty answer gives the default, if there is one. If the session answers 'yes' to all
+    /// prompts, or is not interactive and its `non_interactive` setting allows it, the default is
+    /// chosen without asking. Otherwise a session that is not interactive, or whose input ends,
+    /// fails with [`Error::NonInteractive`]. Fails with [`Error::NothingToChoose`] if there are
+    /// no items.
+    pub fn select<T: std::fmt::Display>(
+        &self,
+        prompt: &str,
+        items: &[T],
+        default: Option<usize>,
+    ) -> Result<usize> {
+        if items.is_empty() {
+            return Err(Error::NothingToChoose {
+                prompt: String::from(prompt),
+            });
+        }
+        let default = default.filter(|&i| i < items.len());
+        let non_interactive = || Error::NonInteractive {
+            prompt: String::from(prompt),
+        };
+        let use_default = match default {
+            Some(_) => {
+                self.yes || (!self.interactive && self.non_interactive != NonInteractive::Fail)
+            }
+            None => false,
+        };
+        if !self.interactive && !use_default {
+            return Err(non_interactive());
+        }
+
+        writeln!(self.output(), "{}", prompt)?;
+        for (i, item) in items.iter().enumerate() {
+            writeln!(self.output(), "{:>4}) {}", i + 1, item)?;
+        }
+        let choose = match default {
+            Some(i) => format!("Choose 1-{} [{}]:", items.len(), i + 1),
+            None => format!("Choose 1-{}:", items.len()),
+        };
+
+        if let (true, Some(i)) = (use_default, default) {
+            writeln!(self.output(), "{} {}", choose, i + 1)?;
+            return Ok(i);
+        }
+
+        let mut input = String::new();
+        loop {
+            write!(self.output(), "{} ", choose)?;
+            self.output().flush()?;
+            if lock(&self.input).read_line(&mut input)? == 0 {
+                return default.ok_or_else(non_interactive);
+            }
+
+            match (input.trim().parse::<usize>(), default) {
+                (Ok(n), _) if n >= 1 && n <= items.len() => return Ok(n - 1),
+                (Err(_), Some(i)) if input.trim().is_empty() => return Ok(i),
+                _ => input.clear(),
+            }
+        }
+    }
+
+    fn respond(
+        &self,
+        res: bool,
+        yes_response: Option<&str>,
+        no_response: Option<&str>,
+    ) -> Result<bool> {
+        let response = if res { yes_response } else { no_response };
+        if let Some(s) = response {
+            writeln!(self.output(), "{}", s)?;
+        }
+
+        Ok(res)
+    }
+}
+
+/// Restores the previous logger of the thread a [`Session`] was entered on when dropped.
+pub struct Entered {
+    previous: Logger,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        logging::replace(self.previous.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::temp::TempDir;
+    use std::sync::Arc;
+
+    /// A writer whose contents can be read after it is given to a session.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            lock(&self.0).write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn text(&self) -> String {
+            String::from_utf8(lock(&self.0).clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn prompts() {
+        let output = Buffer::default();
+        let session = Session::new()
+            .with_output(output.clone())
+            .with_input(&b"maybe\nn\n\n"[..]);
+        assert!(!session.prompt("Go?", None, None, Some("Stopped")).unwrap());
+        assert_eq!(output.text(), "Go? [y/n] Go? [y/n] Stopped\n");
+        assert!(session.prompt("Go?", Some(true), None, None).unwrap());
+        assert!(!session.prompt("Go?", Some(false), None, None).unwrap());
+
+        let session = Session::new().with_yes(true).with_input(&b""[..]);
+        assert!(session.prompt("Go?", Some(false), None, None).unwrap());
+    }
+
+    #[test]
+    fn selection() {
+        let output = Buffer::default();
+        let session = Session::new()
+            .with_output(output.clone())
+            .with_input(&b"0\nthree\n2\n\n"[..]);
+        assert_eq!(session.select("Which?", &["a", "b"], None).unwrap(), 1);
+        assert_eq!(
+            output.text(),
+            "Which?\n   1) a\n   2) b\nChoose 1-2: Choose 1-2: Choose 1-2: "
+        );
+        assert_eq!(session.select("Which?", &["a", "b"], Some(1)).unwrap(), 1);
+        assert!(session.select("Which?", &["a", "b"], None).is_err());
+        assert!(matches!(
+            session.select::<&str>("Which?", &[], Some(0)),
+            Err(Error::NothingToChoose { .. })
+        ));
+
+        let output = Buffer::default();
+        let session = Session::new()
+            .with_output(output.clone())
+            .with_interactive(false);
+        assert!(matches!(
+            session.select("Which?", &["a"], None),
+            Err(Error::NonInteractive { .. })
+        ));
+        assert_eq!(session.select("Which?", &["a", "b"], Some(1)).unwrap(), 1);
+        assert_eq!(
+            output.text(),
+            "Which?\n   1) a\n   2) b\nChoose 1-2 [2]: 2\n"
+        );
+        let session = session.with_non_interactive(NonInteractive::Fail);
+        assert!(session.select("Which?", &["a", "b"], Some(1)).is_err());
+    }
+
+    #[test]
+    fn non_interactive_prompts() {
+        let output = Buffer::default();
+        let session = Session::new()
+            .with_output(output.clone())
+            .with_interactive(false);
+        assert!(session
+            .prompt("Go?", Some(true), Some("Going"), None)
+            .unwrap());
+        assert_eq!(output.text(), "Go? [Y/n] y\nGoing\n");
+        assert!(matches!(
+            session.prompt("Go?", None, None, None),
+            Err(Error::NonInteractive { .. })
+        ));
+
+        let session = session.with_non_interactive(NonInteractive::Yes);
+        assert!(session.prompt("Go?", Some(false), None, None).unwrap());
+        let session = session.with_non_interactive(NonInteractive::Fail);
+        assert!(session.prompt("Go?", Some(true), None, None).is_err());
+    }
+
+    #[test]
+    fn logging() {
+        let errors = Buffer::default();
+        let temp = TempDir::new("log-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let log = dir.join("newt.log");
+        let session = Session::new()
+            .with_errors(errors.clone())
+            .with_log_level(Level::Info)
+            .with_log_file(&log)
+            .unwrap();
+        let quiet = Session::new()
+            .with_errors(errors.clone())
+            .with_log_level(Level::Off);
+
+        {
+            let _entered = session.enter();
+            info!("shown");
+            debug!("hidden");
+            {
+                let _entered = quiet.enter();
+                warn!("hidden");
+            }
+            warn!("careful");
+        }
+        warn!("elsewhere");
+
+        assert_eq!(errors.text().lines().count(), 2);
+        assert!(errors.text().ends_with("Warning: careful\n"));
+        let logged = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(logged.lines().count(), 2);
+        assert!(logged.lines().all(|line| line.starts_with("20")));
+    }
+}
@@ -0,0 +1,298 @@
+//! Sending notes to someone else, by publishing them to a paste service or by email.
+//!
+//! A note is shared with the configured `share_command`, if there is one, which is given the
+//! note's contents on its standard input and prints the URL it was published at. Otherwise the
+//! note is published as a secret GitHub gist, using the token set by `gist_token` or
+//! `$GITHUB_TOKEN`. Gists are created with `curl`, which must be installed.
+//!
+//! Notes are emailed with the configured `mail_command`, by default `msmtp -t` or `sendmail -t`.
+
+use crate::config::Config;
+use crate::edit;
+use crate::error::*;
+use crate::store::NoteStore;
+use crate::util::json::{self, json, Value};
+use crate::util::temp::TempDir;
+
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The GitHub API endpoint for creating gists.
+const GISTS_URL: &str = "https://api.github.com/gists";
+
+const TO_PLACEHOLDER: &str = "{to}";
+
+const SUBJECT_PLACEHOLDER: &str = "{subject}";
+
+/// Publish a note, returning the URL it can be read at.
+pub fn share<P: AsRef<Path>>(store: &NoteStore, name: P) -> Result<String> {
+    let config = store.config();
+    let name = name.as_ref();
+    let note = store.note(name);
+    let title = note
+        .title()?
+        .unwrap_or_else(|| name.to_string_lossy().into_owned());
+    let content = note.content()?;
+
+    if let Some(command) = config.share_command()? {
+        run_share_command(config, &command, name, &title, content)
+    } else if let Some(token) = config.gist_token() {
+        create_gist(&token, name, &title, content)
+    } else {
+        Err(Error::NoShareTarget)
+    }
+}
+
+/// Email a note to the given addresses, with its title as the subject.
+///
+/// The placeholders `{to}` and `{subject}` in the mail command are replaced by the addresses,
+/// separated by commas, and the subject, and the note is given on the command's standard input.
+/// If the command has neither placeholder, it is given the note as a whole message instead, with
+/// `To` and `Subject` headers, as `sendmail -t` expects.
+pub fn mail<P: AsRef<Path>>(store: &NoteStore, name: P, to: &[String]) -> Result<()> {
+    let config = store.config();
+    let name = name.as_ref();
+    let note = store.note(name);
+    let subject = note
+        .title()?
+        .unwrap_or_else(|| name.to_string_lossy().into_owned());
+    let command = config.mail_command()?;
+    let (mut cmd, input) = mail_command(config, &command, to, &subject, note.content()?)?;
+    pipe(&mut cmd, "mail command", &input)?;
+    Ok(())
+}
+
+/// The mail command sending a note, and the text to give it on its standard input.
+///
+/// The addresses and subject are also passed to the command in the `NEWT_MAIL_TO` and
+/// `NEWT_MAIL_SUBJECT` environment variables. A command run by the shell refers to them there in
+/// place of its placeholders, so that their text is never taken as shell syntax.
+fn mail_command(
+    config: &Config,
+    command: &Path,
+    to: &[String],
+    subject: &str,
+    content: &str,
+) -> Result<(Command, String)> {
+    let cmd = edit::configured_command(config, command, None, None)?;
+    let has_placeholder = cmd.get_args().any(|arg| {
+        let arg = arg.to_string_lossy();
+        arg.contains(TO_PLACEHOLDER) || arg.contains(SUBJECT_PLACEHOLDER)
+    });
+    if !has_placeholder {
+        let message = format!(
+            "To: {}\nSubject: {}\nMIME-Version: 1.0\nContent-Type: text/plain; charset=utf-8\n\n{}",
+            to.join(", "),
+            subject,
+            content
+        );
+        return Ok((cmd, message));
+    }
+
+    let to = to.join(",");
+    let (to_arg, subject_arg) = if config.shell_exec() {
+        ("\"$NEWT_MAIL_TO\"", "\"$NEWT_MAIL_SUBJECT\"")
+    } else {
+        (to.as_str(), subject)
+    };
+    let mut substituted = Command::new(cmd.get_program());
+    for arg in cmd.get_args() {
+        match arg.to_str() {
+            Some(arg) => substituted.arg(
+                arg.replace(TO_PLACEHOLDER, to_arg)
+                    .replace(SUBJECT_PLACEHOLDER, subject_arg),
+            ),
+            None => substituted.arg(arg),
+        };
+    }
+    substituted
+        .env("NEWT_MAIL_TO", &to)
+        .env("NEWT_MAIL_SUBJECT", subject);
+    Ok((substituted, String::from(content)))
+}
+
+/// Run the share command on a note, returning the last line it prints.
+///
+/// The note's name and title are passed to the command in the `NEWT_NOTE_NAME` and
+/// `NEWT_NOTE_TITLE` environment variables.
+fn run_share_command(
+    config: &Config,
+    command: &Path,
+    name: &Path,
+    title: &str,
+    content: &str,
+) -> Result<String> {
+    let mut cmd = edit::configured_command(config, command, None, None)?;
+    let output = pipe(
+        cmd.env("NEWT_NOTE_NAME", name)
+            .env("NEWT_NOTE_TITLE", title),
+        "share command",
+        content,
+    )?;
+    output
+        .lines()
+        .map(str::trim)
+        .rfind(|line| !line.is_empty())
+        .map(String::from)
+        .ok_or_else(|| Error::ShareFailed {
+            message: String::from("the share command printed no URL"),
+        })
+}
+
+/// Create a secret gist holding a note, returning its URL.
+fn create_gist(token: &str, name: &Path, title: &str, content: &str) -> Result<String> {
+    // The token is given to curl in a file in a private directory, to keep it out of the process
+    // list, and the request body on its standard input.
+    let dir = TempDir::new("gist")?;
+    let headers = dir.path().join("headers");
+    let text = format!(
+        "Authorization: Bearer {}\nAccept: application/vnd.github+json\n",
+        token
+    );
+    std::fs::write(&headers, text).context("writing", &headers)?;
+
+    let mut cmd = Command::new("curl");
+    cmd.args(["--silent", "--show-error", "--request", "POST"])
+        .arg("--header")
+        .arg(format!("@{}", headers.display()))
+        .args(["--data-binary", "@-"])
+        .arg(GISTS_URL);
+    let body = gist_body(name, title, content).to_string();
+    gist_url(&pipe(&mut cmd, "curl", &body)?)
+}
+
+/// The request body creating a gist with a note in it.
+fn gist_body(name: &Path, title: &str, content: &str) -> Value {
+    let file_name = name
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| String::from("note.md"));
+    json!({
+        "description": title,
+        "public": false,
+        "files": { file_name: { "content": content } },
+    })
+}
+
+/// The URL of the gist created, from GitHub's response to the request creating it.
+fn gist_url(response: &str) -> Result<String> {
+    let invalid = |message: &str| Error::ShareFailed {
+        message: String::from(message),
+    };
+    let response = json::parse(response).map_err(|_| invalid("invalid response from GitHub"))?;
+    if let Some(url) = response.get("html_url").and_then(Value::as_str) {
+        return Ok(String::from(url));
+    }
+    Err(invalid(
+        response
+            .get("message")
+            .and_then(Value::as_str)
+            .unwrap_or("no URL in response from GitHub"),
+    ))
+}
+
+/// Run a command with the given text on its standard input, returning its output.
+fn pipe(cmd: &mut Command, command: &str, input: &str) -> Result<String> {
+    trace!("Running {:?}", cmd);
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|err| cannot_invoke(command, err))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        match stdin.write_all(input.as_bytes()) {
+            Err(err) if err.kind() != ErrorKind::BrokenPipe => return Err(err.into()),
+            _ => {}
+        }
+    }
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(Error::ChildFailed {
+            command: String::from(command),
+            status: output.status,
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    #[test]
+    fn gist_requests() {
+        let body = gist_body(Path::new("drafts/idea.md"), "Idea", "# Idea\n");
+        assert_eq!(
+            body.to_string(),
+            r##"{"description":"Idea","public":false,"files":{"idea.md":{"content":"# Idea\n"}}}"##
+        );
+
+        let response = r#"{"id":"aa5a315d","html_url":"https://gist.github.com/aa5a315d"}"#;
+        assert_eq!(
+            gist_url(response).unwrap(),
+            "https://gist.github.com/aa5a315d"
+        );
+        assert!(matches!(
+            gist_url(r#"{"message":"Bad credentials"}"#),
+            Err(Error::ShareFailed { message }) if message == "Bad credentials"
+        ));
+    }
+
+    #[test]
+    fn mail_commands() {
+        let config = Config::default();
+        let to = vec![String::from("a@example.com"), String::from("b@example.com")];
+        let args = |cmd: &Command| {
+            std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        let command = Path::new("mail -s {subject} {to}");
+        let (cmd, input) = mail_command(&config, command, &to, "An idea", "Text\n").unwrap();
+        assert_eq!(
+            args(&cmd),
+            &["mail", "-s", "An idea", "a@example.com,b@example.com"]
+        );
+        assert_eq!(input, "Text\n");
+
+        let (cmd, input) =
+            mail_command(&config, Path::new("msmtp -t"), &to, "An idea", "Text\n").unwrap();
+        assert_eq!(args(&cmd), &["msmtp", "-t"]);
+        assert!(input.starts_with("To: a@example.com, b@example.com\nSubject: An idea\n"));
+        assert!(input.ends_with("\n\nText\n"));
+
+        let config = config.with_shell_exec(true).with_shell(PathBuf::from("sh"));
+        let (cmd, _) = mail_command(&config, command, &to, "It's $HOME", "Text\n").unwrap();
+        assert_eq!(
+            args(&cmd),
+            &[
+                "sh",
+                "-c",
+                "mail -s \"$NEWT_MAIL_SUBJECT\" \"$NEWT_MAIL_TO\" \"$@\"",
+                "newt"
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn share_command() {
+        let config = Config::default();
+        let url = run_share_command(
+            &config,
+            Path::new("sh -c 'cat >/dev/null; echo; echo https://paste.example/$NEWT_NOTE_NAME'"),
+            Path::new("idea.md"),
+            "Idea",
+            "# Idea\n",
+        )
+        .unwrap();
+        assert_eq!(url, "https://paste.example/idea.md");
+    }
+}
@@ -0,0 +1,256 @@
+//! Rendering notes as a static HTML site, for publishing or browsing without Newt.
+//!
+//! The site has an index page listing every note and tag, a page for each note at the note's name
+//! with an `.html` extension, and a page for each tag under `tags/`. Wiki-style links between
+//! notes are resolved by the target note's name, with or without its extension, or by its title,
+//! ignoring case.
+
+use crate::error::*;
+use crate::frontmatter;
+use crate::store::NoteStore;
+use crate::util::{
+    self,
+    markdown::{self, encode_path, escape, STYLE},
+};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A note, as shown on the site.
+struct Page {
+    name: PathBuf,
+    title: String,
+    tags: Vec<String>,
+    path: PathBuf,
+}
+
+/// Render every note in the store as a site in the given directory, creating it if needed.
+/// Returns the number of notes rendered.
+///
+/// Fails without writing anything if any of the site's files already exists.
+pub fn export(store: &NoteStore, dir: &Path) -> Result<usize> {
+    let notes = store.notes()?;
+    let mut pages = Vec::with_capacity(notes.len());
+    let mut used = HashSet::new();
+    for note in &notes {
+        let name = note.path().to_path_buf();
+        let mut path = name.with_extension("html");
+        if !used.insert(path.clone()) {
+            path = PathBuf::from(format!("{}.html", name.to_string_lossy()));
+            used.insert(path.clone());
+        }
+        pages.push(Page {
+            title: note
+                .title()?
+                .unwrap_or_else(|| name.to_string_lossy().into_owned()),
+            tags: note.tags()?,
+            name,
+            path,
+        });
+    }
+
+    // Earlier notes take precedence when several could be meant by the same link.
+    let mut targets = HashMap::new();
+    for (i, page) in pages.iter().enumerate() {
+        let keys = [
+            Some(page.name.to_string_lossy().into_owned()),
+            Some(page.name.with_extension("").to_string_lossy().into_owned()),
+            page.name
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned()),
+            Some(page.title.clone()),
+        ];
+        for key in keys.iter().flatten() {
+            targets.entry(key.to_lowercase()).or_insert(i);
+        }
+    }
+
+    let mut tags: BTreeMap<String, (String, Vec<usize>)> = BTreeMap::new();
+    for (i, page) in pages.iter().enumerate() {
+        for tag in &page.tags {
+            tags.entry(tag_slug(tag))
+                .or_insert_with(|| (tag.clone(), Vec::new()))
+                .1
+                .push(i);
+        }
+    }
+
+    let mut files = vec![(PathBuf::from("index.html"), index_page(&pages, &tags))];
+    for (page, note) in pages.iter().zip(&notes) {
+        let (_, body) = frontmatter::split(note.content()?);
+        let resolve = |target: &str| {
+            targets
+                .get(&target.to_lowercase())
+                .map(|&i| relative(&page.path, &pages[i].path))
+        };
+        let mut html = markdown::to_html_with_links(body, &resolve);
+        if !page.tags.is_empty() {
+            let links: Vec<_> = page
+                .tags
+                .iter()
+                .map(|tag| tag_link(&page.path, tag))
+                .collect();
+            html.push_str(&format!("<p class=\"path\">Tags: {}</p>", links.join(", ")));
+        }
+        files.push((page.path.clone(), site_page(&page.path, &page.title, &html)));
+    }
+    for (slug, (tag, members)) in &tags {
+        let path = Path::new("tags").join(format!("{}.html", slug));
+        let title = format!("Tag: {}", tag);
+        let body = format!(
+            "<h1>{}</h1>\n{}",
+            escape(&title),
+            note_list(&path, &pages, members)
+        );
+        let html = site_page(&path, &title, &body);
+        files.push((path, html));
+    }
+
+    for (path, _) in &files {
+        let path = dir.join(path);
+        if path.exists() {
+            return Err(Error::FileExists { path });
+        }
+    }
+    for (path, html) in &files {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("creating", parent)?;
+        }
+        fs::write(&path, html).context("writing", &path)?;
+    }
+    Ok(pages.len())
+}
+
+fn index_page(pages: &[Page], tags: &BTreeMap<String, (String, Vec<usize>)>) -> String {
+    let path = Path::new("index.html");
+    let all: Vec<_> = (0..pages.len()).collect();
+    let mut body = format!("<h1>Notes</h1>\n{}", note_list(path, pages, &all));
+    if !tags.is_empty() {
+        body.push_str("\n<h2>Tags</h2>\n<ul class=\"notes\">\n");
+        for (tag, members) in tags.values() {
+            body.push_str(&format!(
+                "<li>{} <span class=\"index\">{}</span></li>\n",
+                tag_link(path, tag),
+                members.len()
+            ));
+        }
+        body.push_str("</ul>");
+    }
+    site_page(path, "Notes", &body)
+}
+
+/// A list of links to the given notes, from the page at `from`.
+fn note_list(from: &Path, pages: &[Page], members: &[usize]) -> String {
+    let mut list = String::from("<ul class=\"notes\">\n");
+    for &i in members {
+        let page = &pages[i];
+        list.push_str(&format!(
+            "<li><a href=\"{}\">{}</a> <span class=\"path\">{}</span></li>\n",
+            escape(&relative(from, &page.path)),
+            escape(&page.title),
+            escape(&page.name.to_string_lossy())
+        ));
+    }
+    list.push_str("</ul>");
+    list
+}
+
+/// The name of a tag's page, without its extension.
+fn tag_slug(tag: &str) -> String {
+    let slug = util::slugify(tag);
+    if slug.is_empty() {
+        String::from("tag")
+    } else {
+        slug
+    }
+}
+
+fn tag_link(from: &Path, tag: &str) -> String {
+    let path = Path::new("tags").join(format!("{}.html", tag_slug(tag)));
+    format!(
+        "<a href=\"{}\">{}</a>",
+        escape(&relative(from, &path)),
+        escape(tag)
+    )
+}
+
+/// The URL of the page at `to`, relative to the page at `from`. Both are relative to the site's
+/// root.
+fn relative(from: &Path, to: &Path) -> String {
+    let depth = from.components().count().saturating_sub(1);
+    format!(
+        "{}{}",
+        "../".repeat(depth),
+        encode_path(&to.to_string_lossy())
+    )
+}
+
+/// A complete HTML page, with a link back to the index page.
+fn site_page(path: &Path, title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <style>{}</style>\n</head>\n<body>\n<nav><a href=\"{}\">All notes</a></nav>\n{}\n\
+         </body>\n</html>\n",
+        escape(title),
+        STYLE,
+        escape(&relative(path, Path::new("index.html"))),
+        body
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::temp::TempDir;
+
+    #[test]
+    fn export_site() {
+        let temp = TempDir::new("site-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let site = dir.join("site");
+        fs::create_dir_all(&dir).unwrap();
+        let store = NoteStore::at(dir.join("notes"));
+        fs::create_dir_all(store.dir().unwrap()).unwrap();
+        store
+            .write(
+                "first.md",
+                "---\ntags: [rust, Big Ideas]\n---\n# First\n\nSee [[beta]] and [[nowhere]].\n",
+            )
+            .unwrap();
+        store
+            .write(
+                "sub/second note.md",
+                "---\ntitle: Beta\ntags: [rust]\n---\nBack to [[First|the first]].\n",
+            )
+            .unwrap();
+
+        assert_eq!(export(&store, &site).unwrap(), 2);
+        let read = |path: &str| fs::read_to_string(site.join(path)).unwrap();
+
+        let index = read("index.html");
+        assert!(index.contains("<a href=\"first.html\">First</a>"));
+        assert!(index.contains("<a href=\"sub/second%20note.html\">Beta</a>"));
+        assert!(index.contains("<a href=\"tags/big-ideas.html\">Big Ideas</a>"));
+
+        let first = read("first.html");
+        assert!(first.contains("<a href=\"sub/second%20note.html\">beta</a>"));
+        assert!(first.contains("<span class=\"missing\">nowhere</span>"));
+        assert!(first.contains("<a href=\"tags/rust.html\">rust</a>"));
+
+        let second = read("sub/second note.html");
+        assert!(second.contains("<a href=\"../first.html\">the first</a>"));
+        assert!(second.contains("<a href=\"../index.html\">All notes</a>"));
+
+        let tag = read("tags/rust.html");
+        assert!(tag.contains("<h1>Tag: rust</h1>"));
+        assert!(tag.contains("<a href=\"../first.html\">First</a>"));
+        assert!(tag.contains("<a href=\"../sub/second%20note.html\">Beta</a>"));
+
+        assert!(matches!(
+            export(&store, &site),
+            Err(Error::FileExists { .. })
+        ));
+    }
+}
@@ -0,0 +1,428 @@
+//! A notes directory, as used by the Newt CLI.
+//!
+//! [`NoteStore`] is the entry point for using Newt as a library. It wraps a [`Config`] and offers
+//! the basic operations on notes, following the same conventions as the CLI: note names are paths
+//! relative to the notes directory, notes are backed up before they are deleted, and edits are
+//! recorded in each note's history. Individual notes can be inspected through [`Note`].
+//!
+//! Notes are kept in the configured notes directory by default, but a store can be given any
+//! [`Backend`], such as a [`MemoryBackend`](crate::backend::MemoryBackend) for tests.
+//! [`NoteStore::open`] chooses the backend named by the `storage` setting.
+
+use crate::backend::{Backend, FsBackend};
+use crate::config::{Config, Storage};
+use crate::error::*;
+use crate::frontmatter::Frontmatter;
+use crate::notes_dir;
+
+use std::cell::OnceCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// A collection of notes, the backend storing them, and the configuration used to manage them.
+#[derive(Debug, Clone)]
+pub struct NoteStore {
+    config: Config,
+    backend: Arc<dyn Backend>,
+}
+
+impl NoteStore {
+    /// Create a store for the notes directory of the given configuration.
+    pub fn new(config: Config) -> NoteStore {
+        let backend = Arc::new(FsBackend::new(config.clone()));
+        NoteStore::with_backend(config, backend)
+    }
+
+    /// Create a store for the notes of the given configuration, stored as its `storage` setting
+    /// says. SQLite storage keeps the notes in `.newt/notes.db` in the notes directory.
+    pub fn open(config: Config) -> Result<NoteStore> {
+        match config.storage() {
+            Storage::Files => Ok(NoteStore::new(config)),
+
+            #[cfg(feature = "sqlite")]
+            Storage::Sqlite => {
+                let path = config
+                    .notes_dir()?
+                    .join(notes_dir::DATA_DIR)
+                    .join("notes.db");
+                let backend = crate::backend::SqliteBackend::open(path)?;
+                Ok(NoteStore::with_backend(config, Arc::new(backend)))
+            }
+
+            #[cfg(not(feature = "sqlite"))]
+            Storage::Sqlite => Err(Error::NoSqliteSupport),
+        }
+    }
+
+    /// Create a store for the given notes directory, with the default configuration otherwise.
+    pub fn at<P: Into<PathBuf>>(path: P) -> NoteStore {
+        NoteStore::new(Config::default().with_notes_dir(path.into()))
+    }
+
+    /// Create a store keeping its notes in the given backend.
+    pub fn with_backend(config: Config, backend: Arc<dyn Backend>) -> NoteStore {
+        NoteStore { config, backend }
+    }
+
+    /// The configuration of this store.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The backend storing the notes.
+    pub fn backend(&self) -> &dyn Backend {
+        &*self.backend
+    }
+
+    /// The path to the notes directory.
+    pub fn dir(&self) -> Result<PathBuf> {
+        self.config.notes_dir()
+    }
+
+    /// The names of the notes, in listing order. See [`notes_dir::list`].
+    pub fn list(&self) -> Result<Vec<PathBuf>> {
+        self.backend.list()
+    }
+
+    /// Iterate over the notes in the order the backend finds them, reading no further than the
+    /// iteration goes. Unlike [`NoteStore::list`], the notes are not sorted into listing order.
+    /// See [`notes_dir::walk`].
+    pub fn iter(&self) -> Result<impl Iterator<Item = Result<Note>>> {
+        let backend = Arc::clone(&self.backend);
+        Ok(self.backend.iter()?.map(move |entry| {
+            entry.map(|name| Note {
+                backend: Arc::clone(&backend),
+                path: name,
+                content: OnceCell::new(),
+                frontmatter: OnceCell::new(),
+            })
+        }))
+    }
+
+    /// The notes, in listing order.
+    pub fn notes(&self) -> Result<Vec<Note>> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .map(|name| self.note(name))
+            .collect())
+    }
+
+    /// The note with the given name. The note is not read until its contents or metadata are
+    /// asked for.
+    pub fn note<P: Into<PathBuf>>(&self, name: P) -> Note {
+        Note {
+            backend: Arc::clone(&self.backend),
+            path: name.into(),
+            content: OnceCell::new(),
+            frontmatter: OnceCell::new(),
+        }
+    }
+
+    /// The name of the note at the given index, as shown by the list command. See
+    /// [`notes_dir::file_at_index`].
+    pub fn note_at(&self, index: usize) -> Result<PathBuf> {
+        self.backend.note_at(index)
+    }
+
+    /// Choose the name for a new note: the given name with the note extension added if it lacks
+    /// one, or else a new name generated from the configured file name format and title.
+    ///
+    /// Fails if the given name is absolute or leads out of the notes directory.
+    pub fn new_name(&self, name: Option<&str>, title: Option<&str>) -> Result<PathBuf> {
+        match name {
+            Some(name) => {
+                notes_dir::check_note_name(Path::new(name))?;
+                Ok(notes_dir::with_note_extension(
+                    &self.config,
+                    PathBuf::from(name),
+                ))
+            }
+            None => notes_dir::new_file_name_among(&self.config, &self.list()?, title),
+        }
+    }
+
+    /// Create a note with the configured initial contents for the given title, creating any
+    /// missing parent directories. Returns the initial contents.
+    ///
+    /// Fails if the note already exists.
+    pub fn create<P: AsRef<Path>>(&self, name: P, title: Option<&str>) -> Result<String> {
+        let name = name.as_ref();
+        if self.backend.exists(name)? {
+            return Err(Error::FileExists {
+                path: name.to_path_buf(),
+            });
+        }
+
+        let contents = notes_dir::initial_contents(&self.config, title);
+        self.backend.write(name, &contents)?;
+        Ok(contents)
+    }
+
+    /// Read the contents of a note.
+    pub fn read<P: AsRef<Path>>(&self, name: P) -> Result<String> {
+        self.backend.read(name.as_ref())
+    }
+
+    /// Replace the contents of a note, creating it if it does not exist.
+    pub fn write<P: AsRef<Path>>(&self, name: P, contents: &str) -> Result<()> {
+        self.backend.write(name.as_ref(), contents)
+    }
+
+    /// Move a note to a new name or into a directory, returning its new name.
+    ///
+    /// If the destination names an existing directory or ends in a path separator, the note is
+    /// moved into that directory under its current file name. Otherwise the destination is taken
+    /// as the note's new name. Existing notes are never overwritten, and the destination must
+    /// stay inside the notes directory.
+    pub fn rename<P: AsRef<Path>>(&self, name: P, destination: &str) -> Result<PathBuf> {
+        let name = name.as_ref();
+        let into_dir = destination.ends_with(std::path::is_separator)
+            || self.backend.is_dir(Path::new(destination))?;
+        let target = if into_dir {
+            Path::new(destination).join(name.file_name().unwrap_or_default())
+        } else {
+            notes_dir::with_note_extension(&self.config, PathBuf::from(destination))
+        };
+
+        notes_dir::check_note_name(&target)?;
+        if self.backend.exists(&target)? {
+            return Err(Error::FileExists { path: target });
+        }
+        self.backend.rename(name, &target)?;
+        Ok(target)
+    }
+
+    /// Delete a note, backing it up first if the backend keeps backups.
+    pub fn delete<P: AsRef<Path>>(&self, name: P) -> Result<()> {
+        self.backend.remove(name.as_ref())
+    }
+
+    /// Copy every note to a plain file under the given directory, creating any missing
+    /// directories. Returns the number of notes copied.
+    ///
+    /// Fails without copying anything if any of the files already exists.
+    pub fn export<P: AsRef<Path>>(&self, dir: P) -> Result<usize> {
+        let names = self.list()?;
+        for name in &names {
+            let path = dir.as_ref().join(name);
+            if path.exists() {
+                return Err(Error::FileExists { path });
+            }
+        }
+
+        for name in &names {
+            let path = dir.as_ref().join(name);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).context("creating", parent)?;
+            }
+            fs::write(&path, self.read(name)?).context("writing", &path)?;
+        }
+        Ok(names.len())
+    }
+}
+
+/// A note in a [`NoteStore`].
+///
+/// The note's contents and frontmatter are read from the backend the first time they are asked
+/// for, and kept from then on.
+#[derive(Debug, Clone)]
+pub struct Note {
+    backend: Arc<dyn Backend>,
+    path: PathBuf,
+    content: OnceCell<String>,
+    frontmatter: OnceCell<Option<Frontmatter>>,
+}
+
+impl Note {
+    /// The name of the note, relative to the notes directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The title of the note: its frontmatter title, its first Markdown heading, or its first
+    /// non-empty line. See [`notes_dir::title`].
+    pub fn title(&self) -> Result<Option<String>> {
+        self.backend.title(&self.path, usize::MAX)
+    }
+
+    /// When the note was created, if the backend records it.
+    pub fn created(&self) -> Result<SystemTime> {
+        self.backend.created(&self.path)
+    }
+
+    /// When the note was last modified.
+    pub fn modified(&self) -> Result<SystemTime> {
+        self.backend.modified(&self.path)
+    }
+
+    /// The note's frontmatter block, if it has one.
+    pub fn frontmatter(&self) -> Result<Option<&Frontmatter>> {
+        if self.frontmatter.get().is_none() {
+            let block = self.backend.frontmatter(&self.path)?;
+            let _ = self.frontmatter.set(block);
+        }
+        Ok(self.frontmatter.get().and_then(Option::as_ref))
+    }
+
+    /// The tags listed in the note's frontmatter.
+    pub fn tags(&self) -> Result<Vec<String>> {
+        Ok(self
+            .frontmatter()?
+            .and_then(|block| block.get_list("tags"))
+            .unwrap_or_default())
+    }
+
+    /// The full contents of the note.
+    pub fn content(&self) -> Result<&str> {
+        if self.content.get().is_none() {
+            let content = self.backend.read(&self.path)?;
+            let _ = self.content.set(content);
+        }
+        Ok(self.content.get().map(String::as_str).unwrap_or_default())
+    }
+
+    /// The first `count` non-empty lines of the note, after any frontmatter, each truncated to at
+    /// most `max_len` characters. See [`notes_dir::preview`].
+    pub fn preview(&self, count: usize, max_len: usize) -> Result<Vec<String>> {
+        self.backend.preview(&self.path, count, max_len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::util::temp::TempDir;
+
+    #[test]
+    fn note_lifecycle() {
+        let temp = TempDir::new("store-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let store = NoteStore::at(&dir);
+
+        let name = store.new_name(Some("ideas"), None).unwrap();
+        assert_eq!(name, Path::new("ideas.md"));
+        assert!(matches!(
+            store.new_name(Some("../ideas"), None),
+            Err(Error::InvalidNoteName { .. })
+        ));
+        assert_eq!(store.create(&name, None).unwrap(), "");
+        assert!(store.create(&name, None).is_err());
+        store.write(&name, "first idea\n").unwrap();
+        assert_eq!(store.read(&name).unwrap(), "first idea\n");
+        assert_eq!(store.list().unwrap(), &[PathBuf::from("ideas.md")]);
+
+        assert!(matches!(
+            store.rename(&name, "../ideas"),
+            Err(Error::InvalidNoteName { .. })
+        ));
+        let renamed = store.rename(&name, "old/ideas").unwrap();
+        assert_eq!(renamed, Path::new("old/ideas.md"));
+        assert_eq!(store.note_at(0).unwrap(), renamed);
+
+        store.delete(&renamed).unwrap();
+        assert!(store.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn memory_store() {
+        let store = NoteStore::with_backend(Config::default(), Arc::new(MemoryBackend::new()));
+        let name = store.new_name(None, Some("Plans")).unwrap();
+        store.create(&name, Some("Plans")).unwrap();
+        store
+            .write("ideas.md", "---\ntags: [a]\n---\nidea\n")
+            .unwrap();
+        assert_eq!(
+            store.list().unwrap(),
+            &[name.clone(), PathBuf::from("ideas.md")]
+        );
+        assert_eq!(store.note("ideas.md").tags().unwrap(), &["a"]);
+
+        let renamed = store.rename("ideas.md", "old/").unwrap();
+        assert_eq!(renamed, Path::new("old/ideas.md"));
+        assert!(store.rename(&name, "old/ideas").is_err());
+        assert_eq!(
+            store.rename(&name, "old").unwrap(),
+            store.note_at(0).unwrap()
+        );
+
+        store.delete(&renamed).unwrap();
+        assert_eq!(store.iter().unwrap().count(), 1);
+
+        let temp = TempDir::new("export-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        assert_eq!(store.export(&dir).unwrap(), 1);
+        assert_eq!(
+            fs::read_to_string(dir.join("old").join(&name)).unwrap(),
+            "# Plans\n\n"
+        );
+        assert!(store.export(&dir).is_err());
+    }
+
+    #[test]
+    fn open_storage() {
+        let store = NoteStore::open(Config::default()).unwrap();
+        assert_eq!(store.config().storage(), Storage::Files);
+
+        let config = Config::default().with_storage(Storage::Sqlite);
+        if cfg!(feature = "sqlite") {
+            let temp = TempDir::new("sqlite-test").unwrap();
+            let dir = temp.path().to_path_buf();
+            let store = NoteStore::open(config.with_notes_dir(dir.clone())).unwrap();
+            store.write("a.md", "note\n").unwrap();
+            assert!(dir.join(notes_dir::DATA_DIR).join("notes.db").is_file());
+            assert!(!dir.join("a.md").exists());
+        } else {
+            assert!(matches!(
+                NoteStore::open(config),
+                Err(Error::NoSqliteSupport)
+            ));
+        }
+    }
+
+    #[test]
+    fn note_metadata() {
+        let temp = TempDir::new("note-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        let store = NoteStore::at(&dir);
+        store
+            .write(
+                "a.md",
+                "---\ntitle: Plans\ntags: [work, q3]\n---\n\nShip it\nThen rest\n",
+            )
+            .unwrap();
+        store.write("b.md", "# Heading\n").unwrap();
+
+        let mut found: Vec<_> = store
+            .iter()
+            .unwrap()
+            .map(|note| note.unwrap().path().display().to_string())
+            .collect();
+        found.sort();
+        assert_eq!(found, &["a.md", "b.md"]);
+        assert_eq!(store.iter().unwrap().take(1).count(), 1);
+
+        let notes = store.notes().unwrap();
+        let a = notes
+            .iter()
+            .find(|note| note.path() == Path::new("a.md"))
+            .unwrap();
+        assert_eq!(a.title().unwrap().as_deref(), Some("Plans"));
+        assert_eq!(a.tags().unwrap(), &["work", "q3"]);
+        assert_eq!(a.preview(1, 80).unwrap(), &["Ship it"]);
+        assert!(a.content().unwrap().ends_with("Then rest\n"));
+        assert!(a.modified().is_ok());
+
+        // The contents are kept once read.
+        std::fs::remove_file(dir.join("a.md")).unwrap();
+        assert!(a.content().unwrap().ends_with("Then rest\n"));
+
+        let b = store.note("b.md");
+        assert_eq!(b.title().unwrap().as_deref(), Some("Heading"));
+        assert!(b.tags().unwrap().is_empty());
+        assert!(b.frontmatter().unwrap().is_none());
+    }
+}
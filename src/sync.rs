@@ -0,0 +1,377 @@
+//! Mirroring the notes directory with a directory elsewhere, such as on another machine over SSH,
+//! using rsync.
+//!
+//! A sync copies each file that is newer on one side, or missing from it, to that side, so that
+//! edits made on either side are kept. Files deleted on one side since the last sync are deleted
+//! on the other; which files those are is worked out from the list of files recorded after each
+//! sync, at `.newt/sync/<remote>`. Each file that a sync replaces or deletes is first moved into
+//! `.newt/sync-backups/<time>/` on the side it was removed from.
+//!
+//! The `.newt` and `.backups` directories, which hold each side's own history of its notes, are
+//! never synced.
+
+use crate::backup::BACKUPS_DIR;
+use crate::config::Config;
+use crate::error::*;
+use crate::notes_dir::DATA_DIR;
+use crate::util;
+
+use chrono::Local;
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The directories at the top of the notes directory that are left out of syncs.
+const EXCLUDED: &[&str] = &[DATA_DIR, BACKUPS_DIR];
+
+/// A change a sync makes, or would make in a dry run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transfer {
+    /// A file copied from the notes directory to the remote.
+    Pushed(PathBuf),
+
+    /// A file copied from the remote to the notes directory.
+    Pulled(PathBuf),
+
+    /// A file deleted from the remote, because it was deleted from the notes directory.
+    DeletedRemote(PathBuf),
+
+    /// A file deleted from the notes directory, because it was deleted from the remote.
+    DeletedLocal(PathBuf),
+}
+
+impl Transfer {
+    /// The file's path relative to the notes directory.
+    pub fn path(&self) -> &Path {
+        match self {
+            Transfer::Pushed(path)
+            | Transfer::Pulled(path)
+            | Transfer::DeletedRemote(path)
+            | Transfer::DeletedLocal(path) => path,
+        }
+    }
+}
+
+impl fmt::Display for Transfer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transfer::Pushed(path) => write!(f, "push {}", path.display()),
+            Transfer::Pulled(path) => write!(f, "pull {}", path.display()),
+            Transfer::DeletedRemote(path) => write!(f, "delete {} (remote)", path.display()),
+            Transfer::DeletedLocal(path) => write!(f, "delete {} (local)", path.display()),
+        }
+    }
+}
+
+/// Which way rsync is copying files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Push,
+    Pull,
+}
+
+/// Sync the notes directory with the given rsync destination, such as `user@host:notes/`.
+/// Returns the changes made, or with `dry_run`, the changes that would be made.
+pub fn sync(config: &Config, remote: &str, dry_run: bool) -> Result<Vec<Transfer>> {
+    let notes_dir = config.notes_dir()?;
+    let local = notes_dir.join("");
+    let local = local.to_string_lossy();
+    let remote = if remote.ends_with('/') {
+        String::from(remote)
+    } else {
+        format!("{}/", remote)
+    };
+    let state = notes_dir
+        .join(DATA_DIR)
+        .join("sync")
+        .join(util::slugify(&remote));
+
+    let backup_dir = format!(
+        "--backup-dir={}/sync-backups/{}",
+        DATA_DIR,
+        Local::now().format("%Y%m%dT%H%M%S")
+    );
+    let excludes: Vec<_> = EXCLUDED
+        .iter()
+        .map(|dir| format!("--exclude=/{}/", dir))
+        .collect();
+    let excludes: Vec<_> = excludes.iter().map(String::as_str).collect();
+    let mut options = vec!["--archive", "--update", "--backup", &backup_dir];
+    options.extend(&excludes);
+    options.push("--out-format=%i %n");
+    if dry_run {
+        options.push("--dry-run");
+    }
+
+    let mut transfers = Vec::new();
+    if let Some(last) = read_state(&state)? {
+        let listing = [&["--list-only", "--recursive"][..], &excludes, &[&remote]].concat();
+        let remote_files = parse_listing(&rsync(&listing, None)?);
+        let (delete_remote, delete_local) =
+            deletions(&last, &local_files(&notes_dir)?, &remote_files);
+
+        let deleting = ["--files-from=-", "--from0", "--delete-missing-args"];
+        for (files, from, to, direction) in [
+            (&delete_remote, &*local, &*remote, Direction::Push),
+            (&delete_local, &*remote, &*local, Direction::Pull),
+        ] {
+            if files.is_empty() {
+                continue;
+            }
+            let mut list = Vec::new();
+            for file in files {
+                list.extend_from_slice(file.to_string_lossy().as_bytes());
+                list.push(0);
+            }
+            let args: Vec<_> = options.iter().chain(&deleting).copied().collect();
+            let output = rsync(&[&args[..], &[from, to]].concat(), Some(&list))?;
+            transfers.extend(parse_output(&output, direction));
+        }
+    }
+
+    for (from, to, direction) in [
+        (&*local, &*remote, Direction::Push),
+        (&*remote, &*local, Direction::Pull),
+    ] {
+        let output = rsync(&[&options[..], &[from, to]].concat(), None)?;
+        // In a dry run, files that would have been deleted are still there to be copied.
+        let deleted: Vec<_> = transfers
+            .iter()
+            .filter(|transfer| {
+                matches!(
+                    transfer,
+                    Transfer::DeletedRemote(_) | Transfer::DeletedLocal(_)
+                )
+            })
+            .map(|transfer| transfer.path().to_path_buf())
+            .collect();
+        transfers.extend(
+            parse_output(&output, direction)
+                .into_iter()
+                .filter(|transfer| !deleted.iter().any(|path| path == transfer.path())),
+        );
+    }
+
+    if !dry_run {
+        write_state(&state, &local_files(&notes_dir)?)?;
+    }
+    Ok(transfers)
+}
+
+/// Run rsync with the given arguments, giving it `input` on its standard input if there is any.
+/// Returns its output.
+fn rsync(args: &[&str], input: Option<&[u8]>) -> Result<String> {
+    let mut cmd = Command::new("rsync");
+    cmd.args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped());
+    trace!("Running {:?}", cmd);
+
+    let mut child = cmd.spawn().map_err(|err| cannot_invoke("rsync", err))?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        match stdin.write_all(input) {
+            Err(err) if err.kind() != ErrorKind::BrokenPipe => return Err(err.into()),
+            _ => {}
+        }
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(Error::ChildFailed {
+            command: String::from("rsync"),
+            status: output.status,
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The changes to files reported by rsync with `--out-format="%i %n"`.
+fn parse_output(output: &str, direction: Direction) -> Vec<Transfer> {
+    output
+        .lines()
+        .filter_map(|line| {
+            if let Some(path) = line.strip_prefix("*deleting") {
+                let path = PathBuf::from(path.trim_start());
+                return Some(match direction {
+                    Direction::Push => Transfer::DeletedRemote(path),
+                    Direction::Pull => Transfer::DeletedLocal(path),
+                });
+            }
+
+            let (item, path) = line.split_once(' ')?;
+            if !matches!(item.as_bytes(), [b'<' | b'>', b'f', ..]) {
+                return None;
+            }
+            let path = PathBuf::from(path);
+            Some(match direction {
+                Direction::Push => Transfer::Pushed(path),
+                Direction::Pull => Transfer::Pulled(path),
+            })
+        })
+        .collect()
+}
+
+/// The files in a listing printed by `rsync --list-only --recursive`.
+fn parse_listing(listing: &str) -> BTreeSet<PathBuf> {
+    listing
+        .lines()
+        .filter(|line| line.starts_with('-'))
+        .filter_map(|line| {
+            // Each line is the permissions, size, date, and time, then a space and the path.
+            let mut rest = line;
+            for _ in 0..4 {
+                rest = rest.trim_start();
+                rest = &rest[rest.find(' ')?..];
+            }
+            rest.strip_prefix(' ').map(PathBuf::from)
+        })
+        .collect()
+}
+
+/// The files deleted from one side since the last sync, which are to be deleted from the other:
+/// those to delete from the remote, then those to delete from the notes directory.
+fn deletions(
+    last: &BTreeSet<PathBuf>,
+    local: &BTreeSet<PathBuf>,
+    remote: &BTreeSet<PathBuf>,
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let gone = |from: &BTreeSet<PathBuf>, to: &BTreeSet<PathBuf>| {
+        last.iter()
+            .filter(|path| !from.contains(*path) && to.contains(*path))
+            .cloned()
+            .collect()
+    };
+    (gone(local, remote), gone(remote, local))
+}
+
+/// Every file in the notes directory, other than those in the directories left out of syncs,
+/// relative to the notes directory.
+fn local_files(notes_dir: &Path) -> Result<BTreeSet<PathBuf>> {
+    fn visit(dir: &Path, prefix: &Path, files: &mut BTreeSet<PathBuf>) -> Result<()> {
+        for entry in fs::read_dir(dir).context("reading", dir)? {
+            let entry = entry.context("reading", dir)?;
+            let name = prefix.join(entry.file_name());
+            let file_type = entry.file_type().context("reading", entry.path())?;
+            if file_type.is_dir() {
+                if !EXCLUDED.iter().any(|dir| name == Path::new(dir)) {
+                    visit(&entry.path(), &name, files)?;
+                }
+            } else {
+                files.insert(name);
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = BTreeSet::new();
+    visit(notes_dir, Path::new(""), &mut files)?;
+    Ok(files)
+}
+
+/// The files there were after the last sync with a remote, if it has been synced with before.
+fn read_state(path: &Path) -> Result<Option<BTreeSet<PathBuf>>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text.lines().map(PathBuf::from).collect())),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).context("reading", path),
+    }
+}
+
+fn write_state(path: &Path, files: &BTreeSet<PathBuf>) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("creating", dir)?;
+    }
+    let mut text = String::new();
+    for file in files {
+        text.push_str(&file.to_string_lossy());
+        text.push('\n');
+    }
+    fs::write(path, text).context("writing", path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::temp::TempDir;
+
+    fn set(paths: &[&str]) -> BTreeSet<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn rsync_output() {
+        let listing = "drwxr-xr-x          4,096 2024/05/01 09:30:00 .\n\
+                       -rw-r--r--             12 2024/05/01 09:30:00 a.md\n\
+                       drwxr-xr-x          4,096 2024/05/01 09:30:00 sub\n\
+                       -rw-r--r--          1,234 2024/05/01 09:30:00 sub/two  words.md\n\
+                       lrwxrwxrwx              4 2024/05/01 09:30:00 link -> a.md\n";
+        assert_eq!(parse_listing(listing), set(&["a.md", "sub/two  words.md"]));
+
+        let output = ">f+++++++++ new.md\n\
+                      cd+++++++++ sub/\n\
+                      >f.st...... sub/changed.md\n\
+                      *deleting   old.md\n";
+        assert_eq!(
+            parse_output(output, Direction::Pull),
+            vec![
+                Transfer::Pulled("new.md".into()),
+                Transfer::Pulled("sub/changed.md".into()),
+                Transfer::DeletedLocal("old.md".into()),
+            ]
+        );
+        assert_eq!(
+            parse_output("<f+++++++++ a.md\n", Direction::Push),
+            vec![Transfer::Pushed("a.md".into())]
+        );
+        assert_eq!(
+            Transfer::DeletedRemote("a.md".into()).to_string(),
+            "delete a.md (remote)"
+        );
+    }
+
+    #[test]
+    fn sync_deletions() {
+        let last = set(&["kept.md", "gone-here.md", "gone-there.md", "gone-both.md"]);
+        let local = set(&["kept.md", "gone-there.md", "new-here.md"]);
+        let remote = set(&["kept.md", "gone-here.md", "new-there.md"]);
+        assert_eq!(
+            deletions(&last, &local, &remote),
+            (
+                vec![PathBuf::from("gone-here.md")],
+                vec![PathBuf::from("gone-there.md")]
+            )
+        );
+        assert_eq!(
+            deletions(&BTreeSet::new(), &local, &remote),
+            (vec![], vec![])
+        );
+    }
+
+    #[test]
+    fn sync_state() {
+        let temp = TempDir::new("sync-test").unwrap();
+        let dir = temp.path().to_path_buf();
+        fs::create_dir_all(dir.join(DATA_DIR)).unwrap();
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.md"), "").unwrap();
+        fs::write(dir.join("sub/b.md"), "").unwrap();
+        fs::write(dir.join(DATA_DIR).join("journal"), "").unwrap();
+        fs::create_dir_all(dir.join(BACKUPS_DIR).join("a.md")).unwrap();
+        fs::write(dir.join(BACKUPS_DIR).join("a.md").join("backup"), "").unwrap();
+
+        let files = local_files(&dir).unwrap();
+        assert_eq!(files, set(&["a.md", "sub/b.md"]));
+
+        let state = dir.join(DATA_DIR).join("sync").join("remote");
+        assert_eq!(read_state(&state).unwrap(), None);
+        write_state(&state, &files).unwrap();
+        assert_eq!(read_state(&state).unwrap(), Some(files));
+    }
+}
@@ -0,0 +1,423 @@
+//! Line-based diffs between two texts.
+
+use std::ops::Range;
+
+/// A single line of a diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// A part of the work of [`diff_lines`]: lines of the old text that are the same in the new text,
+/// or ranges of lines of each text still to be diffed.
+enum Task {
+    Same(Range<usize>),
+    Diff(Range<usize>, Range<usize>),
+}
+
+/// Compute the line-by-line differences between two texts, as a shortest edit script.
+///
+/// The lines the texts start and end with in common are matched first, and the rest is diffed
+/// with Myers' algorithm in its linear-space form, which finds the middle of an optimal edit by
+/// searching from both ends at once and then diffs the parts on either side of it in turn.
+/// Within each run of changes, removed lines come before added ones.
+fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<Line<'a>> {
+    let old: Vec<_> = old.lines().collect();
+    let new: Vec<_> = new.lines().collect();
+
+    let mut lines = Vec::with_capacity(old.len().max(new.len()));
+    let mut tasks = vec![Task::Diff(0..old.len(), 0..new.len())];
+    while let Some(task) = tasks.pop() {
+        let (mut a, mut b) = match task {
+            Task::Same(a) => {
+                lines.extend(old[a].iter().copied().map(Line::Same));
+                continue;
+            }
+            Task::Diff(a, b) => (a, b),
+        };
+
+        while !a.is_empty() && !b.is_empty() && old[a.start] == new[b.start] {
+            lines.push(Line::Same(old[a.start]));
+            a.start += 1;
+            b.start += 1;
+        }
+        let end = a.end;
+        while !a.is_empty() && !b.is_empty() && old[a.end - 1] == new[b.end - 1] {
+            a.end -= 1;
+            b.end -= 1;
+        }
+        tasks.push(Task::Same(a.end..end));
+
+        if a.is_empty() {
+            lines.extend(new[b].iter().copied().map(Line::Added));
+        } else if b.is_empty() {
+            lines.extend(old[a].iter().copied().map(Line::Removed));
+        } else {
+            let (x, y) = split_point(&old[a.clone()], &new[b.clone()]);
+            tasks.push(Task::Diff(a.start + x..a.end, b.start + y..b.end));
+            tasks.push(Task::Diff(a.start..a.start + x, b.start..b.start + y));
+        }
+    }
+
+    removals_first(lines)
+}
+
+/// Find a point on a shortest edit path between two sequences, which differ in their first and
+/// last elements: the end of the middle snake, as Myers calls it, where the furthest paths
+/// searched forward from the start and backward from the end meet.
+fn split_point(a: &[&str], b: &[&str]) -> (usize, usize) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max_d = (n + m + 1) / 2;
+    let offset = max_d;
+    let len = 2 * max_d + 2;
+    let delta = n - m;
+    // With an odd difference in length, the paths meet while searching forward.
+    let front = delta % 2 != 0;
+
+    // forward[offset + k] is the furthest x reached on diagonal k = x - y searching from the
+    // start, and backward[offset + k] the same counted from the end, searching from the end.
+    let mut forward = vec![-1isize; len as usize];
+    let mut backward = vec![-1isize; len as usize];
+    forward[offset as usize + 1] = 0;
+    backward[offset as usize + 1] = 0;
+
+    // Diagonals that have run off the edge of the grid are not searched further.
+    let (mut forward_start, mut forward_end) = (0, 0);
+    let (mut backward_start, mut backward_end) = (0, 0);
+    for d in 0..max_d {
+        let mut k = -d + forward_start;
+        while k <= d - forward_end {
+            let i = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && forward[i - 1] < forward[i + 1]) {
+                forward[i + 1]
+            } else {
+                forward[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[i] = x;
+
+            if x > n {
+                forward_end += 2;
+            } else if y > m {
+                forward_start += 2;
+            } else if front {
+                let j = offset + delta - k;
+                if j >= 0 && j < len && backward[j as usize] != -1 && x >= n - backward[j as usize]
+                {
+                    return (x as usize, y as usize);
+                }
+            }
+            k += 2;
+        }
+
+        let mut k = -d + backward_start;
+        while k <= d - backward_end {
+            let i = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && backward[i - 1] < backward[i + 1]) {
+                backward[i + 1]
+            } else {
+                backward[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[i] = x;
+
+            if x > n {
+                backward_end += 2;
+            } else if y > m {
+                backward_start += 2;
+            } else if !front {
+                let j = offset + delta - k;
+                if j >= 0 && j < len && forward[j as usize] != -1 {
+                    let forward_x = forward[j as usize];
+                    if forward_x >= n - x {
+                        let forward_y = forward_x - (j - offset);
+                        return (forward_x as usize, forward_y as usize);
+                    }
+                }
+            }
+            k += 2;
+        }
+    }
+
+    // The sequences have nothing in common.
+    (a.len(), 0)
+}
+
+/// Reorder each run of changes so that its removed lines come before its added lines.
+fn removals_first(lines: Vec<Line<'_>>) -> Vec<Line<'_>> {
+    let mut res = Vec::with_capacity(lines.len());
+    let mut added = Vec::new();
+    for line in lines {
+        match line {
+            Line::Same(_) => {
+                res.append(&mut added);
+                res.push(line);
+            }
+            Line::Removed(_) => res.push(line),
+            Line::Added(_) => added.push(line),
+        }
+    }
+    res.append(&mut added);
+    res
+}
+
+/// Format the differences between two texts as a unified diff, with the given number of lines of
+/// context around each change.
+///
+/// Returns an empty string if the texts have the same lines.
+pub fn unified(old: &str, new: &str, context: usize) -> String {
+    let lines = diff_lines(old, new);
+    let changed: Vec<_> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Line::Same(_)))
+        .map(|(i, _)| i)
+        .collect();
+
+    // Group the changed lines into hunks, merging those whose context would overlap.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(lines.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut res = String::new();
+    for (start, end) in hunks {
+        let (mut old_start, mut new_start) = (1, 1);
+        for line in &lines[..start] {
+            match line {
+                Line::Same(_) => {
+                    old_start += 1;
+                    new_start += 1;
+                }
+                Line::Removed(_) => old_start += 1,
+                Line::Added(_) => new_start += 1,
+            }
+        }
+
+        let hunk = &lines[start..end];
+        let old_len = hunk
+            .iter()
+            .filter(|line| !matches!(line, Line::Added(_)))
+            .count();
+        let new_len = hunk
+            .iter()
+            .filter(|line| !matches!(line, Line::Removed(_)))
+            .count();
+        // An empty range is given by the line before it, as in `diff -u`.
+        let range_start = |start: usize, len: usize| if len == 0 { start - 1 } else { start };
+        res.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            range_start(old_start, old_len),
+            old_len,
+            range_start(new_start, new_len),
+            new_len
+        ));
+
+        for line in hunk {
+            let (prefix, text) = match line {
+                Line::Same(text) => (' ', text),
+                Line::Removed(text) => ('-', text),
+                Line::Added(text) => ('+', text),
+            };
+            res.push(prefix);
+            res.push_str(text);
+            res.push('\n');
+        }
+    }
+
+    res
+}
+
+/// Combine two texts into one, with the lines they share kept once and the lines that differ
+/// between conflict markers, as left by a merge in git: `<<<<<<< old_label`, the old lines,
+/// `=======`, the new lines, and `>>>>>>> new_label`.
+pub fn with_conflict_markers(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    let mut res = String::new();
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let flush = |res: &mut String, removed: &mut Vec<&str>, added: &mut Vec<&str>| {
+        if removed.is_empty() && added.is_empty() {
+            return;
+        }
+        res.push_str(&format!("<<<<<<< {}\n", old_label));
+        for line in removed.drain(..) {
+            res.push_str(line);
+            res.push('\n');
+        }
+        res.push_str("=======\n");
+        for line in added.drain(..) {
+            res.push_str(line);
+            res.push('\n');
+        }
+        res.push_str(&format!(">>>>>>> {}\n", new_label));
+    };
+
+    for line in diff_lines(old, new) {
+        match line {
+            Line::Same(text) => {
+                flush(&mut res, &mut removed, &mut added);
+                res.push_str(text);
+                res.push('\n');
+            }
+            Line::Removed(text) => removed.push(text),
+            Line::Added(text) => added.push(text),
+        }
+    }
+    flush(&mut res, &mut removed, &mut added);
+    res
+}
+
+/// Color a unified diff for display in a terminal, with removed lines in red, added lines in
+/// green, and hunk headers in cyan.
+pub fn colorize(diff: &str) -> String {
+    let mut res = String::new();
+    for line in diff.lines() {
+        let color = match line.chars().next() {
+            Some('-') => Some("31"),
+            Some('+') => Some("32"),
+            Some('@') => Some("36"),
+            _ => None,
+        };
+
+        match color {
+            Some(color) => res.push_str(&format!("\x1b[{}m{}\x1b[0m\n", color, line)),
+            None => {
+                res.push_str(line);
+                res.push('\n');
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn identical() {
+        assert_eq!(unified("a\nb\n", "a\nb\n", 3), "");
+    }
+
+    #[test]
+    fn changes_with_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\nten\n";
+        assert_eq!(
+            unified(old, new, 1),
+            "@@ -2,3 +2,3 @@\n 2\n-3\n+three\n 4\n@@ -9,1 +9,2 @@\n 9\n+ten\n"
+        );
+        assert_eq!(
+            unified(old, new, 3),
+            "@@ -1,9 +1,10 @@\n 1\n 2\n-3\n+three\n 4\n 5\n 6\n 7\n 8\n 9\n+ten\n"
+        );
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(
+            colorize("@@ -1,2 +1,2 @@\n a\n-b\n+c\n"),
+            "\x1b[36m@@ -1,2 +1,2 @@\x1b[0m\n a\n\x1b[31m-b\x1b[0m\n\x1b[32m+c\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn conflict_markers() {
+        assert_eq!(
+            with_conflict_markers("a\nb\nc\n", "a\nB\nc\nd\n", "mine", "theirs"),
+            "a\n<<<<<<< mine\nb\n=======\nB\n>>>>>>> theirs\nc\n\
+             <<<<<<< mine\n=======\nd\n>>>>>>> theirs\n"
+        );
+        assert_eq!(with_conflict_markers("a\n", "a\n", "x", "y"), "a\n");
+    }
+
+    /// The length of the longest common subsequence of two sequences, computed directly.
+    fn common_len(a: &[&str], b: &[&str]) -> usize {
+        let mut common = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                common[i][j] = if a[i] == b[j] {
+                    common[i + 1][j + 1] + 1
+                } else {
+                    common[i + 1][j].max(common[i][j + 1])
+                };
+            }
+        }
+        common[0][0]
+    }
+
+    #[test]
+    fn shortest_edits() {
+        // Texts of a few random lines from a small alphabet, so that they share many lines.
+        let mut state = 0x2545_f491_u32;
+        let mut random = |bound: u32| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state % bound
+        };
+        for _ in 0..500 {
+            let text = |random: &mut dyn FnMut(u32) -> u32| {
+                let len = random(12);
+                (0..len)
+                    .map(|_| ["a", "b", "c", "d"][random(4) as usize])
+                    .collect::<Vec<_>>()
+            };
+            let (old, new) = (text(&mut random), text(&mut random));
+            let (old_text, new_text) = (old.join("\n"), new.join("\n"));
+            let lines = diff_lines(&old_text, &new_text);
+
+            let (mut old_side, mut new_side) = (Vec::new(), Vec::new());
+            for line in &lines {
+                match *line {
+                    Line::Same(text) => {
+                        old_side.push(text);
+                        new_side.push(text);
+                    }
+                    Line::Removed(text) => old_side.push(text),
+                    Line::Added(text) => new_side.push(text),
+                }
+            }
+            assert_eq!((old_side, new_side), (old.clone(), new.clone()));
+            let same = lines
+                .iter()
+                .filter(|line| matches!(line, Line::Same(_)))
+                .count();
+            assert_eq!(same, common_len(&old, &new), "{:?} {:?}", old, new);
+        }
+    }
+
+    #[test]
+    fn large_texts() {
+        let old: String = (0..200_000).map(|i| format!("line {}\n", i)).collect();
+        let new = old.replace("line 100000\n", "changed\n") + "end\n";
+        assert_eq!(
+            unified(&old, &new, 0),
+            "@@ -100001,1 +100001,1 @@\n-line 100000\n+changed\n@@ -200000,0 +200001,1 @@\n+end\n"
+        );
+
+        // Texts with nothing in common take the longest to diff.
+        let (old, new) = (&old[..20_000], &old[20_000..40_000]);
+        let count = old.lines().count() + new.lines().count();
+        assert_eq!(diff_lines(old, new).len(), count);
+    }
+
+    #[test]
+    fn from_empty() {
+        assert_eq!(unified("", "a\nb\n", 3), "@@ -0,0 +1,2 @@\n+a\n+b\n");
+    }
+}
@@ -1,6 +1,8 @@
+use crate::error::*;
+
 use std::env::{self, VarError};
 use std::ffi::{OsStr, OsString};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub fn env_var<K: AsRef<OsStr>>(name: K) -> Option<OsString> {
     match env::var(name) {
@@ -10,25 +12,135 @@ pub fn env_var<K: AsRef<OsStr>>(name: K) -> Option<OsString> {
     }
 }
 
+/// Interpolate environment variables into the given text.
+///
+/// Variables are written `$VAR` or `${VAR}`, and `$$` is a literal dollar sign. The shell-style
+/// forms `${VAR:-default}` and `${VAR:?message}` are supported; here, a variable that is unset or
+/// empty with the `:?` form expands to nothing. Use [`try_interpolate`] to have it fail instead.
 pub fn interpolate<S: AsRef<str>>(text: S) -> OsString {
+    interpolate_with(text.as_ref(), false).unwrap_or_default()
+}
+
+/// Interpolate environment variables into the given text, failing if a variable written with the
+/// `${VAR:?message}` form is unset or empty.
+pub fn try_interpolate<S: AsRef<str>>(text: S) -> Result<OsString> {
+    interpolate_with(text.as_ref(), true)
+}
+
+fn interpolate_with(text: &str, strict: bool) -> Result<OsString> {
     let mut res = OsString::new();
-    Lexer::new(text.as_ref().chars()).for_each(|tok| match tok {
-        Token::Text(text) => {
-            res.push(text);
+    for tok in Lexer::new(text.chars()) {
+        match tok {
+            Token::Text(text) => {
+                res.push(text);
+            }
+
+            Token::Var(var) => {
+                let (name, modifier) = parse_var(&var);
+                match env_var(name).filter(|val| !val.is_empty()) {
+                    Some(val) => {
+                        if let Some(s) = val.to_str() {
+                            res.push(interpolate_with(s, strict)?);
+                        } else {
+                            res.push(val);
+                        }
+                    }
+
+                    None => match modifier {
+                        Some(Modifier::Default(default)) => {
+                            res.push(interpolate_with(default, strict)?);
+                        }
+
+                        Some(Modifier::Required(message)) if strict => {
+                            return Err(Error::UnsetVariable {
+                                name: String::from(name),
+                                message: if message.is_empty() {
+                                    String::from("parameter null or not set")
+                                } else {
+                                    interpolate_with(message, false)?
+                                        .to_string_lossy()
+                                        .into_owned()
+                                },
+                            });
+                        }
+
+                        _ => (),
+                    },
+                }
+            }
         }
+    }
 
-        Token::Var(name) => {
-            if let Some(val) = env_var(name) {
-                if let Some(s) = val.to_str() {
-                    res.push(interpolate(s));
-                } else {
-                    res.push(val);
+    Ok(res)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Modifier<'a> {
+    Default(&'a str),
+    Required(&'a str),
+}
+
+/// Split the contents of a `${...}` expression into the variable name and its modifier, if any.
+fn parse_var(var: &str) -> (&str, Option<Modifier<'_>>) {
+    let (name, rest) = var.split_at(var.find(|c| !is_id(c)).unwrap_or(var.len()));
+    if let Some(default) = rest.strip_prefix(":-") {
+        (name, Some(Modifier::Default(default)))
+    } else if let Some(message) = rest.strip_prefix(":?") {
+        (name, Some(Modifier::Required(message)))
+    } else {
+        (var, None)
+    }
+}
+
+/// Expand a leading `~` or `~user` to a home directory, then interpolate environment variables.
+///
+/// `~` expands to `$HOME` (or `%USERPROFILE%` if that is unset), and `~user` to the home directory
+/// of the named user. A tilde that cannot be expanded is left as it is.
+pub fn expand_path<S: AsRef<str>>(text: S) -> Result<OsString> {
+    let text = text.as_ref();
+    let mut res = OsString::new();
+    let rest = match text.strip_prefix('~') {
+        Some(rest) => {
+            let (user, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            match home_dir(user) {
+                Some(home) => {
+                    res.push(home);
+                    rest
                 }
+                None => text,
             }
         }
-    });
+        None => text,
+    };
+
+    res.push(try_interpolate(rest)?);
+    Ok(res)
+}
 
-    res
+fn home_dir(user: &str) -> Option<OsString> {
+    if user.is_empty() {
+        env_var("HOME").or_else(|| env_var("USERPROFILE"))
+    } else {
+        user_home_dir(user)
+    }
+}
+
+#[cfg(unix)]
+fn user_home_dir(user: &str) -> Option<OsString> {
+    let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|line| {
+        let fields: Vec<_> = line.split(':').collect();
+        if fields.len() >= 6 && fields[0] == user {
+            Some(OsString::from(fields[5]))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(not(unix))]
+fn user_home_dir(_user: &str) -> Option<OsString> {
+    None
 }
 
 pub fn search_path<S: AsRef<OsStr>>(command: S) -> Option<PathBuf> {
@@ -36,11 +148,14 @@ pub fn search_path<S: AsRef<OsStr>>(command: S) -> Option<PathBuf> {
     if cmd_path.is_absolute() {
         Some(cmd_path)
     } else {
-        for mut base_path in env::split_paths(&env_var("PATH")?) {
-            base_path.extend(&cmd_path);
-            if let Ok(md) = std::fs::metadata(&base_path) {
-                if md.is_file() {
-                    return Some(base_path);
+        let names = executable_names(&cmd_path);
+        for base_path in env::split_paths(&env_var("PATH")?) {
+            for name in &names {
+                let path = base_path.join(name);
+                if let Ok(md) = std::fs::metadata(&path) {
+                    if md.is_file() {
+                        return Some(path);
+                    }
                 }
             }
         }
@@ -49,8 +164,35 @@ pub fn search_path<S: AsRef<OsStr>>(command: S) -> Option<PathBuf> {
     }
 }
 
+/// The file names under which the given command may be found on `$PATH`.
+#[cfg(not(windows))]
+fn executable_names(command: &Path) -> Vec<PathBuf> {
+    vec![PathBuf::from(command)]
+}
+
+/// The file names under which the given command may be found on `%PATH%`.
+///
+/// A command without an extension may be run with any of the extensions listed in `%PATHEXT%`.
+#[cfg(windows)]
+fn executable_names(command: &Path) -> Vec<PathBuf> {
+    if command.extension().is_some() {
+        return vec![PathBuf::from(command)];
+    }
+
+    let pathext = env_var("PATHEXT")
+        .and_then(|exts| exts.into_string().ok())
+        .unwrap_or_else(|| String::from(".COM;.EXE;.BAT;.CMD"));
+    let mut names = vec![PathBuf::from(command)];
+    names.extend(pathext.split(';').filter(|ext| !ext.is_empty()).map(|ext| {
+        let mut name = OsString::from(command);
+        name.push(ext);
+        PathBuf::from(name)
+    }));
+    names
+}
+
 fn is_id(c: char) -> bool {
-    ('A'..='Z').contains(&c) || ('a'..='z').contains(&c) || ('0'..='9').contains(&c) || c == '_'
+    c.is_ascii_uppercase() || c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -74,6 +216,7 @@ struct Lexer<I> {
     lookahead: Option<char>,
     buffer: String,
     state: LexerState,
+    depth: usize,
 }
 
 impl<I: Iterator<Item = char>> Lexer<I> {
@@ -85,6 +228,7 @@ impl<I: Iterator<Item = char>> Lexer<I> {
             lookahead,
             buffer: String::new(),
             state: LexerState::Text,
+            depth: 0,
         }
     }
 
@@ -186,10 +330,17 @@ impl<I: Iterator<Item = char>> Lexer<I> {
         if let Some(c) = self.lookahead {
             self.get_next();
 
-            if c == '}' {
+            if c == '}' && self.depth == 0 {
                 self.state = LexerState::Text;
                 Some(Token::Var(self.clear_buf()))
             } else {
+                // Braces in default values, as in `${A:-${B}}`, must be balanced.
+                if c == '{' {
+                    self.depth += 1;
+                } else if c == '}' {
+                    self.depth -= 1;
+                }
+
                 self.buffer.push(c);
                 None
             }
@@ -235,9 +386,7 @@ mod test {
     use super::*;
     use std::sync::Mutex;
 
-    lazy_static! {
-        static ref ENV_LOCK: Mutex<()> = Mutex::new(());
-    }
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
 
     fn text<S>(s: S) -> Token
     where
@@ -306,6 +455,44 @@ mod test {
         simple_test(input, &[text("what"), text("${gives")]);
     }
 
+    #[test]
+    fn nested_braces() {
+        let input = "${A:-${B}}/x";
+        simple_test(input, &[var("A:-${B}"), text("/x")]);
+    }
+
+    #[test]
+    fn default_values() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        env::set_var("FOO", "foo");
+        env::set_var("EMPTY", "");
+        env::remove_var("UNSET");
+        assert_eq!(interpolate("${FOO:-bar}"), "foo");
+        assert_eq!(interpolate("${UNSET:-bar}"), "bar");
+        assert_eq!(interpolate("${EMPTY:-bar}"), "bar");
+        assert_eq!(interpolate("${UNSET:-$FOO/baz}"), "foo/baz");
+        assert_eq!(interpolate("${UNSET:-${EMPTY:-baz}}"), "baz");
+    }
+
+    #[test]
+    fn required_values() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        env::set_var("FOO", "foo");
+        env::remove_var("UNSET");
+        assert_eq!(try_interpolate("${FOO:?missing}").unwrap(), "foo");
+        assert_eq!(interpolate("a${UNSET:?missing}b"), "ab");
+        assert_eq!(
+            try_interpolate("${UNSET:?set UNSET first}")
+                .unwrap_err()
+                .to_string(),
+            "UNSET: set UNSET first"
+        );
+        assert_eq!(
+            try_interpolate("${UNSET:?}").unwrap_err().to_string(),
+            "UNSET: parameter null or not set"
+        );
+    }
+
     #[test]
     fn interpolate_vars_set() {
         let _guard = ENV_LOCK.lock().unwrap();
@@ -315,6 +502,29 @@ mod test {
         assert_eq!(res, "/home/bar/baz");
     }
 
+    #[test]
+    fn tilde_expansion() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let home = env::var("HOME").unwrap();
+        env::set_var("FOO", "foo");
+        assert_eq!(expand_path("~").unwrap(), OsString::from(&home));
+        assert_eq!(
+            expand_path("~/notes/$FOO").unwrap(),
+            OsString::from(format!("{}/notes/foo", home))
+        );
+        assert_eq!(expand_path("/notes/~").unwrap(), "/notes/~");
+        assert_eq!(
+            expand_path("~no-such-user-here/notes").unwrap(),
+            "~no-such-user-here/notes"
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn tilde_user_expansion() {
+        assert_eq!(expand_path("~root/notes").unwrap(), "/root/notes");
+    }
+
     #[test]
     fn recursive_interpolation() {
         let _guard = ENV_LOCK.lock().unwrap();
@@ -0,0 +1,31 @@
+//! JSON values, parsed and serialized with `serde_json`.
+
+use crate::error::*;
+
+pub use serde_json::{json, Value};
+
+/// Parse a JSON document.
+pub fn parse(text: &str) -> Result<Value> {
+    serde_json::from_str(text).map_err(|err| Error::InvalidJson {
+        message: err.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_errors() {
+        for (text, message) in &[
+            ("", "EOF while parsing a value at line 1 column 0"),
+            ("[1, 2", "EOF while parsing a list at line 1 column 5"),
+            ("{} x", "trailing characters at line 1 column 4"),
+        ] {
+            match parse(text) {
+                Err(Error::InvalidJson { message: found }) => assert_eq!(&found, message),
+                other => panic!("{:?} parsed as {:?}", text, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,360 @@
+//! A small Markdown to HTML renderer, covering the syntax notes commonly use.
+//!
+//! Supported blocks are ATX headings, paragraphs, fenced code blocks, block quotes, bulleted,
+//! numbered, and task lists, and horizontal rules. Supported inline syntax is code spans,
+//! strong and emphasized text, links, and autolinks. Anything else is shown as plain text.
+//!
+//! Wiki-style links, such as `[[other note]]` or `[[other note|shown text]]`, are rendered only
+//! when a function is given to resolve their targets; see [`to_html_with_links`].
+
+use crate::wikilink;
+
+/// A function giving the URL of a wiki-style link's target, if there is such a target.
+pub type Links<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+/// A style sheet for pages of rendered notes.
+pub const STYLE: &str = "body { font-family: sans-serif; max-width: 48em; margin: 2em auto; \
+                         padding: 0 1em; line-height: 1.5; } \
+                         pre { background: #f4f4f4; padding: 0.5em; overflow-x: auto; } \
+                         blockquote { border-left: 3px solid #ccc; margin-left: 0; \
+                         padding-left: 1em; } \
+                         .path, .index, .missing { color: #888; } \
+                         ul.notes { list-style: none; padding: 0; }";
+
+/// Percent-encode a note name for use in a URL path.
+pub fn encode_path(name: &str) -> String {
+    let mut encoded = String::new();
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Escape text for inclusion in HTML, in element content or quoted attribute values.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The kind of block being gathered from consecutive lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Block {
+    None,
+    Paragraph,
+    Quote,
+    List(&'static str),
+}
+
+/// Render Markdown text as HTML. Wiki-style links are shown as plain text.
+#[cfg(any(test, feature = "serve"))]
+pub fn to_html(text: &str) -> String {
+    render(text, None)
+}
+
+/// Render Markdown text as HTML, resolving wiki-style links with the given function. Links to
+/// targets it does not know are shown as text in a `missing` span.
+pub fn to_html_with_links(text: &str, links: Links<'_>) -> String {
+    render(text, Some(links))
+}
+
+fn render(text: &str, links: Option<Links<'_>>) -> String {
+    let mut html = String::new();
+    let mut block = Block::None;
+    let mut lines: Vec<&str> = Vec::new();
+    let mut source = text.lines();
+
+    while let Some(line) = source.next() {
+        let trimmed = line.trim();
+
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            close(&mut html, &mut block, &mut lines, links);
+            let fence = &trimmed[..3];
+            html.push_str("<pre><code>");
+            for line in source.by_ref() {
+                if line.trim_start().starts_with(fence) {
+                    break;
+                }
+                html.push_str(&escape(line));
+                html.push('\n');
+            }
+            html.push_str("</code></pre>\n");
+        } else if trimmed.is_empty() {
+            close(&mut html, &mut block, &mut lines, links);
+        } else if let Some((level, heading)) = heading(trimmed) {
+            close(&mut html, &mut block, &mut lines, links);
+            html.push_str(&format!(
+                "<h{0}>{1}</h{0}>\n",
+                level,
+                inline(heading, links)
+            ));
+        } else if is_rule(trimmed) {
+            close(&mut html, &mut block, &mut lines, links);
+            html.push_str("<hr>\n");
+        } else if let Some(quoted) = trimmed.strip_prefix('>') {
+            if block != Block::Quote {
+                close(&mut html, &mut block, &mut lines, links);
+                block = Block::Quote;
+            }
+            lines.push(quoted.trim_start());
+        } else if let Some((kind, item)) = list_item(trimmed) {
+            if block != Block::List(kind) {
+                close(&mut html, &mut block, &mut lines, links);
+                html.push_str(&format!("<{}>\n", kind));
+                block = Block::List(kind);
+            }
+            html.push_str(&format!("<li>{}</li>\n", task(item, links)));
+        } else {
+            if block != Block::Paragraph {
+                close(&mut html, &mut block, &mut lines, links);
+                block = Block::Paragraph;
+            }
+            lines.push(trimmed);
+        }
+    }
+
+    close(&mut html, &mut block, &mut lines, links);
+    html
+}
+
+/// Finish the block being gathered, if any.
+fn close(html: &mut String, block: &mut Block, lines: &mut Vec<&str>, links: Option<Links<'_>>) {
+    let text = inline(&lines.join("\n"), links);
+    match block {
+        Block::None => (),
+        Block::Paragraph => html.push_str(&format!("<p>{}</p>\n", text)),
+        Block::Quote => html.push_str(&format!("<blockquote><p>{}</p></blockquote>\n", text)),
+        Block::List(kind) => html.push_str(&format!("</{}>\n", kind)),
+    }
+    *block = Block::None;
+    lines.clear();
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    let rest = &line[level..];
+    if (1..=6).contains(&level) && (rest.is_empty() || rest.starts_with(' ')) {
+        Some((level, rest.trim().trim_end_matches('#').trim_end()))
+    } else {
+        None
+    }
+}
+
+fn is_rule(line: &str) -> bool {
+    let marks: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    marks.len() >= 3
+        && ["-", "*", "_"]
+            .iter()
+            .any(|mark| marks.chars().all(|c| mark.starts_with(c)))
+}
+
+fn list_item(line: &str) -> Option<(&'static str, &str)> {
+    for bullet in &["- ", "* ", "+ "] {
+        if let Some(item) = line.strip_prefix(bullet) {
+            return Some(("ul", item));
+        }
+    }
+
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let rest = &line[digits..];
+    if digits > 0 && (rest.starts_with(". ") || rest.starts_with(") ")) {
+        Some(("ol", &rest[2..]))
+    } else {
+        None
+    }
+}
+
+fn task(item: &str, links: Option<Links<'_>>) -> String {
+    let checkbox = |checked| format!("<input type=\"checkbox\" disabled{}>", checked);
+    if let Some(rest) = item.strip_prefix("[ ] ") {
+        format!("{} {}", checkbox(""), inline(rest, links))
+    } else if let Some(rest) = item
+        .strip_prefix("[x] ")
+        .or_else(|| item.strip_prefix("[X] "))
+    {
+        format!("{} {}", checkbox(" checked"), inline(rest, links))
+    } else {
+        inline(item, links)
+    }
+}
+
+/// Render the inline syntax of a block's text.
+fn inline(text: &str, links: Option<Links<'_>>) -> String {
+    let mut html = String::new();
+    let mut rest = text;
+    let mut previous = ' ';
+
+    while let Some(c) = rest.chars().next() {
+        let after = &rest[c.len_utf8()..];
+        let special = match c {
+            '\\' => after
+                .chars()
+                .next()
+                .filter(char::is_ascii_punctuation)
+                .map(|next| (escape(&next.to_string()), 1 + next.len_utf8())),
+            '`' => delimited(rest, "`")
+                .map(|(code, len)| (format!("<code>{}</code>", escape(code)), len)),
+            '*' | '_' if after.starts_with(c) => {
+                let delimiter = if c == '*' { "**" } else { "__" };
+                delimited(rest, delimiter).map(|(strong, len)| {
+                    (format!("<strong>{}</strong>", inline(strong, links)), len)
+                })
+            }
+            '*' | '_' if c == '*' || !previous.is_alphanumeric() => {
+                let delimiter = if c == '*' { "*" } else { "_" };
+                delimited(rest, delimiter)
+                    .map(|(em, len)| (format!("<em>{}</em>", inline(em, links)), len))
+            }
+            '[' => match links {
+                Some(resolve) if after.starts_with('[') => wiki_link(rest, resolve),
+                _ => link(rest, links),
+            },
+            '<' => autolink(rest),
+            _ => None,
+        };
+
+        let (rendered, len) =
+            special.unwrap_or_else(|| (escape(&rest[..c.len_utf8()]), c.len_utf8()));
+        html.push_str(&rendered);
+        previous = rest[..len].chars().last().unwrap_or(previous);
+        rest = &rest[len..];
+    }
+
+    html
+}
+
+/// Find the text between the delimiter at the start of `text` and its next occurrence. Returns
+/// the text and the length of the whole span, including both delimiters.
+fn delimited<'a>(text: &'a str, delimiter: &str) -> Option<(&'a str, usize)> {
+    let inner = &text[delimiter.len()..];
+    let end = inner.find(delimiter)?;
+    let enclosed = &inner[..end];
+    if enclosed.is_empty() || enclosed.starts_with(' ') || enclosed.ends_with(' ') {
+        return None;
+    }
+    Some((enclosed, end + 2 * delimiter.len()))
+}
+
+fn link(text: &str, links: Option<Links<'_>>) -> Option<(String, usize)> {
+    let close = text.find("](")?;
+    let after = &text[close + 2..];
+    let end = after.find(')')?;
+    let html = format!(
+        "<a href=\"{}\">{}</a>",
+        escape(safe_url(after[..end].trim())),
+        inline(&text[1..close], links)
+    );
+    Some((html, close + 3 + end))
+}
+
+/// Render a wiki-style link. Its target is resolved without any `#heading` it links to. See
+/// [`wikilink`].
+fn wiki_link(text: &str, links: Links<'_>) -> Option<(String, usize)> {
+    let (link, len) = wikilink::parse(text)?;
+    let html = match links(link.target) {
+        Some(url) => format!("<a href=\"{}\">{}</a>", escape(&url), escape(&link.text())),
+        None => format!("<span class=\"missing\">{}</span>", escape(&link.text())),
+    };
+    Some((html, len))
+}
+
+fn autolink(text: &str) -> Option<(String, usize)> {
+    let end = text.find('>')?;
+    let url = &text[1..end];
+    if !(url.starts_with("http://") || url.starts_with("https://")) || url.contains(' ') {
+        return None;
+    }
+    let html = format!("<a href=\"{0}\">{0}</a>", escape(url));
+    Some((html, end + 1))
+}
+
+/// Disarm link targets that would run code when followed.
+fn safe_url(url: &str) -> &str {
+    let scheme = url.to_ascii_lowercase();
+    if scheme.starts_with("javascript:") || scheme.starts_with("data:") {
+        "#"
+    } else {
+        url
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn blocks() {
+        let text = "# Title #\n\nSome *text*\nover two lines.\n\n- [ ] one\n- [x] two\n1. first\n\
+                    > quoted\n> more\n\n---\n```\n<code> & *stars*\n```\n####### not a heading";
+        assert_eq!(
+            to_html(text),
+            "<h1>Title</h1>\n\
+             <p>Some <em>text</em>\nover two lines.</p>\n\
+             <ul>\n\
+             <li><input type=\"checkbox\" disabled> one</li>\n\
+             <li><input type=\"checkbox\" disabled checked> two</li>\n\
+             </ul>\n\
+             <ol>\n<li>first</li>\n</ol>\n\
+             <blockquote><p>quoted\nmore</p></blockquote>\n\
+             <hr>\n\
+             <pre><code>&lt;code&gt; &amp; *stars*\n</code></pre>\n\
+             <p>####### not a heading</p>\n"
+        );
+    }
+
+    #[test]
+    fn inline_syntax() {
+        assert_eq!(
+            inline(
+                "**bold** and _em_, `a < b`, snake_case_name, 2 * 3 * 4",
+                None
+            ),
+            "<strong>bold</strong> and <em>em</em>, <code>a &lt; b</code>, snake_case_name, \
+             2 * 3 * 4"
+        );
+        assert_eq!(
+            inline(
+                "[the *docs*](https://example.com/?a=1&b=2) <https://x.org> \\*not em\\*",
+                None
+            ),
+            "<a href=\"https://example.com/?a=1&amp;b=2\">the <em>docs</em></a> \
+             <a href=\"https://x.org\">https://x.org</a> *not em*"
+        );
+        assert_eq!(
+            inline("[x](javascript:alert(1)) <b>", None),
+            "<a href=\"#\">x</a>) &lt;b&gt;"
+        );
+    }
+
+    #[test]
+    fn wiki_links() {
+        let resolve = |target: &str| match target {
+            "Other note" => Some(String::from("other note.html")),
+            _ => None,
+        };
+        assert_eq!(
+            to_html_with_links(
+                "See [[Other note]], [[Other note#Part|*that*]] and [[Gone]].\n\n- [[]] [[Other note]]",
+                &resolve
+            ),
+            "<p>See <a href=\"other note.html\">Other note</a>, \
+             <a href=\"other note.html\">*that*</a> and <span class=\"missing\">Gone</span>.</p>\n\
+             <ul>\n<li>[[]] <a href=\"other note.html\">Other note</a></li>\n</ul>\n"
+        );
+        assert_eq!(to_html("[[Other note]]"), "<p>[[Other note]]</p>\n");
+        assert_eq!(encode_path("sub/a b.md"), "sub/a%20b.md");
+    }
+}
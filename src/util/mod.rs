@@ -1,11 +1,16 @@
-use crate::error::*;
-
-use std::io::{self, Write};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
+pub mod diff;
 pub mod env;
+pub mod json;
+pub mod markdown;
+pub mod regex;
 pub mod sh;
-
-static mut YES: bool = false;
+pub mod temp;
+pub mod term;
 
 /// Get the number of decimal digits in the given number.
 pub fn digits(mut num: usize) -> usize {
@@ -19,72 +24,331 @@ pub fn digits(mut num: usize) -> usize {
     }
 }
 
-/// Set the global 'yes' setting.
-pub fn set_yes(yes: bool) {
-    unsafe { YES = yes };
+/// Convert arbitrary text into a lowercase, hyphen-separated file name component.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+    slug
 }
 
-/// Query the global 'yes' setting.
-pub fn yes() -> bool {
-    unsafe { YES }
+/// Generate a random (version 4) UUID in its usual hyphenated string form.
+pub fn uuid() -> String {
+    let random = || {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or_default(),
+        );
+        hasher.finish()
+    };
+
+    let bits = (u128::from(random()) << 64) | u128::from(random());
+    let bits = (bits & !(0xf << 76) & !(0x3 << 62)) | (0x4 << 76) | (0x2 << 62);
+    let hex = format!("{:032x}", bits);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
 }
 
-/// Present an interactive yes/no prompt.
-pub fn prompt(
-    prompt: &str,
-    default: Option<bool>,
-    yes_response: Option<&str>,
-    no_response: Option<&str>,
-) -> Result<bool> {
-    if yes() {
-        Ok(true)
-    } else {
-        let yn = match default {
-            None => "[y/n]",
-            Some(true) => "[Y/n]",
-            Some(false) => "[y/N]",
-        };
-
-        let mut input = String::new();
-        let stdin = io::stdin();
-        let mut stdout = io::stdout();
-
-        let res = loop {
-            print!("{} {} ", prompt, yn);
-            stdout.flush()?;
-            stdin.read_line(&mut input)?;
-
-            if input.trim().is_empty() {
-                if let Some(def) = default {
-                    break Ok::<_, Error>(def);
-                }
-            } else {
-                match input.to_lowercase().trim() {
-                    "y" | "yes" => {
-                        break Ok(true);
-                    }
+/// The 64-bit FNV-1a hash of the given bytes.
+///
+/// Unlike the standard library's hashers, the result is stable across runs and platforms, so it
+/// can be used to name files.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
 
-                    "n" | "no" => {
-                        break Ok(false);
-                    }
+/// Parse a duration given as a number followed by a unit, such as `90d` or `12h`.
+///
+/// The recognized units are `s` (seconds), `m` (minutes), `h` (hours), `d` (days), and `w`
+/// (weeks). A bare number is taken as a number of days.
+pub fn parse_duration(text: &str) -> Option<chrono::Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (num, unit) = text.split_at(split);
+    let num: i64 = num.parse().ok()?;
 
-                    _ => {}
-                }
-            }
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "" | "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    num.checked_mul(unit_secs)
+        .filter(|&secs| secs <= chrono::Duration::max_value().num_seconds())
+        .map(chrono::Duration::seconds)
+}
+
+/// Describe a duration in the largest of days, hours, minutes, or seconds that measures it
+/// exactly, such as `12 hour(s)` for twelve hours.
+pub fn describe_duration(duration: chrono::Duration) -> String {
+    let secs = duration.num_seconds();
+    let (count, unit) = [(24 * 60 * 60, "day"), (60 * 60, "hour"), (60, "minute")]
+        .iter()
+        .find(|&&(unit_secs, _)| secs % unit_secs == 0)
+        .map_or((secs, "second"), |&(unit_secs, unit)| {
+            (secs / unit_secs, unit)
+        });
+    format!("{} {}(s)", count, unit)
+}
 
-            input.clear();
-        }?;
+/// Match text against a shell-style glob pattern.
+///
+/// `*` matches any sequence of characters, `?` matches any single character, and all other
+/// characters match themselves.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
 
-        if res {
-            if let Some(s) = yes_response {
-                println!("{}", s);
-            }
+    // Position in the pattern just after the last `*`, and the text position it is matched up to.
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            p += 1;
+            star = Some((p, t));
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p;
+            t = star_t + 1;
+            star = Some((star_p, t));
         } else {
-            if let Some(s) = no_response {
-                println!("{}", s);
-            }
+            return false;
         }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// The number of single-character insertions, deletions, and substitutions needed to turn one
+/// string into the other.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substituted = diagonal + if ca == cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The candidate closest to a misspelled word, if any is close enough to be a likely correction.
+///
+/// A candidate is close enough if it is within one edit for every three characters of the word,
+/// and at least one edit. Ties go to the earliest candidate.
+pub fn closest_match<'a, I>(word: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let limit = (word.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|&(distance, _)| distance <= limit)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Shorten a line of text to at most `width` characters, keeping the text around the character
+/// at index `at`. Ellipses mark where text was cut off.
+pub fn snippet(text: &str, at: usize, width: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.len() <= width {
+        return String::from(text);
+    }
+
+    let width = width.saturating_sub(2).max(1);
+    let start = at.saturating_sub(width / 4).min(chars.len() - width);
+    let end = start + width;
+    let mut res = String::new();
+    if start > 0 {
+        res.push('…');
+    }
+    res.extend(&chars[start..end]);
+    if end < chars.len() {
+        res.push('…');
+    }
+    res
+}
+
+/// The number of threads used by [`par_map`].
+///
+/// The work given to it is mostly waiting on file reads, so this is not tied to the number of
+/// CPUs; several reads in flight at once help even on a single slow disk.
+const WORKERS: usize = 8;
+
+/// Apply a function to each item on a pool of worker threads, returning the results in the
+/// order of the items.
+///
+/// The workers log messages as the calling thread does. A panic in any worker is resumed
+/// on the calling thread.
+pub fn par_map<T, R, F>(items: Vec<T>, f: F) -> Vec<R>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn(T) -> R + Send + Sync + 'static,
+{
+    let workers = WORKERS.min(items.len());
+    if workers <= 1 {
+        return items.into_iter().map(f).collect();
+    }
+
+    let queue = Arc::new(Mutex::new(items.into_iter().enumerate()));
+    let f = Arc::new(f);
+    let logger = crate::logging::current();
+    let handles: Vec<_> = (0..workers)
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let f = Arc::clone(&f);
+            let logger = logger.clone();
+            thread::spawn(move || {
+                crate::logging::replace(logger);
+                let mut results = Vec::new();
+                loop {
+                    let next = queue.lock().unwrap_or_else(|err| err.into_inner()).next();
+                    match next {
+                        Some((i, item)) => results.push((i, f(item))),
+                        None => return results,
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let mut results = Vec::new();
+    for handle in handles {
+        match handle.join() {
+            Ok(done) => results.extend(done),
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn slugify_title() {
+        assert_eq!(
+            slugify("Meeting with accounting"),
+            "meeting-with-accounting"
+        );
+        assert_eq!(slugify("  What's up?! "), "what-s-up");
+        assert_eq!(slugify("---"), "");
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("*.pdf", "paper.pdf"));
+        assert!(glob_match("*.pdf", ".pdf"));
+        assert!(!glob_match("*.pdf", "paper.pdf.md"));
+        assert!(glob_match("img-??.*", "img-01.png"));
+        assert!(!glob_match("img-??.*", "img-1.png"));
+        assert!(glob_match("*a*b*", "xxaxxbxx"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("a", ""));
+    }
+
+    #[test]
+    fn suggestions() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+
+        let keys = ["editor", "editor_fallback", "pager"];
+        assert_eq!(closest_match("editr", keys.iter().copied()), Some("editor"));
+        assert_eq!(closest_match("pagr", keys.iter().copied()), Some("pager"));
+        assert_eq!(closest_match("colors", keys.iter().copied()), None);
+    }
+
+    #[test]
+    fn fnv_hash() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn snippets() {
+        assert_eq!(snippet("short line", 6, 20), "short line");
+        assert_eq!(snippet("abcdefghijklmnopqrstuvwxyz", 0, 10), "abcdefgh…");
+        assert_eq!(snippet("abcdefghijklmnopqrstuvwxyz", 12, 10), "…klmnopqr…");
+        assert_eq!(snippet("abcdefghijklmnopqrstuvwxyz", 25, 10), "…stuvwxyz");
+    }
+
+    #[test]
+    fn parallel_map() {
+        let squares = par_map((0..100u64).collect(), |n| {
+            std::thread::sleep(std::time::Duration::from_micros(100 - n));
+            n * n
+        });
+        assert_eq!(squares, (0..100u64).map(|n| n * n).collect::<Vec<_>>());
+        assert_eq!(par_map(vec![3], |n: i32| -n), &[-3]);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90d"), Some(chrono::Duration::days(90)));
+        assert_eq!(parse_duration("12h"), Some(chrono::Duration::hours(12)));
+        assert_eq!(parse_duration("2w"), Some(chrono::Duration::weeks(2)));
+        assert_eq!(parse_duration("7"), Some(chrono::Duration::days(7)));
+        assert_eq!(parse_duration("d"), None);
+        assert_eq!(parse_duration("3y"), None);
+        assert_eq!(parse_duration("99999999999999w"), None);
+        assert_eq!(parse_duration("9223372036854775807s"), None);
+
+        assert_eq!(describe_duration(chrono::Duration::days(90)), "90 day(s)");
+        assert_eq!(describe_duration(chrono::Duration::hours(12)), "12 hour(s)");
+        assert_eq!(describe_duration(chrono::Duration::hours(36)), "36 hour(s)");
+        assert_eq!(
+            describe_duration(chrono::Duration::minutes(90)),
+            "90 minute(s)"
+        );
+        assert_eq!(
+            describe_duration(chrono::Duration::seconds(61)),
+            "61 second(s)"
+        );
+        assert_eq!(describe_duration(chrono::Duration::zero()), "0 day(s)");
+    }
 
-        Ok(res)
+    #[test]
+    fn uuid_format() {
+        let id = uuid();
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "4");
+        assert!("89ab".contains(&id[19..20]));
+        assert_ne!(id, uuid());
     }
 }
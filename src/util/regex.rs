@@ -0,0 +1,138 @@
+//! Regular expressions for the replace and rename-all commands.
+//!
+//! Patterns use the syntax of the [`regex`] crate, with `^` and `$` matching at line boundaries.
+//! Matching takes time linear in the length of the text.
+
+use crate::error::*;
+
+use regex::RegexBuilder;
+
+/// A compiled regular expression.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    inner: regex::Regex,
+}
+
+impl Regex {
+    /// Compile a regular expression.
+    pub fn new(pattern: &str) -> Result<Regex> {
+        let inner = RegexBuilder::new(pattern)
+            .multi_line(true)
+            .build()
+            .map_err(|err| Error::InvalidRegex {
+                pattern: String::from(pattern),
+                message: describe(&err),
+            })?;
+        Ok(Regex { inner })
+    }
+
+    /// Compile a regular expression that matches the given text literally.
+    pub fn literal(text: &str) -> Regex {
+        Regex {
+            inner: regex::Regex::new(&regex::escape(text)).expect("escaped text is a valid regex"),
+        }
+    }
+
+    /// Replace every non-overlapping match in the given text, returning the new text and the
+    /// number of matches replaced.
+    ///
+    /// In the replacement, `$n` or `${n}` is replaced by the text matched by the `n`th capture
+    /// group (`$0` being the whole match), and `$$` by a literal `$`.
+    pub fn replace_all(&self, text: &str, replacement: &str) -> (String, usize) {
+        let mut res = String::new();
+        let mut count = 0;
+        let mut pos = 0;
+        for caps in self.inner.captures_iter(text) {
+            let whole = caps.get(0).expect("group 0 is the whole match");
+            res.push_str(&text[pos..whole.start()]);
+            caps.expand(replacement, &mut res);
+            pos = whole.end();
+            count += 1;
+        }
+        res.push_str(&text[pos..]);
+        (res, count)
+    }
+}
+
+/// The description of what is wrong with a pattern, without the pattern itself, which the error
+/// names separately.
+fn describe(err: &regex::Error) -> String {
+    let text = err.to_string();
+    let message = text.lines().last().unwrap_or_default();
+    String::from(message.strip_prefix("error: ").unwrap_or(message))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn is_match(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().replace_all(text, "").1 > 0
+    }
+
+    fn replace(pattern: &str, text: &str, replacement: &str) -> String {
+        Regex::new(pattern)
+            .unwrap()
+            .replace_all(text, replacement)
+            .0
+    }
+
+    #[test]
+    fn matching() {
+        let re = r"^\d{3}-[a-z]+$";
+        assert!(is_match(re, "123-abc"));
+        assert!(is_match(re, "x\n123-abc\ny"));
+        assert!(!is_match(re, "1234-abc"));
+        assert!(!is_match(re, "123-ABC"));
+
+        let re = "(cat|dog)s?";
+        assert!(is_match(re, "hotdog"));
+        assert!(!is_match(re, "cow"));
+        assert!(is_match("a{2,}", "baab"));
+        assert!(!is_match("a{2,}", "bab"));
+        assert!(is_match(r"[^\s]", " \tx"));
+    }
+
+    #[test]
+    fn replacing() {
+        assert_eq!(replace("o", "foo boo", "0"), "f00 b00");
+        assert_eq!(
+            replace(r"(\w+)@(\w+)", "me@home, you@work", "$2:${1}!"),
+            "home:me!, work:you!"
+        );
+        assert_eq!(replace("a*", "baac", "-"), "-b-c-");
+        assert_eq!(replace("<.+?>", "<a><b>", "[]"), "[][]");
+        assert_eq!(replace("<.+>", "<a><b>", "[]"), "[]");
+        assert_eq!(replace("x", "x", "$$1 $9 $"), "$1  $");
+        assert_eq!(
+            Regex::literal("a.b").replace_all("a.b axb", "c"),
+            (String::from("c axb"), 1)
+        );
+    }
+
+    #[test]
+    fn long_lines() {
+        let line = "x".repeat(100_000);
+        assert_eq!(
+            Regex::new("x+").unwrap().replace_all(&line, "y"),
+            (String::from("y"), 1)
+        );
+        assert_eq!(replace("^.*$", &line, "y"), "y");
+        assert!(!is_match(".*z", &line));
+    }
+
+    #[test]
+    fn invalid() {
+        for pattern in &["(a", "a)", "[a", "*a", "[z-a]", r"a\", "a{3,1}"] {
+            assert!(
+                matches!(Regex::new(pattern), Err(Error::InvalidRegex { .. })),
+                "{}",
+                pattern
+            );
+        }
+        assert!(matches!(
+            Regex::new("(a"),
+            Err(Error::InvalidRegex { message, .. }) if message == "unclosed group"
+        ));
+    }
+}
@@ -1,13 +1,5 @@
-use std::ffi::OsStr;
-use std::process::Command;
-
-pub fn command<S: AsRef<OsStr>>(line: S) -> Option<Command> {
-    let chars = line.as_ref().to_str()?.chars();
-    let mut words = Lexer::new(chars);
-
-    let mut cmd = Command::new(words.next()?);
-    cmd.args(words);
-    Some(cmd)
+pub fn split<S: AsRef<str>>(line: S) -> Vec<String> {
+    Lexer::new(line.as_ref().chars()).collect()
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -0,0 +1,84 @@
+//! Private temporary directories.
+
+use crate::error::*;
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// A directory under the system's temporary directory that only the current user can use,
+/// removed with everything in it when dropped.
+///
+/// The directory gets a random name and is created afresh, so that other users can neither
+/// predict where files put in it will be nor have a file or symbolic link waiting there.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create a temporary directory whose name starts with `newt-` and the given prefix.
+    pub fn new(prefix: &str) -> Result<TempDir> {
+        loop {
+            let path = std::env::temp_dir().join(format!("newt-{}-{}", prefix, super::uuid()));
+            match create_private_dir(&path) {
+                Ok(()) => return Ok(TempDir { path }),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err).context("creating", path),
+            }
+        }
+    }
+
+    /// The path to the directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.path) {
+            debug!(
+                "Could not remove temporary directory {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+}
+
+#[cfg(unix)]
+fn create_private_dir(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new().mode(0o700).create(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(path: &Path) -> std::io::Result<()> {
+    fs::DirBuilder::new().create(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn temp_dirs() {
+        let dir = TempDir::new("temp-test").unwrap();
+        let other = TempDir::new("temp-test").unwrap();
+        assert_ne!(dir.path(), other.path());
+        assert!(dir.path().is_dir());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.path()).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+
+        fs::write(dir.path().join("file"), "contents").unwrap();
+        let path = dir.path().to_path_buf();
+        drop(dir);
+        assert!(!path.exists());
+    }
+}
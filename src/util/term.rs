@@ -0,0 +1,146 @@
+use crate::error::*;
+use crate::util::env;
+
+use std::io::{self, Read};
+use std::process::{Command, Stdio};
+
+/// Whether standard output is connected to a terminal.
+pub fn stdout_is_tty() -> bool {
+    atty::is(atty::Stream::Stdout)
+}
+
+/// Whether standard input is connected to a terminal.
+pub fn stdin_is_tty() -> bool {
+    atty::is(atty::Stream::Stdin)
+}
+
+/// Get the height of the terminal in lines, if it can be determined.
+///
+/// Checks `$LINES` first, then falls back to asking `tput`.
+pub fn height() -> Option<usize> {
+    dimension("LINES", "lines")
+}
+
+/// Get the width of the terminal in columns, if it can be determined.
+///
+/// Checks `$COLUMNS` first, then falls back to asking `tput`.
+pub fn width() -> Option<usize> {
+    dimension("COLUMNS", "cols")
+}
+
+fn dimension(var: &str, capability: &str) -> Option<usize> {
+    if let Some(value) = env::env_var(var) {
+        if let Some(value) = value.to_str().and_then(|s| s.trim().parse().ok()) {
+            return Some(value);
+        }
+    }
+
+    let output = Command::new("tput")
+        .arg(capability)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+    } else {
+        None
+    }
+}
+
+/// A key read from the terminal in raw mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// A printable character.
+    Char(char),
+
+    /// A character typed with the control key held, given in lowercase.
+    Ctrl(char),
+    Enter,
+    Backspace,
+    Up,
+    Down,
+
+    /// Any other key or escape sequence.
+    Other,
+}
+
+/// The terminal in raw mode, in which keys are read as they are pressed without being echoed.
+///
+/// The terminal's previous settings are restored when this is dropped. The mode is set with
+/// `stty`, which must be available.
+pub struct RawMode {
+    saved: String,
+}
+
+fn stty(args: &[&str]) -> Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|err| cannot_invoke("stty", err))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(cannot_invoke("stty", None))
+    }
+}
+
+impl RawMode {
+    /// Put the terminal into raw mode.
+    pub fn enable() -> Result<RawMode> {
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "-isig", "min", "1", "time", "0"])?;
+        Ok(RawMode { saved })
+    }
+
+    /// Read a single key press from standard input.
+    pub fn read_key(&self) -> Result<Key> {
+        let mut stdin = io::stdin();
+        let mut byte = || -> Result<u8> {
+            let mut buf = [0];
+            stdin.read_exact(&mut buf)?;
+            Ok(buf[0])
+        };
+
+        let first = byte()?;
+        Ok(match first {
+            b'\r' | b'\n' => Key::Enter,
+            0x7f | 0x08 => Key::Backspace,
+            0x1b => match byte()? {
+                b'[' | b'O' => match byte()? {
+                    b'A' => Key::Up,
+                    b'B' => Key::Down,
+                    _ => Key::Other,
+                },
+                _ => Key::Other,
+            },
+            0x01..=0x1a => Key::Ctrl((b'a' + first - 1) as char),
+            _ if first < 0x80 => Key::Char(first as char),
+            _ => {
+                // Gather the rest of a UTF-8 encoded character.
+                let len = first.leading_ones() as usize;
+                let mut bytes = vec![first];
+                for _ in 1..len.min(4) {
+                    bytes.push(byte()?);
+                }
+                match std::str::from_utf8(&bytes)
+                    .ok()
+                    .and_then(|s| s.chars().next())
+                {
+                    Some(c) => Key::Char(c),
+                    None => Key::Other,
+                }
+            }
+        })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+    }
+}
@@ -0,0 +1,227 @@
+//! Watching the notes directory for notes created, modified, or deleted by other programs.
+//!
+//! Changes are found by comparing the notes' modification times and sizes before and after
+//! something happens. The `notify` crate reports when something happens in the notes directory
+//! as soon as it does, using inotify on Linux, FSEvents on macOS, kqueue on the BSDs, and
+//! ReadDirectoryChangesW on Windows. The notes directory is also checked again after each
+//! interval, which is all there is to go on where it cannot be watched.
+
+use crate::config::Config;
+use crate::error::*;
+use crate::notes_dir;
+use crate::session::Session;
+use crate::util::json::{json, Value};
+
+use chrono::{DateTime, Local};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, SystemTime};
+
+/// How long to wait after a change is noticed for others made with it, such as by an editor
+/// saving through a temporary file, so that they are reported together.
+const SETTLE: Duration = Duration::from_millis(100);
+
+/// A change to a note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A note that did not exist before.
+    Created(PathBuf),
+
+    /// A note whose modification time or size changed.
+    Modified(PathBuf),
+
+    /// A note that no longer exists.
+    Deleted(PathBuf),
+}
+
+impl Event {
+    /// The kind of change, as `created`, `modified`, or `deleted`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Created(_) => "created",
+            Event::Modified(_) => "modified",
+            Event::Deleted(_) => "deleted",
+        }
+    }
+
+    /// The note's path relative to the notes directory.
+    pub fn path(&self) -> &Path {
+        match self {
+            Event::Created(path) | Event::Modified(path) | Event::Deleted(path) => path,
+        }
+    }
+
+    /// The change as a JSON object, with the fields "event", "note", and "time".
+    pub fn to_json(&self, time: DateTime<Local>) -> Value {
+        json!({
+            "event": self.kind(),
+            "note": self.path().to_string_lossy(),
+            "time": time.to_rfc3339(),
+        })
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.kind(), self.path().display())
+    }
+}
+
+/// The modification time and size of each note, by path relative to the notes directory.
+pub type Snapshot = BTreeMap<PathBuf, (Option<SystemTime>, u64)>;
+
+/// Record the state of every note in the notes directory.
+pub fn snapshot(config: &Config) -> Result<Snapshot> {
+    notes_dir::walk(config)?
+        .map(|entry| entry.map(|(name, md)| (name, (md.modified().ok(), md.len()))))
+        .collect()
+}
+
+/// The changes between two snapshots, in order of path.
+pub fn changes(before: &Snapshot, after: &Snapshot) -> Vec<Event> {
+    let paths: BTreeSet<_> = before.keys().chain(after.keys()).collect();
+    paths
+        .into_iter()
+        .filter_map(|path| match (before.get(path), after.get(path)) {
+            (None, Some(_)) => Some(Event::Created(path.clone())),
+            (Some(_), None) => Some(Event::Deleted(path.clone())),
+            (Some(old), Some(new)) if old != new => Some(Event::Modified(path.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Print changes to notes as they happen, until interrupted.
+///
+/// Each change is printed on a line of its own, as text or as a JSON object. The notes directory
+/// is checked at least once per `interval`.
+pub fn watch(session: &Session, config: &Config, json: bool, interval: Duration) -> Result<()> {
+    let notes_dir = config.notes_dir()?;
+    let notifier = match Notifier::new(&notes_dir) {
+        Ok(notifier) => Some(notifier),
+        Err(err) => {
+            warn!(
+                "Could not watch {}, checking it once per interval instead: {}",
+                notes_dir.display(),
+                err
+            );
+            None
+        }
+    };
+    let mut before = snapshot(config)?;
+    info!("Watching {}", notes_dir.display());
+
+    loop {
+        match &notifier {
+            Some(notifier) => {
+                if notifier.wait(interval) {
+                    std::thread::sleep(SETTLE);
+                    notifier.clear();
+                }
+            }
+            None => std::thread::sleep(interval),
+        }
+
+        // Notes may be removed while the directory is being read, so a failed check is only
+        // reported and tried again next time.
+        let after = match snapshot(config) {
+            Ok(after) => after,
+            Err(err) => {
+                warn!("{}", err);
+                continue;
+            }
+        };
+
+        let now = Local::now();
+        let mut output = session.output();
+        for event in changes(&before, &after) {
+            if json {
+                writeln!(output, "{}", event.to_json(now))?;
+            } else {
+                writeln!(output, "{}", event)?;
+            }
+        }
+        output.flush()?;
+        before = after;
+    }
+}
+
+/// Reports when something happens anywhere in a directory.
+struct Notifier {
+    // Changes are reported for as long as the watcher lives.
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+}
+
+impl Notifier {
+    fn new(dir: &Path) -> notify::Result<Notifier> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+        Ok(Notifier {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// Wait for a change, for at most `timeout`. Returns whether there was one.
+    fn wait(&self, timeout: Duration) -> bool {
+        match self.events.recv_timeout(timeout) {
+            Ok(_) => true,
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => {
+                std::thread::sleep(timeout);
+                false
+            }
+        }
+    }
+
+    /// Discard the changes reported so far.
+    fn clear(&self) {
+        while self.events.try_recv().is_ok() {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn snapshot_changes() {
+        let time = |secs| Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        let before: Snapshot = vec![
+            (PathBuf::from("a.md"), (time(1), 10)),
+            (PathBuf::from("b.md"), (time(1), 10)),
+            (PathBuf::from("sub/c.md"), (time(1), 10)),
+            (PathBuf::from("d.md"), (time(1), 10)),
+        ]
+        .into_iter()
+        .collect();
+        let after: Snapshot = vec![
+            (PathBuf::from("a.md"), (time(1), 10)),
+            (PathBuf::from("b.md"), (time(2), 10)),
+            (PathBuf::from("d.md"), (time(1), 12)),
+            (PathBuf::from("sub/e.md"), (time(2), 0)),
+        ]
+        .into_iter()
+        .collect();
+
+        let events = changes(&before, &after);
+        assert_eq!(
+            events,
+            vec![
+                Event::Modified("b.md".into()),
+                Event::Modified("d.md".into()),
+                Event::Deleted("sub/c.md".into()),
+                Event::Created("sub/e.md".into()),
+            ]
+        );
+        assert_eq!(events[3].to_string(), "created sub/e.md");
+        assert!(changes(&after, &after).is_empty());
+    }
+}
@@ -0,0 +1,230 @@
+//! Wiki-style links between notes, as written in Obsidian vaults and by other note apps.
+//!
+//! A link is written `[[target]]`, where the target is the name of a note, with or without its
+//! extension and the folders it is in. The target may be followed by a `#heading` within the note,
+//! and by `|text` to show in place of the target, as in `[[Ideas#Later|later]]`. A link preceded
+//! by `!` embeds the note rather than linking to it, and is otherwise the same.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A wiki-style link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WikiLink<'a> {
+    /// The name of the linked note, as written.
+    pub target: &'a str,
+
+    /// The heading within the note, after the `#`, if there is one.
+    pub section: Option<&'a str>,
+
+    /// The text to show in place of the target, if there is any.
+    pub label: Option<&'a str>,
+}
+
+impl WikiLink<'_> {
+    /// The text to show for the link: its label, or else its target and section.
+    pub fn text(&self) -> String {
+        match (self.label, self.section) {
+            (Some(label), _) => String::from(label),
+            (None, Some(section)) if self.target.is_empty() => String::from(section),
+            (None, Some(section)) => format!("{} > {}", self.target, section),
+            (None, None) => String::from(self.target),
+        }
+    }
+
+    /// Whether the link's target names the note with the given path relative to the notes
+    /// directory, ignoring case.
+    ///
+    /// A target without folders names any note with that file name, and one with folders names
+    /// the note at that path. Either may leave out the note's extension.
+    pub fn refers_to(&self, note: &Path) -> bool {
+        let target = self.target.trim_start_matches('/').to_lowercase();
+        if target.is_empty() {
+            return false;
+        }
+
+        let name = if target.contains('/') {
+            Some(note.as_os_str())
+        } else {
+            note.file_name()
+        };
+        let name = match name {
+            Some(name) => Path::new(name),
+            None => return false,
+        };
+        [name, &name.with_extension("")]
+            .iter()
+            .any(|candidate| candidate.to_string_lossy().to_lowercase() == target)
+    }
+
+    /// The target that refers to the note `to` in the same way this link refers to the note
+    /// `from`, by file name or by path, and with an extension only if this link has one.
+    fn target_for(&self, from: &Path, to: &Path) -> String {
+        let target = self.target.trim_start_matches('/');
+        let name = if target.contains('/') {
+            Some(to.as_os_str())
+        } else {
+            to.file_name()
+        };
+        let name = Path::new(name.unwrap_or_default());
+        let with_extension = match (Path::new(target).extension(), from.extension()) {
+            (Some(ext), Some(from_ext)) => ext
+                .to_string_lossy()
+                .eq_ignore_ascii_case(&from_ext.to_string_lossy()),
+            _ => false,
+        };
+
+        let leading = &self.target[..self.target.len() - target.len()];
+        let name = if with_extension {
+            name.to_path_buf()
+        } else {
+            name.with_extension("")
+        };
+        format!("{}{}", leading, name.to_string_lossy())
+    }
+}
+
+impl fmt::Display for WikiLink<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[[{}", self.target)?;
+        if let Some(section) = self.section {
+            write!(f, "#{}", section)?;
+        }
+        if let Some(label) = self.label {
+            write!(f, "|{}", label)?;
+        }
+        write!(f, "]]")
+    }
+}
+
+/// Parse the wiki-style link at the start of `text`, returning it and its length.
+pub fn parse(text: &str) -> Option<(WikiLink<'_>, usize)> {
+    let inner = text.strip_prefix("[[")?;
+    let end = inner.find("]]")?;
+    let inner = &inner[..end];
+    if inner.trim().is_empty() || inner.contains(&['\n', '['][..]) {
+        return None;
+    }
+
+    let (target, label) = match inner.split_once('|') {
+        Some((target, label)) => (target, Some(label.trim())),
+        None => (inner, None),
+    };
+    let (target, section) = match target.split_once('#') {
+        Some((target, section)) => (target, Some(section.trim())),
+        None => (target, None),
+    };
+    let link = WikiLink {
+        target: target.trim(),
+        section,
+        label,
+    };
+    Some((link, end + 4))
+}
+
+/// Point the wiki-style links in `text` to any of the renamed notes at their new names, keeping
+/// their headings and labels. Each rename is a pair of old and new paths relative to the notes
+/// directory. Returns the new text, if any link was changed.
+///
+/// Links in code blocks and code spans are left alone.
+pub fn retarget(text: &str, renames: &[(PathBuf, PathBuf)]) -> Option<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut changed = false;
+    let mut fence = None;
+
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+            result.push_str(line);
+            continue;
+        } else if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fence = Some(&trimmed[..3]);
+            result.push_str(line);
+            continue;
+        }
+
+        let mut rest = line;
+        let mut in_code = false;
+        while let Some(c) = rest.chars().next() {
+            if c == '`' {
+                in_code = !in_code;
+            } else if !in_code {
+                if let Some((link, len)) = parse(rest) {
+                    let renamed = renames.iter().find(|(from, _)| link.refers_to(from));
+                    if let Some((from, to)) = renamed {
+                        let target = link.target_for(from, to);
+                        let link = WikiLink {
+                            target: &target,
+                            ..link
+                        };
+                        result.push_str(&link.to_string());
+                        changed = true;
+                        rest = &rest[len..];
+                        continue;
+                    }
+                }
+            }
+            result.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    if changed {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_links() {
+        let (link, len) = parse("[[Ideas#Later | later]] and more").unwrap();
+        assert_eq!(
+            link,
+            WikiLink {
+                target: "Ideas",
+                section: Some("Later"),
+                label: Some("later"),
+            }
+        );
+        assert_eq!(len, 23);
+        assert_eq!(link.text(), "later");
+        assert_eq!(parse("[[Ideas#Later]]").unwrap().0.text(), "Ideas > Later");
+        assert_eq!(parse("[[#Later]]").unwrap().0.text(), "Later");
+        assert_eq!(parse("[[ ]]"), None);
+        assert_eq!(parse("[[a\nb]]"), None);
+        assert_eq!(parse("[x]"), None);
+
+        let link = parse("[[Projects/Plan.MD]]").unwrap().0;
+        assert!(link.refers_to(Path::new("projects/plan.md")));
+        assert!(!link.refers_to(Path::new("plan.md")));
+        let link = parse("[[plan]]").unwrap().0;
+        assert!(link.refers_to(Path::new("projects/Plan.md")));
+        assert!(!link.refers_to(Path::new("planning.md")));
+    }
+
+    #[test]
+    fn retarget_links() {
+        let renames = vec![
+            (PathBuf::from("old.md"), PathBuf::from("archive/new.md")),
+            (PathBuf::from("a/b.md"), PathBuf::from("c/d.md")),
+        ];
+        let text = "See [[Old]], [[old.md#Top|the top]] and ![[a/b]].\n\
+                    Not [[older]] or `[[old]]`.\n\
+                    ```\n[[old]]\n```\n";
+        assert_eq!(
+            retarget(text, &renames).unwrap(),
+            "See [[new]], [[new.md#Top|the top]] and ![[c/d]].\n\
+             Not [[older]] or `[[old]]`.\n\
+             ```\n[[old]]\n```\n"
+        );
+        assert_eq!(retarget("[[older]]", &renames), None);
+    }
+}
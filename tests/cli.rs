@@ -0,0 +1,358 @@
+//! Runs CLI commands against temporary notes directories, capturing their output.
+
+use newt::cli::{self, Options};
+use newt::config::Config;
+use newt::error::Error;
+use newt::logging::Level;
+use newt::Session;
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use structopt::StructOpt;
+
+/// A writer whose contents can be read after it is given to a session.
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Buffer {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+/// A notes directory holding the given notes, removed when dropped.
+struct Notes {
+    dir: PathBuf,
+}
+
+impl Notes {
+    fn new(name: &str, notes: &[(&str, &str)]) -> Notes {
+        let dir = std::env::temp_dir().join(format!("newt-cli-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in notes {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        Notes { dir }
+    }
+
+    /// Run the command given by the arguments, answering prompts with the given input, and
+    /// return the result with the normal and warning output.
+    fn run(&self, args: &[&str], input: &'static str) -> (Result<(), Error>, String, String) {
+        let (output, errors) = (Buffer::default(), Buffer::default());
+        let session = Session::new()
+            .with_output(output.clone())
+            .with_errors(errors.clone())
+            .with_input(input.as_bytes());
+        let options = Options::from_iter(std::iter::once("newt").chain(args.iter().copied()));
+        let config = Config::default().with_notes_dir(self.dir.clone());
+        let result = cli::execute(&session, options.command.unwrap_or_default(), config);
+        (result, output.text(), errors.text())
+    }
+}
+
+impl Drop for Notes {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn list_and_cat() {
+    let notes = Notes::new("list", &[("a.md", "# Groceries\n\nmilk\n")]);
+
+    let (result, output, errors) = notes.run(&["list"], "");
+    result.unwrap();
+    assert_eq!(output, "0 a.md - Groceries\n");
+    assert_eq!(errors, "");
+
+    let (result, output, _) = notes.run(&["cat", "0"], "");
+    result.unwrap();
+    assert_eq!(output, "# Groceries\n\nmilk\n");
+
+    let (result, output, _) = notes.run(&["cat", "1"], "");
+    let err = result.unwrap_err();
+    assert!(matches!(
+        err,
+        Error::FileIndexOutOfRange { index: 1, count: 1 }
+    ));
+    assert_eq!(
+        err.to_string(),
+        "No file with index 1; the only valid index is 0"
+    );
+    assert_eq!(output, "");
+}
+
+#[test]
+fn list_deeply_nested_notes() {
+    let notes = Notes::new("deep", &[]);
+    let dir = notes.dir.join("d/".repeat(20));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.md"), "# Deep\n\ntext\n").unwrap();
+
+    let (result, output, _) = notes.run(&["list", "--tree", "--preview-lines", "1"], "");
+    result.unwrap();
+    assert!(output.contains("0 a.md"), "{}", output);
+}
+
+#[test]
+fn rm_prompts() {
+    let notes = Notes::new("rm", &[("a.md", "first line\n")]);
+    notes.run(&["list"], "").0.unwrap();
+
+    let (result, output, _) = notes.run(&["rm", "0"], "n\n");
+    result.unwrap();
+    assert_eq!(
+        output,
+        "Remove file a.md with first line:\n\"first line\"? [y/N] Cancelling\n"
+    );
+    assert!(notes.dir.join("a.md").exists());
+
+    let (result, output, _) = notes.run(&["rm", "0"], "y\n");
+    result.unwrap();
+    assert!(output.ends_with("[y/N] Removing file\n"));
+    assert!(!notes.dir.join("a.md").exists());
+}
+
+#[test]
+fn mv_reports_move() {
+    let notes = Notes::new("mv", &[("a.md", "note\n")]);
+    notes.run(&["list"], "").0.unwrap();
+
+    let (result, output, _) = notes.run(&["mv", "0", "old/"], "");
+    result.unwrap();
+    assert_eq!(output, "Moved a.md to old/a.md\n");
+    assert!(notes.dir.join("old").join("a.md").is_file());
+
+    for destination in &["../escaped", "/tmp/escaped", "old/../../escaped"] {
+        let (result, _, _) = notes.run(&["mv", "0", destination], "");
+        assert!(matches!(result, Err(Error::InvalidNoteName { .. })));
+    }
+    let (result, _, _) = notes.run(
+        &["--yes", "rename-all", "--from", "^(.*)$", "--to", "../$1"],
+        "",
+    );
+    assert!(matches!(result, Err(Error::InvalidNoteName { .. })));
+    assert!(notes.dir.join("old").join("a.md").is_file());
+}
+
+#[test]
+fn indices_match_last_printed() {
+    let notes = Notes::new(
+        "indices",
+        &[
+            ("a.md", "note a\n"),
+            ("b.md", "note b\n"),
+            ("c.md", "note c\n"),
+        ],
+    );
+    notes.run(&["list"], "").0.unwrap();
+    notes.run(&["rm", "0"], "y\n").0.unwrap();
+
+    let (result, output, _) = notes.run(&["search", "note c"], "");
+    result.unwrap();
+    assert!(output.starts_with("1 c.md:1:"));
+    let (result, output, errors) = notes.run(&["cat", "1"], "");
+    result.unwrap();
+    assert_eq!(output, "note c\n");
+    assert_eq!(errors, "");
+}
+
+#[test]
+fn config_check_warns_about_unknown_keys() {
+    let notes = Notes::new("config-check", &[]);
+    let path = notes.dir.join("config");
+    let notes_dir = notes.dir.to_str().unwrap();
+    fs::write(&path, format!("notes_dir {}\neditr vim\n", notes_dir)).unwrap();
+    let path = path.to_str().unwrap();
+
+    let (result, output, _) = notes.run(&["config", "check", path], "");
+    result.unwrap();
+    assert!(output.contains("line 2: unrecognized key \"editr\""));
+    assert!(output.contains("did you mean \"editor\"?"));
+    assert!(output.ends_with("Configuration OK\n"));
+
+    let contents = format!("notes_dir {}\neditr vim\npreview_lines many\n", notes_dir);
+    fs::write(path, contents).unwrap();
+    let (result, output, _) = notes.run(&["config", "check", path], "");
+    assert!(matches!(result, Err(Error::ConfigCheckFailed { count: 1 })));
+    assert!(output.contains("warning: "));
+    assert!(output.contains("error: "));
+}
+
+#[test]
+fn restore_suggests_note() {
+    let notes = Notes::new("restore", &[("groceries.md", "milk\n")]);
+    notes.run(&["list"], "").0.unwrap();
+    notes.run(&["rm", "0"], "y\n").0.unwrap();
+
+    let (result, _, _) = notes.run(&["restore-backup", "grocries.md"], "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "No note grocries.md, did you mean \"groceries.md\"?"
+    );
+
+    let (result, output, _) = notes.run(&["restore-backup", "groceries.md"], "");
+    result.unwrap();
+    assert!(output.starts_with("Restored groceries.md"));
+}
+
+#[test]
+fn io_errors_name_the_file() {
+    let notes = Notes::new("io", &[("a.md", "note\n")]);
+    notes.run(&["list"], "").0.unwrap();
+
+    let missing = notes.dir.join("missing.png");
+    let (result, _, _) = notes.run(&["attach", "0", missing.to_str().unwrap()], "");
+    let err = result.unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with(&format!("Error copying {}: ", missing.display())));
+
+    let errors = Buffer::default();
+    let session = Session::new()
+        .with_errors(errors.clone())
+        .with_log_level(Level::Info);
+    cli::report(&session, &err);
+    let lines: Vec<_> = errors.text().lines().map(String::from).collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].starts_with("  caused by: "));
+}
+
+#[test]
+fn restore_asks_which_note() {
+    let notes = Notes::new("select", &[]);
+    for dir in &["work", "home"] {
+        fs::create_dir_all(notes.dir.join(dir)).unwrap();
+        fs::write(notes.dir.join(dir).join("todo.md"), *dir).unwrap();
+    }
+    for _ in 0..2 {
+        notes.run(&["list"], "").0.unwrap();
+        notes.run(&["rm", "0"], "y\n").0.unwrap();
+    }
+
+    let (result, output, _) = notes.run(&["restore-backup", "todo.md"], "2\n");
+    result.unwrap();
+    assert!(output.starts_with(
+        "Several notes are named todo.md:\n   1) home/todo.md\n   2) work/todo.md\n\
+         Choose 1-2: Restored work/todo.md from the backup taken "
+    ));
+    assert!(notes.dir.join("work/todo.md").is_file());
+    assert!(!notes.dir.join("home/todo.md").exists());
+}
+
+#[test]
+fn import_asks_about_existing_notes() {
+    let notes = Notes::new("import", &[]);
+    let source = Notes::new("import_source", &[("Ideas.txt", "first\n")]);
+    let source_dir = source.dir.to_str().unwrap();
+    let import = ["import", "--from", "nvalt", source_dir];
+    notes.run(&import, "").0.unwrap();
+    let count = || {
+        fs::read_dir(&notes.dir)
+            .unwrap()
+            .filter(|entry| {
+                !entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with('.')
+            })
+            .count()
+    };
+    let before = count();
+
+    fs::write(source.dir.join("Ideas.txt"), "second\n").unwrap();
+    let (result, output, _) = notes.run(&import, "3\n");
+    result.unwrap();
+    assert!(output.contains("\"Ideas\" would be imported as "));
+    assert!(output.contains("Choose 1-3 [1]: Imported 0 note(s)"));
+    assert_eq!(count(), before);
+
+    notes.run(&import, "2\n").0.unwrap();
+    assert_eq!(count(), before);
+    notes.run(&["list"], "").0.unwrap();
+    let (result, output, _) = notes.run(&["cat", "0"], "");
+    result.unwrap();
+    assert!(output.ends_with("second\n"));
+
+    notes.run(&import, "\n").0.unwrap();
+    assert_eq!(count(), before + 1);
+}
+
+#[test]
+fn yes_and_no_input_flags() {
+    let notes = Notes::new("flags", &[("a.md", "note\n")]);
+    notes.run(&["list"], "").0.unwrap();
+
+    for (flag, removed) in &[("--no-input", false), ("--yes", true)] {
+        let output = Buffer::default();
+        let options = Options::from_iter(&["newt", flag, "rm", "0"]);
+        let session = options.session().with_output(output.clone());
+        let config = Config::default().with_notes_dir(notes.dir.clone());
+        cli::execute(&session, options.command.unwrap(), config).unwrap();
+        assert_eq!(notes.dir.join("a.md").exists(), !removed, "{}", flag);
+        if !removed {
+            assert!(output.text().ends_with("[y/N] n\nCancelling\n"));
+        }
+    }
+}
+
+#[test]
+fn dry_run_changes_nothing() {
+    let notes = Notes::new("dry_run", &[("a.md", "alpha\n"), ("b.md", "beta\n")]);
+    notes.run(&["list"], "").0.unwrap();
+    let source = notes.dir.join("nvalt");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("Ideas.txt"), "gamma\n").unwrap();
+    let source = source.to_str().unwrap();
+
+    let commands: &[&[&str]] = &[
+        &["rm", "0"],
+        &["tidy", "--older-than", "0d", "--delete"],
+        &["replace", "alpha", "gamma"],
+        &["rename-all", "--from", "a", "--to", "c"],
+        &["import", "--from", "nvalt", source],
+    ];
+    for args in commands {
+        let output = Buffer::default();
+        let options = Options::from_iter(["newt", "--yes", "--dry-run"].iter().chain(*args));
+        let session = options.session().with_output(output.clone());
+        let config = Config::default().with_notes_dir(notes.dir.clone());
+        cli::execute(&session, options.command.unwrap(), config).unwrap();
+        assert!(!output.text().is_empty(), "{:?}", args);
+    }
+
+    assert_eq!(
+        fs::read_to_string(notes.dir.join("a.md")).unwrap(),
+        "alpha\n"
+    );
+    assert_eq!(
+        fs::read_to_string(notes.dir.join("b.md")).unwrap(),
+        "beta\n"
+    );
+    let names: Vec<_> = fs::read_dir(&notes.dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert!(
+        names
+            .iter()
+            .all(|name| !name.to_string_lossy().ends_with("ideas.md")),
+        "{:?}",
+        names
+    );
+}